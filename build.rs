@@ -0,0 +1,53 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory containing the GLSL shader sources.
+const SHADER_DIR: &str = "shaders";
+/// The shader source extensions that are compiled to SPIR-V.
+const SHADER_EXTENSIONS: &[&str] = &["vert", "frag", "comp"];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let glslc = env::var("GLSLC").unwrap_or_else(|_| "glslc".into());
+
+    println!("cargo:rerun-if-env-changed=GLSLC");
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+
+    for entry in fs::read_dir(SHADER_DIR).unwrap() {
+        let path = entry.unwrap().path();
+        if is_shader(&path) {
+            println!("cargo:rerun-if-changed={}", path.display());
+            compile_shader(&glslc, &path, &out_dir);
+        }
+    }
+}
+
+fn is_shader(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SHADER_EXTENSIONS.contains(&e))
+}
+
+/// Compiles `shaders/<name>.<stage>` to `$OUT_DIR/<name>.<stage>.spv`.
+fn compile_shader(glslc: &str, path: &Path, out_dir: &Path) {
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    let output = out_dir.join(format!("{}.spv", file_name));
+
+    let status = Command::new(glslc)
+        .arg(path)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap_or_else(|e| {
+            panic!(
+                "Failed to run `{}` (is the Vulkan SDK installed?): {}",
+                glslc, e
+            )
+        });
+
+    if !status.success() {
+        panic!("Failed to compile shader `{}`.", path.display());
+    }
+}
@@ -0,0 +1,368 @@
+#version 450
+
+// Shades the materials with the Blinn-Phong model (see `Shading`), using the
+// same descriptors and push constants as `shader.frag`.
+
+// Whether the output has to be encoded as sRGB by this shader because the
+// swapchain format is not an sRGB format (which would encode it on write).
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// How the output is encoded for the swapchain color space (see
+// `OutputTransfer`): 0 = SDR, 1 = linear scRGB, 2 = HDR10 (PQ).
+layout(constant_id = 1) const uint OUTPUT_TRANSFER = 0;
+// Whether the albedo is tinted with the color of the vertices, which is the
+// color of the instance for instances (see `InstanceData`).
+layout(constant_id = 2) const bool TINTED = false;
+
+// The luminance of SDR white and the peak luminance of the display in nits.
+const float PAPER_WHITE_NITS = 200.0;
+const float PEAK_NITS = 1000.0;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+    uint parallaxMinLayers;
+    uint parallaxMaxLayers;
+    uint clustered;
+    float cameraNear;
+    float cameraFar;
+    float screenWidth;
+    float screenHeight;
+} ubo;
+
+// The textures of the material (see `MaterialTextures`).
+layout(binding = 1) uniform sampler2D albedoSampler;
+layout(binding = 2) uniform sampler2D normalSampler;
+layout(binding = 3) uniform sampler2D metallicRoughnessSampler;
+layout(binding = 4) uniform sampler2D occlusionSampler;
+layout(binding = 5) uniform sampler2D emissiveSampler;
+layout(binding = 6) uniform sampler2D heightSampler;
+
+// A light (see `GpuLight`).
+struct Light {
+    // The position and, in W, the kind (0 = directional, 1 = point, 2 = spot).
+    vec4 position;
+    // The direction the light shines in and, in W, its range.
+    vec4 direction;
+    // The color multiplied by the intensity.
+    vec4 color;
+    // The cosines of the inner and outer angles of a spot light and, in Z,
+    // whether the light is shadowed by a shadow map and, in W, the index of
+    // the shadow map of a point light.
+    vec4 cone;
+};
+
+// The lights of the scene (see `LightBufferObject`).
+layout(std430, binding = 7) readonly buffer LightBuffer {
+    uint lightCount;
+    Light lights[];
+};
+
+// The shadow map of the light which casts shadows (see `ShadowMap`).
+layout(binding = 8) uniform sampler2DShadow shadowSampler;
+
+// The maximum number of point lights which cast shadows (see
+// `MAX_POINT_SHADOWS`).
+const uint MAX_POINT_SHADOWS = 4;
+
+// The distances from the point lights which cast shadows (relative to their
+// ranges, see `PointShadow`).
+layout(binding = 9) uniform samplerCubeShadow pointShadowSamplers[MAX_POINT_SHADOWS];
+
+// The distance (relative to the range of a point light) by which the
+// distances in its shadow map are biased, which keeps surfaces from
+// shadowing themselves.
+const float POINT_SHADOW_BIAS = 0.005;
+
+// The number of clusters across the screen, down the screen, and in depth
+// (see `CLUSTER_GRID`).
+const uvec3 CLUSTER_GRID = uvec3(16, 9, 24);
+// The number of lights listed for a cluster plus its count (see
+// `MAX_LIGHTS_PER_CLUSTER`).
+const uint CLUSTER_STRIDE = 64;
+
+// The number of lights which reach each cluster followed by their indices
+// (see `Clusters`).
+layout(std430, binding = 10) readonly buffer ClusterBuffer {
+    uint clusterLights[];
+};
+
+// The opacity of the object and the factors of the material (see
+// `MaterialInstance::push_constants`).
+layout(push_constant) uniform PushConstants {
+    layout(offset = 72) float opacity;
+    layout(offset = 76) float metallic;
+    layout(offset = 80) vec4 baseColor;
+    layout(offset = 96) vec3 emissive;
+    layout(offset = 108) float roughness;
+    layout(offset = 112) float normalScale;
+    layout(offset = 116) float occlusionStrength;
+    layout(offset = 120) float heightScale;
+} pcs;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 4) in vec4 fragTangent;
+
+layout(location = 0) out vec4 outColor;
+
+// The ambient light, which stands in for the light reflected by the rest of
+// the scene.
+const vec3 AMBIENT_COLOR = vec3(0.03);
+
+const float PI = 3.14159265;
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// Compresses colors above a knee so that they approach but never exceed the
+// peak (both relative to SDR white).
+vec3 toneMap(vec3 color, float peak) {
+    float knee = 0.75 * peak;
+    vec3 excess = max(color - knee, 0.0);
+    float range = peak - knee;
+    return min(color, knee) + range * excess / (excess + range);
+}
+
+// Rec. 709 (sRGB) primaries to Rec. 2020 primaries.
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// The SMPTE ST 2084 (PQ) inverse EOTF for luminance normalized to 10000 nits.
+vec3 linearToPq(vec3 color) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(max(color, 0.0), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // scRGB: 1.0 is 80 nits.
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return color * (PAPER_WHITE_NITS / 80.0);
+    } else if (OUTPUT_TRANSFER == 2) {
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return linearToPq(REC709_TO_REC2020 * color * (PAPER_WHITE_NITS / 10000.0));
+    } else if (ENCODE_SRGB) {
+        return linearToSrgb(color);
+    } else {
+        return color;
+    }
+}
+
+// Returns the direction towards a light and the radiance arriving from it at
+// the supplied position.
+vec3 getLight(Light light, vec3 position, out vec3 direction) {
+    if (light.position.w == 0.0) {
+        direction = -light.direction.xyz;
+        return light.color.rgb;
+    }
+
+    vec3 offset = light.position.xyz - position;
+    float dist = length(offset);
+    direction = offset / max(dist, 1e-4);
+
+    // The inverse square falloff is windowed to reach zero at the range.
+    float window = clamp(1.0 - pow(dist / light.direction.w, 4.0), 0.0, 1.0);
+    float attenuation = window * window / max(dist * dist, 1e-4);
+
+    if (light.position.w == 2.0) {
+        float cosAngle = dot(-direction, light.direction.xyz);
+        attenuation *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+    }
+
+    return light.color.rgb * attenuation;
+}
+
+// Returns how much of the light which casts shadows reaches the supplied
+// position (from 0 in shadow to 1 lit), filtering the shadow map with PCF
+// (percentage-closer filtering) over 3x3 texels.
+float getShadow(vec3 position) {
+    vec4 clip = ubo.lightSpace * vec4(position, 1.0);
+    vec3 coords = clip.xyz / clip.w;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texCoord = coords.xy * 0.5 + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(shadowSampler, 0));
+
+    // Each comparison is also filtered linearly by the sampler.
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadowSampler, vec3(texCoord + vec2(x, y) * texel, coords.z));
+        }
+    }
+
+    return lit / 9.0;
+}
+
+// Returns how much of a point light which casts shadows reaches the supplied
+// position (from 0 in shadow to 1 lit), comparing its distance from the light
+// with the shadow map of the light (filtered linearly by the sampler).
+float getPointShadow(Light light, vec3 position) {
+    vec3 relativePosition = position - light.position.xyz;
+    float dist = length(relativePosition) / light.direction.w - POINT_SHADOW_BIAS;
+    uint index = uint(light.cone.w);
+
+    // The index may differ between fragments (with clustered lighting), so
+    // the shadow maps aren't indexed with it directly.
+    float lit = 1.0;
+    for (uint i = 0; i < MAX_POINT_SHADOWS; i++) {
+        if (i == index) {
+            lit = texture(pointShadowSamplers[i], vec4(relativePosition, dist));
+        }
+    }
+
+    return lit;
+}
+
+// Returns the offset in the cluster buffer of the cluster which contains the
+// supplied position: the screen tile of the fragment and the slice of the
+// depth (exponentially distributed between the near and far distances).
+uint getCluster(vec3 position) {
+    float depth = max(-(ubo.view * vec4(position, 1.0)).z, ubo.cameraNear);
+    float slice = log(depth / ubo.cameraNear) / log(ubo.cameraFar / ubo.cameraNear);
+    vec2 tile = gl_FragCoord.xy / vec2(ubo.screenWidth, ubo.screenHeight);
+    uvec3 cluster = min(
+        uvec3(vec3(tile, slice) * vec3(CLUSTER_GRID)),
+        CLUSTER_GRID - 1
+    );
+
+    return ((cluster.z * CLUSTER_GRID.y + cluster.y) * CLUSTER_GRID.x + cluster.x) * CLUSTER_STRIDE;
+}
+
+// Returns the tangent space (tangent, bitangent, and normal) in world space,
+// or just the normal if there are no tangents.
+mat3 getTbn(out bool hasTangent) {
+    vec3 normal = normalize(fragNormal);
+    hasTangent = dot(fragTangent.xyz, fragTangent.xyz) != 0.0;
+    if (!hasTangent) {
+        return mat3(vec3(0.0), vec3(0.0), normal);
+    }
+
+    vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+    vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+    return mat3(tangent, bitangent, normal);
+}
+
+// Returns the texture coordinates of the point on the height map seen in the
+// supplied direction (in tangent space, towards the camera) by marching
+// through layers of depth, more of them at grazing angles.
+//
+// The height map is sampled in a loop, so it is sampled with the derivatives
+// of the undisplaced texture coordinates (which are computed in uniform
+// control flow).
+vec2 getParallaxTexCoord(vec3 view, vec2 dx, vec2 dy) {
+    if (ubo.parallaxMaxLayers == 0 || pcs.heightScale == 0.0 || view.z <= 0.0) {
+        return fragTexCoord;
+    }
+
+    float layers = mix(float(ubo.parallaxMaxLayers), float(ubo.parallaxMinLayers), view.z);
+    float layerDepth = 1.0 / layers;
+    vec2 delta = view.xy / view.z * pcs.heightScale / layers;
+
+    vec2 texCoord = fragTexCoord;
+    float depth = 0.0;
+    float surfaceDepth = 1.0 - textureGrad(heightSampler, texCoord, dx, dy).r;
+    for (uint i = 0; i < ubo.parallaxMaxLayers && depth < surfaceDepth; i++) {
+        texCoord -= delta;
+        depth += layerDepth;
+        surfaceDepth = 1.0 - textureGrad(heightSampler, texCoord, dx, dy).r;
+    }
+
+    // The surface is between the last two layers, so the texture coordinates
+    // are interpolated between them.
+    vec2 previous = texCoord + delta;
+    float after = surfaceDepth - depth;
+    float before = 1.0 - textureGrad(heightSampler, previous, dx, dy).r - (depth - layerDepth);
+    float weight = after / min(after - before, -1e-4);
+    return mix(texCoord, previous, weight);
+}
+
+// Returns the normal from the normal map (in tangent space) in world space.
+vec3 getNormal(mat3 tbn, bool hasTangent, vec2 texCoord) {
+    if (!hasTangent) {
+        return tbn[2];
+    }
+
+    vec3 local = texture(normalSampler, texCoord).xyz * 2.0 - 1.0;
+    local.xy *= pcs.normalScale;
+    return normalize(tbn * local);
+}
+
+void main() {
+    // Parallax
+
+    bool hasTangent;
+    mat3 tbn = getTbn(hasTangent);
+    vec3 view = normalize(ubo.cameraPosition.xyz - fragPosition);
+    vec2 dx = dFdx(fragTexCoord);
+    vec2 dy = dFdy(fragTexCoord);
+    vec2 texCoord = hasTangent ? getParallaxTexCoord(transpose(tbn) * view, dx, dy) : fragTexCoord;
+
+    // Material
+
+    // Sampling an sRGB texture returns linear colors.
+    vec4 albedo = texture(albedoSampler, texCoord) * pcs.baseColor;
+    if (TINTED) {
+        albedo.rgb *= fragColor;
+    }
+    float roughness = clamp(pcs.roughness, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(occlusionSampler, texCoord).r, pcs.occlusionStrength);
+    vec3 emissive = texture(emissiveSampler, texCoord).rgb * pcs.emissive;
+
+    // Smoother surfaces have smaller and brighter highlights (this matches the
+    // width of the highlights of the GGX distribution of the same roughness).
+    float a = roughness * roughness;
+    float shininess = max(2.0 / (a * a) - 2.0, 1.0);
+    vec3 specularColor = mix(vec3(0.04), albedo.rgb, pcs.metallic);
+
+    vec3 normal = getNormal(tbn, hasTangent, texCoord);
+
+    // Lighting (Blinn-Phong)
+
+    vec3 color = AMBIENT_COLOR * albedo.rgb * occlusion;
+    // With clustered lighting, only the lights which reach the cluster of the
+    // fragment are shaded.
+    uint cluster = getCluster(fragPosition);
+    uint count = ubo.clustered != 0 ? clusterLights[cluster] : lightCount;
+    for (uint j = 0; j < count; j++) {
+        uint i = ubo.clustered != 0 ? clusterLights[cluster + 1 + j] : j;
+        vec3 direction;
+        vec3 radiance = getLight(lights[i], fragPosition, direction);
+        if (lights[i].cone.z != 0.0) {
+            radiance *= lights[i].position.w == 1.0
+                ? getPointShadow(lights[i], fragPosition)
+                : getShadow(fragPosition);
+        }
+
+        vec3 halfway = normalize(view + direction);
+        float nDotL = max(dot(normal, direction), 0.0);
+        float nDotH = max(dot(normal, halfway), 0.0);
+
+        // The specular highlight is normalized so that it doesn't reflect
+        // more light than it receives.
+        vec3 diffuse = albedo.rgb * (1.0 - pcs.metallic);
+        vec3 specular = specularColor * (shininess + 8.0) / 8.0 * pow(nDotH, shininess);
+        color += (diffuse + specular) * radiance * nDotL / PI;
+    }
+
+    color += emissive;
+
+    outColor = vec4(encodeOutput(color), albedo.a * pcs.opacity);
+}
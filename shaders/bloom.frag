@@ -0,0 +1,20 @@
+#version 450
+
+// The HDR scene and the blurred bloom image (see `BloomEffect`).
+layout(binding = 0) uniform sampler2D hdrSampler;
+layout(binding = 1) uniform sampler2D bloomSampler;
+
+layout(push_constant) uniform PushConstants {
+    // How much of the bloom is added to the scene.
+    float intensity;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 texCoord = gl_FragCoord.xy / vec2(textureSize(hdrSampler, 0));
+    vec3 color = textureLod(hdrSampler, texCoord, 0.0).rgb;
+    vec3 bloom = textureLod(bloomSampler, texCoord, 0.0).rgb;
+
+    outColor = vec4(color + bloom * pcs.intensity, 1.0);
+}
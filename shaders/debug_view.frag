@@ -0,0 +1,63 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+    uint parallaxMinLayers;
+    uint parallaxMaxLayers;
+    uint clustered;
+    float cameraNear;
+    float cameraFar;
+} ubo;
+
+// The base color texture of the material (see `MaterialTextures`).
+layout(binding = 1) uniform sampler2D albedoSampler;
+
+layout(push_constant) uniform PushConstants {
+    // The debug view (see `DebugView` and `DEBUG_VIEW_PUSH_CONSTANT_OFFSET`).
+    layout(offset = 124) uint debugView;
+} pcs;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+
+layout(location = 0) out vec4 outColor;
+
+// The colors of the mip levels (the last is used for any further levels).
+const vec3 MIP_COLORS[6] = vec3[](
+    vec3(1.0, 1.0, 1.0),
+    vec3(1.0, 0.0, 0.0),
+    vec3(1.0, 0.5, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, 1.0)
+);
+
+// How much each fragment adds to the overdraw (so 10 layers are saturated).
+const vec3 OVERDRAW_STEP = vec3(0.1, 0.05, 0.02);
+
+void main() {
+    vec3 color = vec3(0.0);
+    if (pcs.debugView == 1) {
+        color = normalize(fragNormal) * 0.5 + 0.5;
+    } else if (pcs.debugView == 2) {
+        float depth = -(ubo.view * vec4(fragPosition, 1.0)).z;
+        color = vec3((depth - ubo.cameraNear) / (ubo.cameraFar - ubo.cameraNear));
+    } else if (pcs.debugView == 3) {
+        color = vec3(fract(fragTexCoord), 0.0);
+    } else if (pcs.debugView == 4) {
+        // The mip levels between two levels are blended.
+        float level = clamp(textureQueryLod(albedoSampler, fragTexCoord).x, 0.0, 5.0);
+        uint lower = uint(floor(level));
+        uint upper = min(lower + 1, 5);
+        color = mix(MIP_COLORS[lower], MIP_COLORS[upper], fract(level));
+    } else if (pcs.debugView == 5) {
+        color = OVERDRAW_STEP;
+    }
+
+    outColor = vec4(color, 1.0);
+}
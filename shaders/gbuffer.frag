@@ -0,0 +1,139 @@
+#version 450
+
+// Writes the material of the surface to the G-buffer (see `GBuffer`), which
+// is shaded by the lighting pass (`lighting.frag`).
+
+// Whether the albedo is tinted with the color of the vertices, which is the
+// color of the instance for instances (see `InstanceData`).
+layout(constant_id = 2) const bool TINTED = false;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+    uint parallaxMinLayers;
+    uint parallaxMaxLayers;
+} ubo;
+
+// The textures of the material (see `MaterialTextures`).
+layout(binding = 1) uniform sampler2D albedoSampler;
+layout(binding = 2) uniform sampler2D normalSampler;
+layout(binding = 3) uniform sampler2D metallicRoughnessSampler;
+layout(binding = 4) uniform sampler2D occlusionSampler;
+layout(binding = 5) uniform sampler2D emissiveSampler;
+layout(binding = 6) uniform sampler2D heightSampler;
+
+// The opacity of the object (which is ignored) and the factors of the
+// material (see `MaterialInstance::push_constants`).
+layout(push_constant) uniform PushConstants {
+    layout(offset = 72) float opacity;
+    layout(offset = 76) float metallic;
+    layout(offset = 80) vec4 baseColor;
+    layout(offset = 96) vec3 emissive;
+    layout(offset = 108) float roughness;
+    layout(offset = 112) float normalScale;
+    layout(offset = 116) float occlusionStrength;
+    layout(offset = 120) float heightScale;
+} pcs;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 4) in vec4 fragTangent;
+
+// The attachments of the G-buffer (see `GBUFFER_FORMATS`).
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out vec4 outNormal;
+layout(location = 2) out vec4 outMaterial;
+layout(location = 3) out vec4 outEmissive;
+
+// Returns the tangent space (tangent, bitangent, and normal) in world space,
+// or just the normal if there are no tangents.
+mat3 getTbn(out bool hasTangent) {
+    vec3 normal = normalize(fragNormal);
+    hasTangent = dot(fragTangent.xyz, fragTangent.xyz) != 0.0;
+    if (!hasTangent) {
+        return mat3(vec3(0.0), vec3(0.0), normal);
+    }
+
+    vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+    vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+    return mat3(tangent, bitangent, normal);
+}
+
+// Returns the texture coordinates of the point on the height map seen in the
+// supplied direction (in tangent space, towards the camera) by marching
+// through layers of depth, more of them at grazing angles.
+//
+// The height map is sampled in a loop, so it is sampled with the derivatives
+// of the undisplaced texture coordinates (which are computed in uniform
+// control flow).
+vec2 getParallaxTexCoord(vec3 view, vec2 dx, vec2 dy) {
+    if (ubo.parallaxMaxLayers == 0 || pcs.heightScale == 0.0 || view.z <= 0.0) {
+        return fragTexCoord;
+    }
+
+    float layers = mix(float(ubo.parallaxMaxLayers), float(ubo.parallaxMinLayers), view.z);
+    float layerDepth = 1.0 / layers;
+    vec2 delta = view.xy / view.z * pcs.heightScale / layers;
+
+    vec2 texCoord = fragTexCoord;
+    float depth = 0.0;
+    float surfaceDepth = 1.0 - textureGrad(heightSampler, texCoord, dx, dy).r;
+    for (uint i = 0; i < ubo.parallaxMaxLayers && depth < surfaceDepth; i++) {
+        texCoord -= delta;
+        depth += layerDepth;
+        surfaceDepth = 1.0 - textureGrad(heightSampler, texCoord, dx, dy).r;
+    }
+
+    // The surface is between the last two layers, so the texture coordinates
+    // are interpolated between them.
+    vec2 previous = texCoord + delta;
+    float after = surfaceDepth - depth;
+    float before = 1.0 - textureGrad(heightSampler, previous, dx, dy).r - (depth - layerDepth);
+    float weight = after / min(after - before, -1e-4);
+    return mix(texCoord, previous, weight);
+}
+
+// Returns the normal from the normal map (in tangent space) in world space.
+vec3 getNormal(mat3 tbn, bool hasTangent, vec2 texCoord) {
+    if (!hasTangent) {
+        return tbn[2];
+    }
+
+    vec3 local = texture(normalSampler, texCoord).xyz * 2.0 - 1.0;
+    local.xy *= pcs.normalScale;
+    return normalize(tbn * local);
+}
+
+void main() {
+    // Parallax
+
+    bool hasTangent;
+    mat3 tbn = getTbn(hasTangent);
+    vec3 view = normalize(ubo.cameraPosition.xyz - fragPosition);
+    vec2 dx = dFdx(fragTexCoord);
+    vec2 dy = dFdy(fragTexCoord);
+    vec2 texCoord = hasTangent ? getParallaxTexCoord(transpose(tbn) * view, dx, dy) : fragTexCoord;
+
+    // Material
+
+    // The G-buffer stores a single opaque surface, so the opacity is ignored
+    // (and the albedo is encoded as sRGB by the attachment).
+    vec4 albedo = texture(albedoSampler, texCoord) * pcs.baseColor;
+    if (TINTED) {
+        albedo.rgb *= fragColor;
+    }
+    vec2 metallicRoughness = texture(metallicRoughnessSampler, texCoord).bg;
+    float metallic = clamp(metallicRoughness.x * pcs.metallic, 0.0, 1.0);
+    float roughness = clamp(metallicRoughness.y * pcs.roughness, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(occlusionSampler, texCoord).r, pcs.occlusionStrength);
+    vec3 emissive = texture(emissiveSampler, texCoord).rgb * pcs.emissive;
+
+    outAlbedo = vec4(albedo.rgb, 1.0);
+    outNormal = vec4(getNormal(tbn, hasTangent, texCoord), 0.0);
+    outMaterial = vec4(metallic, roughness, occlusion, 0.0);
+    outEmissive = vec4(emissive, 0.0);
+}
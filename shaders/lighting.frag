@@ -0,0 +1,352 @@
+#version 450
+
+// Shades the surfaces in the G-buffer (see `GBuffer`) like `shader.frag`
+// shades the surfaces of objects.
+
+// Whether the output has to be encoded as sRGB by this shader because the
+// swapchain format is not an sRGB format (which would encode it on write).
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// How the output is encoded for the swapchain color space (see
+// `OutputTransfer`): 0 = SDR, 1 = linear scRGB, 2 = HDR10 (PQ).
+layout(constant_id = 1) const uint OUTPUT_TRANSFER = 0;
+
+// The luminance of SDR white and the peak luminance of the display in nits.
+const float PAPER_WHITE_NITS = 200.0;
+const float PEAK_NITS = 1000.0;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+    uint parallaxMinLayers;
+    uint parallaxMaxLayers;
+    uint clustered;
+    float cameraNear;
+    float cameraFar;
+    float screenWidth;
+    float screenHeight;
+} ubo;
+
+// A light (see `GpuLight`).
+struct Light {
+    // The position and, in W, the kind (0 = directional, 1 = point, 2 = spot).
+    vec4 position;
+    // The direction the light shines in and, in W, its range.
+    vec4 direction;
+    // The color multiplied by the intensity.
+    vec4 color;
+    // The cosines of the inner and outer angles of a spot light and, in Z,
+    // whether the light is shadowed by a shadow map and, in W, the index of
+    // the shadow map of a point light.
+    vec4 cone;
+};
+
+// The lights of the scene (see `LightBufferObject`).
+layout(std430, binding = 7) readonly buffer LightBuffer {
+    uint lightCount;
+    Light lights[];
+};
+
+// The shadow map of the light which casts shadows (see `ShadowMap`).
+layout(binding = 8) uniform sampler2DShadow shadowSampler;
+
+// The maximum number of point lights which cast shadows (see
+// `MAX_POINT_SHADOWS`).
+const uint MAX_POINT_SHADOWS = 4;
+
+// The distances from the point lights which cast shadows (relative to their
+// ranges, see `PointShadow`).
+layout(binding = 9) uniform samplerCubeShadow pointShadowSamplers[MAX_POINT_SHADOWS];
+
+// The distance (relative to the range of a point light) by which the
+// distances in its shadow map are biased, which keeps surfaces from
+// shadowing themselves.
+const float POINT_SHADOW_BIAS = 0.005;
+
+// The number of clusters across the screen, down the screen, and in depth
+// (see `CLUSTER_GRID`).
+const uvec3 CLUSTER_GRID = uvec3(16, 9, 24);
+// The number of lights listed for a cluster plus its count (see
+// `MAX_LIGHTS_PER_CLUSTER`).
+const uint CLUSTER_STRIDE = 64;
+
+// The number of lights which reach each cluster followed by their indices
+// (see `Clusters`).
+layout(std430, binding = 10) readonly buffer ClusterBuffer {
+    uint clusterLights[];
+};
+
+// The image-based lighting (see `Ibl`).
+layout(set = 1, binding = 0) uniform samplerCube irradianceSampler;
+layout(set = 1, binding = 1) uniform samplerCube prefilteredSampler;
+layout(set = 1, binding = 2) uniform sampler2D brdfLutSampler;
+
+// The attachments of the G-buffer (see `GBUFFER_FORMATS`).
+layout(set = 2, binding = 0) uniform sampler2D albedoSampler;
+layout(set = 2, binding = 1) uniform sampler2D normalSampler;
+layout(set = 2, binding = 2) uniform sampler2D materialSampler;
+layout(set = 2, binding = 3) uniform sampler2D emissiveSampler;
+layout(set = 2, binding = 4) uniform sampler2D depthSampler;
+
+layout(push_constant) uniform PushConstants {
+    // The inverse of the view-projection matrix of the camera.
+    mat4 invViewProj;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+// The number of mip levels of the prefiltered environment map (see
+// `PREFILTERED_MIP_LEVELS`).
+const float PREFILTERED_MIP_LEVELS = 5.0;
+
+const float PI = 3.14159265;
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// Compresses colors above a knee so that they approach but never exceed the
+// peak (both relative to SDR white).
+vec3 toneMap(vec3 color, float peak) {
+    float knee = 0.75 * peak;
+    vec3 excess = max(color - knee, 0.0);
+    float range = peak - knee;
+    return min(color, knee) + range * excess / (excess + range);
+}
+
+// Rec. 709 (sRGB) primaries to Rec. 2020 primaries.
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// The SMPTE ST 2084 (PQ) inverse EOTF for luminance normalized to 10000 nits.
+vec3 linearToPq(vec3 color) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(max(color, 0.0), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // scRGB: 1.0 is 80 nits.
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return color * (PAPER_WHITE_NITS / 80.0);
+    } else if (OUTPUT_TRANSFER == 2) {
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return linearToPq(REC709_TO_REC2020 * color * (PAPER_WHITE_NITS / 10000.0));
+    } else if (ENCODE_SRGB) {
+        return linearToSrgb(color);
+    } else {
+        return color;
+    }
+}
+
+// The GGX (Trowbridge-Reitz) normal distribution function.
+float distributionGgx(float nDotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// The Smith geometry function with Schlick-GGX (remapped for direct
+// lighting).
+float geometrySmith(float nDotV, float nDotL, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    float ggxV = nDotV / (nDotV * (1.0 - k) + k);
+    float ggxL = nDotL / (nDotL * (1.0 - k) + k);
+    return ggxV * ggxL;
+}
+
+// The Schlick approximation of the Fresnel reflectance.
+vec3 fresnelSchlick(float cosTheta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// The Schlick approximation of the Fresnel reflectance, which is damped for
+// rough surfaces (for image-based lighting, where the reflectance is averaged
+// over the specular lobe).
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 f0, float roughness) {
+    vec3 f90 = max(vec3(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Returns the direction towards a light and the radiance arriving from it at
+// the supplied position.
+vec3 getLight(Light light, vec3 position, out vec3 direction) {
+    if (light.position.w == 0.0) {
+        direction = -light.direction.xyz;
+        return light.color.rgb;
+    }
+
+    vec3 offset = light.position.xyz - position;
+    float dist = length(offset);
+    direction = offset / max(dist, 1e-4);
+
+    // The inverse square falloff is windowed to reach zero at the range.
+    float window = clamp(1.0 - pow(dist / light.direction.w, 4.0), 0.0, 1.0);
+    float attenuation = window * window / max(dist * dist, 1e-4);
+
+    if (light.position.w == 2.0) {
+        float cosAngle = dot(-direction, light.direction.xyz);
+        attenuation *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+    }
+
+    return light.color.rgb * attenuation;
+}
+
+// Returns how much of the light which casts shadows reaches the supplied
+// position (from 0 in shadow to 1 lit), filtering the shadow map with PCF
+// (percentage-closer filtering) over 3x3 texels.
+float getShadow(vec3 position) {
+    vec4 clip = ubo.lightSpace * vec4(position, 1.0);
+    vec3 coords = clip.xyz / clip.w;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texCoord = coords.xy * 0.5 + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(shadowSampler, 0));
+
+    // Each comparison is also filtered linearly by the sampler.
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadowSampler, vec3(texCoord + vec2(x, y) * texel, coords.z));
+        }
+    }
+
+    return lit / 9.0;
+}
+
+// Returns how much of a point light which casts shadows reaches the supplied
+// position (from 0 in shadow to 1 lit), comparing its distance from the light
+// with the shadow map of the light (filtered linearly by the sampler).
+float getPointShadow(Light light, vec3 position) {
+    vec3 relativePosition = position - light.position.xyz;
+    float dist = length(relativePosition) / light.direction.w - POINT_SHADOW_BIAS;
+    uint index = uint(light.cone.w);
+
+    // The index may differ between fragments (with clustered lighting), so
+    // the shadow maps aren't indexed with it directly.
+    float lit = 1.0;
+    for (uint i = 0; i < MAX_POINT_SHADOWS; i++) {
+        if (i == index) {
+            lit = texture(pointShadowSamplers[i], vec4(relativePosition, dist));
+        }
+    }
+
+    return lit;
+}
+
+// Returns the offset in the cluster buffer of the cluster which contains the
+// supplied position: the screen tile of the fragment and the slice of the
+// depth (exponentially distributed between the near and far distances).
+uint getCluster(vec3 position) {
+    float depth = max(-(ubo.view * vec4(position, 1.0)).z, ubo.cameraNear);
+    float slice = log(depth / ubo.cameraNear) / log(ubo.cameraFar / ubo.cameraNear);
+    vec2 tile = gl_FragCoord.xy / vec2(ubo.screenWidth, ubo.screenHeight);
+    uvec3 cluster = min(
+        uvec3(vec3(tile, slice) * vec3(CLUSTER_GRID)),
+        CLUSTER_GRID - 1
+    );
+
+    return ((cluster.z * CLUSTER_GRID.y + cluster.y) * CLUSTER_GRID.x + cluster.x) * CLUSTER_STRIDE;
+}
+
+void main() {
+    // G-Buffer
+
+    // The G-buffer is the size of the framebuffer, so each pixel is read
+    // directly (and nothing is shaded where nothing was drawn).
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(depthSampler, texel, 0).r;
+    if (depth == 1.0) {
+        discard;
+    }
+
+    // The textures are sampled without derivatives below (which are undefined
+    // after some of the pixels are discarded).
+
+    // Objects drawn after the lighting pass are tested against the depth of
+    // the G-buffer.
+    gl_FragDepth = depth;
+
+    vec2 ndc = gl_FragCoord.xy / vec2(textureSize(depthSampler, 0)) * 2.0 - 1.0;
+    vec4 clip = pcs.invViewProj * vec4(ndc, depth, 1.0);
+    vec3 position = clip.xyz / clip.w;
+
+    // Material
+
+    vec3 albedo = texelFetch(albedoSampler, texel, 0).rgb;
+    vec3 normal = normalize(texelFetch(normalSampler, texel, 0).xyz);
+    vec3 material = texelFetch(materialSampler, texel, 0).rgb;
+    float metallic = material.r;
+    float roughness = material.g;
+    float occlusion = material.b;
+    vec3 emissive = texelFetch(emissiveSampler, texel, 0).rgb;
+
+    vec3 view = normalize(ubo.cameraPosition.xyz - position);
+    float nDotV = max(dot(normal, view), 1e-4);
+
+    // Dielectrics reflect 4% at normal incidence, metals reflect their albedo.
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+
+    // Direct Lighting (Cook-Torrance)
+
+    vec3 color = vec3(0.0);
+    // With clustered lighting, only the lights which reach the cluster of the
+    // fragment are shaded.
+    uint cluster = getCluster(position);
+    uint count = ubo.clustered != 0 ? clusterLights[cluster] : lightCount;
+    for (uint j = 0; j < count; j++) {
+        uint i = ubo.clustered != 0 ? clusterLights[cluster + 1 + j] : j;
+        vec3 direction;
+        vec3 radiance = getLight(lights[i], position, direction);
+        if (lights[i].cone.z != 0.0) {
+            radiance *= lights[i].position.w == 1.0
+                ? getPointShadow(lights[i], position)
+                : getShadow(position);
+        }
+
+        vec3 halfway = normalize(view + direction);
+        float nDotL = max(dot(normal, direction), 0.0);
+        float nDotH = max(dot(normal, halfway), 0.0);
+
+        vec3 f = fresnelSchlick(max(dot(halfway, view), 0.0), f0);
+        float d = distributionGgx(nDotH, roughness);
+        float g = geometrySmith(nDotV, nDotL, roughness);
+        vec3 specular = d * g * f / (4.0 * nDotV * max(nDotL, 1e-4));
+
+        // Metals have no diffuse reflection.
+        vec3 kd = (1.0 - f) * (1.0 - metallic);
+        color += (kd * albedo / PI + specular) * radiance * nDotL;
+    }
+
+    // Image-Based Lighting
+
+    vec3 fAmbient = fresnelSchlickRoughness(nDotV, f0, roughness);
+    vec3 kdAmbient = (1.0 - fAmbient) * (1.0 - metallic);
+    vec3 diffuse = textureLod(irradianceSampler, normal, 0.0).rgb * albedo;
+
+    vec3 reflection = reflect(-view, normal);
+    float lod = roughness * (PREFILTERED_MIP_LEVELS - 1.0);
+    vec3 prefiltered = textureLod(prefilteredSampler, reflection, lod).rgb;
+    vec2 brdf = textureLod(brdfLutSampler, vec2(nDotV, roughness), 0.0).rg;
+    vec3 ambientSpecular = prefiltered * (fAmbient * brdf.x + brdf.y);
+
+    color += (kdAmbient * diffuse + ambientSpecular) * occlusion;
+    color += emissive;
+
+    outColor = vec4(encodeOutput(color), 1.0);
+}
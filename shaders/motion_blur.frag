@@ -0,0 +1,46 @@
+#version 450
+
+// The HDR intermediate target the scene was drawn into and the velocity
+// buffer and its depth (see `MotionBlur`).
+layout(binding = 0) uniform sampler2D hdrSampler;
+layout(binding = 1) uniform sampler2D velocitySampler;
+layout(binding = 2) uniform sampler2D depthSampler;
+
+layout(push_constant) uniform PushConstants {
+    // The matrix which transforms NDC of this frame to the clip space of the
+    // previous frame (for the background, which has no velocity).
+    mat4 reprojection;
+    // The number of samples along the motion of each pixel.
+    uint samples;
+    // The fraction of the frame the shutter is open for.
+    float shutter;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec2 texCoord = gl_FragCoord.xy / vec2(textureSize(hdrSampler, 0));
+
+    // Where nothing was drawn, the background (on the far plane) only moves
+    // with the camera.
+    vec2 velocity = texelFetch(velocitySampler, texel, 0).rg;
+    if (texelFetch(depthSampler, texel, 0).r == 1.0) {
+        vec2 position = texCoord * 2.0 - 1.0;
+        vec4 previous = pcs.reprojection * vec4(position, 1.0, 1.0);
+        velocity = (position - previous.xy / previous.w) * 0.5;
+    }
+
+    // The samples are spread evenly over the motion while the shutter was
+    // open (centered on the pixel).
+    vec2 blur = velocity * pcs.shutter;
+    uint samples = max(pcs.samples, 1);
+
+    vec3 color = vec3(0.0);
+    for (uint i = 0; i < samples; i++) {
+        float t = (float(i) + 0.5) / float(samples) - 0.5;
+        color += textureLod(hdrSampler, texCoord + blur * t, 0.0).rgb;
+    }
+
+    outColor = vec4(color / float(samples), 1.0);
+}
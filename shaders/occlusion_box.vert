@@ -0,0 +1,24 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // The transform from the unit cube to the bounding box of an object in
+    // clip space.
+    mat4 transform;
+} pcs;
+
+// The corners of the triangles of the faces of the unit cube, whose bits are
+// its coordinates (x, y, z).
+const uint CORNERS[36] = uint[](
+    0, 2, 6, 0, 6, 4,
+    1, 5, 7, 1, 7, 3,
+    0, 4, 5, 0, 5, 1,
+    2, 3, 7, 2, 7, 6,
+    0, 1, 3, 0, 3, 2,
+    4, 6, 7, 4, 7, 5
+);
+
+void main() {
+    uint corner = CORNERS[gl_VertexIndex];
+    vec3 position = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+    gl_Position = pcs.transform * vec4(position, 1.0);
+}
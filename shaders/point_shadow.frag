@@ -0,0 +1,10 @@
+#version 450
+
+// Stores the distance from the point light (relative to its range) rather
+// than the depth of the projection, which differs between the faces.
+
+layout(location = 0) in vec3 fragRelativePosition;
+
+void main() {
+    gl_FragDepth = length(fragRelativePosition);
+}
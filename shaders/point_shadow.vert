@@ -0,0 +1,22 @@
+#version 450
+
+// Renders the distances of the scene from a point light into a face of its
+// shadow map (see `PointShadow`).
+
+layout(push_constant) uniform PushConstants {
+    // The projection of the face of the cube map.
+    mat4 faceProjection;
+    // The model matrix followed by the translation to the light and the
+    // scale to its range.
+    mat4 lightModel;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+
+layout(location = 0) out vec3 fragRelativePosition;
+
+void main() {
+    vec4 relativePosition = pcs.lightModel * vec4(inPosition, 1.0);
+    gl_Position = pcs.faceProjection * relativePosition;
+    fragRelativePosition = relativePosition.xyz;
+}
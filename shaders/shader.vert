@@ -0,0 +1,38 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+} ubo;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+layout(location = 4) in vec4 inTangent;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 4) out vec4 fragTangent;
+
+void main() {
+    vec4 position = pcs.model * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * position;
+    fragColor = inColor;
+    fragTexCoord = inTexCoord;
+
+    // The normals are transformed by the inverse transpose of the model
+    // matrix (so they stay perpendicular to non-uniformly scaled surfaces).
+    mat3 normalMatrix = transpose(inverse(mat3(pcs.model)));
+    fragPosition = position.xyz;
+    fragNormal = normalMatrix * inNormal;
+    fragTangent = vec4(mat3(pcs.model) * inTangent.xyz, inTangent.w);
+}
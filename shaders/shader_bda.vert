@@ -0,0 +1,69 @@
+#version 450
+#extension GL_EXT_buffer_reference : require
+
+// The vertices as tightly packed floats (see `Vertex`), which are read through
+// a buffer device address instead of being supplied as vertex attributes.
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+    float values[];
+};
+
+// The number of floats in a vertex (a position, a color, a texture
+// coordinate, a normal, and a tangent).
+const uint VERTEX_SIZE = 15;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 cameraPosition;
+} ubo;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    Vertices vertices;
+} pcs;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 4) out vec4 fragTangent;
+
+void main() {
+    uint base = uint(gl_VertexIndex) * VERTEX_SIZE;
+    Vertices vertices = pcs.vertices;
+
+    vec3 inPosition = vec3(
+        vertices.values[base],
+        vertices.values[base + 1],
+        vertices.values[base + 2]
+    );
+    vec3 inColor = vec3(
+        vertices.values[base + 3],
+        vertices.values[base + 4],
+        vertices.values[base + 5]
+    );
+    vec2 inTexCoord = vec2(vertices.values[base + 6], vertices.values[base + 7]);
+    vec3 inNormal = vec3(
+        vertices.values[base + 8],
+        vertices.values[base + 9],
+        vertices.values[base + 10]
+    );
+    vec4 inTangent = vec4(
+        vertices.values[base + 11],
+        vertices.values[base + 12],
+        vertices.values[base + 13],
+        vertices.values[base + 14]
+    );
+
+    vec4 position = pcs.model * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * position;
+    fragColor = inColor;
+    fragTexCoord = inTexCoord;
+
+    // See `shader.vert`.
+    mat3 normalMatrix = transpose(inverse(mat3(pcs.model)));
+    fragPosition = position.xyz;
+    fragNormal = normalMatrix * inNormal;
+    fragTangent = vec4(mat3(pcs.model) * inTangent.xyz, inTangent.w);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+// Renders the depth of the scene from the light which casts shadows (see
+// `ShadowMap`).
+
+layout(push_constant) uniform PushConstants {
+    // The light space matrix multiplied by the model matrix.
+    mat4 lightSpaceModel;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+
+void main() {
+    gl_Position = pcs.lightSpaceModel * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,72 @@
+#version 450
+
+// Whether the output has to be encoded as sRGB by this shader because the
+// swapchain format is not an sRGB format (which would encode it on write).
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// How the output is encoded for the swapchain color space (see
+// `OutputTransfer`): 0 = SDR, 1 = linear scRGB, 2 = HDR10 (PQ).
+layout(constant_id = 1) const uint OUTPUT_TRANSFER = 0;
+
+// The luminance of SDR white and the peak luminance of the display in nits.
+const float PAPER_WHITE_NITS = 200.0;
+const float PEAK_NITS = 1000.0;
+
+// The environment map (in world space, where Z is up).
+layout(binding = 0) uniform samplerCube environmentSampler;
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// Compresses colors above a knee so that they approach but never exceed the
+// peak (both relative to SDR white).
+vec3 toneMap(vec3 color, float peak) {
+    float knee = 0.75 * peak;
+    vec3 excess = max(color - knee, 0.0);
+    float range = peak - knee;
+    return min(color, knee) + range * excess / (excess + range);
+}
+
+// Rec. 709 (sRGB) primaries to Rec. 2020 primaries.
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// The SMPTE ST 2084 (PQ) inverse EOTF for luminance normalized to 10000 nits.
+vec3 linearToPq(vec3 color) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(max(color, 0.0), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // scRGB: 1.0 is 80 nits.
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return color * (PAPER_WHITE_NITS / 80.0);
+    } else if (OUTPUT_TRANSFER == 2) {
+        color = toneMap(color, PEAK_NITS / PAPER_WHITE_NITS);
+        return linearToPq(REC709_TO_REC2020 * color * (PAPER_WHITE_NITS / 10000.0));
+    } else if (ENCODE_SRGB) {
+        return linearToSrgb(color);
+    } else {
+        return color;
+    }
+}
+
+void main() {
+    vec3 color = textureLod(environmentSampler, fragDirection, 0.0).rgb;
+    outColor = vec4(encodeOutput(color), 1.0);
+}
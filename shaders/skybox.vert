@@ -0,0 +1,21 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // The inverse of the view-projection matrix of the camera.
+    mat4 invViewProj;
+} pcs;
+
+layout(location = 0) out vec3 fragDirection;
+
+void main() {
+    // A triangle which covers the screen, at the far plane (so it is only
+    // drawn where nothing else has been).
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 1.0, 1.0);
+
+    // The direction from the near plane to the far plane through the pixel
+    // (which works for orthographic projections too).
+    vec4 near = pcs.invViewProj * vec4(position, 0.0, 1.0);
+    vec4 far = pcs.invViewProj * vec4(position, 1.0, 1.0);
+    fragDirection = far.xyz / far.w - near.xyz / near.w;
+}
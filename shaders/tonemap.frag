@@ -0,0 +1,114 @@
+#version 450
+
+// Whether the output has to be encoded as sRGB by this shader because the
+// swapchain format is not an sRGB format (which would encode it on write).
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// How the output is encoded for the swapchain color space (see
+// `OutputTransfer`): 0 = SDR, 1 = linear scRGB, 2 = HDR10 (PQ).
+layout(constant_id = 1) const uint OUTPUT_TRANSFER = 0;
+
+// The luminance of SDR white and the peak luminance of the display in nits.
+const float PAPER_WHITE_NITS = 200.0;
+const float PEAK_NITS = 1000.0;
+
+// The HDR intermediate target the scene was drawn into (see `ToneMap`).
+layout(binding = 0) uniform sampler2D hdrSampler;
+
+layout(push_constant) uniform PushConstants {
+    // The operator (see `ToneMapping`): 0 = Reinhard, 1 = ACES,
+    // 2 = Uncharted 2.
+    uint operator;
+    // The factor the colors are multiplied by before they are tone mapped.
+    float exposure;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// Rec. 709 (sRGB) primaries to Rec. 2020 primaries.
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// The SMPTE ST 2084 (PQ) inverse EOTF for luminance normalized to 10000 nits.
+vec3 linearToPq(vec3 color) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(max(color, 0.0), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+// Unlike the shaders which draw the scene without tone mapping, the colors
+// never exceed the peak here, so they aren't compressed again.
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == 1) {
+        // scRGB: 1.0 is 80 nits.
+        return color * (PAPER_WHITE_NITS / 80.0);
+    } else if (OUTPUT_TRANSFER == 2) {
+        return linearToPq(REC709_TO_REC2020 * color * (PAPER_WHITE_NITS / 10000.0));
+    } else if (ENCODE_SRGB) {
+        return linearToSrgb(color);
+    } else {
+        return color;
+    }
+}
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// The fit of the ACES reference rendering transform by Krzysztof Narkowicz.
+vec3 aces(vec3 color) {
+    return clamp(
+        color * (2.51 * color + 0.03) / (color * (2.43 * color + 0.59) + 0.14),
+        0.0,
+        1.0
+    );
+}
+
+// The filmic curve of Uncharted 2 by John Hable, which is normalized so the
+// white point maps to 1.
+vec3 hable(vec3 x) {
+    const float a = 0.15;
+    const float b = 0.50;
+    const float c = 0.10;
+    const float d = 0.20;
+    const float e = 0.02;
+    const float f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+vec3 uncharted2(vec3 color) {
+    const float WHITE_POINT = 11.2;
+    const float EXPOSURE_BIAS = 2.0;
+    return hable(color * EXPOSURE_BIAS) / hable(vec3(WHITE_POINT));
+}
+
+void main() {
+    vec3 color = texelFetch(hdrSampler, ivec2(gl_FragCoord.xy), 0).rgb * pcs.exposure;
+
+    // The operators map the colors to [0, 1], which is SDR white or, for HDR
+    // output, the peak of the display.
+    float peak = OUTPUT_TRANSFER == 0 ? 1.0 : PEAK_NITS / PAPER_WHITE_NITS;
+    color = max(color / peak, 0.0);
+
+    if (pcs.operator == 0) {
+        color = reinhard(color);
+    } else if (pcs.operator == 1) {
+        color = aces(color);
+    } else {
+        color = uncharted2(color);
+    }
+
+    outColor = vec4(encodeOutput(color * peak), 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+
+// Whether the output has to be encoded as sRGB by this shader because the
+// swapchain format is not an sRGB format (which would encode it on write).
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 srgbToLinear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main() {
+    // The vertex colors are sRGB (with premultiplied alpha), while sampling
+    // an sRGB texture returns linear colors.
+    vec4 color = vec4(srgbToLinear(fragColor.rgb), fragColor.a);
+    color *= texture(texSampler, fragTexCoord);
+
+    if (ENCODE_SRGB) {
+        color.rgb = linearToSrgb(color.rgb);
+    }
+
+    outColor = color;
+}
@@ -0,0 +1,21 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // The size of the screen in pixels.
+    vec2 screenSize;
+} pcs;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inTexCoord;
+layout(location = 2) in vec4 inColor;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+
+void main() {
+    // Positions start at the top left corner of the screen, like Vulkan's
+    // normalized device coordinates.
+    gl_Position = vec4(2.0 * inPosition / pcs.screenSize - 1.0, 0.0, 1.0);
+    fragColor = inColor;
+    fragTexCoord = inTexCoord;
+}
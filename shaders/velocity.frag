@@ -0,0 +1,16 @@
+#version 450
+
+layout(location = 0) in vec4 fragClip;
+layout(location = 1) in vec4 fragPreviousClip;
+
+layout(location = 0) out vec2 outVelocity;
+
+void main() {
+    // The clip space positions are divided per fragment, since they don't
+    // interpolate linearly after the division.
+    vec2 position = fragClip.xy / fragClip.w;
+    vec2 previousPosition = fragPreviousClip.xy / fragPreviousClip.w;
+
+    // The motion in texture coordinates (which span half as much as NDC).
+    outVelocity = (position - previousPosition) * 0.5;
+}
@@ -0,0 +1,22 @@
+#version 450
+
+// Renders the screen space motion of the scene since the previous frame (see
+// `MotionBlur`).
+
+layout(push_constant) uniform PushConstants {
+    // The view projection matrix multiplied by the model matrix of this frame
+    // and of the previous frame.
+    mat4 viewProjModel;
+    mat4 previousViewProjModel;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+
+layout(location = 0) out vec4 fragClip;
+layout(location = 1) out vec4 fragPreviousClip;
+
+void main() {
+    fragClip = pcs.viewProjModel * vec4(inPosition, 1.0);
+    fragPreviousClip = pcs.previousViewProjModel * vec4(inPosition, 1.0);
+    gl_Position = fragClip;
+}
@@ -0,0 +1,22 @@
+#version 450
+
+// The output of the previous effect (see `VignetteEffect`).
+layout(binding = 0) uniform sampler2D inputSampler;
+
+layout(push_constant) uniform PushConstants {
+    // How much the corners are darkened (from 0 to 1).
+    float strength;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 texCoord = gl_FragCoord.xy / vec2(textureSize(inputSampler, 0));
+    vec3 color = textureLod(inputSampler, texCoord, 0.0).rgb;
+
+    // The distance from the center is 1 in the corners.
+    float distance = length(texCoord - 0.5) * sqrt(2.0);
+    float factor = 1.0 - pcs.strength * smoothstep(0.4, 1.0, distance);
+
+    outColor = vec4(color * factor, 1.0);
+}
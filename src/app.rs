@@ -0,0 +1,2302 @@
+//! Our Vulkan app and the handles it owns.
+
+use std::mem::{self, size_of};
+use std::path::PathBuf;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::loader::{LibloadingLoader, LIBRARY};
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSwapchainExtension;
+use winit::dpi::LogicalSize;
+use winit::event::WindowEvent;
+use winit::window::{Window, WindowAttributes};
+
+use crate::buffers::{create_uniform_buffers, Buffer, UniformBufferObject};
+use crate::camera::Camera;
+use crate::clock::{FixedTimestep, FpsCounter, FrameClock, FrameLimiter};
+use crate::clusters::{cmd_cluster_lights, create_clusters, Clusters};
+use crate::commands::{
+    create_command_buffers, create_command_pool, record_secondary_command_buffers, DrawBatch,
+};
+use crate::controller::{grab_cursor, CameraController, FlyController};
+use crate::culling::{cmd_cull_draws, create_culling, read_culling_stats, Culling};
+use crate::debug::{
+    begin_command_label, end_command_label, set_object_names, CLUSTER_LABEL_COLOR,
+    CULLING_LABEL_COLOR, GEOMETRY_LABEL_COLOR, MOTION_BLUR_LABEL_COLOR, SHADOW_LABEL_COLOR,
+};
+use crate::debug_view::{
+    create_debug_view_pipelines, DebugView, DebugViewPipelines, DEBUG_VIEW_PUSH_CONSTANT_OFFSET,
+};
+use crate::deferred::{
+    cmd_begin_geometry_pass, create_gbuffer, create_lighting_pipeline, get_lighting_batch, GBuffer,
+};
+use crate::descriptors::{create_descriptor_pool, create_descriptor_sets};
+use crate::device::{
+    create_logical_device, list_physical_devices, pick_physical_device, Features, GpuSelector,
+    OwnedDevice, RenderingPath,
+};
+use crate::display::{Display, DisplayMode, MonitorSelector, VideoModeSelector};
+#[cfg(feature = "ecs")]
+use crate::ecs::{
+    extract_camera, extract_draws, extract_lights, spawn_entities, spin_system, Entities,
+};
+use crate::environment::{
+    create_environment, create_skybox_pipeline, get_skybox_batch, Environment,
+};
+use crate::framebuffers::{create_color_objects, create_depth_objects, create_framebuffers};
+use crate::handle::{Owned, OwnedCommandBuffers};
+use crate::handler::BackgroundMode;
+use crate::hiz::{cmd_build_hiz, create_hiz, HiZ};
+use crate::ibl::{create_ibl, Ibl};
+use crate::image::Image;
+use crate::indirect::{
+    create_indirect, create_indirect_pipelines, prepare_indirect_draws, Indirect,
+};
+use crate::input::Input;
+use crate::instance::{
+    create_instance, create_surface, debug_severity, validation_enabled, OwnedInstance,
+    OwnedSurface,
+};
+use crate::instancing::{create_instanced_pipeline, create_instances, Instances};
+use crate::light::{create_light_buffers, create_lights, Light, LightBufferObject};
+use crate::material::{create_materials, MaterialInstance, Shading};
+use crate::memory::{get_allocator, Allocator, MemoryAllocator};
+use crate::mesh::create_meshes;
+use crate::motion_blur::{
+    cmd_motion_blur, cmd_velocity_pass, create_motion_blur, update_previous_frame, MotionBlur,
+    DEFAULT_MOTION_BLUR_SAMPLES,
+};
+use crate::occlusion::{
+    cmd_begin_predicate, cmd_copy_occlusion_predicates, cmd_end_predicate,
+    cmd_reset_occlusion_queries, create_occlusion_queries, get_occlusion_batch,
+    read_occlusion_queries, OcclusionQueries,
+};
+use crate::overlay::StatsOverlay;
+use crate::particles::{
+    create_particle_pipeline, create_particles, get_particles_batch, submit_particle_update,
+    Particles,
+};
+use crate::pipeline::{create_descriptor_set_layout, create_pipeline, create_render_pass};
+use crate::post_process::{cmd_post_process, create_post_process, PostProcessStack};
+use crate::profiling::{frame_mark, profile_scope, GpuProfiler};
+use crate::queries::{
+    cmd_reset_timestamps, cmd_write_timestamp, create_query_pool, get_timestamps, FrameStats,
+    FRAME_BEGIN, FRAME_END, MAIN_PASS_BEGIN, MAIN_PASS_END,
+};
+use crate::recorder::Recorder;
+use crate::rendering::{
+    cmd_begin_main_pass, cmd_end_main_pass, create_main_pass_graphs, MainPassGraph,
+};
+use crate::resources::Resources;
+use crate::scene::{create_scene, Scene};
+use crate::screenshot::{get_screenshot_path, Screenshot};
+#[cfg(feature = "sdf-text")]
+use crate::sdf_text::{
+    create_sdf_text, create_sdf_text_pipeline, get_sdf_text_batch, prepare_sdf_text, SdfText,
+    TextStyle,
+};
+use crate::shadows::{
+    cmd_shadow_pass, create_shadow_map, update_light_space, ShadowMap, DEFAULT_SHADOW_BIAS,
+};
+use crate::swapchain::{
+    create_offscreen_swapchain, create_swapchain, create_swapchain_image_views, PresentMode,
+    Swapchain,
+};
+use crate::sync::{create_sync_objects, submit_frame, wait_for_frame, wait_for_image};
+use crate::text::Text;
+use crate::texture::create_texture_sampler;
+use crate::tonemap::{create_tone_map, ToneMap, ToneMapping};
+#[cfg(any(feature = "egui", feature = "imgui"))]
+use crate::ui::UiFrame;
+use crate::ui::{cmd_draw_ui, create_ui_objects, create_ui_pipeline, prepare_ui, UiRenderer};
+#[cfg(feature = "egui")]
+use crate::ui_egui::Egui;
+#[cfg(feature = "imgui")]
+use crate::ui_imgui::Imgui;
+use crate::upload::{create_upload_context, flush_uploads, UploadContext};
+use crate::vertex::Mat4;
+use crate::view::View;
+
+/// The maximum number of frames that can be processed concurrently.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Our Vulkan app.
+///
+/// The fields are dropped in declaration order, so the handles owned by
+/// `data` (and `recorder` and `capture`) are destroyed before the device, the
+/// surface, and the instance.
+#[derive(Debug)]
+pub struct App {
+    pub data: AppData,
+    pub recorder: Option<Recorder>,
+    /// The screenshot captured in the frame being rendered, which is saved
+    /// once the frame has completed.
+    pub capture: Option<Screenshot>,
+    pub views: Vec<View>,
+    pub device: OwnedDevice,
+    /// The surface of the window, which is `None` when rendering offscreen.
+    pub surface: Option<OwnedSurface>,
+    pub instance: OwnedInstance,
+    pub entry: Entry,
+    pub frame: usize,
+    pub resized: bool,
+    pub screenshot: Option<PathBuf>,
+    pub clock: FrameClock,
+    pub timestep: FixedTimestep,
+    /// The number of updates run for the current frame.
+    pub steps: u32,
+    /// The simulated time (in seconds) as of the last update and the update
+    /// before it.
+    pub time: f32,
+    pub previous_time: f32,
+    pub fps: FpsCounter,
+    pub limiter: Option<FrameLimiter>,
+    pub stats: FrameStats,
+    pub profiler: GpuProfiler,
+    pub input: Input,
+    pub controller: CameraController,
+    pub cursor_grabbed: bool,
+    pub display: Display,
+    pub overlay: StatsOverlay,
+    pub text: Text,
+    /// The egui UI, which is `None` when rendering offscreen.
+    #[cfg(feature = "egui")]
+    pub egui: Option<Egui>,
+    /// The Dear ImGui UI, which is only taken while it is built.
+    #[cfg(feature = "imgui")]
+    pub imgui: Option<Imgui>,
+}
+
+impl App {
+    /// Returns a builder for our Vulkan app with the default options.
+    pub fn builder() -> AppBuilder {
+        AppBuilder::default()
+    }
+
+    /// Creates our Vulkan app.
+    pub unsafe fn create(window: &Window, options: AppOptions) -> Result<Self> {
+        Self::create_for(Some(window), options)
+    }
+
+    /// Creates our Vulkan app without a window (or a window system), which
+    /// renders to offscreen images of the size in the supplied options (in
+    /// pixels) instead of a swapchain.
+    pub unsafe fn create_offscreen(options: AppOptions) -> Result<Self> {
+        Self::create_for(None, options)
+    }
+
+    unsafe fn create_for(window: Option<&Window>, options: AppOptions) -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+        let limiter = options.max_fps.map(FrameLimiter::new);
+        let timestep = FixedTimestep::new(options.tick_rate);
+        let recorder = options.record.map(Recorder::new).transpose()?;
+        let display = Display::new(options.monitor.clone(), options.video_mode);
+        let text = options.font.as_deref().map(Text::load).transpose()?;
+        #[cfg(not(feature = "sdf-text"))]
+        if options.sdf_font.is_some() {
+            warn!("SDF text is only drawn when built with the `sdf-text` feature.");
+        }
+        let mut data = AppData {
+            options,
+            ..Default::default()
+        };
+        let controller =
+            CameraController::Fly(FlyController::new(&data.camera, data.options.camera_speed));
+        let (instance, surface, device) = {
+            profile_scope!("Create Instance");
+            let instance = create_instance(window, &entry, &mut data)?;
+            let surface = window
+                .map(|w| create_surface(w, &instance, &mut data))
+                .transpose()?;
+            pick_physical_device(&instance, &mut data)?;
+            let device = create_logical_device(&entry, &instance, &mut data)?;
+            (instance, surface, device)
+        };
+
+        // Everything created from here on is owned by the app, so it is
+        // cleaned up by `Drop` if any of the remaining steps fail.
+        let mut app = Self {
+            data,
+            recorder,
+            capture: None,
+            views: vec![],
+            device,
+            surface,
+            instance,
+            entry,
+            frame: 0,
+            resized: false,
+            screenshot: None,
+            clock: FrameClock::new(),
+            timestep,
+            steps: 0,
+            time: 0.0,
+            previous_time: 0.0,
+            fps: FpsCounter::new(Duration::from_secs(1)),
+            limiter,
+            stats: FrameStats::default(),
+            profiler: GpuProfiler::default(),
+            input: Input::default(),
+            controller,
+            cursor_grabbed: false,
+            display,
+            overlay: StatsOverlay::default(),
+            text: text.unwrap_or_default(),
+            #[cfg(feature = "egui")]
+            egui: window.map(Egui::new),
+            #[cfg(feature = "imgui")]
+            imgui: window.map(Imgui::new),
+        };
+
+        app.create_device_objects(window)?;
+
+        // The swapchain is recreated for the new size of the window once it
+        // has been switched to the initial display mode.
+        let mode = app.data.options.display_mode;
+        if let Some(window) = window.filter(|_| mode != DisplayMode::Windowed) {
+            app.display.set_mode(window, mode);
+            app.resized = true;
+        }
+
+        Ok(app)
+    }
+
+    /// Creates everything our Vulkan app creates with the logical device (with
+    /// offscreen images instead of a swapchain if there is no window).
+    pub(crate) unsafe fn create_device_objects(&mut self, window: Option<&Window>) -> Result<()> {
+        let (instance, device, data) = (&self.instance, &self.device, &mut self.data);
+        data.allocator = Some(Allocator::new(instance, device, data)?);
+        match window {
+            Some(window) => create_swapchain(window, instance, device, data)?,
+            None => create_offscreen_swapchain(instance, device, data)?,
+        }
+        create_swapchain_image_views(device, data)?;
+        create_render_pass(instance, device, data)?;
+        create_gbuffer(instance, device, data)?;
+        create_tone_map(instance, device, data)?;
+        create_motion_blur(instance, device, data)?;
+        create_post_process(instance, device, data)?;
+        create_descriptor_set_layout(device, data)?;
+        create_particle_pipeline(instance, device, data)?;
+        create_color_objects(instance, device, data)?;
+        create_depth_objects(instance, device, data)?;
+        create_framebuffers(device, data)?;
+        create_main_pass_graphs(instance, device, data)?;
+        create_command_pool(instance, device, data)?;
+        create_upload_context(instance, device, data)?;
+        create_texture_sampler(
+            device,
+            data,
+            vk::Filter::LINEAR,
+            vk::SamplerAddressMode::REPEAT,
+        )?;
+        create_meshes(instance, device, data)?;
+        create_materials(instance, device, data)?;
+        create_environment(instance, device, data)?;
+        create_ibl(instance, device, data)?;
+        create_shadow_map(instance, device, data)?;
+        create_scene(instance, device, data)?;
+        create_instances(instance, device, data)?;
+        create_indirect(instance, device, data)?;
+        create_lights(data);
+        #[cfg(feature = "ecs")]
+        spawn_entities(data)?;
+        flush_uploads(instance, device, data)?;
+        create_pipeline(instance, device, data)?;
+        create_instanced_pipeline(instance, device, data)?;
+        create_indirect_pipelines(instance, device, data)?;
+        create_uniform_buffers(instance, device, data)?;
+        create_light_buffers(instance, device, data)?;
+        create_clusters(instance, device, data)?;
+        create_culling(instance, device, data)?;
+        create_hiz(instance, device, data)?;
+        create_descriptor_pool(device, data)?;
+        create_descriptor_sets(device, data)?;
+        create_command_buffers(device, data)?;
+        create_query_pool(instance, device, data)?;
+        create_sync_objects(device, data)?;
+        create_particles(instance, device, data)?;
+        create_ui_objects(device, data)?;
+        create_ui_pipeline(instance, device, data)?;
+        create_skybox_pipeline(instance, device, data)?;
+        create_occlusion_queries(instance, device, data)?;
+        create_debug_view_pipelines(instance, device, data)?;
+        create_lighting_pipeline(instance, device, data)?;
+        #[cfg(feature = "sdf-text")]
+        create_sdf_text(instance, device, data)?;
+        #[cfg(feature = "sdf-text")]
+        create_sdf_text_pipeline(instance, device, data)?;
+        set_object_names(instance, device, data)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.create_buffers(&self.instance, &self.device, &self.data)?;
+        }
+        self.profiler = GpuProfiler::create(&self.device, &self.data)?;
+        get_allocator(&self.data)?.log_stats();
+        Ok(())
+    }
+
+    /// Renders a frame for our Vulkan app (and its views), recovering from
+    /// the logical device or the surface being lost.
+    pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
+        if let Some(limiter) = &mut self.limiter {
+            limiter.wait();
+        }
+
+        self.clock.tick();
+        self.overlay
+            .record(self.clock.delta() * 1000.0, self.stats.gpu_frame_time);
+        self.handle_input(window);
+        self.run_ui(window);
+
+        self.steps = self.timestep.advance(self.clock.delta());
+        for _ in 0..self.steps {
+            self.update(self.timestep.step());
+        }
+
+        if let Some((fps, ms)) = self.fps.tick() {
+            window.set_title(&format!(
+                "{} - {:.0} FPS ({:.2} ms, GPU {:.2} ms)",
+                self.data.options.title, fps, ms, self.stats.gpu_frame_time
+            ));
+        }
+
+        match self.render_frame(window) {
+            Err(e) => match e.downcast_ref::<vk::ErrorCode>() {
+                Some(&error @ (vk::ErrorCode::DEVICE_LOST | vk::ErrorCode::SURFACE_LOST_KHR)) => {
+                    self.recover(window, error)
+                }
+                _ => Err(e),
+            },
+            result => result,
+        }?;
+
+        self.render_views()
+    }
+
+    /// Renders a frame to the window of the swapchain in `data`.
+    pub(crate) unsafe fn render_frame(&mut self, window: &Window) -> Result<()> {
+        wait_for_frame(&self.device, &self.data, self.frame)?;
+        self.data.resources.begin_frame();
+
+        let result = self.device.acquire_next_image_khr(
+            self.data.swapchain.swapchain,
+            u64::MAX,
+            *self.data.image_available_semaphores[self.frame],
+            vk::Fence::null(),
+        );
+
+        // A suboptimal swapchain can still be presented to, so it is only
+        // recreated once the acquired image has been presented.
+        let (image_index, suboptimal) = match result {
+            Ok((image_index, code)) => (
+                image_index as usize,
+                code == vk::SuccessCode::SUBOPTIMAL_KHR,
+            ),
+            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return self.recreate_swapchain(window),
+            Err(e) => return Err(anyhow!(e)),
+        };
+
+        self.submit_image(image_index)?;
+
+        let signal_semaphores = &[*self.data.render_finished_semaphores[self.frame]];
+        let swapchains = &[self.data.swapchain.swapchain];
+        let image_indices = &[image_index as u32];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(signal_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        let result = {
+            profile_scope!("Present");
+            self.device
+                .queue_present_khr(self.data.present_queue, &present_info)
+        };
+
+        frame_mark();
+
+        self.save_capture(image_index)?;
+
+        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
+            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+
+        if self.resized || suboptimal || changed {
+            self.resized = false;
+            self.recreate_swapchain(window)?;
+        } else if let Err(e) = result {
+            return Err(anyhow!(e));
+        }
+
+        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        Ok(())
+    }
+
+    /// Renders a frame for our Vulkan app created with `App::create_offscreen`
+    /// to the next of its offscreen images, which isn't presented.
+    ///
+    /// There is no window to receive input or to draw the UIs for.
+    pub unsafe fn render_offscreen(&mut self) -> Result<()> {
+        self.clock.tick();
+
+        self.steps = self.timestep.advance(self.clock.delta());
+        for _ in 0..self.steps {
+            self.update(self.timestep.step());
+        }
+
+        wait_for_frame(&self.device, &self.data, self.frame)?;
+        self.data.resources.begin_frame();
+
+        // Each frame in flight renders to its own image.
+        let image_index = self.frame;
+        self.submit_image(image_index)?;
+        frame_mark();
+        self.save_capture(image_index)?;
+
+        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        Ok(())
+    }
+
+    /// Records and submits the commands which render the frame to the
+    /// swapchain image at the supplied index.
+    unsafe fn submit_image(&mut self, image_index: usize) -> Result<()> {
+        wait_for_image(&self.device, &self.data, image_index)?;
+
+        prepare_ui(&self.instance, &self.device, &mut self.data, image_index)?;
+
+        self.update_command_buffer(image_index)?;
+        self.update_uniform_buffer(image_index)?;
+
+        profile_scope!("Submit");
+        flush_uploads(&self.instance, &self.device, &mut self.data)?;
+        if self.data.options.particles {
+            // The particles are advanced by the updates run for this frame
+            // (and don't move while the animation is frozen).
+            let delta = match self.data.options.time {
+                Some(_) => 0.0,
+                None => self.steps as f32 * self.timestep.step(),
+            };
+            submit_particle_update(
+                &self.instance,
+                &self.device,
+                &mut self.data,
+                self.frame,
+                delta,
+            )?;
+        }
+        submit_frame(&self.device, &mut self.data, self.frame, image_index)
+    }
+
+    /// Saves the copy of the swapchain image at the supplied index captured
+    /// by the frame (if there is one), once the frame has completed.
+    unsafe fn save_capture(&mut self, image_index: usize) -> Result<()> {
+        if let Some(capture) = self.capture.take() {
+            wait_for_image(&self.device, &self.data, image_index)?;
+            match capture.save(&self.device) {
+                Ok(path) => info!("Saved screenshot to `{}`.", path.display()),
+                Err(e) => error!("Failed to save screenshot: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Toggles between gamma-correct (sRGB) and linear output.
+    pub fn toggle_srgb(&mut self) {
+        self.data.options.srgb = !self.data.options.srgb;
+        info!(
+            "Switching to {} output.",
+            if self.data.options.srgb {
+                "sRGB"
+            } else {
+                "linear"
+            }
+        );
+
+        // The surface format is picked when the swapchain is (re)created.
+        self.resized = true;
+    }
+
+    /// Switches between parallax occlusion mapping and flat normal mapping.
+    pub fn toggle_parallax(&mut self) {
+        self.data.options.parallax = !self.data.options.parallax;
+        info!(
+            "Switching to {} mapping.",
+            if self.data.options.parallax {
+                "parallax occlusion"
+            } else {
+                "normal"
+            }
+        );
+    }
+
+    /// Switches between drawing the scene filled and as a wireframe (if the
+    /// device supports drawing lines).
+    pub fn toggle_wireframe(&mut self) {
+        if !self.data.features.fill_mode_non_solid {
+            warn!("Wireframes aren't supported by the device.");
+            return;
+        }
+
+        self.data.options.wireframe = !self.data.options.wireframe;
+        info!(
+            "Switching to {} rendering.",
+            if self.data.options.wireframe {
+                "wireframe"
+            } else {
+                "filled"
+            }
+        );
+    }
+
+    /// Switches to the next debug view (or back to shading the scene after
+    /// the last one).
+    pub fn cycle_debug_view(&mut self) {
+        if self.data.options.deferred {
+            warn!("Debug views aren't supported with deferred rendering.");
+            return;
+        }
+
+        self.data.options.debug_view = DebugView::next(self.data.options.debug_view);
+        match self.data.options.debug_view {
+            Some(view) => info!("Switching to the {:?} debug view.", view),
+            None => info!("Switching to shading the scene."),
+        }
+    }
+
+    /// Switches between clustered lighting and shading every light.
+    pub fn toggle_clustering(&mut self) {
+        self.data.options.clustered = !self.data.options.clustered;
+        info!(
+            "Switching to {} lighting.",
+            if self.data.options.clustered {
+                "clustered"
+            } else {
+                "unculled"
+            }
+        );
+    }
+
+    /// Switches to the next tone mapping operator (if the scene is tone
+    /// mapped).
+    pub fn cycle_tone_mapping(&mut self) {
+        if let Some(tone_mapping) = &mut self.data.options.tone_mapping {
+            *tone_mapping = tone_mapping.next();
+            info!("Switching to {:?} tone mapping.", tone_mapping);
+        }
+    }
+
+    /// Changes the exposure of the tone mapped scene by the supplied number
+    /// of stops.
+    pub fn adjust_exposure(&mut self, stops: f32) {
+        if self.data.options.tone_mapping.is_some() {
+            self.data.options.exposure += stops;
+            info!(
+                "Setting the exposure to {:+} EV.",
+                self.data.options.exposure
+            );
+        }
+    }
+
+    /// Switches between the fly and orbit camera controllers.
+    pub fn toggle_camera_controller(&mut self) {
+        self.controller
+            .toggle(&self.data.camera, self.data.options.camera_speed);
+        info!(
+            "Switching to the {} camera.",
+            match self.controller {
+                CameraController::Fly(_) => "fly",
+                CameraController::Orbit(_) => "orbit",
+            }
+        );
+    }
+
+    /// Advances the simulation by the supplied fixed time step (in seconds).
+    fn update(&mut self, delta: f32) {
+        self.previous_time = self.time;
+        self.time += delta;
+    }
+
+    /// Handles the actions triggered by the input received since the last
+    /// frame and moves the camera.
+    fn handle_input(&mut self, window: &Window) {
+        #[cfg(feature = "gamepad")]
+        self.input.poll_gamepads();
+
+        if self.input.is_pressed("toggle_srgb") {
+            self.toggle_srgb();
+        }
+
+        if self.input.is_pressed("screenshot") {
+            self.request_screenshot();
+        }
+
+        if self.input.is_pressed("toggle_stats") {
+            self.overlay.toggle();
+        }
+
+        if self.input.is_pressed("toggle_fullscreen") {
+            self.display.toggle(window);
+            info!("Switching to {}.", self.display.mode());
+            self.resized = true;
+        }
+
+        if self.input.is_pressed("toggle_parallax") {
+            self.toggle_parallax();
+        }
+
+        if self.input.is_pressed("toggle_wireframe") {
+            self.toggle_wireframe();
+        }
+
+        if self.input.is_pressed("cycle_debug_view") {
+            self.cycle_debug_view();
+        }
+
+        if self.input.is_pressed("toggle_clustering") {
+            self.toggle_clustering();
+        }
+
+        if self.input.is_pressed("cycle_tone_mapping") {
+            self.cycle_tone_mapping();
+        }
+
+        if self.input.is_pressed("increase_exposure") {
+            self.adjust_exposure(0.5);
+        }
+
+        if self.input.is_pressed("decrease_exposure") {
+            self.adjust_exposure(-0.5);
+        }
+
+        if self.input.is_pressed("toggle_camera") {
+            self.toggle_camera_controller();
+        }
+
+        if self.input.is_pressed("release_cursor") {
+            self.input.release_all();
+        }
+
+        // The camera is moved every frame (rather than every update) so it
+        // responds to input without waiting for the next update.
+        self.controller
+            .update(&self.input, &mut self.data.camera, self.clock.delta());
+
+        // The cursor is grabbed while the camera controller is dragging with
+        // the mouse.
+        let grab = self.controller.is_dragging(&self.input);
+        if grab != self.cursor_grabbed {
+            grab_cursor(window, grab);
+            self.cursor_grabbed = grab;
+        }
+
+        self.input.end_frame();
+    }
+
+    /// Passes a window event to the UIs, returning whether one of them used it
+    /// (in which case our Vulkan app shouldn't).
+    pub fn handle_ui_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        let used = false;
+
+        // Every UI is passed the event, even if another UI used it.
+        #[cfg(feature = "egui")]
+        let used = self
+            .egui
+            .as_mut()
+            .is_some_and(|e| e.handle_window_event(window, event))
+            | used;
+        #[cfg(feature = "imgui")]
+        let used = self
+            .imgui
+            .as_mut()
+            .is_some_and(|i| i.handle_window_event(window, event))
+            | used;
+
+        used
+    }
+
+    /// Builds the UIs drawn over the next frame (the egui UI is drawn over the
+    /// bitmap font text, and the Dear ImGui UI is drawn over both).
+    fn run_ui(&mut self, window: &Window) {
+        // The frame time is drawn in the top left corner of the window with
+        // the bitmap font (if there is one).
+        let scale = window.scale_factor() as f32;
+        let frame_time = self.clock.smoothed_frame_time() * 1000.0;
+        self.text.draw(
+            &format!("{:.2} ms", frame_time),
+            [8.0 * scale, 8.0 * scale],
+            scale,
+            [255; 4],
+        );
+
+        // The frame is only appended to by the UI features.
+        #[allow(unused_mut)]
+        let mut frame = self.text.end_frame();
+
+        #[cfg(feature = "egui")]
+        frame.append(self.run_egui(window));
+        #[cfg(feature = "imgui")]
+        frame.append(self.run_imgui(window));
+
+        self.data.ui.queue(frame);
+    }
+
+    #[cfg(feature = "egui")]
+    fn run_egui(&mut self, window: &Window) -> UiFrame {
+        let Some(egui) = &mut self.egui else {
+            return UiFrame::default();
+        };
+
+        let input = egui.begin_frame(window);
+        let context = egui.context.clone();
+        let output = context.run(input, |ctx| self.ui(ctx));
+        match &mut self.egui {
+            Some(egui) => egui.end_frame(window, output),
+            None => UiFrame::default(),
+        }
+    }
+
+    #[cfg(feature = "imgui")]
+    fn run_imgui(&mut self, window: &Window) -> UiFrame {
+        // The UI is taken while it is built so `App::imgui_ui` can borrow our
+        // Vulkan app.
+        let Some(mut imgui) = self.imgui.take() else {
+            return UiFrame::default();
+        };
+
+        let frame = imgui.run(window, |ui| self.imgui_ui(ui));
+        self.imgui = Some(imgui);
+        frame
+    }
+
+    /// Builds the egui UI drawn over every frame, which is the place for debug
+    /// panels and parameter tweakers.
+    #[cfg(feature = "egui")]
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        if self.overlay.visible {
+            let memory = get_allocator(&self.data)
+                .map(|a| a.stats(vk::MemoryPropertyFlags::DEVICE_LOCAL))
+                .unwrap_or_default();
+            self.overlay.show(ctx, &self.stats, &memory);
+        }
+
+        egui::Window::new("Vulkan Tutorial").show(ctx, |ui| {
+            let position = self.data.camera.position;
+            ui.label(format!(
+                "Camera: ({:.2}, {:.2}, {:.2})",
+                position.x, position.y, position.z
+            ));
+
+            let mut srgb = self.data.options.srgb;
+            if ui.checkbox(&mut srgb, "sRGB output").changed() {
+                self.toggle_srgb();
+            }
+        });
+    }
+
+    /// Builds the Dear ImGui UI drawn over every frame, which is the place for
+    /// debug panels and parameter tweakers.
+    #[cfg(feature = "imgui")]
+    pub fn imgui_ui(&mut self, ui: &imgui::Ui) {
+        ui.window("Vulkan Tutorial")
+            .size([240.0, 80.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let position = self.data.camera.position;
+                ui.text(format!(
+                    "Camera: ({:.2}, {:.2}, {:.2})",
+                    position.x, position.y, position.z
+                ));
+
+                let mut srgb = self.data.options.srgb;
+                if ui.checkbox("sRGB output", &mut srgb) {
+                    self.toggle_srgb();
+                }
+            });
+    }
+
+    /// Saves the next frame rendered to a timestamped PNG file.
+    pub fn request_screenshot(&mut self) {
+        self.request_screenshot_to(get_screenshot_path());
+    }
+
+    /// Saves the next frame rendered to the supplied PNG file.
+    pub fn request_screenshot_to(&mut self, path: PathBuf) {
+        self.screenshot = Some(path);
+    }
+
+    /// Records the command buffer for the swapchain image at the supplied index.
+    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
+        profile_scope!("Record Commands");
+
+        // Reset
+
+        let command_buffer = self.data.command_buffers[image_index];
+
+        self.device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+        // Stats
+
+        // The last frame rendered to this swapchain image has completed, so
+        // its timestamps can be read before they are reset.
+        if let Some(timestamps) = get_timestamps(&self.device, &self.data, image_index)? {
+            self.stats = FrameStats::new(&self.data, &timestamps);
+            self.profiler.upload(&self.data, image_index, &timestamps);
+        }
+
+        // The culling stats of that frame are read (and reset) likewise.
+        self.stats.culling = read_culling_stats(&self.data, image_index)?;
+
+        // Likewise, the occlusion queries of that frame can be read.
+        read_occlusion_queries(&self.device, &mut self.data, image_index)?;
+
+        // Likewise, the frame captured from this swapchain image can be read.
+        if let Some(recorder) = &mut self.recorder {
+            recorder.read_frame(image_index)?;
+        }
+
+        // Scene
+
+        // The scene is animated at a time interpolated between the last two
+        // updates, which keeps the animation smooth when the frame rate
+        // doesn't match the tick rate.
+        let time = self.data.options.time.unwrap_or_else(|| {
+            glm::lerp_scalar(self.previous_time, self.time, self.timestep.alpha())
+        });
+
+        #[cfg(not(feature = "ecs"))]
+        let draws = {
+            let scene = &mut self.data.scene;
+            scene.node_mut(scene.root()).transform.rotation = glm::quat_angle_axis(
+                time * glm::radians(&glm::vec1(90.0))[0],
+                &glm::vec3(0.0, 0.0, 1.0),
+            );
+            scene.update_transforms();
+            scene.get_draws()
+        };
+
+        // With an ECS, the entities are updated and extracted instead.
+        #[cfg(feature = "ecs")]
+        let draws = {
+            spin_system(&mut self.data.entities.0, time);
+            extract_draws(&self.data.entities.0)
+        };
+
+        // Lights
+
+        // The point and spot lights orbit the quads in the opposite direction.
+        #[cfg(not(feature = "ecs"))]
+        let lights = {
+            let rotation = glm::quat_angle_axis(
+                -time * glm::radians(&glm::vec1(45.0))[0],
+                &glm::vec3(0.0, 0.0, 1.0),
+            );
+            self.data
+                .lights
+                .iter()
+                .map(|l| Light {
+                    position: glm::quat_rotate_vec3(&rotation, &l.position),
+                    ..*l
+                })
+                .collect::<Vec<_>>()
+        };
+
+        #[cfg(feature = "ecs")]
+        let lights = extract_lights(&self.data.entities.0);
+
+        self.update_light_buffer(image_index, &lights)?;
+        update_light_space(&mut self.data, &draws, &lights);
+
+        // Commands
+
+        let info = vk::CommandBufferBeginInfo::builder();
+
+        self.device.begin_command_buffer(command_buffer, &info)?;
+
+        cmd_reset_timestamps(&self.device, &self.data, command_buffer, image_index);
+        cmd_reset_occlusion_queries(&self.device, &self.data, command_buffer, image_index);
+        cmd_write_timestamp(
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+            FRAME_BEGIN,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        );
+        self.profiler.begin_zone(image_index, "Frame", FRAME_BEGIN);
+
+        let color_clear_value = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        };
+
+        let depth_clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+
+        // Shadow Pass
+
+        begin_command_label(
+            &self.instance,
+            &self.data,
+            command_buffer,
+            "Shadow Pass",
+            SHADOW_LABEL_COLOR,
+        )?;
+        cmd_shadow_pass(&self.device, &self.data, command_buffer, &draws);
+        end_command_label(&self.instance, &self.data, command_buffer);
+
+        // Light Culling
+
+        let (view, proj) = self.get_view_proj();
+        if self.data.options.clustered {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Light Culling",
+                CLUSTER_LABEL_COLOR,
+            )?;
+            cmd_cluster_lights(&self.device, &self.data, command_buffer, image_index, &proj);
+            end_command_label(&self.instance, &self.data, command_buffer);
+        }
+
+        // Draw Culling
+
+        // The draws whose meshes are in the shared buffers are drawn
+        // indirectly, unless they are drawn with another pipeline (as a
+        // wireframe or a debug view).
+        let view_proj = proj * view;
+        let indirect = if self.data.options.wireframe || self.data.options.debug_view.is_some() {
+            None
+        } else {
+            prepare_indirect_draws(
+                &self.instance,
+                &self.device,
+                &mut self.data,
+                image_index,
+                &draws,
+            )?
+        };
+
+        if let Some(indirect) = indirect.as_ref().filter(|i| i.is_culled()) {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Draw Culling",
+                CULLING_LABEL_COLOR,
+            )?;
+            cmd_cull_draws(
+                &self.device,
+                &self.data,
+                command_buffer,
+                image_index,
+                indirect,
+                &view_proj,
+            );
+            end_command_label(&self.instance, &self.data, command_buffer);
+        }
+
+        // The draws are culled by occlusion with a pyramid built after the
+        // main pass (once the draws are no longer needed).
+        let build_hiz =
+            indirect.as_ref().is_some_and(|i| i.is_culled()) && self.data.hiz.is_enabled();
+
+        // Velocity Pass
+
+        let motion_blur = self.data.options.motion_blur && self.data.options.tone_mapping.is_some();
+        if motion_blur {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Velocity Pass",
+                MOTION_BLUR_LABEL_COLOR,
+            )?;
+            cmd_velocity_pass(&self.device, &self.data, command_buffer, &draws, &view_proj);
+            end_command_label(&self.instance, &self.data, command_buffer);
+        }
+
+        cmd_write_timestamp(
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+            MAIN_PASS_BEGIN,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        );
+        self.profiler
+            .begin_zone(image_index, "Main Pass", MAIN_PASS_BEGIN);
+        begin_command_label(
+            &self.instance,
+            &self.data,
+            command_buffer,
+            "Render Pass",
+            [0.2, 0.4, 0.8, 1.0],
+        )?;
+
+        // Draw Batches
+
+        // The batches may be recorded on other threads, so they only capture
+        // the handles they use (the app data can't be shared between threads).
+        let pipeline_layout = *self.data.pipeline_layout;
+        let buffer_device_address = self.data.features.buffer_device_address;
+        let ibl_descriptor_set = self.data.ibl.descriptor_set;
+        let wireframe = self.data.options.wireframe;
+        let debug_view = self.data.options.debug_view;
+
+        // The draws are grouped by material, so each pipeline and descriptor
+        // set is only bound once.
+        let resources = &self.data.resources;
+        let materials = resources
+            .materials
+            .iter()
+            .filter_map(|(handle, material)| {
+                let draws = draws
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, d)| d.material.material == handle)
+                    .filter(|(_, d)| indirect.is_none() || !self.data.indirect.contains(d))
+                    .filter(|(i, d)| !self.data.occlusion.is_occluded(*i, d))
+                    .filter_map(|(i, d)| {
+                        let mesh = resources.mesh(d.mesh)?.handles();
+                        let push_constants = d.material.push_constants(&material.factors);
+                        let predicate = self.data.occlusion.get_predicate(i, d);
+                        Some((d.model, push_constants, mesh, predicate))
+                    })
+                    .collect::<Vec<_>>();
+                // The wireframe pipeline is only created if lines are
+                // supported.
+                let pipeline = if let Some(view) = debug_view {
+                    self.data.debug_view.get(view)
+                } else if wireframe && !material.wireframe_pipeline.is_null() {
+                    *material.wireframe_pipeline
+                } else {
+                    *material.pipeline
+                };
+
+                (!draws.is_empty())
+                    .then(|| (pipeline, material.descriptor_sets[image_index], draws))
+            })
+            .collect::<Vec<_>>();
+
+        self.stats.draw_calls = materials
+            .iter()
+            .flat_map(|(_, _, draws)| draws)
+            .map(|(_, _, mesh, _)| mesh.submeshes.len())
+            .sum();
+
+        // The copies of the first mesh are drawn with a single instanced draw
+        // call per submesh.
+        let instances = &self.data.instances;
+        let instanced = instances.mesh.zip(instances.material).and_then(|(m, h)| {
+            let mesh = resources.mesh(m)?.handles();
+            let material = resources.material(h)?;
+            let push_constants = MaterialInstance {
+                material: h,
+                opacity: 1.0,
+            }
+            .push_constants(&material.factors);
+            Some((
+                *instances.pipeline,
+                material.descriptor_sets[image_index],
+                push_constants,
+                mesh,
+                instances.buffer.buffer,
+                instances.count,
+            ))
+        });
+
+        if let Some((_, _, _, mesh, _, _)) = &instanced {
+            self.stats.draw_calls += mesh.submeshes.len();
+        }
+
+        if let Some(indirect) = &indirect {
+            self.stats.draw_calls += indirect.draw_calls();
+        }
+
+        let scene = DrawBatch {
+            name: "Scene",
+            color: [0.8, 0.6, 0.2, 1.0],
+            record: Box::new(move |device, command_buffer| unsafe {
+                for (pipeline, descriptor_set, draws) in &materials {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        *pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        0,
+                        &[*descriptor_set, ibl_descriptor_set],
+                        &[],
+                    );
+                    for (model, material, mesh, predicate) in draws {
+                        let model_bytes = std::slice::from_raw_parts(
+                            model as *const Mat4 as *const u8,
+                            size_of::<Mat4>(),
+                        );
+                        device.cmd_push_constants(
+                            command_buffer,
+                            pipeline_layout,
+                            vk::ShaderStageFlags::VERTEX,
+                            0,
+                            model_bytes,
+                        );
+                        if buffer_device_address {
+                            device.cmd_push_constants(
+                                command_buffer,
+                                pipeline_layout,
+                                vk::ShaderStageFlags::VERTEX,
+                                64,
+                                &mesh.vertex_buffer_address.to_ne_bytes(),
+                            );
+                        }
+                        device.cmd_push_constants(
+                            command_buffer,
+                            pipeline_layout,
+                            vk::ShaderStageFlags::FRAGMENT,
+                            72,
+                            material,
+                        );
+                        if let Some(view) = debug_view {
+                            device.cmd_push_constants(
+                                command_buffer,
+                                pipeline_layout,
+                                vk::ShaderStageFlags::FRAGMENT,
+                                DEBUG_VIEW_PUSH_CONSTANT_OFFSET,
+                                &(view as u32).to_ne_bytes(),
+                            );
+                        }
+                        // Heavy objects are only drawn if their bounding
+                        // boxes were visible in the last frame (with
+                        // conditional rendering).
+                        cmd_begin_predicate(device, command_buffer, *predicate);
+                        mesh.draw(device, command_buffer);
+                        cmd_end_predicate(device, command_buffer, *predicate);
+                    }
+                }
+
+                if let Some((pipeline, descriptor_set, material, mesh, buffer, count)) = &instanced
+                {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        *pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        0,
+                        &[*descriptor_set, ibl_descriptor_set],
+                        &[],
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline_layout,
+                        vk::ShaderStageFlags::FRAGMENT,
+                        72,
+                        material,
+                    );
+                    mesh.draw_instanced(device, command_buffer, *buffer, *count);
+                }
+
+                if let Some(indirect) = &indirect {
+                    indirect.draw(device, command_buffer, pipeline_layout, ibl_descriptor_set);
+                }
+            }),
+        };
+
+        // With deferred rendering, the scene is drawn into the G-buffer
+        // before the main pass and shaded at the start of the main pass.
+        let mut batches = vec![];
+        if let Some(batch) = get_lighting_batch(&self.data, image_index, &view, &proj) {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Geometry Pass",
+                GEOMETRY_LABEL_COLOR,
+            )?;
+            cmd_begin_geometry_pass(&self.device, &self.data, command_buffer);
+            (scene.record)(&self.device, command_buffer);
+            self.device.cmd_end_render_pass(command_buffer);
+            end_command_label(&self.instance, &self.data, command_buffer);
+
+            batches.push(batch);
+            self.stats.draw_calls += 1;
+        } else {
+            batches.push(scene);
+        }
+
+        // The bounding boxes of the heavy objects are queried against the
+        // depth of the scene.
+        if let Some(batch) = get_occlusion_batch(&mut self.data, image_index, &draws, &view, &proj)
+        {
+            batches.push(batch);
+            self.stats.draw_calls += self.data.occlusion.query_count(image_index);
+        }
+
+        // The environment is drawn after the scene so it is only shaded where
+        // nothing else has been drawn (but before anything transparent).
+        if let Some(batch) = get_skybox_batch(&self.data, &view, &proj) {
+            batches.push(batch);
+            self.stats.draw_calls += 1;
+        }
+
+        if self.data.options.particles {
+            batches.push(get_particles_batch(&self.data, self.frame));
+            self.stats.draw_calls += 1;
+        }
+
+        #[cfg(feature = "sdf-text")]
+        {
+            // A label standing upright behind the quads (facing the camera).
+            let model = glm::translate(&Mat4::identity(), &glm::vec3(-1.0, 1.0, 1.0));
+            let model = glm::rotate(&model, 90f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+            let style = TextStyle {
+                outline_width: 0.15,
+                ..Default::default()
+            };
+            self.data
+                .sdf_text
+                .draw("Vulkan Tutorial", model, 0.25, style);
+
+            prepare_sdf_text(&self.instance, &self.device, &mut self.data, image_index)?;
+            if let Some(batch) = get_sdf_text_batch(&self.data, image_index, &view, &proj) {
+                batches.push(batch);
+                self.stats.draw_calls += 1;
+            }
+        }
+
+        // Main Pass
+
+        // The batches are recorded inline unless there are recording threads,
+        // in which case they are recorded into secondary command buffers
+        // (which aren't labeled).
+        let contents = if self.data.thread_command_pools.is_empty() {
+            vk::SubpassContents::INLINE
+        } else {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        };
+
+        cmd_begin_main_pass(
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+            color_clear_value,
+            depth_clear_value,
+            contents,
+        );
+
+        if contents == vk::SubpassContents::INLINE {
+            for batch in &batches {
+                begin_command_label(
+                    &self.instance,
+                    &self.data,
+                    command_buffer,
+                    batch.name,
+                    batch.color,
+                )?;
+                (batch.record)(&self.device, command_buffer);
+                end_command_label(&self.instance, &self.data, command_buffer);
+            }
+        } else {
+            let secondary_command_buffers =
+                record_secondary_command_buffers(&self.device, &self.data, image_index, &batches)?;
+            self.device
+                .cmd_execute_commands(command_buffer, &secondary_command_buffers);
+        }
+
+        cmd_end_main_pass(&self.device, &self.data, command_buffer, image_index);
+        end_command_label(&self.instance, &self.data, command_buffer);
+        cmd_write_timestamp(
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+            MAIN_PASS_END,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        );
+        self.profiler.end_zone(image_index, MAIN_PASS_END);
+
+        // Occlusion Predicates
+
+        cmd_copy_occlusion_predicates(&self.device, &self.data, command_buffer, image_index);
+
+        // Hi-Z
+
+        // The pyramid is built from the depth of this frame to cull the draws
+        // of the next frame.
+        if build_hiz {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Hi-Z",
+                CULLING_LABEL_COLOR,
+            )?;
+            cmd_build_hiz(&self.device, &self.data, command_buffer);
+            end_command_label(&self.instance, &self.data, command_buffer);
+            self.data.hiz.view_proj = Some(view_proj);
+        }
+
+        // Motion Blur
+
+        if motion_blur {
+            begin_command_label(
+                &self.instance,
+                &self.data,
+                command_buffer,
+                "Motion Blur",
+                MOTION_BLUR_LABEL_COLOR,
+            )?;
+            cmd_motion_blur(&self.device, &self.data, command_buffer, &view_proj);
+            end_command_label(&self.instance, &self.data, command_buffer);
+            update_previous_frame(&mut self.data, &draws, &view_proj);
+        }
+
+        // Post-Processing
+
+        cmd_post_process(
+            &self.instance,
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+        )?;
+
+        // UI Pass
+
+        cmd_draw_ui(
+            &self.instance,
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+        )?;
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.cmd_capture(&self.device, &self.data, command_buffer, image_index);
+        }
+
+        // The image is captured before it is presented (after which it is
+        // owned by the presentation engine until it is acquired again).
+        if let Some(path) = self.screenshot.take() {
+            match Screenshot::new(&self.instance, &self.device, &self.data, path) {
+                Ok(capture) => {
+                    capture.cmd_capture(&self.device, &self.data, command_buffer, image_index);
+                    self.capture = Some(capture);
+                }
+                Err(e) => error!("Failed to save screenshot: {}", e),
+            }
+        }
+
+        cmd_write_timestamp(
+            &self.device,
+            &self.data,
+            command_buffer,
+            image_index,
+            FRAME_END,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        );
+        self.profiler.end_zone(image_index, FRAME_END);
+
+        self.device.end_command_buffer(command_buffer)?;
+
+        Ok(())
+    }
+
+    /// Updates the uniform buffer object for our Vulkan app.
+    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
+        // VP
+
+        let (view, proj) = self.get_view_proj();
+
+        let camera_position = glm::inverse(&view).column(3).into_owned();
+
+        // Parallax
+
+        let (parallax_min_layers, parallax_max_layers) = if self.data.options.parallax {
+            self.data.options.parallax_layers
+        } else {
+            (0, 0)
+        };
+
+        // Clusters
+
+        let (camera_near, camera_far) = self.get_camera().depth_range();
+        let extent = self.data.swapchain.extent;
+
+        let ubo = UniformBufferObject {
+            view,
+            proj,
+            light_space: self.data.shadow_map.light_space,
+            camera_position,
+            parallax_min_layers,
+            parallax_max_layers,
+            clustered: self.data.options.clustered as u32,
+            camera_near,
+            camera_far,
+            screen_width: extent.width as f32,
+            screen_height: extent.height as f32,
+        };
+
+        // Copy
+
+        let memory = self.data.uniform_buffers[image_index].allocation.map()?;
+
+        memcpy(&ubo, memory.cast(), 1);
+
+        Ok(())
+    }
+
+    /// Updates the light buffer for our Vulkan app.
+    unsafe fn update_light_buffer(&self, image_index: usize, lights: &[Light]) -> Result<()> {
+        let object = LightBufferObject::new(lights);
+
+        let memory = self.data.light_buffers[image_index].allocation.map()?;
+
+        memcpy(&object, memory.cast(), 1);
+
+        Ok(())
+    }
+
+    /// Returns the camera the scene is rendered from.
+    fn get_camera(&self) -> Camera {
+        #[cfg(feature = "ecs")]
+        if let Some(camera) = extract_camera(&self.data.entities.0) {
+            return camera;
+        }
+
+        self.data.camera
+    }
+
+    /// Returns the view and projection matrices of the camera.
+    fn get_view_proj(&self) -> (Mat4, Mat4) {
+        let camera = self.get_camera();
+        let aspect =
+            self.data.swapchain.extent.width as f32 / self.data.swapchain.extent.height as f32;
+        (camera.view(), camera.proj(aspect))
+    }
+
+    /// Recreates the swapchain for our Vulkan app.
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        // A swapchain can't be created with a zero-sized extent, so defer the
+        // recreation until the window has been restored.
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            self.resized = true;
+            return Ok(());
+        }
+
+        profile_scope!("Recreate Swapchain");
+
+        self.device.device_wait_idle()?;
+        self.destroy_swapchain();
+        self.profiler.discard();
+        create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
+        create_swapchain_image_views(&self.device, &mut self.data)?;
+        create_render_pass(&self.instance, &self.device, &mut self.data)?;
+        create_gbuffer(&self.instance, &self.device, &mut self.data)?;
+        create_tone_map(&self.instance, &self.device, &mut self.data)?;
+        create_motion_blur(&self.instance, &self.device, &mut self.data)?;
+        create_post_process(&self.instance, &self.device, &mut self.data)?;
+        create_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_instanced_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_indirect_pipelines(&self.instance, &self.device, &mut self.data)?;
+        create_particle_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_color_objects(&self.instance, &self.device, &mut self.data)?;
+        create_depth_objects(&self.instance, &self.device, &mut self.data)?;
+        create_framebuffers(&self.device, &mut self.data)?;
+        create_main_pass_graphs(&self.instance, &self.device, &mut self.data)?;
+        create_ui_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_skybox_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_occlusion_queries(&self.instance, &self.device, &mut self.data)?;
+        create_debug_view_pipelines(&self.instance, &self.device, &mut self.data)?;
+        create_lighting_pipeline(&self.instance, &self.device, &mut self.data)?;
+        #[cfg(feature = "sdf-text")]
+        create_sdf_text_pipeline(&self.instance, &self.device, &mut self.data)?;
+        create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
+        create_light_buffers(&self.instance, &self.device, &mut self.data)?;
+        create_clusters(&self.instance, &self.device, &mut self.data)?;
+        create_culling(&self.instance, &self.device, &mut self.data)?;
+        create_hiz(&self.instance, &self.device, &mut self.data)?;
+        create_descriptor_pool(&self.device, &mut self.data)?;
+        create_descriptor_sets(&self.device, &mut self.data)?;
+        create_command_buffers(&self.device, &mut self.data)?;
+        create_query_pool(&self.instance, &self.device, &mut self.data)?;
+        set_object_names(&self.instance, &self.device, &self.data)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.create_buffers(&self.instance, &self.device, &self.data)?;
+        }
+        // The device is idle, so none of the swapchain images are in flight.
+        let images = self.data.swapchain.images.len();
+        if self.data.features.timeline_semaphore {
+            self.data.image_timeline_values = vec![0; images];
+        } else {
+            self.data.images_in_flight = vec![vk::Fence::null(); images];
+        }
+        Ok(())
+    }
+
+    /// Recreates the logical device (and the surface if it was lost) and
+    /// everything created with it after the supplied error.
+    unsafe fn recover(&mut self, window: &Window, error: vk::ErrorCode) -> Result<()> {
+        warn!("{}, recreating the logical device.", error);
+
+        // A lost logical device can't be waited on, but the commands that were
+        // executing on it are guaranteed to finish.
+        let _ = self.device.device_wait_idle();
+        self.destroy_view_objects();
+        self.destroy_device_objects();
+
+        if error == vk::ErrorCode::SURFACE_LOST_KHR {
+            self.surface = Some(create_surface(window, &self.instance, &mut self.data)?);
+        }
+
+        self.device = create_logical_device(&self.entry, &self.instance, &mut self.data)?;
+        self.frame = 0;
+        self.resized = false;
+        self.create_device_objects(Some(window))?;
+        self.create_view_objects()
+    }
+
+    /// Destroys everything our Vulkan app created with the logical device.
+    ///
+    /// The options, the physical device, the scene's camera and lights, and
+    /// the UI state are kept so the app can be recreated, the rest is dropped
+    /// in the order of the fields of `AppData`.
+    pub(crate) unsafe fn destroy_device_objects(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.destroy_buffers();
+        }
+
+        self.capture = None;
+
+        let mut data = mem::take(&mut self.data);
+        self.data = AppData {
+            options: mem::take(&mut data.options),
+            api_version: data.api_version,
+            surface: data.surface,
+            physical_device: data.physical_device,
+            msaa_samples: data.msaa_samples,
+            features: data.features,
+            rendering_path: data.rendering_path,
+            graphics_queue: data.graphics_queue,
+            present_queue: data.present_queue,
+            transfer_queue: data.transfer_queue,
+            compute_queue: data.compute_queue,
+            camera: data.camera,
+            lights: mem::take(&mut data.lights),
+            ui: data.ui.take_persistent(),
+            #[cfg(feature = "sdf-text")]
+            sdf_text: data.sdf_text.take_persistent(),
+            ..Default::default()
+        };
+    }
+
+    /// Destroys the parts of our Vulkan app related to the swapchain.
+    unsafe fn destroy_swapchain(&mut self) {
+        self.data.main_pass_graphs.clear();
+        self.data.command_buffers = OwnedCommandBuffers::default();
+        self.data.secondary_command_buffers.clear();
+        self.data.framebuffers.clear();
+        self.data.color_image = Image::default();
+        self.data.depth_image = Image::default();
+        self.data.descriptor_pool = Owned::default();
+        self.data.query_pool = Owned::default();
+        self.data.uniform_buffers.clear();
+        self.data.light_buffers.clear();
+        self.data.occlusion = OcclusionQueries::default();
+        self.data.debug_view = DebugViewPipelines::default();
+        self.data.gbuffer = GBuffer::default();
+        self.data.clusters = Clusters::default();
+        self.data.hiz = HiZ::default();
+        self.data.culling = Culling::default();
+        self.data.post_process = PostProcessStack::default();
+        self.data.motion_blur = MotionBlur::default();
+        self.data.tone_map = ToneMap::default();
+        self.data.pipeline_layout = Owned::default();
+        self.data.render_pass = Owned::default();
+        self.data.swapchain = Swapchain::default();
+    }
+}
+
+impl Drop for App {
+    /// Destroys our Vulkan app.
+    ///
+    /// Only the handles without an owning wrapper are destroyed here, the
+    /// rest are destroyed as the fields of the app are dropped.
+    fn drop(&mut self) {
+        unsafe {
+            // A lost logical device can't be waited on, but the commands that
+            // were executing on it are guaranteed to finish, so everything can
+            // still be destroyed.
+            if let Err(e) = self.device.device_wait_idle() {
+                error!("Failed to wait for the logical device: {}", e);
+            }
+
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.flush() {
+                    error!("Failed to save recorded frames: {}", e);
+                }
+            }
+
+            self.destroy_view_objects();
+            self.destroy_device_objects();
+        }
+    }
+}
+
+/// The options our Vulkan app is created with.
+#[derive(Clone, Debug)]
+pub struct AppOptions {
+    /// The title of the window.
+    pub title: String,
+    /// The initial (logical) size of the window.
+    pub width: u32,
+    pub height: u32,
+    /// How presented images are synchronized with the display.
+    pub present_mode: PresentMode,
+    /// Whether the output should be gamma-correct (i.e., encoded as sRGB)
+    /// rather than the linear colors the fragment shader computes.
+    pub srgb: bool,
+    /// Whether an HDR surface format should be used if one is supported.
+    pub hdr: bool,
+    /// The frame rate that rendering is limited to, regardless of the
+    /// present mode.
+    pub max_fps: Option<u32>,
+    /// The number of seconds every rendered frame should be captured for.
+    pub record: Option<u32>,
+    /// The time (in seconds) the animation should be frozen at, which makes
+    /// every frame identical.
+    pub time: Option<f32>,
+    /// Whether a software rasterizer should be preferred over hardware
+    /// devices (with relaxed feature requirements).
+    pub software: bool,
+    /// Whether dynamic rendering should be used instead of a render pass and
+    /// framebuffers if it is supported.
+    pub dynamic_rendering: bool,
+    /// What manages the memory buffers and images are allocated from.
+    pub memory_allocator: MemoryAllocator,
+    /// Whether the validation layers should be enabled.
+    pub validation: bool,
+    /// The debug message severities reported by the validation layers.
+    pub debug_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    /// The physical device that should be used instead of the highest scoring
+    /// suitable physical device.
+    pub gpu: Option<GpuSelector>,
+    /// Whether particles updated by an async compute queue should be drawn.
+    pub particles: bool,
+    /// The number of threads the draw commands are recorded on, or zero if
+    /// they should be recorded on the main thread.
+    pub recording_threads: usize,
+    /// The OBJ or glTF model that should be rendered instead of the textured
+    /// quads.
+    pub model: Option<PathBuf>,
+    /// The texture (a PNG, KTX2, or DDS file) the quads or the model should be
+    /// rendered with instead of `resources/texture.png`.
+    pub texture: Option<PathBuf>,
+    /// The speed the camera moves at (in units per second).
+    pub camera_speed: f32,
+    /// The number of times the simulation is updated per second.
+    pub tick_rate: u32,
+    /// The number of windows which render the scene (see `View`).
+    pub windows: u32,
+    /// The initial display mode of the window.
+    pub display_mode: DisplayMode,
+    /// The monitor used for fullscreen instead of the monitor the window is
+    /// on.
+    pub monitor: Option<MonitorSelector>,
+    /// The video mode used for exclusive fullscreen instead of the native
+    /// resolution of the monitor.
+    pub video_mode: Option<VideoModeSelector>,
+    /// What the render loop does while the window is in the background.
+    pub background: BackgroundMode,
+    /// The BMFont font the frame time is drawn with (see `Text`).
+    pub font: Option<PathBuf>,
+    /// The TrueType or OpenType font text is drawn in the scene with (see
+    /// `SdfText`).
+    pub sdf_font: Option<PathBuf>,
+    /// The equirectangular Radiance HDR panorama drawn behind the scene (see
+    /// `Environment`).
+    pub environment: Option<PathBuf>,
+    /// How the materials are shaded.
+    pub shading: Shading,
+    /// Whether the scene is rendered with deferred rendering (see `GBuffer`).
+    pub deferred: bool,
+    /// Whether the lights are culled into clusters before the main pass (see
+    /// `Clusters`).
+    pub clustered: bool,
+    /// The number of small point lights scattered around the quads (see
+    /// `scatter_lights`).
+    pub lights: usize,
+    /// The number of copies of the first mesh drawn with instanced rendering
+    /// (see `Instances`).
+    pub instances: u32,
+    /// Whether the meshes are consolidated into shared buffers and the scene
+    /// is drawn with multi-draw indirect calls (see `Indirect`).
+    pub indirect: bool,
+    /// Whether the indirect draws are culled against the view frustum on the
+    /// GPU (see `Culling`).
+    pub gpu_culling: bool,
+    /// Whether the indirect draws culled on the GPU are also culled by
+    /// occlusion with a Hi-Z pyramid of the previous frame (see `HiZ`).
+    pub occlusion_culling: bool,
+    /// Whether the heavy objects are skipped when occlusion queries of their
+    /// bounding boxes passed no samples in a previous frame (see
+    /// `OcclusionQueries`).
+    pub occlusion_queries: bool,
+    /// The operator the scene is tone mapped with (see `ToneMap`), if any.
+    pub tone_mapping: Option<ToneMapping>,
+    /// The exposure of the tone mapped scene (in stops).
+    pub exposure: f32,
+    /// Whether the tone mapped scene is blurred along its motion (see
+    /// `MotionBlur`).
+    pub motion_blur: bool,
+    /// The number of samples along the motion of each pixel.
+    pub motion_blur_samples: u32,
+    /// The fraction of the frame the shutter is open for (which scales the
+    /// length of the blur).
+    pub shutter: f32,
+    /// How much of the bloom of the bright parts of the scene is added to it
+    /// before it is tone mapped (see `BloomEffect`), where 0 disables bloom.
+    pub bloom: f32,
+    /// How much the corners of the tone mapped scene are darkened (see
+    /// `VignetteEffect`), where 0 disables the vignette.
+    pub vignette: f32,
+    /// The height map the texture coordinates of the quads or the OBJ model
+    /// are displaced with.
+    pub height_map: Option<PathBuf>,
+    /// Whether the height maps are used for parallax occlusion mapping.
+    pub parallax: bool,
+    /// Whether the scene is drawn as a wireframe (if the device supports
+    /// drawing lines).
+    pub wireframe: bool,
+    /// The property of the surfaces which is visualized instead of shading
+    /// them (see `DebugView`), if any.
+    pub debug_view: Option<DebugView>,
+    /// The minimum and maximum number of layers the height maps are sampled
+    /// in (more layers are used at grazing angles).
+    pub parallax_layers: (u32, u32),
+    /// The constant and slope-scaled depth bias of the shadow map.
+    pub shadow_bias: (f32, f32),
+}
+
+impl Default for AppOptions {
+    fn default() -> Self {
+        Self {
+            title: "Vulkan Tutorial (Rust)".into(),
+            width: 1024,
+            height: 768,
+            present_mode: PresentMode::default(),
+            srgb: true,
+            hdr: false,
+            max_fps: None,
+            record: None,
+            time: None,
+            software: false,
+            dynamic_rendering: true,
+            memory_allocator: MemoryAllocator::default(),
+            validation: validation_enabled(),
+            debug_severity: debug_severity(),
+            gpu: None,
+            particles: false,
+            recording_threads: 0,
+            model: None,
+            texture: None,
+            camera_speed: 2.0,
+            tick_rate: 60,
+            windows: 1,
+            display_mode: DisplayMode::Windowed,
+            monitor: None,
+            video_mode: None,
+            background: BackgroundMode::Render,
+            font: None,
+            sdf_font: None,
+            environment: None,
+            shading: Shading::Pbr,
+            deferred: false,
+            clustered: false,
+            lights: 0,
+            instances: 0,
+            indirect: false,
+            gpu_culling: false,
+            occlusion_culling: false,
+            occlusion_queries: false,
+            tone_mapping: None,
+            exposure: 0.0,
+            motion_blur: false,
+            motion_blur_samples: DEFAULT_MOTION_BLUR_SAMPLES,
+            shutter: 0.5,
+            bloom: 0.0,
+            vignette: 0.0,
+            height_map: None,
+            parallax: true,
+            wireframe: false,
+            debug_view: None,
+            parallax_layers: (8, 32),
+            shadow_bias: DEFAULT_SHADOW_BIAS,
+        }
+    }
+}
+
+/// A builder for our Vulkan app and the window it renders to.
+#[derive(Clone, Debug, Default)]
+pub struct AppBuilder {
+    options: AppOptions,
+}
+
+impl AppBuilder {
+    /// Sets the title of the window.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.options.title = title.into();
+        self
+    }
+
+    /// Sets the initial (logical) size of the window.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.options.width = width;
+        self.options.height = height;
+        self
+    }
+
+    /// Sets how presented images are synchronized with the display.
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.options.present_mode = present_mode;
+        self
+    }
+
+    /// Sets whether presentation should wait for vertical blanks, i.e., picks
+    /// either the FIFO or the mailbox present mode.
+    pub fn vsync(self, vsync: bool) -> Self {
+        self.present_mode(if vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Mailbox
+        })
+    }
+
+    /// Sets whether the output should be gamma-correct.
+    pub fn srgb(mut self, srgb: bool) -> Self {
+        self.options.srgb = srgb;
+        self
+    }
+
+    /// Sets whether an HDR surface format should be used if one is supported.
+    pub fn hdr(mut self, hdr: bool) -> Self {
+        self.options.hdr = hdr;
+        self
+    }
+
+    /// Limits rendering to the supplied frame rate.
+    pub fn max_fps(mut self, max_fps: u32) -> Self {
+        self.options.max_fps = Some(max_fps);
+        self
+    }
+
+    /// Captures every frame rendered for the supplied number of seconds.
+    pub fn record(mut self, seconds: u32) -> Self {
+        self.options.record = Some(seconds);
+        self
+    }
+
+    /// Freezes the animation at the supplied time (in seconds).
+    pub fn time(mut self, time: f32) -> Self {
+        self.options.time = Some(time);
+        self
+    }
+
+    /// Sets whether a software rasterizer should be preferred.
+    pub fn software(mut self, software: bool) -> Self {
+        self.options.software = software;
+        self
+    }
+
+    /// Sets whether dynamic rendering should be used if it is supported.
+    pub fn dynamic_rendering(mut self, dynamic_rendering: bool) -> Self {
+        self.options.dynamic_rendering = dynamic_rendering;
+        self
+    }
+
+    /// Sets what manages the memory buffers and images are allocated from.
+    pub fn memory_allocator(mut self, memory_allocator: MemoryAllocator) -> Self {
+        self.options.memory_allocator = memory_allocator;
+        self
+    }
+
+    /// Sets whether the validation layers should be enabled.
+    pub fn validation(mut self, validation: bool) -> Self {
+        self.options.validation = validation;
+        self
+    }
+
+    /// Sets the debug message severities reported by the validation layers.
+    pub fn debug_severity(mut self, severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.options.debug_severity = severity;
+        self
+    }
+
+    /// Sets the physical device that should be used.
+    pub fn gpu(mut self, gpu: GpuSelector) -> Self {
+        self.options.gpu = Some(gpu);
+        self
+    }
+
+    /// Sets whether particles updated by an async compute queue should be
+    /// drawn.
+    pub fn particles(mut self, particles: bool) -> Self {
+        self.options.particles = particles;
+        self
+    }
+
+    /// Sets the number of threads the draw commands are recorded on (or zero
+    /// to record them on the main thread).
+    pub fn recording_threads(mut self, threads: usize) -> Self {
+        self.options.recording_threads = threads;
+        self
+    }
+
+    /// Sets the OBJ or glTF model rendered instead of the textured quads.
+    pub fn model(mut self, model: Option<PathBuf>) -> Self {
+        self.options.model = model;
+        self
+    }
+
+    /// Sets the texture the quads or the model are rendered with.
+    pub fn texture(mut self, texture: Option<PathBuf>) -> Self {
+        self.options.texture = texture;
+        self
+    }
+
+    /// Sets the equirectangular HDR panorama drawn behind the scene.
+    pub fn environment(mut self, environment: Option<PathBuf>) -> Self {
+        self.options.environment = environment;
+        self
+    }
+
+    /// Sets how the materials are shaded.
+    pub fn shading(mut self, shading: Shading) -> Self {
+        self.options.shading = shading;
+        self
+    }
+
+    /// Sets whether the scene is rendered with deferred rendering.
+    pub fn deferred(mut self, deferred: bool) -> Self {
+        self.options.deferred = deferred;
+        self
+    }
+
+    /// Sets whether the lights are culled into clusters.
+    pub fn clustered(mut self, clustered: bool) -> Self {
+        self.options.clustered = clustered;
+        self
+    }
+
+    /// Sets the number of small point lights scattered around the quads.
+    pub fn lights(mut self, lights: usize) -> Self {
+        self.options.lights = lights;
+        self
+    }
+
+    /// Sets the number of copies of the first mesh drawn with instanced
+    /// rendering.
+    pub fn instances(mut self, instances: u32) -> Self {
+        self.options.instances = instances;
+        self
+    }
+
+    /// Sets whether the scene is drawn with multi-draw indirect calls.
+    pub fn indirect(mut self, indirect: bool) -> Self {
+        self.options.indirect = indirect;
+        self
+    }
+
+    /// Sets whether the indirect draws are culled on the GPU.
+    pub fn gpu_culling(mut self, gpu_culling: bool) -> Self {
+        self.options.gpu_culling = gpu_culling;
+        self
+    }
+
+    /// Sets whether the indirect draws culled on the GPU are also culled by
+    /// occlusion.
+    pub fn occlusion_culling(mut self, occlusion_culling: bool) -> Self {
+        self.options.occlusion_culling = occlusion_culling;
+        self
+    }
+
+    /// Sets whether the heavy objects are skipped when they were occluded.
+    pub fn occlusion_queries(mut self, occlusion_queries: bool) -> Self {
+        self.options.occlusion_queries = occlusion_queries;
+        self
+    }
+
+    /// Sets the operator the scene is tone mapped with, if any.
+    pub fn tone_mapping(mut self, tone_mapping: Option<ToneMapping>) -> Self {
+        self.options.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Sets the exposure of the tone mapped scene (in stops).
+    pub fn exposure(mut self, exposure: f32) -> Self {
+        self.options.exposure = exposure;
+        self
+    }
+
+    /// Sets whether the tone mapped scene is blurred along its motion.
+    pub fn motion_blur(mut self, motion_blur: bool) -> Self {
+        self.options.motion_blur = motion_blur;
+        self
+    }
+
+    /// Sets the number of samples along the motion of each pixel.
+    pub fn motion_blur_samples(mut self, samples: u32) -> Self {
+        self.options.motion_blur_samples = samples;
+        self
+    }
+
+    /// Sets the fraction of the frame the shutter is open for.
+    pub fn shutter(mut self, shutter: f32) -> Self {
+        self.options.shutter = shutter;
+        self
+    }
+
+    /// Sets how much of the bloom of the scene is added to it.
+    pub fn bloom(mut self, bloom: f32) -> Self {
+        self.options.bloom = bloom;
+        self
+    }
+
+    /// Sets how much the corners of the tone mapped scene are darkened.
+    pub fn vignette(mut self, vignette: f32) -> Self {
+        self.options.vignette = vignette;
+        self
+    }
+
+    /// Sets the height map the quads or the OBJ model are rendered with.
+    pub fn height_map(mut self, height_map: Option<PathBuf>) -> Self {
+        self.options.height_map = height_map;
+        self
+    }
+
+    /// Sets the minimum and maximum number of layers the height maps are
+    /// sampled in.
+    pub fn parallax_layers(mut self, min: u32, max: u32) -> Self {
+        self.options.parallax_layers = (min, max.max(min));
+        self
+    }
+
+    /// Sets the constant and slope-scaled depth bias of the shadow map.
+    pub fn shadow_bias(mut self, constant: f32, slope: f32) -> Self {
+        self.options.shadow_bias = (constant, slope);
+        self
+    }
+
+    /// Sets the speed the camera moves at (in units per second).
+    pub fn camera_speed(mut self, speed: f32) -> Self {
+        self.options.camera_speed = speed;
+        self
+    }
+
+    /// Sets the number of windows which render the scene, each of which
+    /// (after the first) views it from a quarter turn further around.
+    pub fn windows(mut self, count: u32) -> Self {
+        self.options.windows = count;
+        self
+    }
+
+    /// Sets the initial display mode of the window.
+    pub fn display_mode(mut self, mode: DisplayMode) -> Self {
+        self.options.display_mode = mode;
+        self
+    }
+
+    /// Sets the monitor used for fullscreen.
+    pub fn monitor(mut self, monitor: MonitorSelector) -> Self {
+        self.options.monitor = Some(monitor);
+        self
+    }
+
+    /// Sets the video mode used for exclusive fullscreen.
+    pub fn video_mode(mut self, video_mode: VideoModeSelector) -> Self {
+        self.options.video_mode = Some(video_mode);
+        self
+    }
+
+    /// Sets what the render loop does while the window is in the background.
+    pub fn background(mut self, background: BackgroundMode) -> Self {
+        self.options.background = background;
+        self
+    }
+
+    /// Sets the BMFont font the frame time is drawn with.
+    pub fn font(mut self, font: Option<PathBuf>) -> Self {
+        self.options.font = font;
+        self
+    }
+
+    /// Sets the TrueType or OpenType font text is drawn in the scene with.
+    pub fn sdf_font(mut self, sdf_font: Option<PathBuf>) -> Self {
+        self.options.sdf_font = sdf_font;
+        self
+    }
+
+    /// Returns the options our Vulkan app will be created with.
+    pub fn options(&self) -> &AppOptions {
+        &self.options
+    }
+
+    /// Sets the number of times the simulation is updated per second.
+    pub fn tick_rate(mut self, rate: u32) -> Self {
+        self.options.tick_rate = rate;
+        self
+    }
+
+    /// Returns the attributes of the window for our Vulkan app.
+    pub fn window_attributes(&self) -> WindowAttributes {
+        Window::default_attributes()
+            .with_title(&self.options.title)
+            .with_inner_size(LogicalSize::new(self.options.width, self.options.height))
+    }
+
+    /// Creates our Vulkan app for a window created by this builder.
+    pub unsafe fn create(self, window: &Window) -> Result<App> {
+        App::create(window, self.options)
+    }
+
+    /// Creates our Vulkan app without a window (see `App::create_offscreen`).
+    pub unsafe fn create_offscreen(self) -> Result<App> {
+        App::create_offscreen(self.options)
+    }
+
+    /// Prints the physical devices available to our Vulkan app.
+    pub unsafe fn list_gpus(self, window: &Window) -> Result<()> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+        let mut data = AppData {
+            options: self.options,
+            ..Default::default()
+        };
+        let instance = create_instance(Some(window), &entry, &mut data)?;
+        let _surface = create_surface(window, &instance, &mut data)?;
+        list_physical_devices(&instance, &data)
+    }
+}
+
+/// The Vulkan handles and associated properties used by our Vulkan app.
+#[derive(Debug, Default)]
+pub struct AppData {
+    // Options
+    pub options: AppOptions,
+    // Instance
+    pub api_version: u32,
+    // Surface
+    pub surface: vk::SurfaceKHR,
+    // Physical Device / Logical Device
+    pub physical_device: vk::PhysicalDevice,
+    pub msaa_samples: vk::SampleCountFlags,
+    pub features: Features,
+    pub rendering_path: RenderingPath,
+    pub graphics_queue: vk::Queue,
+    pub present_queue: vk::Queue,
+    pub transfer_queue: vk::Queue,
+    pub compute_queue: vk::Queue,
+    // Memory
+    pub allocator: Option<Allocator>,
+    pub upload: UploadContext,
+    // Swapchain
+    pub swapchain: Swapchain,
+    // Pipeline
+    pub render_pass: Owned<vk::RenderPass>,
+    pub descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    pub pipeline_layout: Owned<vk::PipelineLayout>,
+    // Framebuffers
+    pub framebuffers: Vec<Owned<vk::Framebuffer>>,
+    // Render Graph
+    pub main_pass_graphs: Vec<MainPassGraph>,
+    // Command Buffers (freed before the command pools they're allocated from)
+    pub command_buffers: OwnedCommandBuffers,
+    pub secondary_command_buffers: Vec<OwnedCommandBuffers>,
+    // Command Pool
+    pub command_pool: Owned<vk::CommandPool>,
+    pub thread_command_pools: Vec<Owned<vk::CommandPool>>,
+    // Color
+    pub color_image: Image,
+    // Depth
+    pub depth_format: vk::Format,
+    pub depth_image: Image,
+    // Resources
+    pub resources: Resources,
+    pub texture_sampler: Owned<vk::Sampler>,
+    // Scene
+    pub scene: Scene,
+    pub camera: Camera,
+    #[cfg(feature = "ecs")]
+    pub entities: Entities,
+    pub lights: Vec<Light>,
+    // Buffers
+    pub uniform_buffers: Vec<Buffer>,
+    pub light_buffers: Vec<Buffer>,
+    // Descriptors
+    pub descriptor_pool: Owned<vk::DescriptorPool>,
+    // Queries
+    pub query_pool: Owned<vk::QueryPool>,
+    pub timestamp_period: f32,
+    pub timestamp_mask: u64,
+    // Particles
+    pub particles: Particles,
+    // UI
+    pub ui: UiRenderer,
+    // SDF Text
+    #[cfg(feature = "sdf-text")]
+    pub sdf_text: SdfText,
+    // Environment
+    pub environment: Environment,
+    pub ibl: Ibl,
+    // Shadows
+    pub shadow_map: ShadowMap,
+    // Debug Views
+    pub debug_view: DebugViewPipelines,
+    // Instancing
+    pub instances: Instances,
+    // Indirect
+    pub indirect: Indirect,
+    pub culling: Culling,
+    pub hiz: HiZ,
+    // Occlusion Queries
+    pub occlusion: OcclusionQueries,
+    // Deferred
+    pub gbuffer: GBuffer,
+    // Clusters
+    pub clusters: Clusters,
+    // Tone Mapping
+    pub tone_map: ToneMap,
+    // Motion Blur
+    pub motion_blur: MotionBlur,
+    // Post-Processing
+    pub post_process: PostProcessStack,
+    // Sync Objects
+    pub image_available_semaphores: Vec<Owned<vk::Semaphore>>,
+    pub render_finished_semaphores: Vec<Owned<vk::Semaphore>>,
+    pub in_flight_fences: Vec<Owned<vk::Fence>>,
+    pub images_in_flight: Vec<vk::Fence>,
+    pub timeline_semaphore: Owned<vk::Semaphore>,
+    pub timeline_value: u64,
+    pub frame_timeline_values: Vec<u64>,
+    pub image_timeline_values: Vec<u64>,
+}
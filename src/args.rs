@@ -0,0 +1,229 @@
+//! Command line arguments.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::device::GpuSelector;
+use crate::display::{DisplayMode, MonitorSelector, VideoModeSelector};
+use crate::handler::BackgroundMode;
+use crate::material::Shading;
+use crate::memory::MemoryAllocator;
+use crate::shadows::DEFAULT_SHADOW_BIAS;
+use crate::swapchain::PresentMode;
+use crate::tonemap::ToneMapping;
+
+/// A Vulkan renderer following the Vulkan tutorial.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// The physical device to use, either as an index (see `--list-gpus`) or
+    /// as a substring of its name.
+    #[arg(long, value_name = "INDEX|NAME")]
+    pub gpu: Option<GpuSelector>,
+    /// Prefer a software rasterizer (e.g., SwiftShader or llvmpipe), for
+    /// running without a GPU.
+    #[arg(long)]
+    pub software: bool,
+    /// Render with a render pass and framebuffers even if dynamic rendering
+    /// is supported.
+    #[arg(long)]
+    pub render_pass: bool,
+    /// What manages the memory buffers and images are allocated from.
+    #[arg(long, value_enum, default_value_t = MemoryAllocator::default())]
+    pub memory_allocator: MemoryAllocator,
+    /// How presented images are synchronized with the display (falls back to
+    /// a supported present mode if necessary).
+    #[arg(long, value_enum, default_value_t = PresentMode::default())]
+    pub present_mode: PresentMode,
+    /// Draw particles which are updated by a compute shader on an async
+    /// compute queue (if there is one) while the graphics queue renders.
+    #[arg(long)]
+    pub particles: bool,
+    /// Record the draw commands into secondary command buffers on this many
+    /// threads instead of on the main thread.
+    #[arg(long, value_name = "THREADS", default_value_t = 0)]
+    pub recording_threads: usize,
+    /// Render this OBJ model or glTF model (a `.gltf` or `.glb` file, with
+    /// its own materials) instead of the textured quads.
+    #[arg(long, value_name = "PATH")]
+    pub model: Option<PathBuf>,
+    /// Render the quads (or the OBJ model) with this texture (a PNG, KTX2, or DDS
+    /// file) instead of `resources/texture.png`.
+    #[arg(long, value_name = "PATH")]
+    pub texture: Option<PathBuf>,
+    /// Draw the frame time with this bitmap font (an AngelCode BMFont file in
+    /// the text format, next to the PNG files of its pages).
+    #[arg(long, value_name = "FNT")]
+    pub font: Option<PathBuf>,
+    /// Draw a label in the scene with this TrueType or OpenType font (as
+    /// signed distance fields, with the `sdf-text` feature).
+    #[arg(long, value_name = "TTF")]
+    pub sdf_font: Option<PathBuf>,
+    /// Draw this equirectangular panorama (a Radiance HDR file) behind the
+    /// scene, converted to a cube map when it is loaded.
+    #[arg(long, value_name = "HDR")]
+    pub environment: Option<PathBuf>,
+    /// How the materials are shaded.
+    #[arg(long, value_enum, value_name = "MODEL", default_value_t = Shading::default())]
+    pub shading: Shading,
+    /// Render the scene with deferred rendering: draw the materials into a
+    /// G-buffer and shade it in a separate pass (with the PBR BRDF, which
+    /// `--shading` doesn't change, and without transparency).
+    #[arg(long)]
+    pub deferred: bool,
+    /// Cull the lights into clusters of the view frustum with a compute pass
+    /// before the main pass, so the materials only shade the lights which
+    /// reach each fragment (Forward+), which `L` toggles.
+    #[arg(long)]
+    pub clustered: bool,
+    /// Scatter this many small colored point lights around the quads (at most
+    /// 512 lights are shaded in total).
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub lights: usize,
+    /// Draw this many copies of the quads (or the model) in a grid below them
+    /// with a single instanced draw call.
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub instances: u32,
+    /// Consolidate the meshes into shared vertex and index buffers and draw
+    /// the scene with a multi-draw indirect call per material.
+    #[arg(long)]
+    pub indirect: bool,
+    /// Cull the draws against the view frustum with a compute pass which
+    /// compacts the draws that survive for the indirect draw calls.
+    #[arg(long, requires = "indirect")]
+    pub gpu_culling: bool,
+    /// Also cull the draws hidden behind the depth of the previous frame
+    /// with a Hi-Z pyramid built after the main pass.
+    #[arg(long, requires = "gpu_culling")]
+    pub occlusion_culling: bool,
+    /// Skip drawing the heavy objects whose bounding boxes passed no samples
+    /// in an occlusion query in a previous frame.
+    #[arg(long)]
+    pub occlusion_queries: bool,
+    /// Draw the scene into an HDR intermediate target and tone map it to the
+    /// swapchain images with this operator, which `T` cycles.
+    #[arg(long, value_enum, value_name = "OPERATOR")]
+    pub tone_mapping: Option<ToneMapping>,
+    /// The exposure of the tone mapped scene in stops, which `-` and `=`
+    /// lower and raise.
+    #[arg(
+        long,
+        value_name = "EV",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    pub exposure: f32,
+    /// Blur the tone mapped scene along the motion of the camera and the
+    /// objects since the previous frame.
+    #[arg(long, requires = "tone_mapping")]
+    pub motion_blur: bool,
+    /// The number of samples along the motion of each pixel.
+    #[arg(long, value_name = "SAMPLES", default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    pub motion_blur_samples: u32,
+    /// The fraction of the frame the shutter is open for, which scales the
+    /// length of the motion blur.
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.5)]
+    pub shutter: f32,
+    /// Add the bloom of the parts of the scene brighter than 1 to it, scaled
+    /// by this intensity, before it is tone mapped.
+    #[arg(
+        long,
+        value_name = "INTENSITY",
+        default_value_t = 0.0,
+        requires = "tone_mapping"
+    )]
+    pub bloom: f32,
+    /// Darken the corners of the tone mapped scene by this much (from 0 to
+    /// 1).
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        default_value_t = 0.0,
+        requires = "tone_mapping"
+    )]
+    pub vignette: f32,
+    /// Displace the texture coordinates of the quads (or the OBJ model) with
+    /// this height map (an RGBA PNG file or a texture container, whose red
+    /// channel is the height) using parallax occlusion mapping, which `P`
+    /// toggles.
+    #[arg(long, value_name = "PATH")]
+    pub height_map: Option<PathBuf>,
+    /// The minimum number of layers the height map is sampled in (when
+    /// looking at the surface head-on).
+    #[arg(long, value_name = "LAYERS", default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    pub parallax_min_layers: u32,
+    /// The maximum number of layers the height map is sampled in (when
+    /// looking at the surface at a grazing angle).
+    #[arg(long, value_name = "LAYERS", default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..))]
+    pub parallax_max_layers: u32,
+    /// The constant depth bias of the shadow map (in units of the smallest
+    /// depth difference), which keeps surfaces from shadowing themselves.
+    #[arg(long, value_name = "BIAS", default_value_t = DEFAULT_SHADOW_BIAS.0)]
+    pub shadow_bias: f32,
+    /// The depth bias of the shadow map per unit of the slope of the surface
+    /// (relative to the light).
+    #[arg(long, value_name = "BIAS", default_value_t = DEFAULT_SHADOW_BIAS.1)]
+    pub shadow_slope_bias: f32,
+    /// The speed the camera moves at with `W`, `A`, `S`, and `D` (in units
+    /// per second, four times faster while `Shift` is held).
+    #[arg(long, value_name = "SPEED", default_value_t = 2.0)]
+    pub camera_speed: f32,
+    /// Use an HDR surface format (scRGB or HDR10) if the display supports one.
+    #[arg(long)]
+    pub hdr: bool,
+    /// Limit rendering to this many frames per second, regardless of the
+    /// present mode.
+    #[arg(long, value_name = "FPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_fps: Option<u32>,
+    /// Update the simulation this many times per second, regardless of the
+    /// frame rate.
+    #[arg(long, value_name = "HZ", default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tick_rate: u32,
+    /// Open this many windows, each of which (after the first) renders the
+    /// scene from a quarter turn further around it.
+    #[arg(long, value_name = "COUNT", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub windows: u32,
+    /// Start in this display mode (`Alt+Enter` cycles through them).
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = DisplayMode::default())]
+    pub fullscreen: DisplayMode,
+    /// The monitor to use for fullscreen, either as an index (see
+    /// `--list-monitors`) or as a substring of its name.
+    #[arg(long, value_name = "INDEX|NAME")]
+    pub monitor: Option<MonitorSelector>,
+    /// The video mode to use for exclusive fullscreen (e.g., `1920x1080` or
+    /// `1920x1080@144`) instead of the native resolution of the monitor.
+    #[arg(long, value_name = "WIDTHxHEIGHT[@HZ]")]
+    pub video_mode: Option<VideoModeSelector>,
+    /// What to do while the window is unfocused or hidden by other windows:
+    /// keep rendering, throttle rendering to a few frames per second, or
+    /// pause rendering.
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = BackgroundMode::default())]
+    pub background: BackgroundMode,
+    /// Capture every frame rendered for this many seconds to numbered PNG
+    /// files in `recording/`.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub record: Option<u32>,
+    /// Render a deterministic frame offscreen and compare it to this reference
+    /// image, then exit.
+    #[arg(long, value_name = "REFERENCE")]
+    pub golden: Option<PathBuf>,
+    /// The largest difference between the channels of a pixel and the
+    /// reference image which is ignored by `--golden`.
+    #[arg(long, default_value_t = 2)]
+    pub tolerance: u8,
+    /// Save the frame rendered by `--golden` as the new reference image
+    /// instead of comparing it (also enabled by `GOLDEN_BLESS=1`).
+    #[arg(long, requires = "golden")]
+    pub bless: bool,
+    /// Print the available physical devices and their properties, then exit.
+    #[arg(long)]
+    pub list_gpus: bool,
+    /// Print the available monitors and their video modes, then exit.
+    #[arg(long)]
+    pub list_monitors: bool,
+    /// Print a report of the instance layers and extensions and of the
+    /// capabilities of every physical device, then exit.
+    #[arg(long)]
+    pub info: bool,
+}
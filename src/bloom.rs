@@ -0,0 +1,282 @@
+//! Bloom, which adds a blurred copy of the brightest parts of the HDR scene to
+//! it as an effect of the post-processing stack (before tone mapping).
+//!
+//! Before the render pass of its stage, a compute shader
+//! (`bloom_threshold.comp`) writes the brightness of the scene above a
+//! threshold to the first of a pair of ping-pong images at half resolution,
+//! which are then blurred back and forth by a separable Gaussian blur
+//! (`bloom_blur.comp`, alternating between horizontal and vertical passes).
+//! The effect itself adds the blurred image to the scene (`bloom.frag`).
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::ping_pong::{
+    cmd_begin_ping_pong, cmd_end_ping_pong, cmd_swap_ping_pong, create_ping_pong, PingPong,
+};
+use crate::pipeline::create_compute_pipeline;
+use crate::post_process::{EffectPipeline, PostProcess, Stage};
+use crate::shaders;
+use crate::tonemap::HDR_FORMAT;
+
+/// The brightness above which the scene blooms.
+const BLOOM_THRESHOLD: f32 = 1.0;
+
+/// The number of passes of the blur (alternating between horizontal and
+/// vertical, so this should be even).
+const BLOOM_BLUR_PASSES: usize = 4;
+
+/// The size of the workgroups of the compute shaders (see
+/// `bloom_threshold.comp` and `bloom_blur.comp`).
+const WORKGROUP_SIZE: u32 = 8;
+
+/// An effect which adds the bloom of the scene to it, scaled by the intensity
+/// in the options.
+#[derive(Debug, Default)]
+pub struct BloomEffect {
+    ping_pong: PingPong,
+    /// The descriptor set of the threshold pass, which reads the input of the
+    /// stage and writes the first ping-pong image.
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    threshold_pipeline_layout: Owned<vk::PipelineLayout>,
+    threshold_pipeline: Owned<vk::Pipeline>,
+    blur_pipeline_layout: Owned<vk::PipelineLayout>,
+    blur_pipeline: Owned<vk::Pipeline>,
+    /// The pipeline which adds the blurred image to the scene.
+    pipeline: EffectPipeline,
+}
+
+impl PostProcess for BloomEffect {
+    fn name(&self) -> &'static str {
+        "Bloom"
+    }
+
+    unsafe fn create(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        stage: &Stage,
+    ) -> Result<()> {
+        // Ping-Pong
+
+        let extent = vk::Extent2D {
+            width: (data.swapchain.extent.width / 2).max(1),
+            height: (data.swapchain.extent.height / 2).max(1),
+        };
+
+        self.ping_pong =
+            create_ping_pong(instance, device, data, extent, HDR_FORMAT, stage.sampler)?;
+
+        // Descriptor Set Layout
+
+        let bindings = [
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::STORAGE_IMAGE,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(binding, type_)| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(type_)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        self.descriptor_set_layout =
+            Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+        // Descriptor Pool
+
+        let sampler_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1);
+
+        let storage_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1);
+
+        let pool_sizes = &[sampler_size, storage_size];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+
+        self.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+        // Descriptor Set
+
+        let layouts = &[*self.descriptor_set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(*self.descriptor_pool)
+            .set_layouts(layouts);
+
+        self.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+        let read_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(stage.input)
+            .sampler(stage.sampler);
+
+        let write_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.ping_pong.images[0].view);
+
+        let image_infos = [[read_info], [write_info]];
+        let writes = image_infos
+            .iter()
+            .zip(&bindings)
+            .map(|(image_info, binding)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(binding.descriptor_type)
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        // Pipelines
+
+        // The threshold (a float) and the direction of the blur (a vec2).
+        (self.threshold_pipeline_layout, self.threshold_pipeline) = create_compute_pipeline(
+            device,
+            *self.descriptor_set_layout,
+            shaders::BLOOM_THRESHOLD_COMP,
+            size_of::<f32>(),
+        )?;
+
+        (self.blur_pipeline_layout, self.blur_pipeline) = create_compute_pipeline(
+            device,
+            *self.ping_pong.descriptor_set_layout,
+            shaders::BLOOM_BLUR_COMP,
+            size_of::<[f32; 2]>(),
+        )?;
+
+        // The intensity of the bloom.
+        let bloom = self.ping_pong.output(BLOOM_BLUR_PASSES).view;
+        self.pipeline = EffectPipeline::create(
+            device,
+            data,
+            stage,
+            &[stage.input, bloom],
+            shaders::BLOOM_FRAG,
+            size_of::<f32>(),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    unsafe fn cmd_prepare(
+        &self,
+        device: &Device,
+        _data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        // The input was written by a render pass, which has to be done before
+        // the compute shader reads it.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        cmd_begin_ping_pong(device, &self.ping_pong, command_buffer);
+
+        let extent = self.ping_pong.extent;
+        let workgroups_x = extent.width.div_ceil(WORKGROUP_SIZE);
+        let workgroups_y = extent.height.div_ceil(WORKGROUP_SIZE);
+
+        // Threshold
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *self.threshold_pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *self.threshold_pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            *self.threshold_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &BLOOM_THRESHOLD.to_ne_bytes(),
+        );
+        device.cmd_dispatch(command_buffer, workgroups_x, workgroups_y, 1);
+
+        // Blur
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *self.blur_pipeline,
+        );
+
+        for iteration in 0..BLOOM_BLUR_PASSES {
+            cmd_swap_ping_pong(device, &self.ping_pong, command_buffer, iteration);
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.blur_pipeline_layout,
+                0,
+                &[self.ping_pong.descriptor_set(iteration)],
+                &[],
+            );
+
+            let direction = if iteration % 2 == 0 {
+                [1.0f32, 0.0]
+            } else {
+                [0.0, 1.0]
+            };
+
+            let push_constants = direction.map(f32::to_ne_bytes).concat();
+            device.cmd_push_constants(
+                command_buffer,
+                *self.blur_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &push_constants,
+            );
+            device.cmd_dispatch(command_buffer, workgroups_x, workgroups_y, 1);
+        }
+
+        cmd_end_ping_pong(device, &self.ping_pong, command_buffer, BLOOM_BLUR_PASSES);
+    }
+
+    unsafe fn cmd_apply(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+        let push_constants = data.options.bloom.to_ne_bytes();
+        self.pipeline
+            .cmd_draw(device, command_buffer, &push_constants);
+    }
+}
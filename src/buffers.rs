@@ -0,0 +1,167 @@
+//! Uniform buffers and the helpers used to create buffers.
+
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_2::*;
+
+use crate::app::AppData;
+use crate::memory::{get_allocator, Allocation, Allocator};
+use crate::vertex::{Mat4, Vec4};
+
+/// A buffer and its backing memory which are destroyed when dropped.
+///
+/// A default buffer owns no Vulkan handles.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    allocator: Option<Allocator>,
+    pub buffer: vk::Buffer,
+    pub allocation: Allocation,
+    /// The address of the buffer in shaders (if it was created with
+    /// `SHADER_DEVICE_ADDRESS` usage).
+    pub address: vk::DeviceAddress,
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(allocator) = &self.allocator {
+            unsafe {
+                allocator.device().destroy_buffer(self.buffer, None);
+                allocator.free(&mut self.allocation);
+            }
+        }
+    }
+}
+
+pub unsafe fn create_uniform_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.uniform_buffers.clear();
+
+    for _ in 0..data.swapchain.images.len() {
+        let uniform_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<UniformBufferObject>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.uniform_buffers.push(uniform_buffer);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn create_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<Buffer> {
+    create_shared_buffer(instance, device, data, size, usage, properties, &[])
+}
+
+/// Creates a buffer which can be used by the supplied queue families at the
+/// same time without transferring its ownership between them.
+///
+/// The buffer is owned by a single queue family at a time if fewer than two
+/// queue families are supplied (like the buffers created by `create_buffer`).
+pub unsafe fn create_shared_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+    queue_family_indices: &[u32],
+) -> Result<Buffer> {
+    // Buffer
+
+    let mut buffer_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    if queue_family_indices.len() > 1 {
+        buffer_info = buffer_info
+            .sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(queue_family_indices);
+    }
+
+    let allocator = get_allocator(data)?;
+    let mut buffer = Buffer {
+        allocator: Some(allocator.clone()),
+        buffer: device.create_buffer(&buffer_info, None)?,
+        allocation: Allocation::default(),
+        address: 0,
+    };
+
+    // Memory
+
+    let requirements = device.get_buffer_memory_requirements(buffer.buffer);
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
+
+    buffer.allocation = allocator.allocate(requirements, memory_type_index, true)?;
+
+    device.bind_buffer_memory(
+        buffer.buffer,
+        buffer.allocation.memory,
+        buffer.allocation.offset,
+    )?;
+
+    // Address
+
+    if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(buffer.buffer);
+        buffer.address = device.get_buffer_device_address(&info);
+    }
+
+    Ok(buffer)
+}
+
+pub unsafe fn get_memory_type_index(
+    instance: &Instance,
+    data: &AppData,
+    properties: vk::MemoryPropertyFlags,
+    requirements: vk::MemoryRequirements,
+) -> Result<u32> {
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+    (0..memory.memory_type_count)
+        .find(|i| {
+            let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+            let memory_type = memory.memory_types[*i as usize];
+            suitable && memory_type.property_flags.contains(properties)
+        })
+        .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct UniformBufferObject {
+    pub view: Mat4,
+    pub proj: Mat4,
+    /// The light space of the shadow map (see `ShadowMap`).
+    pub light_space: Mat4,
+    /// The position of the camera (in world space, with a W of 1).
+    pub camera_position: Vec4,
+    /// The minimum and maximum number of layers the height maps are sampled
+    /// in by parallax occlusion mapping (both zero while it is disabled).
+    pub parallax_min_layers: u32,
+    pub parallax_max_layers: u32,
+    /// Whether the materials only shade the lights in the cluster of each
+    /// fragment (see `Clusters`).
+    pub clustered: u32,
+    /// The near and far distances of the camera, between which the clusters
+    /// are sliced.
+    pub camera_near: f32,
+    pub camera_far: f32,
+    /// The size of the swapchain images (in pixels).
+    pub screen_width: f32,
+    pub screen_height: f32,
+}
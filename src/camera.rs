@@ -0,0 +1,138 @@
+//! The camera the scene is rendered from.
+
+use nalgebra_glm as glm;
+
+use crate::vertex::{Mat4, Vec3};
+
+/// How a camera projects the scene onto the screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// A perspective projection with a vertical field of view (in radians).
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// An orthographic projection of a view volume of a height (in world
+    /// units) whose width depends on the aspect ratio.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+/// A camera at a position and orientation in the scene.
+///
+/// Like in Vulkan, the camera looks down its negative Z axis with its Y axis
+/// up (the view matrix is the inverse of its transform).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: glm::Quat,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::look_at(
+            glm::vec3(2.0, 2.0, 2.0),
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            Projection::Perspective {
+                fov_y: glm::radians(&glm::vec1(45.0))[0],
+                near: 0.1,
+                far: 10.0,
+            },
+        )
+    }
+}
+
+impl Camera {
+    /// Returns a camera at a position looking at a target, with the supplied
+    /// direction up.
+    pub fn look_at(position: Vec3, target: Vec3, up: Vec3, projection: Projection) -> Self {
+        let view = glm::look_at(&position, &target, &up);
+        Self {
+            position,
+            rotation: glm::to_quat(&glm::inverse(&view)),
+            projection,
+        }
+    }
+
+    /// Returns the direction the camera is looking in.
+    pub fn forward(&self) -> Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    /// Returns the matrix which transforms from world space to view space.
+    pub fn view(&self) -> Mat4 {
+        glm::quat_to_mat4(&glm::quat_inverse(&self.rotation)) * glm::translation(&-self.position)
+    }
+
+    /// Returns the near and far distances of the projection.
+    pub fn depth_range(&self) -> (f32, f32) {
+        match self.projection {
+            Projection::Perspective { near, far, .. }
+            | Projection::Orthographic { near, far, .. } => (near, far),
+        }
+    }
+
+    /// Returns the matrix which transforms from view space to Vulkan's clip
+    /// space, whose Y axis points down and whose depth range is 0 to 1
+    /// (unlike OpenGL's).
+    pub fn proj(&self, aspect: f32) -> Mat4 {
+        let mut proj = match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                glm::perspective_rh_zo(aspect, fov_y, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let (x, y) = (height * aspect / 2.0, height / 2.0);
+                glm::ortho_rh_zo(-x, x, -y, y, near, far)
+            }
+        };
+
+        proj[(1, 1)] *= -1.0;
+        proj
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(camera: &Camera, point: Vec3) -> Vec3 {
+        let clip = camera.proj(1.0) * camera.view() * glm::vec4(point.x, point.y, point.z, 1.0);
+        clip.xyz() / clip.w
+    }
+
+    #[test]
+    fn projections() {
+        for projection in [
+            Projection::Perspective {
+                fov_y: glm::radians(&glm::vec1(90.0))[0],
+                near: 1.0,
+                far: 10.0,
+            },
+            Projection::Orthographic {
+                height: 2.0,
+                near: 1.0,
+                far: 10.0,
+            },
+        ] {
+            let camera = Camera::look_at(
+                glm::vec3(0.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, -1.0),
+                glm::vec3(0.0, 1.0, 0.0),
+                projection,
+            );
+
+            // Depth is 0 at the near plane and 1 at the far plane.
+            assert!(project(&camera, glm::vec3(0.0, 0.0, -1.0)).z.abs() < 1e-6);
+            assert!((project(&camera, glm::vec3(0.0, 0.0, -10.0)).z - 1.0).abs() < 1e-6);
+
+            // Up is the top of the screen, which is -1 in clip space.
+            assert!((project(&camera, glm::vec3(0.0, 1.0, -1.0)).y + 1.0).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Frame timing.
+
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much of each new frame time is mixed into the smoothed frame time.
+const SMOOTHING: f32 = 0.1;
+
+/// Tracks the time between frames, the total elapsed time, and a smoothed
+/// frame time which is less noisy than the time between individual frames.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameClock {
+    start: Instant,
+    last: Instant,
+    delta: Duration,
+    smoothed: f32,
+    frames: u64,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            delta: Duration::ZERO,
+            smoothed: 0.0,
+            frames: 0,
+        }
+    }
+
+    /// Starts a new frame.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.delta = now - self.last;
+        self.last = now;
+
+        let delta = self.delta.as_secs_f32();
+        self.smoothed = if self.frames == 0 {
+            delta
+        } else {
+            self.smoothed + (delta - self.smoothed) * SMOOTHING
+        };
+
+        self.frames += 1;
+    }
+
+    /// The time between the start of the last frame and the frame before it
+    /// (in seconds).
+    pub fn delta(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// The time between the creation of this clock and the start of the last
+    /// frame (in seconds).
+    pub fn elapsed(&self) -> f32 {
+        (self.last - self.start).as_secs_f32()
+    }
+
+    /// The exponential moving average of the frame time (in seconds).
+    pub fn smoothed_frame_time(&self) -> f32 {
+        self.smoothed
+    }
+
+    /// The number of frames that have been started.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts frames to report the average frame rate once per period.
+#[derive(Copy, Clone, Debug)]
+pub struct FpsCounter {
+    period: Duration,
+    start: Instant,
+    frames: u32,
+}
+
+impl FpsCounter {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    /// Counts a frame and, once per period, returns the average frames per
+    /// second and milliseconds per frame over that period.
+    pub fn tick(&mut self) -> Option<(f32, f32)> {
+        self.frames += 1;
+
+        let elapsed = self.start.elapsed();
+        if elapsed < self.period {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f32();
+        let average = (
+            self.frames as f32 / seconds,
+            seconds * 1000.0 / self.frames as f32,
+        );
+
+        self.start = Instant::now();
+        self.frames = 0;
+
+        Some(average)
+    }
+}
+
+/// The most updates run for a frame, which keeps a slow frame from making the
+/// next frame slower still (the simulation slows down instead).
+const MAX_STEPS: u32 = 8;
+
+/// Divides the time between frames into updates at a fixed rate, so the
+/// simulation behaves identically regardless of the frame rate.
+#[derive(Copy, Clone, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            step: Duration::from_secs(1) / rate.max(1),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// Adds the supplied time (in seconds) since the last frame and returns
+    /// the number of updates to run for this frame.
+    pub fn advance(&mut self, delta: f32) -> u32 {
+        self.accumulator += Duration::from_secs_f32(delta);
+
+        let steps = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+        self.accumulator -= self.step * steps;
+
+        steps.min(MAX_STEPS)
+    }
+
+    /// The time between updates (in seconds).
+    pub fn step(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// How far the current frame is between the last update and the next,
+    /// from `0` to `1`, which is used to interpolate between the states of
+    /// the last two updates.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+/// How long before the start of the next frame the frame limiter stops
+/// sleeping and starts spinning, since sleeps can overshoot by about this much.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Limits the frame rate by waiting until the start of the next frame.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameLimiter {
+    frame_time: Duration,
+    next: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            frame_time: Duration::from_secs(1) / max_fps.max(1),
+            next: Instant::now(),
+        }
+    }
+
+    /// Waits until the next frame should start, sleeping for most of the wait
+    /// and spinning for the rest of it.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+
+        if self.next > now {
+            let remaining = self.next - now;
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+
+            while Instant::now() < self.next {
+                hint::spin_loop();
+            }
+
+            self.next += self.frame_time;
+        } else {
+            // Don't try to catch up after falling behind (e.g., after a stall).
+            self.next = now + self.frame_time;
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Clustered light culling (Forward+), which lets the materials shade
+//! hundreds of lights by only evaluating the lights which reach each
+//! fragment.
+//!
+//! The view frustum is divided into a grid of clusters: screen tiles which
+//! are sliced exponentially in depth between the near and far distances of
+//! the camera. Before the main pass, a compute shader (`cluster_lights.comp`)
+//! lists the lights whose ranges intersect the bounds of each cluster in a
+//! cluster buffer, and the fragment shaders look up the cluster of each
+//! fragment and only loop over its lights.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer, UniformBufferObject};
+use crate::handle::Owned;
+use crate::light::LightBufferObject;
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::vertex::Mat4;
+
+/// The number of clusters across the screen, down the screen, and in depth.
+pub const CLUSTER_GRID: (u32, u32, u32) = (16, 9, 24);
+
+/// The maximum number of lights listed for a cluster (any further lights
+/// which reach the cluster are ignored).
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 63;
+
+/// The number of clusters culled by a workgroup of the compute shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The number of clusters.
+pub const fn get_cluster_count() -> u32 {
+    CLUSTER_GRID.0 * CLUSTER_GRID.1 * CLUSTER_GRID.2
+}
+
+/// The size of a cluster buffer, where each cluster is the number of its
+/// lights followed by their indices (in the light buffer).
+pub const fn get_cluster_buffer_size() -> usize {
+    get_cluster_count() as usize * (1 + MAX_LIGHTS_PER_CLUSTER) * size_of::<u32>()
+}
+
+/// The Vulkan handles used to cull the lights into clusters.
+#[derive(Debug, Default)]
+pub struct Clusters {
+    /// The cluster buffer for each swapchain image.
+    pub buffers: Vec<Buffer>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+}
+
+/// Creates a cluster buffer for each swapchain image and the compute pipeline
+/// which culls the lights into them.
+///
+/// The cluster buffers are bound to the materials even if the lights aren't
+/// culled (see `AppOptions::clustered`), so they are always created.
+pub unsafe fn create_clusters(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Buffers
+
+    data.clusters.buffers.clear();
+
+    for _ in 0..data.swapchain.images.len() {
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            get_cluster_buffer_size() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        data.clusters.buffers.push(buffer);
+    }
+
+    // Descriptor Set Layout
+
+    // The uniform buffer, the light buffer, and the cluster buffer.
+    let bindings = [
+        vk::DescriptorType::UNIFORM_BUFFER,
+        vk::DescriptorType::STORAGE_BUFFER,
+        vk::DescriptorType::STORAGE_BUFFER,
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(binding, type_)| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding as u32)
+            .descriptor_type(type_)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    })
+    .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.clusters.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let sets = data.swapchain.images.len() as u32;
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(sets);
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(sets * 2);
+
+    let pool_sizes = &[ubo_size, storage_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(sets);
+
+    data.clusters.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Sets
+
+    let layouts = vec![*data.clusters.descriptor_set_layout; sets as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.clusters.descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.clusters.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (i, descriptor_set) in data.clusters.descriptor_sets.iter().enumerate() {
+        let buffers = [
+            (
+                data.uniform_buffers[i].buffer,
+                size_of::<UniformBufferObject>(),
+            ),
+            (data.light_buffers[i].buffer, size_of::<LightBufferObject>()),
+            (data.clusters.buffers[i].buffer, get_cluster_buffer_size()),
+        ];
+
+        let buffer_infos = buffers.map(|(buffer, size)| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(size as u64)
+                .build()]
+        });
+
+        let writes = buffer_infos
+            .iter()
+            .zip(&bindings)
+            .map(|(buffer_info, binding)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(binding.descriptor_type)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Pipeline
+
+    // The inverse of the projection matrix of the camera.
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<Mat4>() as u32);
+
+    let set_layouts = &[*data.clusters.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.clusters.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    let comp_shader_module = create_shader_module(device, shaders::CLUSTER_LIGHTS_COMP)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(comp_shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(*data.clusters.pipeline_layout);
+
+    data.clusters.pipeline = Owned::new(
+        device,
+        device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    device.destroy_shader_module(comp_shader_module, None);
+
+    Ok(())
+}
+
+/// Records culling the lights into the cluster buffer for the swapchain image
+/// at the supplied index with the supplied projection matrix, which is then
+/// ready to be read by the fragment shaders.
+pub unsafe fn cmd_cluster_lights(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    proj: &Mat4,
+) {
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.clusters.pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.clusters.pipeline_layout,
+        0,
+        &[data.clusters.descriptor_sets[image_index]],
+        &[],
+    );
+
+    let inv_proj = glm::inverse(proj);
+    let push_constants =
+        std::slice::from_raw_parts(&inv_proj as *const Mat4 as *const u8, size_of::<Mat4>());
+    device.cmd_push_constants(
+        command_buffer,
+        *data.clusters.pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants,
+    );
+
+    let workgroups = get_cluster_count().div_ceil(WORKGROUP_SIZE);
+    device.cmd_dispatch(command_buffer, workgroups, 1, 1);
+
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.clusters.buffers[image_index].buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
@@ -0,0 +1,211 @@
+//! Command pools and command buffers.
+//!
+//! The draw commands of the main pass can be recorded by several threads at
+//! once. Each thread records its share of the draw batches into a secondary
+//! command buffer allocated from a command pool of its own (since command
+//! pools can't be used by several threads at once), and the secondary command
+//! buffers are then executed by the primary command buffer.
+
+use std::thread;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::device::{QueueFamilyIndices, RenderingPath};
+use crate::framebuffers::get_color_format;
+use crate::handle::{Owned, OwnedCommandBuffers};
+
+/// Records draw commands into a command buffer.
+pub type RecordFn<'a> = Box<dyn Fn(&Device, vk::CommandBuffer) + Send + Sync + 'a>;
+
+/// A batch of draw commands for the main pass which can be recorded into a
+/// command buffer on any thread.
+pub struct DrawBatch<'a> {
+    /// The name and color of the label around the batch.
+    pub name: &'static str,
+    pub color: [f32; 4],
+    pub record: RecordFn<'a>,
+}
+
+pub unsafe fn create_command_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(indices.graphics);
+
+    data.command_pool = Owned::new(device, device.create_command_pool(&info, None)?);
+
+    // Recording Threads
+
+    for _ in 0..data.options.recording_threads {
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(indices.graphics);
+
+        let command_pool = device.create_command_pool(&info, None)?;
+        data.thread_command_pools
+            .push(Owned::new(device, command_pool));
+    }
+
+    Ok(())
+}
+
+pub unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<()> {
+    // Allocate
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*data.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(data.swapchain.images.len() as u32);
+
+    let command_buffers = device.allocate_command_buffers(&allocate_info)?;
+    data.command_buffers = OwnedCommandBuffers::new(device, *data.command_pool, command_buffers);
+
+    // Secondary
+
+    // Every recording thread has a secondary command buffer for each swapchain
+    // image, which is allocated from the command pool of that thread.
+    data.secondary_command_buffers.clear();
+
+    for command_pool in &data.thread_command_pools {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(**command_pool)
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(data.swapchain.images.len() as u32);
+
+        let command_buffers = device.allocate_command_buffers(&allocate_info)?;
+        data.secondary_command_buffers
+            .push(OwnedCommandBuffers::new(
+                device,
+                **command_pool,
+                command_buffers,
+            ));
+    }
+
+    Ok(())
+}
+
+/// Records the supplied draw batches into the secondary command buffers for
+/// the swapchain image at the supplied index, splitting the batches between
+/// the recording threads.
+///
+/// Returns the secondary command buffers which have been recorded, which have
+/// to be executed during a main pass begun with secondary command buffer
+/// contents.
+pub unsafe fn record_secondary_command_buffers(
+    device: &Device,
+    data: &AppData,
+    image_index: usize,
+    batches: &[DrawBatch],
+) -> Result<Vec<vk::CommandBuffer>> {
+    let threads = data.thread_command_pools.len().min(batches.len());
+    let command_buffers = data.secondary_command_buffers[..threads]
+        .iter()
+        .map(|b| b[image_index])
+        .collect::<Vec<_>>();
+
+    // Begin
+
+    // The secondary command buffers continue the main pass, so they inherit
+    // either the render pass or the attachment formats it renders to.
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(data.depth_format)
+        .rasterization_samples(data.msaa_samples);
+
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder();
+    if data.rendering_path == RenderingPath::RenderPass {
+        inheritance_info = inheritance_info
+            .render_pass(*data.render_pass)
+            .subpass(0)
+            .framebuffer(*data.framebuffers[image_index]);
+    } else {
+        inheritance_info = inheritance_info.push_next(&mut rendering_info);
+    }
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+        )
+        .inheritance_info(&inheritance_info);
+
+    for command_buffer in &command_buffers {
+        device.reset_command_buffer(*command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(*command_buffer, &info)?;
+    }
+
+    // Record
+
+    thread::scope(|scope| {
+        for (index, command_buffer) in command_buffers.iter().enumerate() {
+            scope.spawn(move || {
+                for batch in batches.iter().skip(index).step_by(threads) {
+                    (batch.record)(device, *command_buffer);
+                }
+            });
+        }
+    });
+
+    // End
+
+    for command_buffer in &command_buffers {
+        device.end_command_buffer(*command_buffer)?;
+    }
+
+    Ok(command_buffers)
+}
+
+pub unsafe fn begin_single_time_commands(
+    device: &Device,
+    data: &AppData,
+) -> Result<vk::CommandBuffer> {
+    // Allocate
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(*data.command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    // Begin
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(command_buffer)
+}
+
+pub unsafe fn end_single_time_commands(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+) -> Result<()> {
+    // End
+
+    device.end_command_buffer(command_buffer)?;
+
+    // Submit
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+    device.queue_submit(data.graphics_queue, &[info], vk::Fence::null())?;
+    device.queue_wait_idle(data.graphics_queue)?;
+
+    // Cleanup
+
+    device.free_command_buffers(*data.command_pool, &[command_buffer]);
+
+    Ok(())
+}
@@ -0,0 +1,486 @@
+//! Texture containers (KTX2 and DDS files), which store the pixels of a
+//! texture in the format it is sampled in (e.g., a block-compressed format)
+//! along with its pre-baked mip levels and array layers, so it doesn't have to
+//! be decoded and mipmapped at runtime.
+//!
+//! Basis Universal textures (`.basis` files and KTX2 files with UASTC
+//! textures, which may be supercompressed with Zstandard) are stored in an
+//! intermediate format instead, which is transcoded to a GPU-compressed format
+//! supported by the physical device when they are loaded (with the `basis`
+//! feature, see `src/transcode.rs`).
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+#[cfg(feature = "basis")]
+use crate::transcode::{inflate_zstd, transcode_basis, transcode_uastc};
+
+/// The identifier at the start of KTX2 files.
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// The magic number at the start of DDS files.
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// The signature at the start of Basis Universal files.
+const BASIS_SIGNATURE: &[u8; 2] = b"sB";
+
+// KTX2 Constants
+
+const KTX2_SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const KTX2_SUPERCOMPRESSION_ZSTD: u32 = 2;
+const KHR_DF_MODEL_UASTC: u8 = 166;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+
+// DDS Flags
+
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_DIMENSION_TEXTURE3D: u32 = 4;
+
+/// The pixels of a texture loaded from a texture container.
+#[derive(Clone, Debug, Default)]
+pub struct TextureContainer {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    /// The number of array layers (with six layers per cube map, one for each
+    /// face).
+    pub array_layers: u32,
+    /// Whether the array layers are the faces of cube maps.
+    pub cube: bool,
+    /// The pixels of the mip levels (largest first), each of which has the
+    /// pixels of its array layers one after another.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureContainer {
+    /// Loads a KTX2, DDS, or Basis Universal file, transcoding Basis Universal
+    /// textures to the supplied target.
+    pub fn load(path: &Path, target: TranscodeTarget) -> Result<Self> {
+        let bytes =
+            fs::read(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+        Self::parse(&bytes, target)
+            .map_err(|e| anyhow!("Failed to load `{}`: {}", path.display(), e))
+    }
+
+    /// Parses a KTX2, DDS, or Basis Universal file (which are told apart by
+    /// their first bytes).
+    pub fn parse(bytes: &[u8], target: TranscodeTarget) -> Result<Self> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            parse_ktx2(bytes, target)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            parse_dds(bytes)
+        } else if bytes.starts_with(BASIS_SIGNATURE) {
+            transcode_basis(bytes, target)
+        } else {
+            Err(anyhow!("Not a KTX2, DDS, or Basis Universal file."))
+        }
+    }
+
+    /// Returns the number of array layers of the texture which aren't cube
+    /// map faces.
+    pub fn layers(&self) -> u32 {
+        if self.cube {
+            self.array_layers / 6
+        } else {
+            self.array_layers
+        }
+    }
+}
+
+/// The formats Basis Universal textures can be transcoded to (best first).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TranscodeTarget {
+    Bc7,
+    Astc4x4,
+    Etc2,
+    /// Uncompressed pixels, for physical devices which support none of the
+    /// GPU-compressed formats.
+    #[default]
+    Rgba8,
+}
+
+impl TranscodeTarget {
+    pub const ALL: [Self; 4] = [Self::Bc7, Self::Astc4x4, Self::Etc2, Self::Rgba8];
+
+    pub fn format(self, srgb: bool) -> vk::Format {
+        match (self, srgb) {
+            (Self::Bc7, false) => vk::Format::BC7_UNORM_BLOCK,
+            (Self::Bc7, true) => vk::Format::BC7_SRGB_BLOCK,
+            (Self::Astc4x4, false) => vk::Format::ASTC_4X4_UNORM_BLOCK,
+            (Self::Astc4x4, true) => vk::Format::ASTC_4X4_SRGB_BLOCK,
+            (Self::Etc2, false) => vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+            (Self::Etc2, true) => vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+            (Self::Rgba8, false) => vk::Format::R8G8B8A8_UNORM,
+            (Self::Rgba8, true) => vk::Format::R8G8B8A8_SRGB,
+        }
+    }
+}
+
+/// Returns whether a path has the extension of a texture container.
+pub fn is_container_path(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        ["ktx2", "dds", "basis"]
+            .iter()
+            .any(|x| e.eq_ignore_ascii_case(x))
+    })
+}
+
+/// Returns the size of the blocks of pixels of a format (in pixels) and the
+/// number of bytes in each block, if the format is supported.
+///
+/// Uncompressed formats have blocks of a single pixel.
+pub fn get_block_size(format: vk::Format) -> Option<(u32, u32)> {
+    let size = match format {
+        vk::Format::R8_UNORM => (1, 1),
+        vk::Format::R8G8_UNORM => (1, 2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => (1, 4),
+        vk::Format::R16G16B16A16_SFLOAT => (1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 16),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => (4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ASTC_4X4_UNORM_BLOCK
+        | vk::Format::ASTC_4X4_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => (4, 16),
+        _ => return None,
+    };
+
+    Some(size)
+}
+
+/// Returns the number of bytes of an image of the supplied size in a format.
+pub fn get_image_size(format: vk::Format, width: u32, height: u32) -> Result<usize> {
+    let (block, bytes) =
+        get_block_size(format).ok_or_else(|| anyhow!("Unsupported format ({:?}).", format))?;
+    let blocks_x = width.div_ceil(block) as usize;
+    let blocks_y = height.div_ceil(block) as usize;
+    Ok(blocks_x * blocks_y * bytes as usize)
+}
+
+/// Parses a KTX2 file, whose format is a `VkFormat` and whose mip levels are
+/// stored with the pixels of their array layers and faces one after another.
+fn parse_ktx2(bytes: &[u8], target: TranscodeTarget) -> Result<TextureContainer> {
+    let format = vk::Format::from_raw(read_u32(bytes, 12)? as i32);
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let layers = read_u32(bytes, 32)?;
+    let faces = read_u32(bytes, 36)?;
+    let level_count = read_u32(bytes, 40)?;
+    let supercompression = read_u32(bytes, 44)?;
+    let dfd_offset = read_u32(bytes, 48)? as usize;
+
+    if depth > 1 {
+        return Err(anyhow!("3D textures are not supported."));
+    } else if faces != 1 && faces != 6 {
+        return Err(anyhow!("Invalid number of faces ({}).", faces));
+    } else if supercompression == KTX2_SUPERCOMPRESSION_BASIS_LZ {
+        return Err(anyhow!(
+            "ETC1S (BasisLZ) KTX2 textures are not supported (use UASTC or a `.basis` file)."
+        ));
+    }
+
+    // A level count of 0 asks for the mip levels to be generated at runtime,
+    // which we don't do, so only the first level is used.
+    let level_count = level_count.max(1);
+
+    // The level index follows the 80 byte header and index.
+    let levels = (0..level_count as usize)
+        .map(|i| {
+            let offset = read_u64(bytes, 80 + i * 24)? as usize;
+            let length = read_u64(bytes, 80 + i * 24 + 8)? as usize;
+            let level = read_bytes(bytes, offset, length)?;
+            match supercompression {
+                0 => Ok(level.to_vec()),
+                KTX2_SUPERCOMPRESSION_ZSTD => inflate_zstd(level),
+                _ => Err(anyhow!(
+                    "Unsupported supercompression scheme ({}).",
+                    supercompression
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut container = TextureContainer {
+        format,
+        width,
+        height: height.max(1),
+        array_layers: layers.max(1) * faces,
+        cube: faces == 6,
+        levels,
+    };
+
+    // Basis Universal textures don't have a format until they are transcoded,
+    // so they are told apart by the color model in their data format
+    // descriptor (which also has their transfer function).
+    if format == vk::Format::UNDEFINED {
+        let color_model = read_bytes(bytes, dfd_offset + 12, 1)?[0];
+        let transfer = read_bytes(bytes, dfd_offset + 14, 1)?[0];
+        if color_model != KHR_DF_MODEL_UASTC {
+            return Err(anyhow!("Unsupported color model ({}).", color_model));
+        }
+
+        transcode_uastc(&mut container, transfer == KHR_DF_TRANSFER_SRGB, target)?;
+    }
+
+    Ok(container)
+}
+
+/// Parses a DDS file, whose format is either a DXGI format (in the DX10
+/// header) or described by its pixel format, and whose array layers are
+/// stored with the pixels of their mip levels one after another.
+fn parse_dds(bytes: &[u8]) -> Result<TextureContainer> {
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let level_count = read_u32(bytes, 28)?.max(1);
+    let pixel_format_flags = read_u32(bytes, 80)?;
+    let four_cc = read_bytes(bytes, 84, 4)?;
+    let caps2 = read_u32(bytes, 112)?;
+
+    if caps2 & DDSCAPS2_VOLUME != 0 {
+        return Err(anyhow!("3D textures are not supported."));
+    }
+
+    let (format, layers, cube, offset) =
+        if pixel_format_flags & DDPF_FOURCC != 0 && four_cc == b"DX10" {
+            let dxgi_format = read_u32(bytes, 128)?;
+            let dimension = read_u32(bytes, 132)?;
+            let misc_flags = read_u32(bytes, 136)?;
+            let array_size = read_u32(bytes, 140)?.max(1);
+
+            if dimension == DDS_DIMENSION_TEXTURE3D {
+                return Err(anyhow!("3D textures are not supported."));
+            }
+
+            let format = get_dxgi_format(dxgi_format)
+                .ok_or_else(|| anyhow!("Unsupported DXGI format ({}).", dxgi_format))?;
+            let cube = misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+            (format, array_size, cube, 148)
+        } else {
+            let format = get_legacy_dds_format(bytes, pixel_format_flags, four_cc)?;
+            (format, 1, caps2 & DDSCAPS2_CUBEMAP != 0, 128)
+        };
+
+    let array_layers = if cube { layers * 6 } else { layers };
+
+    // The pixels are rearranged so that each mip level has the pixels of its
+    // array layers one after another (like KTX2 files).
+    let level_sizes = (0..level_count)
+        .map(|i| get_image_size(format, (width >> i).max(1), (height >> i).max(1)))
+        .collect::<Result<Vec<_>>>()?;
+    let layer_size = level_sizes.iter().sum::<usize>();
+
+    let mut levels = level_sizes
+        .iter()
+        .map(|s| Vec::with_capacity(s * array_layers as usize))
+        .collect::<Vec<_>>();
+    for layer in 0..array_layers as usize {
+        let mut offset = offset + layer * layer_size;
+        for (level, size) in levels.iter_mut().zip(&level_sizes) {
+            level.extend_from_slice(read_bytes(bytes, offset, *size)?);
+            offset += size;
+        }
+    }
+
+    Ok(TextureContainer {
+        format,
+        width,
+        height,
+        array_layers,
+        cube,
+        levels,
+    })
+}
+
+/// Returns the format of a DDS file without a DX10 header from its pixel
+/// format.
+///
+/// The color formats are assumed to be sRGB since they can't say otherwise.
+fn get_legacy_dds_format(bytes: &[u8], flags: u32, four_cc: &[u8]) -> Result<vk::Format> {
+    if flags & DDPF_FOURCC != 0 {
+        return match four_cc {
+            b"DXT1" => Ok(vk::Format::BC1_RGBA_SRGB_BLOCK),
+            b"DXT2" | b"DXT3" => Ok(vk::Format::BC2_SRGB_BLOCK),
+            b"DXT4" | b"DXT5" => Ok(vk::Format::BC3_SRGB_BLOCK),
+            b"ATI1" | b"BC4U" => Ok(vk::Format::BC4_UNORM_BLOCK),
+            b"BC4S" => Ok(vk::Format::BC4_SNORM_BLOCK),
+            b"ATI2" | b"BC5U" => Ok(vk::Format::BC5_UNORM_BLOCK),
+            b"BC5S" => Ok(vk::Format::BC5_SNORM_BLOCK),
+            _ => Err(anyhow!(
+                "Unsupported DDS format ({}).",
+                String::from_utf8_lossy(four_cc)
+            )),
+        };
+    }
+
+    let bit_count = read_u32(bytes, 88)?;
+    let red_mask = read_u32(bytes, 92)?;
+    let alpha_mask = read_u32(bytes, 104)?;
+
+    match (flags & DDPF_RGB != 0, bit_count, red_mask, alpha_mask) {
+        (true, 32, 0x000000FF, 0xFF000000) => Ok(vk::Format::R8G8B8A8_SRGB),
+        (true, 32, 0x00FF0000, 0xFF000000) => Ok(vk::Format::B8G8R8A8_SRGB),
+        _ => Err(anyhow!(
+            "Unsupported DDS pixel format ({} bits, red mask {:#010X}).",
+            bit_count,
+            red_mask
+        )),
+    }
+}
+
+/// Returns the format for a DXGI format.
+fn get_dxgi_format(format: u32) -> Option<vk::Format> {
+    let format = match format {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        49 => vk::Format::R8G8_UNORM,
+        61 => vk::Format::R8_UNORM,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+#[cfg(not(feature = "basis"))]
+fn inflate_zstd(_: &[u8]) -> Result<Vec<u8>> {
+    Err(anyhow!(
+        "Zstandard supercompressed textures require the `basis` feature."
+    ))
+}
+
+#[cfg(not(feature = "basis"))]
+fn transcode_uastc(_: &mut TextureContainer, _: bool, _: TranscodeTarget) -> Result<()> {
+    Err(anyhow!("UASTC textures require the `basis` feature."))
+}
+
+#[cfg(not(feature = "basis"))]
+fn transcode_basis(_: &[u8], _: TranscodeTarget) -> Result<TextureContainer> {
+    Err(anyhow!(
+        "Basis Universal textures require the `basis` feature."
+    ))
+}
+
+fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| anyhow!("Unexpected end of file."))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let bytes = read_bytes(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let bytes = read_bytes(bytes, offset, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn ktx2() {
+        // An 8x4 BC7 texture with two array layers and two mip levels.
+        let mut bytes = vec![0; 128];
+        bytes[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        put_u32(&mut bytes, 12, vk::Format::BC7_SRGB_BLOCK.as_raw() as u32);
+        put_u32(&mut bytes, 20, 8);
+        put_u32(&mut bytes, 24, 4);
+        put_u32(&mut bytes, 32, 2);
+        put_u32(&mut bytes, 36, 1);
+        put_u32(&mut bytes, 40, 2);
+
+        // The smallest mip level is stored first.
+        put_u64(&mut bytes, 80, 160);
+        put_u64(&mut bytes, 88, 64);
+        put_u64(&mut bytes, 104, 128);
+        put_u64(&mut bytes, 112, 32);
+        bytes.extend((0..32).map(|_| 2));
+        bytes.extend((0..64).map(|_| 1));
+
+        let container = TextureContainer::parse(&bytes, TranscodeTarget::default()).unwrap();
+        assert_eq!(container.format, vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!((container.width, container.height), (8, 4));
+        assert_eq!((container.array_layers, container.cube), (2, false));
+        assert_eq!(container.levels, vec![vec![1; 64], vec![2; 32]]);
+    }
+
+    #[test]
+    fn dds() {
+        // A 4x4 DXT5 texture with three mip levels.
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        put_u32(&mut bytes, 4, 124);
+        put_u32(&mut bytes, 12, 4);
+        put_u32(&mut bytes, 16, 4);
+        put_u32(&mut bytes, 28, 3);
+        put_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(b"DXT5");
+
+        // The 2x2 and 1x1 mip levels take a whole block each.
+        bytes.extend((0..48).map(|i| i / 16));
+
+        let container = TextureContainer::parse(&bytes, TranscodeTarget::default()).unwrap();
+        assert_eq!(container.format, vk::Format::BC3_SRGB_BLOCK);
+        assert_eq!(container.levels.len(), 3);
+        assert_eq!(container.levels[2], vec![2; 16]);
+
+        // A truncated file is an error rather than a panic.
+        assert!(TextureContainer::parse(&bytes[..150], TranscodeTarget::default()).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Controllers which move the camera in response to keyboard and mouse input
+//! (see `default_bindings` for the keys and buttons bound to their actions).
+
+use log::*;
+use nalgebra_glm as glm;
+use winit::window::{CursorGrabMode, Window};
+
+use crate::camera::Camera;
+use crate::input::Input;
+use crate::vertex::Vec3;
+
+/// The direction which is up in the scene.
+const UP: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+/// The largest angle the camera can be pitched up or down by, which keeps it
+/// from flipping over when looking straight up or down.
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+/// Moves the camera like a first-person fly camera.
+///
+/// `W`, `A`, `S`, and `D` move the camera along the direction it is looking in
+/// (and `Space` and `C` move it up and down), faster while `Shift` is held.
+/// Moving the mouse while the right mouse button is held (or the arrow keys)
+/// turns the camera.
+#[derive(Clone, Debug)]
+pub struct FlyController {
+    /// The speed the camera moves at (in units per second).
+    pub speed: f32,
+    /// The factor the speed is multiplied by while sprinting.
+    pub sprint_multiplier: f32,
+    /// The angle the camera turns by per pixel the mouse moves (in radians).
+    pub sensitivity: f32,
+    /// The speed the camera turns at while the look actions are held (in
+    /// radians per second).
+    pub turn_speed: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl FlyController {
+    /// Creates a controller which starts in the direction the supplied camera
+    /// is looking in.
+    pub fn new(camera: &Camera, speed: f32) -> Self {
+        let forward = camera.forward();
+        Self {
+            speed,
+            sprint_multiplier: 4.0,
+            sensitivity: 0.0025,
+            turn_speed: 2.0,
+            yaw: forward.y.atan2(forward.x),
+            pitch: forward.z.clamp(-1.0, 1.0).asin(),
+        }
+    }
+
+    /// Returns whether the mouse is turning the camera.
+    pub fn is_dragging(&self, input: &Input) -> bool {
+        input.is_held("look")
+    }
+
+    /// Turns and moves the camera for the supplied time (in seconds) since the
+    /// last update.
+    pub fn update(&mut self, input: &Input, camera: &mut Camera, delta: f32) {
+        // Look
+
+        if self.is_dragging(input) {
+            let (dx, dy) = input.mouse_delta();
+            self.yaw -= dx as f32 * self.sensitivity;
+            self.pitch -= dy as f32 * self.sensitivity;
+        }
+
+        let turn = self.turn_speed * delta;
+        self.yaw -= input.axis("look_left", "look_right") * turn;
+        self.pitch += input.axis("look_down", "look_up") * turn;
+        self.pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+
+        let forward = glm::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        );
+        let right = glm::normalize(&glm::cross(&forward, &UP));
+
+        // Move
+
+        let direction = forward * input.axis("move_back", "move_forward")
+            + right * input.axis("move_left", "move_right")
+            + UP * input.axis("move_down", "move_up");
+
+        let mut speed = self.speed;
+        if input.is_held("sprint") {
+            speed *= self.sprint_multiplier;
+        }
+
+        if direction != Vec3::zeros() {
+            camera.position += glm::normalize(&direction) * speed * delta;
+        }
+
+        *camera = Camera::look_at(
+            camera.position,
+            camera.position + forward,
+            UP,
+            camera.projection,
+        );
+    }
+}
+
+/// Moves the camera around a target, which is useful for inspecting models.
+///
+/// Dragging with the left mouse button rotates the camera around the target,
+/// dragging with the middle mouse button pans the target, and scrolling zooms
+/// in or out.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub target: Vec3,
+    /// The distance between the camera and the target.
+    pub distance: f32,
+    /// The angle the camera rotates by per pixel the mouse moves (in
+    /// radians).
+    pub sensitivity: f32,
+    /// The factor the distance is multiplied by per line scrolled.
+    pub zoom_factor: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl OrbitController {
+    /// Creates a controller which orbits the point in front of the supplied
+    /// camera which is closest to the origin of the scene.
+    pub fn new(camera: &Camera) -> Self {
+        let forward = camera.forward();
+        let distance = glm::dot(&-camera.position, &forward).max(1.0);
+        Self {
+            target: camera.position + forward * distance,
+            distance,
+            sensitivity: 0.005,
+            zoom_factor: 0.9,
+            yaw: (-forward.y).atan2(-forward.x),
+            pitch: (-forward.z).clamp(-1.0, 1.0).asin(),
+        }
+    }
+
+    /// Returns whether the mouse is rotating the camera or panning the target.
+    pub fn is_dragging(&self, input: &Input) -> bool {
+        input.is_held("orbit") || input.is_held("pan")
+    }
+
+    /// Moves the camera around the target.
+    pub fn update(&mut self, input: &Input, camera: &mut Camera) {
+        let (dx, dy) = input.mouse_delta();
+        let (dx, dy) = (dx as f32, dy as f32);
+
+        if input.is_held("orbit") {
+            self.yaw -= dx * self.sensitivity;
+            self.pitch = (self.pitch + dy * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        // The target moves with the cursor, by an amount which keeps the
+        // target under the cursor at any distance.
+        if input.is_held("pan") {
+            let scale = self.distance * self.sensitivity / 4.0;
+            self.target += (camera.up() * dy - camera.right() * dx) * scale;
+        }
+
+        self.distance = (self.distance * self.zoom_factor.powf(input.scroll())).max(0.1);
+
+        let offset = glm::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        );
+
+        *camera = Camera::look_at(
+            self.target + offset * self.distance,
+            self.target,
+            UP,
+            camera.projection,
+        );
+    }
+}
+
+/// The controller which is currently moving the camera.
+#[derive(Clone, Debug)]
+pub enum CameraController {
+    Fly(FlyController),
+    Orbit(OrbitController),
+}
+
+impl CameraController {
+    /// Switches between the fly and orbit controllers, starting from the
+    /// current position of the camera.
+    pub fn toggle(&mut self, camera: &Camera, speed: f32) {
+        *self = match self {
+            Self::Fly(_) => Self::Orbit(OrbitController::new(camera)),
+            Self::Orbit(_) => Self::Fly(FlyController::new(camera, speed)),
+        };
+    }
+
+    pub fn is_dragging(&self, input: &Input) -> bool {
+        match self {
+            Self::Fly(controller) => controller.is_dragging(input),
+            Self::Orbit(controller) => controller.is_dragging(input),
+        }
+    }
+
+    pub fn update(&mut self, input: &Input, camera: &mut Camera, delta: f32) {
+        match self {
+            Self::Fly(controller) => controller.update(input, camera, delta),
+            Self::Orbit(controller) => controller.update(input, camera),
+        }
+    }
+}
+
+/// Grabs and hides the cursor (so it stays put while the mouse moves the
+/// camera), or releases and shows it.
+pub fn grab_cursor(window: &Window, grab: bool) {
+    // Locking the cursor in place is only supported by some platforms (e.g.,
+    // macOS) and confining it to the window by others (e.g., Windows and X11),
+    // so we fall back to the other mode if the first isn't supported.
+    let result = if grab {
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+
+    if let Err(error) = result {
+        warn!(
+            "Failed to {} the cursor: {}",
+            if grab { "grab" } else { "release" },
+            error
+        );
+    }
+
+    window.set_cursor_visible(!grab);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use winit::event::{ElementState, MouseScrollDelta};
+    use winit::keyboard::KeyCode;
+
+    use crate::camera::Projection;
+
+    #[test]
+    fn fly() {
+        let projection = Projection::Orthographic {
+            height: 1.0,
+            near: 0.1,
+            far: 10.0,
+        };
+        let mut camera = Camera::look_at(
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            UP,
+            projection,
+        );
+        let mut controller = FlyController::new(&camera, 2.0);
+        let mut input = Input::default();
+
+        // Forward for half a second.
+        input.key(KeyCode::KeyW, ElementState::Pressed);
+        controller.update(&input, &mut camera, 0.5);
+        assert!(glm::distance(&camera.position, &glm::vec3(1.0, 0.0, 0.0)) < 1e-5);
+
+        // Sprinting.
+        input.key(KeyCode::ShiftLeft, ElementState::Pressed);
+        controller.update(&input, &mut camera, 0.5);
+        assert!(glm::distance(&camera.position, &glm::vec3(5.0, 0.0, 0.0)) < 1e-4);
+
+        // The mouse only turns the camera while the right button is held.
+        input.release_all();
+        input.mouse_motion((100.0, 0.0));
+        controller.update(&input, &mut camera, 0.0);
+        assert!(glm::distance(&camera.forward(), &glm::vec3(1.0, 0.0, 0.0)) < 1e-5);
+    }
+
+    #[test]
+    fn orbit() {
+        let mut camera = Camera::default();
+        let mut controller = OrbitController::new(&camera);
+        assert!(glm::length(&controller.target) < 1e-5);
+
+        // Zooming keeps the camera looking at the target.
+        let distance = controller.distance;
+        let mut input = Input::default();
+        input.mouse_wheel(MouseScrollDelta::LineDelta(0.0, 1.0));
+        controller.update(&input, &mut camera);
+        assert!((glm::length(&camera.position) - distance * 0.9).abs() < 1e-4);
+        assert!(glm::distance(&camera.forward(), &-glm::normalize(&camera.position)) < 1e-5);
+    }
+}
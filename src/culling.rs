@@ -0,0 +1,435 @@
+//! GPU frustum culling, which drives multi-draw indirect rendering (see
+//! `Indirect`) from the GPU.
+//!
+//! The draw commands written on the CPU are accompanied by the world-space
+//! bounding spheres of their draws (see `CullCommand`). Before the main pass,
+//! a compute shader (`cull_draws.comp`) tests each sphere against the planes
+//! of the view frustum and copies the draw commands which survive to the start
+//! of the range of their batch in a second indirect buffer, counting them in a
+//! count buffer. The batches are then drawn with
+//! `cmd_draw_indexed_indirect_count`, which reads the number of draw commands
+//! from the count buffer, so the culled draws never reach the GPU's vertex
+//! stage.
+//!
+//! With occlusion culling (see `HiZ`), the draw commands which are hidden
+//! behind the depth of the previous frame are rejected before they are
+//! tested against the frustum. The number of draw commands culled each way is
+//! read back once the frame is done (see `CullingStats`).
+//!
+//! This requires the `drawIndirectCount` feature (Vulkan 1.2).
+
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::handle::Owned;
+use crate::hiz::cmd_occlusion_cull;
+use crate::indirect::IndirectDraws;
+use crate::mesh::Aabb;
+use crate::pipeline::create_compute_pipeline;
+use crate::shaders;
+use crate::vertex::{Mat4, Vec4};
+
+/// The number of draw commands culled by a workgroup of the compute shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The bounding sphere of a draw command (its center in `xyz` and its radius
+/// in `w`) and the batch it is drawn in.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CullCommand {
+    pub sphere: Vec4,
+    /// The index of the batch.
+    pub batch: u32,
+    /// The index of the first draw command of the batch.
+    pub batch_start: u32,
+    pub _padding: [u32; 2],
+}
+
+/// The number of draw commands culled against the view frustum and by
+/// occlusion culling in a frame.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub frustum_culled: u32,
+    pub occlusion_culled: u32,
+}
+
+/// The push constants of the compute shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CullPushConstants {
+    planes: [Vec4; 6],
+    command_count: u32,
+}
+
+/// The Vulkan handles used to cull the draw commands.
+///
+/// The descriptor sets (one per swapchain image) are recreated with the
+/// swapchain and are updated each frame, since the buffers they bind grow
+/// with the draws.
+#[derive(Debug, Default)]
+pub struct Culling {
+    /// The host-visible buffers the stats of the frames rendered to each
+    /// swapchain image are written to.
+    pub stats: Vec<Buffer>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+}
+
+impl Culling {
+    /// Returns whether the draw commands are culled.
+    pub fn is_enabled(&self) -> bool {
+        !self.pipeline.is_null()
+    }
+}
+
+/// Returns the world-space bounding sphere of a mesh with the supplied
+/// bounds drawn with the supplied model matrix.
+pub fn get_bounding_sphere(bounds: &Aabb, model: &Mat4) -> Vec4 {
+    let center = model * glm::vec4(bounds.center().x, bounds.center().y, bounds.center().z, 1.0);
+    let radius = glm::distance(&bounds.min, &bounds.max) / 2.0;
+
+    // The radius is scaled by the largest scale of the model matrix.
+    let scale = (0..3)
+        .map(|i| glm::length(&model.column(i).xyz()))
+        .fold(0.0, f32::max);
+
+    glm::vec4(center.x, center.y, center.z, radius * scale)
+}
+
+/// Returns the planes of the view frustum of the supplied view-projection
+/// matrix (with depths from 0 to 1), whose normals point inwards.
+///
+/// A point `p` is in front of a plane if `dot(plane.xyz, p) + plane.w` is
+/// positive.
+pub fn get_frustum_planes(view_proj: &Mat4) -> [Vec4; 6] {
+    let row = |i: usize| view_proj.row(i).transpose();
+    let planes = [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ];
+
+    planes.map(|p| p / glm::length(&p.xyz()))
+}
+
+/// Creates the compute pipeline which culls the draw commands (unless they
+/// aren't drawn indirectly or GPU culling is disabled in the options).
+pub unsafe fn create_culling(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.gpu_culling || !data.indirect.is_enabled() {
+        return Ok(());
+    }
+
+    if !data.features.draw_indirect_count {
+        warn!("GPU culling isn't supported by the device.");
+        return Ok(());
+    }
+
+    // Stats
+
+    for _ in 0..data.swapchain.images.len() {
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<CullingStats>() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let stats = CullingStats::default();
+        memcpy(&stats, buffer.allocation.map()?.cast(), 1);
+
+        data.culling.stats.push(buffer);
+    }
+
+    // Descriptor Set Layout
+
+    // The draw commands, the cull commands, the culled draw commands, the
+    // counts, and the stats.
+    let bindings = (0..5)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.culling.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let sets = data.swapchain.images.len() as u32;
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(sets * bindings.len() as u32);
+
+    let pool_sizes = &[storage_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(sets);
+
+    data.culling.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Sets
+
+    let layouts = vec![*data.culling.descriptor_set_layout; sets as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.culling.descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.culling.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // The other buffers are bound each frame (see
+    // `update_culling_descriptor_set`).
+    for (descriptor_set, stats) in data.culling.descriptor_sets.iter().zip(&data.culling.stats) {
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(stats.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+
+        let buffer_info = &[info];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(4)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Pipeline
+
+    (data.culling.pipeline_layout, data.culling.pipeline) = create_compute_pipeline(
+        device,
+        *data.culling.descriptor_set_layout,
+        shaders::CULL_DRAWS_COMP,
+        size_of::<CullPushConstants>(),
+    )?;
+
+    Ok(())
+}
+
+/// Updates the descriptor set for the swapchain image at the supplied index
+/// to bind the supplied draw command, cull command, culled draw command, and
+/// count buffers.
+pub unsafe fn update_culling_descriptor_set(
+    device: &Device,
+    data: &AppData,
+    image_index: usize,
+    buffers: [vk::Buffer; 4],
+) {
+    let buffer_infos = buffers.map(|buffer| {
+        [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64)
+            .build()]
+    });
+
+    let writes = buffer_infos
+        .iter()
+        .enumerate()
+        .map(|(binding, buffer_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.culling.descriptor_sets[image_index])
+                .dst_binding(binding as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(buffer_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+}
+
+/// Returns the stats of the last frame rendered to the swapchain image at the
+/// supplied index (if the draw commands are culled), which are reset in case
+/// the next frame isn't culled.
+///
+/// This must only be called once the last frame rendered to the swapchain
+/// image has completed.
+pub unsafe fn read_culling_stats(
+    data: &AppData,
+    image_index: usize,
+) -> Result<Option<CullingStats>> {
+    let Some(buffer) = data.culling.stats.get(image_index) else {
+        return Ok(None);
+    };
+
+    let memory = buffer.allocation.map()?.cast::<CullingStats>();
+    let stats = memory.read();
+    memory.write(CullingStats::default());
+
+    Ok(Some(stats))
+}
+
+/// Records culling the supplied draw commands against the view frustum of the
+/// supplied view-projection matrix (after rejecting the occluded draw
+/// commands, if occlusion culling is enabled), after which the culled draw
+/// commands and their counts are ready to be drawn.
+pub unsafe fn cmd_cull_draws(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    draws: &IndirectDraws,
+    view_proj: &Mat4,
+) {
+    // Reset
+
+    device.cmd_fill_buffer(command_buffer, draws.counts, 0, vk::WHOLE_SIZE as u64, 0);
+    device.cmd_fill_buffer(
+        command_buffer,
+        data.culling.stats[image_index].buffer,
+        0,
+        vk::WHOLE_SIZE as u64,
+        0,
+    );
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    // Occlusion Culling
+
+    cmd_occlusion_cull(device, data, command_buffer, image_index, draws);
+
+    // Frustum Culling
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.culling.pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.culling.pipeline_layout,
+        0,
+        &[data.culling.descriptor_sets[image_index]],
+        &[],
+    );
+
+    let push_constants = CullPushConstants {
+        planes: get_frustum_planes(view_proj),
+        command_count: draws.command_count,
+    };
+
+    let push_constants = std::slice::from_raw_parts(
+        &push_constants as *const CullPushConstants as *const u8,
+        size_of::<CullPushConstants>(),
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *data.culling.pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants,
+    );
+
+    let workgroups = draws.command_count.div_ceil(WORKGROUP_SIZE);
+    device.cmd_dispatch(command_buffer, workgroups, 1, 1);
+
+    // The culled draw commands and their counts are read by the indirect
+    // draw calls (and the counts by conditional rendering, if it is
+    // supported), and the stats are read by the host once the frame is done.
+    let mut dst_access_mask = vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::HOST_READ;
+    let mut dst_stage_mask = vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::HOST;
+    if data.features.conditional_rendering {
+        dst_access_mask |= vk::AccessFlags::CONDITIONAL_RENDERING_READ_EXT;
+        dst_stage_mask |= vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT;
+    }
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(dst_access_mask);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frustum() {
+        let view = glm::look_at_rh(
+            &glm::vec3(0.0, 0.0, 5.0),
+            &glm::vec3(0.0, 0.0, 0.0),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        let proj = glm::perspective_rh_zo(1.0, glm::half_pi(), 0.1, 10.0);
+        let planes = get_frustum_planes(&(proj * view));
+
+        let visible = |sphere: Vec4| {
+            planes
+                .iter()
+                .all(|p| glm::dot(&p.xyz(), &sphere.xyz()) + p.w >= -sphere.w)
+        };
+
+        let bounds = Aabb {
+            min: glm::vec3(-1.0, -1.0, -1.0),
+            max: glm::vec3(1.0, 1.0, 1.0),
+        };
+
+        // A cube in front of the camera.
+        let sphere = get_bounding_sphere(&bounds, &Mat4::identity());
+        assert!(visible(sphere));
+
+        // The cube moved behind the camera.
+        let model = glm::translate(&Mat4::identity(), &glm::vec3(0.0, 0.0, 8.0));
+        assert!(!visible(get_bounding_sphere(&bounds, &model)));
+
+        // The cube moved off to the side, but scaled up enough to reach into
+        // the frustum.
+        let model = glm::translate(&Mat4::identity(), &glm::vec3(8.0, 0.0, 0.0));
+        assert!(!visible(get_bounding_sphere(&bounds, &model)));
+        let model = glm::scale(&model, &glm::vec3(3.0, 3.0, 3.0));
+        assert!(visible(get_bounding_sphere(&bounds, &model)));
+    }
+}
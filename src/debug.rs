@@ -0,0 +1,277 @@
+//! Debug utils object names and labels which show up in validation messages
+//! and graphics debuggers (e.g., RenderDoc) instead of raw handles.
+
+use std::ffi::CString;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::ExtDebugUtilsExtension;
+
+use crate::app::AppData;
+use crate::memory::get_allocator;
+
+/// The color of the labels around uploads.
+pub const UPLOAD_LABEL_COLOR: [f32; 4] = [0.2, 0.8, 0.4, 1.0];
+
+/// The color of the labels around updating and drawing particles.
+pub const PARTICLES_LABEL_COLOR: [f32; 4] = [0.8, 0.2, 0.6, 1.0];
+
+/// The color of the labels around drawing the UI.
+pub const UI_LABEL_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// The color of the labels around drawing text in the scene.
+pub const TEXT_LABEL_COLOR: [f32; 4] = [0.9, 0.9, 0.3, 1.0];
+
+/// The color of the labels around drawing the environment.
+pub const SKYBOX_LABEL_COLOR: [f32; 4] = [0.3, 0.6, 0.9, 1.0];
+
+/// The color of the labels of the shadow pass.
+pub const SHADOW_LABEL_COLOR: [f32; 4] = [0.4, 0.3, 0.5, 1.0];
+
+/// The color of the labels of the geometry pass of deferred rendering.
+pub const GEOMETRY_LABEL_COLOR: [f32; 4] = [0.7, 0.5, 0.2, 1.0];
+
+/// The color of the labels around shading the G-buffer.
+pub const LIGHTING_LABEL_COLOR: [f32; 4] = [0.9, 0.7, 0.4, 1.0];
+
+/// The color of the labels around culling the lights into clusters.
+pub const CLUSTER_LABEL_COLOR: [f32; 4] = [1.0, 0.9, 0.5, 1.0];
+
+/// The color of the labels of the passes of the post-processing stack.
+pub const POST_PROCESS_LABEL_COLOR: [f32; 4] = [0.9, 0.5, 0.7, 1.0];
+
+/// The color of the labels of the velocity and motion blur passes.
+pub const MOTION_BLUR_LABEL_COLOR: [f32; 4] = [0.6, 0.4, 0.9, 1.0];
+
+/// The color of the labels around culling the draws.
+pub const CULLING_LABEL_COLOR: [f32; 4] = [0.5, 0.9, 0.9, 1.0];
+
+/// The color of the labels around the occlusion queries.
+pub const OCCLUSION_LABEL_COLOR: [f32; 4] = [0.3, 0.7, 0.7, 1.0];
+
+/// Names the images, buffers, pipelines, and semaphores owned by our Vulkan app.
+///
+/// This does nothing if the validation layers (and therefore the debug utils
+/// extension) are not enabled.
+pub unsafe fn set_object_names(instance: &Instance, device: &Device, data: &AppData) -> Result<()> {
+    if !data.options.validation {
+        return Ok(());
+    }
+
+    // Swapchain
+
+    set_object_name(instance, device, data.swapchain.swapchain, "Swapchain")?;
+    for (i, image) in data.swapchain.images.iter().enumerate() {
+        set_object_name(instance, device, *image, &format!("Swapchain Image {}", i))?;
+    }
+
+    // Images
+
+    for (image, label) in [(&data.color_image, "Color"), (&data.depth_image, "Depth")] {
+        set_object_name(instance, device, image.image, &format!("{} Image", label))?;
+    }
+
+    for (_, texture) in data.resources.textures.iter() {
+        set_object_name(
+            instance,
+            device,
+            texture.image.image,
+            &format!("{} Texture Image", texture.name),
+        )?;
+    }
+
+    // Buffers
+
+    for (_, mesh) in data.resources.meshes.iter() {
+        for (buffer, label) in [
+            (&mesh.vertex_buffer, "Vertex Buffer"),
+            (&mesh.index_buffer, "Index Buffer"),
+        ] {
+            set_object_name(
+                instance,
+                device,
+                buffer.buffer,
+                &format!("{} {}", mesh.name, label),
+            )?;
+        }
+    }
+
+    for (i, buffer) in data.uniform_buffers.iter().enumerate() {
+        set_object_name(
+            instance,
+            device,
+            buffer.buffer,
+            &format!("Uniform Buffer {}", i),
+        )?;
+    }
+
+    for (i, buffer) in data.light_buffers.iter().enumerate() {
+        set_object_name(
+            instance,
+            device,
+            buffer.buffer,
+            &format!("Light Buffer {}", i),
+        )?;
+    }
+
+    for (i, buffer) in data.particles.buffers.iter().enumerate() {
+        set_object_name(
+            instance,
+            device,
+            buffer.buffer,
+            &format!("Particle Buffer {}", i),
+        )?;
+    }
+
+    // Memory
+
+    // Buffers and images share blocks of memory (see `Allocator`).
+    for (i, (memory_type_index, memory)) in get_allocator(data)?.blocks().into_iter().enumerate() {
+        set_object_name(
+            instance,
+            device,
+            memory,
+            &format!("Memory Block {} (Type {})", i, memory_type_index),
+        )?;
+    }
+
+    // Pipeline
+
+    set_object_name(instance, device, *data.render_pass, "Render Pass")?;
+    set_object_name(instance, device, *data.pipeline_layout, "Pipeline Layout")?;
+    for (_, material) in data.resources.materials.iter() {
+        set_object_name(
+            instance,
+            device,
+            *material.pipeline,
+            &format!("{} Pipeline", material.info.name),
+        )?;
+
+        if !material.wireframe_pipeline.is_null() {
+            set_object_name(
+                instance,
+                device,
+                *material.wireframe_pipeline,
+                &format!("{} Wireframe Pipeline", material.info.name),
+            )?;
+        }
+    }
+
+    // Semaphores
+
+    for i in 0..data.image_available_semaphores.len() {
+        set_object_name(
+            instance,
+            device,
+            *data.image_available_semaphores[i],
+            &format!("Image Available Semaphore {}", i),
+        )?;
+        set_object_name(
+            instance,
+            device,
+            *data.render_finished_semaphores[i],
+            &format!("Render Finished Semaphore {}", i),
+        )?;
+    }
+
+    set_object_name(
+        instance,
+        device,
+        *data.timeline_semaphore,
+        "Timeline Semaphore",
+    )?;
+
+    for (i, semaphore) in data.particles.semaphores.iter().enumerate() {
+        set_object_name(
+            instance,
+            device,
+            **semaphore,
+            &format!("Particles Updated Semaphore {}", i),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Names a non-dispatchable Vulkan handle.
+unsafe fn set_object_name<H: vk::Handle<Repr = u64>>(
+    instance: &Instance,
+    device: &Device,
+    handle: H,
+    name: &str,
+) -> Result<()> {
+    // Some handles are only created on some rendering paths.
+    if handle.is_null() {
+        return Ok(());
+    }
+
+    let name = CString::new(name)?;
+
+    let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(H::TYPE)
+        .object_handle(handle.as_raw())
+        .object_name(name.as_bytes_with_nul());
+
+    instance.set_debug_utils_object_name_ext(device.handle(), &info)?;
+
+    Ok(())
+}
+
+/// Begins a labeled region of the supplied command buffer.
+///
+/// This does nothing if the validation layers are not enabled, as is the case
+/// for the other label functions.
+pub unsafe fn begin_command_label(
+    instance: &Instance,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    name: &str,
+    color: [f32; 4],
+) -> Result<()> {
+    if data.options.validation {
+        let name = CString::new(name)?;
+        let info = vk::DebugUtilsLabelEXT::builder()
+            .label_name(name.as_bytes_with_nul())
+            .color(color);
+        instance.cmd_begin_debug_utils_label_ext(command_buffer, &info);
+    }
+
+    Ok(())
+}
+
+/// Ends the last labeled region of the supplied command buffer.
+pub unsafe fn end_command_label(
+    instance: &Instance,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+) {
+    if data.options.validation {
+        instance.cmd_end_debug_utils_label_ext(command_buffer);
+    }
+}
+
+/// Begins a labeled region of the work submitted to the supplied queue.
+pub unsafe fn begin_queue_label(
+    instance: &Instance,
+    data: &AppData,
+    queue: vk::Queue,
+    name: &str,
+    color: [f32; 4],
+) -> Result<()> {
+    if data.options.validation {
+        let name = CString::new(name)?;
+        let info = vk::DebugUtilsLabelEXT::builder()
+            .label_name(name.as_bytes_with_nul())
+            .color(color);
+        instance.queue_begin_debug_utils_label_ext(queue, &info);
+    }
+
+    Ok(())
+}
+
+/// Ends the last labeled region of the work submitted to the supplied queue.
+pub unsafe fn end_queue_label(instance: &Instance, data: &AppData, queue: vk::Queue) {
+    if data.options.validation {
+        instance.queue_end_debug_utils_label_ext(queue);
+    }
+}
@@ -0,0 +1,278 @@
+//! Debug views, which draw the scene with a fragment shader
+//! (`debug_view.frag`) that visualizes a property of the surfaces instead of
+//! shading them, for diagnosing rendering problems.
+//!
+//! The debug views are drawn with the pipeline layout of the materials, whose
+//! fragment push constants are followed by the debug view (see
+//! `DEBUG_VIEW_PUSH_CONSTANT_OFFSET`), so the descriptor sets of the materials
+//! (and their textures) are bound as usual. Overdraw is drawn without depth
+//! testing and with additive blending, so it has its own pipeline.
+//!
+//! The debug views are only drawn in the main pass, so they aren't available
+//! with deferred rendering.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::material::MATERIAL_PUSH_CONSTANTS_SIZE;
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::vertex::Vertex;
+
+/// The offset of the debug view in the push constants (after the model
+/// matrix, the vertex buffer address, and the material push constants).
+pub const DEBUG_VIEW_PUSH_CONSTANT_OFFSET: u32 = 72 + MATERIAL_PUSH_CONSTANTS_SIZE as u32;
+
+/// A property of the surfaces of the scene which can be visualized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DebugView {
+    /// The world-space normals (mapped from -1..1 to 0..1).
+    Normals = 1,
+    /// The view-space depth between the near and far planes of the camera.
+    Depth = 2,
+    /// The texture coordinates (wrapped to 0..1).
+    TexCoords = 3,
+    /// The mip level of the base color texture which is sampled.
+    MipLevels = 4,
+    /// How many times each pixel is drawn.
+    Overdraw = 5,
+}
+
+impl DebugView {
+    /// Returns the debug view after the supplied one (or the first debug view
+    /// if there is none), where the last debug view is followed by none.
+    pub fn next(view: Option<Self>) -> Option<Self> {
+        match view {
+            None => Some(Self::Normals),
+            Some(Self::Normals) => Some(Self::Depth),
+            Some(Self::Depth) => Some(Self::TexCoords),
+            Some(Self::TexCoords) => Some(Self::MipLevels),
+            Some(Self::MipLevels) => Some(Self::Overdraw),
+            Some(Self::Overdraw) => None,
+        }
+    }
+}
+
+/// The pipelines which draw the debug views.
+///
+/// The pipelines are recreated with the swapchain.
+#[derive(Debug, Default)]
+pub struct DebugViewPipelines {
+    /// The pipeline which draws every debug view but overdraw.
+    pub pipeline: Owned<vk::Pipeline>,
+    /// The pipeline which draws overdraw.
+    pub overdraw_pipeline: Owned<vk::Pipeline>,
+}
+
+impl DebugViewPipelines {
+    /// Returns the pipeline which draws the supplied debug view.
+    pub fn get(&self, view: DebugView) -> vk::Pipeline {
+        if view == DebugView::Overdraw {
+            *self.overdraw_pipeline
+        } else {
+            *self.pipeline
+        }
+    }
+}
+
+/// Creates the pipelines which draw the debug views (unless the scene is
+/// rendered with deferred rendering).
+pub unsafe fn create_debug_view_pipelines(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if data.options.deferred {
+        return Ok(());
+    }
+
+    data.debug_view.pipeline = Owned::new(
+        device,
+        create_debug_view_pipeline(instance, device, data, false)?,
+    );
+    data.debug_view.overdraw_pipeline = Owned::new(
+        device,
+        create_debug_view_pipeline(instance, device, data, true)?,
+    );
+
+    Ok(())
+}
+
+/// Creates a pipeline which draws the debug views (or overdraw, without depth
+/// testing and with additive blending).
+unsafe fn create_debug_view_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    overdraw: bool,
+) -> Result<vk::Pipeline> {
+    // Stages
+
+    // See `create_material_pipeline`.
+    let vert_shader = if data.features.buffer_device_address {
+        shaders::VERT_BDA
+    } else {
+        shaders::VERT
+    };
+
+    let vert_shader_module = create_shader_module(device, vert_shader)?;
+    let frag_shader_module = create_shader_module(device, shaders::DEBUG_VIEW_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Vertex Input State
+
+    let binding_descriptions = &[Vertex::binding_description()];
+    let attribute_descriptions = Vertex::attribute_descriptions();
+    let mut vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+    if !data.features.buffer_device_address {
+        vertex_input_state = vertex_input_state
+            .vertex_binding_descriptions(binding_descriptions)
+            .vertex_attribute_descriptions(&attribute_descriptions);
+    }
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // Both sides of the triangles are drawn, so overdraw counts the back
+    // faces of double-sided materials too.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    // Overdraw counts every fragment, including the hidden ones.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(!overdraw)
+        .depth_write_enable(!overdraw)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(overdraw)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next() {
+        let mut view = None;
+        let mut views = vec![];
+        loop {
+            view = DebugView::next(view);
+            match view {
+                Some(view) => views.push(view as u32),
+                None => break,
+            }
+        }
+
+        assert_eq!(views, [1, 2, 3, 4, 5]);
+    }
+}
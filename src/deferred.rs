@@ -0,0 +1,588 @@
+//! A deferred rendering path, which draws the materials into a G-buffer in a
+//! geometry pass before the main pass and then shades each pixel of the
+//! G-buffer once in a lighting pass at the start of the main pass (instead of
+//! shading every fragment of every object).
+//!
+//! The lighting pass writes the depth of the G-buffer too, so the skybox,
+//! particles, and text are still drawn over the scene in the main pass. The
+//! G-buffer stores a single surface per pixel, so the objects are drawn
+//! opaque (and without multisampling).
+//!
+//! Like the shadow pass, the geometry pass always uses a render pass.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::commands::DrawBatch;
+use crate::debug::LIGHTING_LABEL_COLOR;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view, Image};
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::swapchain::get_scene_encoding;
+use crate::vertex::Mat4;
+
+/// The formats of the color attachments of the G-buffer: the albedo (sRGB),
+/// the normal (in world space), the material (metallic, roughness, and
+/// occlusion), and the emitted color.
+pub const GBUFFER_FORMATS: [vk::Format; 4] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+/// The format of the depth attachment of the G-buffer (which is sampled to
+/// reconstruct the positions of the surfaces).
+const GBUFFER_DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// The G-buffer and the pipeline which shades it.
+///
+/// The G-buffer is the size of the swapchain images, so it is recreated with
+/// the swapchain.
+#[derive(Debug, Default)]
+pub struct GBuffer {
+    /// The color attachments (see `GBUFFER_FORMATS`).
+    pub attachments: Vec<Image>,
+    pub depth: Image,
+    sampler: Owned<vk::Sampler>,
+    /// The render pass of the geometry pass, which the material pipelines
+    /// are created for.
+    pub render_pass: Owned<vk::RenderPass>,
+    framebuffer: Owned<vk::Framebuffer>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+}
+
+/// Creates the G-buffer, the render pass and framebuffer which render to it,
+/// and the descriptor set which reads it.
+///
+/// This does nothing unless deferred rendering is enabled in the options.
+pub unsafe fn create_gbuffer(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.deferred {
+        return Ok(());
+    }
+
+    let extent = data.swapchain.extent;
+
+    // Images
+
+    for format in GBUFFER_FORMATS {
+        let mut image = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            vk::SampleCountFlags::_1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.view =
+            create_image_view(device, image.image, format, vk::ImageAspectFlags::COLOR, 1)?;
+
+        data.gbuffer.attachments.push(image);
+    }
+
+    let mut depth = create_image(
+        instance,
+        device,
+        data,
+        extent.width,
+        extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        GBUFFER_DEPTH_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    depth.view = create_image_view(
+        device,
+        depth.image,
+        GBUFFER_DEPTH_FORMAT,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+    )?;
+
+    data.gbuffer.depth = depth;
+
+    // Sampler
+
+    // The lighting pass reads the texels of the G-buffer directly.
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.gbuffer.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Render Pass
+
+    let color_attachments = GBUFFER_FORMATS.map(|format| {
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    });
+
+    let depth_attachment = vk::AttachmentDescription::builder()
+        .format(GBUFFER_DEPTH_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let color_attachment_refs = (0..GBUFFER_FORMATS.len() as u32)
+        .map(|attachment| {
+            vk::AttachmentReference::builder()
+                .attachment(attachment)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(GBUFFER_FORMATS.len() as u32)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)
+        .depth_stencil_attachment(&depth_attachment_ref);
+
+    // The previous frame has to be done shading the G-buffer before it is
+    // cleared, and the lighting pass has to wait for it to be rendered.
+    let dependencies = &[
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let attachments = color_attachments
+        .into_iter()
+        .chain([depth_attachment.build()])
+        .collect::<Vec<_>>();
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.gbuffer.render_pass = Owned::new(device, device.create_render_pass(&info, None)?);
+
+    // Framebuffer
+
+    let attachments = data
+        .gbuffer
+        .attachments
+        .iter()
+        .chain([&data.gbuffer.depth])
+        .map(|i| i.view)
+        .collect::<Vec<_>>();
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(*data.gbuffer.render_pass)
+        .attachments(&attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+
+    data.gbuffer.framebuffer = Owned::new(device, device.create_framebuffer(&info, None)?);
+
+    // Descriptor Set Layout
+
+    // The color attachments followed by the depth attachment.
+    let bindings = (0..=GBUFFER_FORMATS.len() as u32)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.gbuffer.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(bindings.len() as u32);
+
+    let pool_sizes = &[sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.gbuffer.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Set
+
+    let layouts = &[*data.gbuffer.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.gbuffer.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.gbuffer.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let image_infos = attachments
+        .iter()
+        .map(|v| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(*v)
+                .sampler(*data.gbuffer.sampler)
+                .build()]
+        })
+        .collect::<Vec<_>>();
+
+    let writes = image_infos
+        .iter()
+        .enumerate()
+        .map(|(i, image_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.gbuffer.descriptor_set)
+                .dst_binding(i as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/// Creates the pipeline which shades the G-buffer in the main pass.
+///
+/// This does nothing unless deferred rendering is enabled in the options.
+pub unsafe fn create_lighting_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.deferred {
+        return Ok(());
+    }
+
+    // Layout
+
+    // The uniform buffer, lights, and shadow maps are bound with the
+    // descriptor set of a material (which all share them), followed by the
+    // image-based lighting and the G-buffer.
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<Mat4>() as u32);
+
+    let set_layouts = &[
+        *data.descriptor_set_layout,
+        *data.ibl.descriptor_set_layout,
+        *data.gbuffer.descriptor_set_layout,
+    ];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.gbuffer.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::LIGHTING_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::LIGHTING_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The scene is encoded like it is by the materials.
+    let (encode_srgb, output_transfer) = get_scene_encoding(data);
+
+    let specialization_data = [encode_srgb as vk::Bool32, output_transfer as u32]
+        .map(u32::to_ne_bytes)
+        .concat();
+    let map_entries = &[
+        vk::SpecializationMapEntry::builder()
+            .constant_id(0)
+            .offset(0)
+            .size(size_of::<vk::Bool32>()),
+        vk::SpecializationMapEntry::builder()
+            .constant_id(1)
+            .offset(4)
+            .size(size_of::<u32>()),
+    ];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&specialization_data);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Vertex Input State
+
+    // The vertices of the triangle are generated by the vertex shader.
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    // The depth of the G-buffer is copied to the depth attachment.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::ALWAYS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.gbuffer.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.gbuffer.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Begins the geometry pass, which renders the materials into the G-buffer
+/// (see `create_material_pipeline`) after clearing it.
+pub unsafe fn cmd_begin_geometry_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+) {
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 0.0],
+        },
+    };
+
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    };
+
+    let clear_values = [color_clear_value; GBUFFER_FORMATS.len()]
+        .into_iter()
+        .chain([depth_clear_value])
+        .collect::<Vec<_>>();
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain.extent);
+
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.gbuffer.render_pass)
+        .framebuffer(*data.gbuffer.framebuffer)
+        .render_area(render_area)
+        .clear_values(&clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+}
+
+/// Returns a draw batch which shades the G-buffer with the supplied view and
+/// projection matrices for the swapchain image at the supplied index, if
+/// deferred rendering is enabled.
+pub fn get_lighting_batch(
+    data: &AppData,
+    image_index: usize,
+    view: &Mat4,
+    proj: &Mat4,
+) -> Option<DrawBatch<'static>> {
+    let pipeline = *data.gbuffer.pipeline;
+    if pipeline.is_null() {
+        return None;
+    }
+
+    let (_, material) = data.resources.materials.iter().next()?;
+    let descriptor_sets = [
+        material.descriptor_sets[image_index],
+        data.ibl.descriptor_set,
+        data.gbuffer.descriptor_set,
+    ];
+
+    let pipeline_layout = *data.gbuffer.pipeline_layout;
+    let inv_view_proj = glm::inverse(&(proj * view));
+
+    Some(DrawBatch {
+        name: "Lighting",
+        color: LIGHTING_LABEL_COLOR,
+        record: Box::new(move |device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &descriptor_sets,
+                &[],
+            );
+            let push_constants = std::slice::from_raw_parts(
+                &inv_view_proj as *const Mat4 as *const u8,
+                size_of::<Mat4>(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constants,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }),
+    })
+}
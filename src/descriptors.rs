@@ -0,0 +1,200 @@
+//! Descriptor pools and the descriptor sets allocated from them.
+
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::UniformBufferObject;
+use crate::clusters::get_cluster_buffer_size;
+use crate::handle::Owned;
+use crate::light::LightBufferObject;
+use crate::resources::MaterialHandle;
+use crate::shadows::MAX_POINT_SHADOWS;
+
+pub unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
+    // Each material has a descriptor set for each swapchain image.
+    let sets = (data.swapchain.images.len() * data.resources.materials.len()) as u32;
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(sets);
+
+    // Each descriptor set has the textures of its material (see
+    // `MaterialTextures`) and the shadow maps.
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(sets * (7 + MAX_POINT_SHADOWS as u32));
+
+    // Each descriptor set has the light buffer and the cluster buffer.
+    let light_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(sets * 2);
+
+    let pool_sizes = &[ubo_size, sampler_size, light_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(sets);
+
+    data.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    Ok(())
+}
+
+pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
+    let materials = data
+        .resources
+        .materials
+        .iter()
+        .map(|(h, _)| h)
+        .collect::<Vec<_>>();
+    for material in materials {
+        create_material_descriptor_sets(device, data, material)?;
+    }
+
+    Ok(())
+}
+
+unsafe fn create_material_descriptor_sets(
+    device: &Device,
+    data: &mut AppData,
+    material: MaterialHandle,
+) -> Result<()> {
+    let texture_views = data
+        .resources
+        .material(material)
+        .ok_or_else(|| anyhow!("Material has been removed."))?
+        .textures
+        .handles()
+        .map(|h| data.resources.texture(h).map(|t| t.image.view));
+    let texture_views = texture_views
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("Material uses a texture which has been removed."))?;
+
+    // Allocate
+
+    let layouts = vec![*data.descriptor_set_layout; data.swapchain.images.len()];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.descriptor_pool)
+        .set_layouts(&layouts);
+
+    let descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // Update
+
+    for (i, descriptor_set) in descriptor_sets.iter().enumerate() {
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.uniform_buffers[i].buffer)
+            .offset(0)
+            .range(size_of::<UniformBufferObject>() as u64);
+
+        let buffer_info = &[info];
+        let ubo_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.light_buffers[i].buffer)
+            .offset(0)
+            .range(size_of::<LightBufferObject>() as u64);
+
+        let light_buffer_info = &[info];
+        let light_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(7)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(light_buffer_info);
+
+        let image_infos = texture_views
+            .iter()
+            .map(|v| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(*v)
+                    .sampler(*data.texture_sampler)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let sampler_writes = image_infos.iter().enumerate().map(|(i, image_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(1 + i as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+        });
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.shadow_map.image.view)
+            .sampler(*data.shadow_map.sampler);
+
+        let shadow_image_info = &[info];
+        let shadow_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(8)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(shadow_image_info);
+
+        let point_shadow_image_info = data
+            .shadow_map
+            .point_shadows
+            .iter()
+            .map(|s| {
+                vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(s.image.view)
+                    .sampler(*data.shadow_map.sampler)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let point_shadow_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(9)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&point_shadow_image_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.clusters.buffers[i].buffer)
+            .offset(0)
+            .range(get_cluster_buffer_size() as u64);
+
+        let cluster_buffer_info = &[info];
+        let cluster_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(10)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(cluster_buffer_info);
+
+        let writes = [
+            ubo_write.build(),
+            light_write.build(),
+            shadow_write.build(),
+            point_shadow_write.build(),
+            cluster_write.build(),
+        ]
+        .into_iter()
+        .chain(sampler_writes)
+        .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    if let Some(material) = data.resources.materials.get_mut(material) {
+        material.descriptor_sets = descriptor_sets;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,656 @@
+//! Physical device selection and logical device creation.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use thiserror::Error;
+use vulkanalia::prelude::v1_2::*;
+use vulkanalia::vk::KhrSurfaceExtension;
+
+use crate::app::AppData;
+use crate::instance::{PORTABILITY_MACOS_VERSION, VALIDATION_LAYER};
+use crate::swapchain::SwapchainSupport;
+
+/// The required device extensions.
+const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct SuitabilityError(pub &'static str);
+
+/// How our Vulkan app begins and ends rendering.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenderingPath {
+    /// A render pass and a framebuffer per swapchain image (Vulkan 1.0).
+    #[default]
+    RenderPass,
+    /// Dynamic rendering (Vulkan 1.3).
+    DynamicRendering,
+    /// Dynamic rendering provided by `VK_KHR_dynamic_rendering`.
+    DynamicRenderingKhr,
+}
+
+/// The Vulkan version and the optional features enabled for the logical
+/// device.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Features {
+    /// The older of the instance and physical device Vulkan versions.
+    pub api_version: u32,
+    // Vulkan 1.0
+    pub sampler_anisotropy: bool,
+    pub texture_compression_bc: bool,
+    pub texture_compression_astc_ldr: bool,
+    pub texture_compression_etc2: bool,
+    pub fill_mode_non_solid: bool,
+    pub multi_draw_indirect: bool,
+    pub draw_indirect_first_instance: bool,
+    // Vulkan 1.1
+    pub shader_draw_parameters: bool,
+    // Vulkan 1.2
+    pub timeline_semaphore: bool,
+    pub buffer_device_address: bool,
+    pub draw_indirect_count: bool,
+    // Vulkan 1.3 (or `VK_KHR_dynamic_rendering`)
+    pub dynamic_rendering: bool,
+    // `VK_EXT_conditional_rendering`
+    pub conditional_rendering: bool,
+}
+
+/// Selects a physical device by its index in the enumerated physical devices
+/// or by a substring of its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl GpuSelector {
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(n) => name.contains(n.as_str()),
+        }
+    }
+}
+
+impl FromStr for GpuSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(s.into()),
+        })
+    }
+}
+
+impl fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{}", index),
+            Self::Name(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
+    // Score
+
+    let mut candidates = vec![];
+
+    for (index, physical_device) in instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .enumerate()
+    {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let name = properties.device_name.to_string();
+
+        if let Err(error) = check_physical_device(instance, data, physical_device) {
+            warn!("Skipping physical device (`{}`): {}", name, error);
+        } else {
+            let score = score_physical_device(instance, data, physical_device);
+            candidates.push((score, index, physical_device, name));
+        }
+    }
+
+    // Stable, so equally scored physical devices keep their enumeration order.
+    candidates.sort_by_key(|c| Reverse(c.0));
+
+    for (rank, (score, _, _, name)) in candidates.iter().enumerate() {
+        info!(
+            "Physical device #{} (`{}`): score {}.",
+            rank + 1,
+            name,
+            score
+        );
+    }
+
+    // Select
+
+    let (_, _, physical_device, name) = if let Some(gpu) = &data.options.gpu {
+        candidates
+            .iter()
+            .find(|(_, i, _, n)| gpu.matches(*i, n))
+            .ok_or_else(|| anyhow!("No suitable physical device matches {}.", gpu))?
+    } else {
+        candidates
+            .first()
+            .ok_or_else(|| anyhow!("Failed to find suitable physical device."))?
+    };
+
+    info!("Selected physical device (`{}`).", name);
+    data.physical_device = *physical_device;
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let software = properties.device_type == vk::PhysicalDeviceType::CPU;
+
+    if data.options.software && !software {
+        warn!("No software rasterizer is available, using a hardware device instead.");
+    }
+
+    // Multisampling is too slow to be worthwhile on a software rasterizer.
+    data.msaa_samples = if software {
+        vk::SampleCountFlags::_1
+    } else {
+        get_max_msaa_samples(instance, data.physical_device)
+    };
+    data.features = get_features(instance, data, data.physical_device);
+    data.rendering_path = get_rendering_path(instance, data, data.physical_device)?;
+    data.features.dynamic_rendering = data.rendering_path != RenderingPath::RenderPass;
+
+    info!(
+        "Using Vulkan {} with {:?}.",
+        vulkanalia::Version::from(data.features.api_version),
+        data.features
+    );
+    info!("Rendering with {:?}.", data.rendering_path);
+    info!(
+        "Synchronizing frames with {}.",
+        if data.features.timeline_semaphore {
+            "a timeline semaphore"
+        } else {
+            "fences"
+        }
+    );
+
+    Ok(())
+}
+
+/// Prints the enumerated physical devices and their properties.
+pub unsafe fn list_physical_devices(instance: &Instance, data: &AppData) -> Result<()> {
+    for (index, physical_device) in instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .enumerate()
+    {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let memory = instance.get_physical_device_memory_properties(physical_device);
+
+        let device_local = memory.memory_heaps[..memory.memory_heap_count as usize]
+            .iter()
+            .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|h| h.size)
+            .sum::<u64>();
+
+        let suitability = match check_physical_device(instance, data, physical_device) {
+            Ok(_) => format!(
+                "suitable (score {})",
+                score_physical_device(instance, data, physical_device)
+            ),
+            Err(error) => format!("unsuitable ({})", error),
+        };
+
+        println!("{}: {}", index, properties.device_name);
+        println!("    Type: {:?}", properties.device_type);
+        println!(
+            "    API version: {}",
+            vulkanalia::Version::from(properties.api_version)
+        );
+        println!("    Driver version: {:#x}", properties.driver_version);
+        println!(
+            "    Vendor / device ID: {:#06x} / {:#06x}",
+            properties.vendor_id, properties.device_id
+        );
+        println!("    Device-local memory: {} MiB", device_local >> 20);
+        println!("    {}", suitability);
+    }
+
+    Ok(())
+}
+
+/// Scores a suitable physical device, where higher scores are preferred.
+unsafe fn score_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> u64 {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let features = instance.get_physical_device_features(physical_device);
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+
+    // Type
+
+    let mut score = match properties.device_type {
+        // Software rasterizers (e.g., SwiftShader or llvmpipe) outrank
+        // everything else when they are preferred.
+        vk::PhysicalDeviceType::CPU if data.options.software => 1_000_000,
+        vk::PhysicalDeviceType::DISCRETE_GPU => 100_000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 10_000,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 1_000,
+        _ => 0,
+    };
+
+    // Limits
+
+    score += properties.limits.max_image_dimension_2d as u64 / 16;
+    score += get_max_msaa_samples(instance, physical_device).bits() as u64 * 10;
+
+    // Device-local memory (in GiB)
+
+    score += memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size >> 30)
+        .sum::<u64>()
+        * 100;
+
+    // Optional Features
+
+    if features.sample_rate_shading == vk::TRUE {
+        score += 100;
+    }
+
+    if features.fill_mode_non_solid == vk::TRUE {
+        score += 100;
+    }
+
+    score
+}
+
+/// Returns the optional features supported by a physical device.
+unsafe fn get_features(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Features {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let api_version = properties.api_version.min(data.api_version);
+
+    let mut vulkan_11_features = vk::PhysicalDeviceVulkan11Features::builder();
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder();
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder();
+
+    // The structures for a Vulkan version can only be chained if it is
+    // supported (and those for Vulkan 1.1 were added in Vulkan 1.2).
+    let mut features = vk::PhysicalDeviceFeatures2::builder();
+    if api_version >= vk::make_version(1, 2, 0) {
+        features = features
+            .push_next(&mut vulkan_11_features)
+            .push_next(&mut vulkan_12_features);
+    }
+    if api_version >= vk::make_version(1, 3, 0) {
+        features = features.push_next(&mut vulkan_13_features);
+    }
+
+    // The features of an extension can only be chained if it is supported.
+    let conditional_rendering_extension = instance
+        .enumerate_device_extension_properties(physical_device, None)
+        .unwrap_or_default()
+        .iter()
+        .any(|e| e.extension_name == vk::EXT_CONDITIONAL_RENDERING_EXTENSION.name);
+
+    let mut conditional_rendering_features =
+        vk::PhysicalDeviceConditionalRenderingFeaturesEXT::builder();
+    if conditional_rendering_extension {
+        features = features.push_next(&mut conditional_rendering_features);
+    }
+
+    let features = if api_version >= vk::make_version(1, 1, 0) {
+        instance.get_physical_device_features2(physical_device, &mut features);
+        features.features
+    } else {
+        instance.get_physical_device_features(physical_device)
+    };
+
+    Features {
+        api_version,
+        sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+        texture_compression_bc: features.texture_compression_bc == vk::TRUE,
+        texture_compression_astc_ldr: features.texture_compression_astc_ldr == vk::TRUE,
+        texture_compression_etc2: features.texture_compression_etc2 == vk::TRUE,
+        fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+        multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+        draw_indirect_first_instance: features.draw_indirect_first_instance == vk::TRUE,
+        shader_draw_parameters: vulkan_11_features.shader_draw_parameters == vk::TRUE,
+        timeline_semaphore: vulkan_12_features.timeline_semaphore == vk::TRUE,
+        buffer_device_address: vulkan_12_features.buffer_device_address == vk::TRUE,
+        draw_indirect_count: vulkan_12_features.draw_indirect_count == vk::TRUE,
+        dynamic_rendering: vulkan_13_features.dynamic_rendering == vk::TRUE,
+        conditional_rendering: conditional_rendering_features.conditional_rendering == vk::TRUE,
+    }
+}
+
+/// Returns the newest way of rendering supported by a physical device (unless
+/// dynamic rendering is disabled).
+unsafe fn get_rendering_path(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Result<RenderingPath> {
+    if !data.options.dynamic_rendering {
+        return Ok(RenderingPath::RenderPass);
+    }
+
+    if data.features.dynamic_rendering {
+        return Ok(RenderingPath::DynamicRendering);
+    }
+
+    // The extension depends on extensions which are core in Vulkan 1.2.
+    if data.features.api_version < vk::make_version(1, 2, 0) {
+        return Ok(RenderingPath::RenderPass);
+    }
+
+    let extension = instance
+        .enumerate_device_extension_properties(physical_device, None)?
+        .iter()
+        .any(|e| e.extension_name == vk::KHR_DYNAMIC_RENDERING_EXTENSION.name);
+
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder();
+    let mut features =
+        vk::PhysicalDeviceFeatures2::builder().push_next(&mut dynamic_rendering_features);
+    if extension {
+        instance.get_physical_device_features2(physical_device, &mut features);
+    }
+
+    if extension && dynamic_rendering_features.dynamic_rendering == vk::TRUE {
+        Ok(RenderingPath::DynamicRenderingKhr)
+    } else {
+        Ok(RenderingPath::RenderPass)
+    }
+}
+
+unsafe fn get_max_msaa_samples(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::SampleCountFlags {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let counts = properties.limits.framebuffer_color_sample_counts
+        & properties.limits.framebuffer_depth_sample_counts;
+    [
+        vk::SampleCountFlags::_64,
+        vk::SampleCountFlags::_32,
+        vk::SampleCountFlags::_16,
+        vk::SampleCountFlags::_8,
+        vk::SampleCountFlags::_4,
+        vk::SampleCountFlags::_2,
+    ]
+    .iter()
+    .cloned()
+    .find(|c| counts.contains(*c))
+    .unwrap_or(vk::SampleCountFlags::_1)
+}
+
+unsafe fn check_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Result<()> {
+    QueueFamilyIndices::get(instance, data, physical_device)?;
+    check_physical_device_extensions(instance, physical_device)?;
+
+    // There is no surface to check when rendering offscreen.
+    if !data.surface.is_null() {
+        let support = SwapchainSupport::get(instance, data, physical_device)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
+        }
+    }
+
+    // Anisotropic filtering is optional for software rasterizers, which
+    // don't necessarily support it.
+    let features = instance.get_physical_device_features(physical_device);
+    if features.sampler_anisotropy != vk::TRUE && !data.options.software {
+        return Err(anyhow!(SuitabilityError("No sampler anisotropy.")));
+    }
+
+    Ok(())
+}
+
+unsafe fn check_physical_device_extensions(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Result<()> {
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device, None)?
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+    if DEVICE_EXTENSIONS.iter().all(|e| extensions.contains(e)) {
+        Ok(())
+    } else {
+        Err(anyhow!(SuitabilityError(
+            "Missing required device extensions."
+        )))
+    }
+}
+
+/// A logical device which is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedDevice {
+    device: Device,
+}
+
+impl Deref for OwnedDevice {
+    type Target = Device;
+
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
+impl Drop for OwnedDevice {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_device(None) };
+    }
+}
+
+pub unsafe fn create_logical_device(
+    entry: &Entry,
+    instance: &Instance,
+    data: &mut AppData,
+) -> Result<OwnedDevice> {
+    // Queue Create Infos
+
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let mut unique_indices = HashSet::new();
+    unique_indices.insert(indices.graphics);
+    unique_indices.insert(indices.present);
+    unique_indices.insert(indices.transfer);
+    unique_indices.insert(indices.compute);
+
+    let queue_priorities = &[1.0];
+    let queue_infos = unique_indices
+        .iter()
+        .map(|i| {
+            vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(*i)
+                .queue_priorities(queue_priorities)
+        })
+        .collect::<Vec<_>>();
+
+    // Layers
+
+    let layers = if data.options.validation {
+        vec![VALIDATION_LAYER.as_ptr()]
+    } else {
+        vec![]
+    };
+
+    // Extensions
+
+    let mut extensions = DEVICE_EXTENSIONS
+        .iter()
+        .map(|n| n.as_ptr())
+        .collect::<Vec<_>>();
+
+    // Required by Vulkan SDK on macOS since 1.3.216.
+    if cfg!(target_os = "macos") && entry.version()? >= PORTABILITY_MACOS_VERSION {
+        extensions.push(vk::KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
+    }
+
+    if data.rendering_path == RenderingPath::DynamicRenderingKhr {
+        extensions.push(vk::KHR_DYNAMIC_RENDERING_EXTENSION.name.as_ptr());
+    }
+
+    if data.features.conditional_rendering {
+        extensions.push(vk::EXT_CONDITIONAL_RENDERING_EXTENSION.name.as_ptr());
+    }
+
+    // Features
+
+    let enabled = data.features;
+
+    let features = vk::PhysicalDeviceFeatures::builder()
+        .sampler_anisotropy(enabled.sampler_anisotropy)
+        .texture_compression_bc(enabled.texture_compression_bc)
+        .texture_compression_astc_ldr(enabled.texture_compression_astc_ldr)
+        .texture_compression_etc2(enabled.texture_compression_etc2)
+        .fill_mode_non_solid(enabled.fill_mode_non_solid)
+        .multi_draw_indirect(enabled.multi_draw_indirect)
+        .draw_indirect_first_instance(enabled.draw_indirect_first_instance);
+    let mut vulkan_11_features = vk::PhysicalDeviceVulkan11Features::builder()
+        .shader_draw_parameters(enabled.shader_draw_parameters);
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .timeline_semaphore(enabled.timeline_semaphore)
+        .buffer_device_address(enabled.buffer_device_address)
+        .draw_indirect_count(enabled.draw_indirect_count);
+    let mut vulkan_13_features =
+        vk::PhysicalDeviceVulkan13Features::builder().dynamic_rendering(enabled.dynamic_rendering);
+
+    let mut dynamic_rendering_features =
+        vk::PhysicalDeviceDynamicRenderingFeatures::builder().dynamic_rendering(true);
+
+    let mut conditional_rendering_features =
+        vk::PhysicalDeviceConditionalRenderingFeaturesEXT::builder().conditional_rendering(true);
+
+    // Create
+
+    let mut info = vk::DeviceCreateInfo::builder()
+        .queue_create_infos(&queue_infos)
+        .enabled_layer_names(&layers)
+        .enabled_extension_names(&extensions)
+        .enabled_features(&features);
+
+    // See `get_features`.
+    if enabled.api_version >= vk::make_version(1, 2, 0) {
+        info = info
+            .push_next(&mut vulkan_11_features)
+            .push_next(&mut vulkan_12_features);
+    }
+
+    if enabled.api_version >= vk::make_version(1, 3, 0) {
+        info = info.push_next(&mut vulkan_13_features);
+    }
+
+    if data.rendering_path == RenderingPath::DynamicRenderingKhr {
+        info = info.push_next(&mut dynamic_rendering_features);
+    }
+
+    if enabled.conditional_rendering {
+        info = info.push_next(&mut conditional_rendering_features);
+    }
+
+    let device = instance.create_device(data.physical_device, &info, None)?;
+
+    // Queues
+
+    data.graphics_queue = device.get_device_queue(indices.graphics, 0);
+    data.present_queue = device.get_device_queue(indices.present, 0);
+    data.transfer_queue = device.get_device_queue(indices.transfer, 0);
+    data.compute_queue = device.get_device_queue(indices.compute, 0);
+
+    Ok(OwnedDevice { device })
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct QueueFamilyIndices {
+    pub graphics: u32,
+    pub present: u32,
+    /// A queue family which only supports transfers if there is one, which is
+    /// the graphics queue family otherwise.
+    pub transfer: u32,
+    /// A queue family which supports compute but not graphics if there is
+    /// one (so compute work runs concurrently with rendering), which is the
+    /// graphics queue family otherwise.
+    pub compute: u32,
+}
+
+impl QueueFamilyIndices {
+    pub unsafe fn get(
+        instance: &Instance,
+        data: &AppData,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Self> {
+        let properties = instance.get_physical_device_queue_family_properties(physical_device);
+
+        let graphics = properties
+            .iter()
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|i| i as u32);
+
+        let transfer = properties
+            .iter()
+            .position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !p
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|i| i as u32);
+
+        let compute = properties
+            .iter()
+            .position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|i| i as u32);
+
+        // Nothing is presented when rendering offscreen, so the graphics queue
+        // stands in for the present queue.
+        let mut present = graphics.filter(|_| data.surface.is_null());
+        if present.is_none() {
+            for (index, properties) in properties.iter().enumerate() {
+                if instance.get_physical_device_surface_support_khr(
+                    physical_device,
+                    index as u32,
+                    data.surface,
+                )? {
+                    present = Some(index as u32);
+                    break;
+                }
+            }
+        }
+
+        if let (Some(graphics), Some(present)) = (graphics, present) {
+            Ok(Self {
+                graphics,
+                present,
+                transfer: transfer.unwrap_or(graphics),
+                compute: compute.unwrap_or(graphics),
+            })
+        } else {
+            Err(anyhow!(SuitabilityError(
+                "Missing required queue families."
+            )))
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Switching the window between windowed and fullscreen modes, and selecting
+//! the monitor and video mode used for fullscreen.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::*;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::monitor::{MonitorHandle, VideoModeHandle};
+use winit::window::{Fullscreen, Window};
+
+/// How the window is displayed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DisplayMode {
+    #[default]
+    Windowed,
+    /// A borderless window covering the monitor, which doesn't change the
+    /// video mode of the monitor.
+    Borderless,
+    /// Exclusive control of the monitor, in a video mode of our choosing.
+    Exclusive,
+}
+
+impl DisplayMode {
+    /// Returns the mode after this one when cycling through the modes.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Windowed => Self::Borderless,
+            Self::Borderless => Self::Exclusive,
+            Self::Exclusive => Self::Windowed,
+        }
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Windowed => write!(f, "windowed"),
+            Self::Borderless => write!(f, "borderless fullscreen"),
+            Self::Exclusive => write!(f, "exclusive fullscreen"),
+        }
+    }
+}
+
+/// Selects a monitor by its index in the available monitors (see
+/// `--list-monitors`) or by a substring of its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MonitorSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl MonitorSelector {
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(n) => name.contains(n.as_str()),
+        }
+    }
+}
+
+impl FromStr for MonitorSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(s.into()),
+        })
+    }
+}
+
+impl fmt::Display for MonitorSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{}", index),
+            Self::Name(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+/// Selects a video mode by its resolution and, optionally, its refresh rate
+/// (e.g., `1920x1080` or `1920x1080@144`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VideoModeSelector {
+    pub width: u32,
+    pub height: u32,
+    /// The refresh rate (in hertz, rounded to the nearest integer).
+    pub refresh_rate: Option<u32>,
+}
+
+impl VideoModeSelector {
+    fn matches(&self, mode: &VideoModeHandle) -> bool {
+        let size = mode.size();
+        let refresh_rate = (mode.refresh_rate_millihertz() + 500) / 1000;
+        (size.width, size.height) == (self.width, self.height)
+            && self.refresh_rate.is_none_or(|r| r == refresh_rate)
+    }
+}
+
+impl FromStr for VideoModeSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (size, refresh_rate) = match s.split_once('@') {
+            Some((size, refresh_rate)) => (size, Some(refresh_rate.parse()?)),
+            None => (s, None),
+        };
+
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| anyhow!("Expected `<WIDTH>x<HEIGHT>[@<HZ>]`."))?;
+
+        Ok(Self {
+            width: width.parse()?,
+            height: height.parse()?,
+            refresh_rate,
+        })
+    }
+}
+
+impl fmt::Display for VideoModeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(refresh_rate) = self.refresh_rate {
+            write!(f, "@{}", refresh_rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// Switches a window between display modes, remembering the position and
+/// size of the window while it is windowed so they can be restored.
+#[derive(Clone, Debug, Default)]
+pub struct Display {
+    mode: DisplayMode,
+    windowed: Option<(Option<PhysicalPosition<i32>>, PhysicalSize<u32>)>,
+    /// The monitor and video mode used for fullscreen, which default to the
+    /// monitor the window is on and its native resolution.
+    monitor: Option<MonitorSelector>,
+    video_mode: Option<VideoModeSelector>,
+}
+
+impl Display {
+    pub fn new(monitor: Option<MonitorSelector>, video_mode: Option<VideoModeSelector>) -> Self {
+        Self {
+            monitor,
+            video_mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    /// Switches the supplied window to the next display mode.
+    pub fn toggle(&mut self, window: &Window) {
+        self.set_mode(window, self.mode.next());
+    }
+
+    /// Switches the supplied window to a display mode.
+    ///
+    /// The swapchain must be recreated afterwards, since not every platform
+    /// reports the change in size of the window (or makes the swapchain out
+    /// of date).
+    pub fn set_mode(&mut self, window: &Window, mode: DisplayMode) {
+        if self.mode == DisplayMode::Windowed {
+            self.windowed = Some((window.outer_position().ok(), window.inner_size()));
+        }
+
+        self.mode = match mode {
+            DisplayMode::Windowed => {
+                window.set_fullscreen(None);
+                if let Some((position, size)) = self.windowed {
+                    if let Some(position) = position {
+                        window.set_outer_position(position);
+                    }
+                    let _ = window.request_inner_size(size);
+                }
+                mode
+            }
+            DisplayMode::Borderless => {
+                window.set_fullscreen(Some(Fullscreen::Borderless(self.get_monitor(window))));
+                mode
+            }
+            DisplayMode::Exclusive => match self.get_video_mode(window) {
+                Some(video_mode) => {
+                    window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode)));
+                    mode
+                }
+                None => {
+                    warn!("No video mode is available, using borderless fullscreen instead.");
+                    window.set_fullscreen(Some(Fullscreen::Borderless(self.get_monitor(window))));
+                    DisplayMode::Borderless
+                }
+            },
+        };
+    }
+
+    /// Returns the selected monitor, or the monitor the window is on (or the
+    /// primary monitor) if there isn't one.
+    fn get_monitor(&self, window: &Window) -> Option<MonitorHandle> {
+        if let Some(selector) = &self.monitor {
+            let monitor = window
+                .available_monitors()
+                .enumerate()
+                .find(|(i, m)| selector.matches(*i, &m.name().unwrap_or_default()))
+                .map(|(_, m)| m);
+            if monitor.is_some() {
+                return monitor;
+            }
+
+            warn!(
+                "No monitor matches {}, using the current monitor instead.",
+                selector
+            );
+        }
+
+        window
+            .current_monitor()
+            .or_else(|| window.primary_monitor())
+    }
+
+    /// Returns the selected video mode of the monitor, or the video mode with
+    /// the native resolution of the monitor and the highest bit depth and
+    /// refresh rate if there isn't one.
+    fn get_video_mode(&self, window: &Window) -> Option<VideoModeHandle> {
+        let monitor = self.get_monitor(window)?;
+        let best = |m: &VideoModeHandle| (m.bit_depth(), m.refresh_rate_millihertz());
+
+        if let Some(selector) = &self.video_mode {
+            let video_mode = monitor
+                .video_modes()
+                .filter(|m| selector.matches(m))
+                .max_by_key(best);
+            if video_mode.is_some() {
+                return video_mode;
+            }
+
+            warn!(
+                "No video mode matches `{}`, using the native resolution instead.",
+                selector
+            );
+        }
+
+        let size = monitor.size();
+        monitor
+            .video_modes()
+            .filter(|m| m.size() == size)
+            .max_by_key(best)
+            .or_else(|| monitor.video_modes().next())
+    }
+}
+
+/// Prints the available monitors and their video modes.
+pub fn list_monitors(window: &Window) -> Result<()> {
+    for (index, monitor) in window.available_monitors().enumerate() {
+        let size = monitor.size();
+        println!(
+            "{}: {}",
+            index,
+            monitor.name().unwrap_or_else(|| "Unknown".into())
+        );
+        println!("    Resolution: {}x{}", size.width, size.height);
+        if let Some(refresh_rate) = monitor.refresh_rate_millihertz() {
+            println!("    Refresh rate: {:.2} Hz", refresh_rate as f32 / 1000.0);
+        }
+        println!("    Scale factor: {}", monitor.scale_factor());
+        println!("    Video modes:");
+        for video_mode in monitor.video_modes() {
+            let size = video_mode.size();
+            println!(
+                "      {}x{} @ {:.2} Hz ({}-bit)",
+                size.width,
+                size.height,
+                video_mode.refresh_rate_millihertz() as f32 / 1000.0,
+                video_mode.bit_depth(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_mode_selectors() {
+        let selector = "1920x1080@144".parse::<VideoModeSelector>().unwrap();
+        assert_eq!(
+            selector,
+            VideoModeSelector {
+                width: 1920,
+                height: 1080,
+                refresh_rate: Some(144),
+            }
+        );
+        assert_eq!(selector.to_string(), "1920x1080@144");
+
+        assert_eq!(
+            "800x600".parse::<VideoModeSelector>().unwrap().refresh_rate,
+            None
+        );
+        assert!("800".parse::<VideoModeSelector>().is_err());
+        assert!("800x600@".parse::<VideoModeSelector>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Components and systems for driving the renderer from an ECS (`hecs`), which
+//! are enabled by the `ecs` feature.
+//!
+//! When the feature is enabled, the world in `AppData` replaces the scene
+//! graph: every frame the systems update the entities and then extract the
+//! draws and the camera the frame is rendered with.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use hecs::World;
+use nalgebra_glm as glm;
+
+use crate::app::AppData;
+use crate::camera::{self, Projection};
+use crate::light;
+use crate::material::MaterialInstance;
+use crate::resources::MeshHandle;
+use crate::scene::{Draw, Transform};
+use crate::vertex::Vec3;
+
+/// Draws a mesh with a material at the transform of its entity.
+#[derive(Copy, Clone, Debug)]
+pub struct MeshRenderer {
+    pub mesh: MeshHandle,
+    pub material: MaterialInstance,
+}
+
+/// Renders the scene from the transform of its entity (which looks down its
+/// negative Z axis with its Y axis up).
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub projection: Projection,
+}
+
+/// A point light at the transform of its entity.
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub color: Vec3,
+    pub intensity: f32,
+    /// The distance at which the light stops lighting.
+    pub range: f32,
+}
+
+/// Rotates its entity around an axis at a constant speed (in radians per
+/// second).
+#[derive(Copy, Clone, Debug)]
+pub struct Spin {
+    pub axis: Vec3,
+    pub speed: f32,
+}
+
+/// The world of entities rendered by our Vulkan app.
+#[derive(Default)]
+pub struct Entities(pub World);
+
+impl fmt::Debug for Entities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Entities")
+            .field("len", &self.0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Spawns the entities rendered by our Vulkan app, which match the scene
+/// created by `create_scene`, and a camera and light.
+pub fn spawn_entities(data: &mut AppData) -> Result<()> {
+    let material = data
+        .resources
+        .materials
+        .iter()
+        .map(|(h, _)| h)
+        .next()
+        .ok_or_else(|| anyhow!("There are no materials to draw the meshes with."))?;
+
+    let world = &mut data.entities.0;
+    world.clear();
+
+    for (mesh, _) in data.resources.meshes.iter() {
+        world.spawn((
+            Transform::default(),
+            MeshRenderer {
+                mesh,
+                material: MaterialInstance {
+                    material,
+                    opacity: 0.25,
+                },
+            },
+            Spin {
+                axis: glm::vec3(0.0, 0.0, 1.0),
+                speed: glm::radians(&glm::vec1(90.0))[0],
+            },
+        ));
+    }
+
+    let camera = data.camera;
+    world.spawn((
+        Transform {
+            translation: camera.position,
+            rotation: camera.rotation,
+            ..Default::default()
+        },
+        Camera {
+            projection: camera.projection,
+        },
+    ));
+
+    world.spawn((
+        Transform {
+            translation: glm::vec3(0.0, 0.0, 2.0),
+            ..Default::default()
+        },
+        Light {
+            color: glm::vec3(1.0, 1.0, 1.0),
+            intensity: 4.0,
+            range: light::DEFAULT_RANGE,
+        },
+    ));
+
+    for light in light::scatter_lights(data.options.lights) {
+        world.spawn((
+            Transform {
+                translation: light.position,
+                ..Default::default()
+            },
+            Light {
+                color: light.color,
+                intensity: light.intensity,
+                range: light.range,
+            },
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sets the rotation of the spinning entities for the supplied time (in
+/// seconds).
+pub fn spin_system(world: &mut World, time: f32) {
+    for (_, (transform, spin)) in world.query_mut::<(&mut Transform, &Spin)>() {
+        transform.rotation = glm::quat_angle_axis(time * spin.speed, &spin.axis);
+    }
+}
+
+/// Returns the draws for the entities with a mesh renderer.
+pub fn extract_draws(world: &World) -> Vec<Draw> {
+    world
+        .query::<(&Transform, &MeshRenderer)>()
+        .iter()
+        .map(|(_, (transform, renderer))| Draw {
+            mesh: renderer.mesh,
+            material: renderer.material,
+            model: transform.matrix(),
+        })
+        .collect()
+}
+
+/// Returns the first camera, if any (ignoring the scale of its entity).
+pub fn extract_camera(world: &World) -> Option<camera::Camera> {
+    let mut query = world.query::<(&Transform, &Camera)>();
+    let (_, (transform, camera)) = query.iter().next()?;
+
+    Some(camera::Camera {
+        position: transform.translation,
+        rotation: transform.rotation,
+        projection: camera.projection,
+    })
+}
+
+/// Returns the lights shaded by the materials.
+pub fn extract_lights(world: &World) -> Vec<light::Light> {
+    world
+        .query::<(&Transform, &Light)>()
+        .iter()
+        .map(|(_, (transform, light))| {
+            light::Light::point(
+                transform.translation,
+                light.color,
+                light.intensity,
+                light.range,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,987 @@
+//! An environment map, which is converted from an equirectangular panorama
+//! (a Radiance `.hdr` file, the usual format of HDRIs) to a cube map by a
+//! compute shader when it is loaded, and drawn behind the scene as a skybox.
+//!
+//! The environment map also lights the scene (see `Ibl`), so without a
+//! panorama it is a small cube map of a uniform gray (which isn't drawn).
+
+use std::fs;
+use std::mem::size_of;
+use std::path::Path;
+use std::str;
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::commands::DrawBatch;
+use crate::debug::SKYBOX_LABEL_COLOR;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::image::{
+    cmd_transition_image_layers, cmd_transition_image_layout, create_image, create_image_view,
+    create_layered_image, create_layered_image_view, Image,
+};
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::swapchain::get_scene_encoding;
+use crate::texture::cmd_generate_mipmaps;
+use crate::upload::{
+    flush_uploads, get_graphics_upload_command_buffer, get_upload_command_buffer, upload_to_image,
+};
+use crate::vertex::Mat4;
+
+/// The format of the panorama and the cube map (which have to be filtered).
+const ENVIRONMENT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The largest size of the faces of the cube map (in pixels).
+const MAX_CUBEMAP_SIZE: u32 = 1024;
+
+/// The size of the faces of the cube map without a panorama (in pixels).
+const FALLBACK_CUBEMAP_SIZE: u32 = 16;
+
+/// The color of the cube map without a panorama.
+const FALLBACK_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+/// The size of the workgroups of the compute shader (see
+/// `equirect_to_cube.comp`).
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The pixels of a Radiance HDR image.
+#[derive(Clone, Debug, Default)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// The linear colors of the pixels, row by row from the top.
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            fs::read(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+        Self::parse(&bytes).map_err(|e| anyhow!("Failed to load `{}`: {}", path.display(), e))
+    }
+
+    /// Parses a Radiance HDR image (with flat or run-length encoded RGBE
+    /// scanlines).
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+
+        // Header
+
+        if !read_line(bytes, &mut offset)?.starts_with("#?") {
+            return Err(anyhow!("Not a Radiance HDR file."));
+        }
+
+        loop {
+            let line = read_line(bytes, &mut offset)?;
+            if line.is_empty() {
+                break;
+            } else if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(anyhow!("Unsupported format ({}).", format));
+                }
+            }
+        }
+
+        // Only images stored from the top left corner row by row (which is how
+        // almost all of them are stored) are supported.
+        let resolution = read_line(bytes, &mut offset)?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+            _ => return Err(anyhow!("Unsupported orientation ({}).", resolution)),
+        };
+
+        // Scanlines
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        let mut scanline = vec![[0; 4]; width as usize];
+        for _ in 0..height {
+            read_scanline(bytes, &mut offset, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|p| decode_rgbe(*p)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// The environment map and the Vulkan handles used to draw it.
+#[derive(Debug, Default)]
+pub struct Environment {
+    /// The environment map (in world space, where Z is up).
+    pub cubemap: Image,
+    /// The size of the faces of the cube map (in pixels).
+    pub size: u32,
+    pub mip_levels: u32,
+    pub sampler: Owned<vk::Sampler>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    // Swapchain
+    pipeline: Owned<vk::Pipeline>,
+}
+
+/// Loads the environment map, converts it to a mipmapped cube map, and
+/// creates the descriptor set and pipeline layout used to draw it.
+pub unsafe fn create_environment(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let panorama = match &data.options.environment {
+        Some(path) => Some(HdrImage::load(path)?),
+        None => None,
+    };
+
+    // Sampler
+
+    // The panorama wraps around horizontally (and cube maps are sampled
+    // across their faces regardless of the address modes).
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    data.environment.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Cube Map
+
+    let size = panorama.as_ref().map_or(FALLBACK_CUBEMAP_SIZE, |p| {
+        (p.width / 4)
+            .max(1)
+            .next_power_of_two()
+            .min(MAX_CUBEMAP_SIZE)
+    });
+    let mip_levels = size.ilog2() + 1;
+
+    let mut cubemap = create_layered_image(
+        instance,
+        device,
+        data,
+        size,
+        size,
+        mip_levels,
+        6,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::SampleCountFlags::_1,
+        ENVIRONMENT_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    cubemap.view = create_layered_image_view(
+        device,
+        cubemap.image,
+        vk::ImageViewType::CUBE,
+        ENVIRONMENT_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        mip_levels,
+        6,
+    )?;
+
+    data.environment.cubemap = cubemap;
+    data.environment.size = size;
+    data.environment.mip_levels = mip_levels;
+
+    match &panorama {
+        Some(panorama) => convert_panorama(instance, device, data, panorama)?,
+        None => fill_cubemap(instance, device, data)?,
+    }
+
+    // Descriptor Set
+
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.environment.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.environment.descriptor_pool =
+        Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = &[*data.environment.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.environment.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.environment.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.environment.cubemap.view)
+        .sampler(*data.environment.sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.environment.descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<Mat4>() as u32);
+
+    let set_layouts = &[*data.environment.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.environment.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    Ok(())
+}
+
+/// Fills the cube map with `FALLBACK_COLOR` (for lighting without a
+/// panorama).
+unsafe fn fill_cubemap(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let command_buffer = get_graphics_upload_command_buffer(device, data)?;
+    let image = data.environment.cubemap.image;
+    let mip_levels = data.environment.mip_levels;
+
+    cmd_transition_image_layers(
+        device,
+        command_buffer,
+        image,
+        ENVIRONMENT_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+        6,
+    )?;
+
+    let range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(6);
+
+    let color = vk::ClearColorValue {
+        float32: FALLBACK_COLOR,
+    };
+
+    device.cmd_clear_color_image(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &color,
+        &[range],
+    );
+
+    cmd_generate_mipmaps(
+        instance,
+        device,
+        data,
+        command_buffer,
+        image,
+        ENVIRONMENT_FORMAT,
+        data.environment.size,
+        data.environment.size,
+        mip_levels,
+        6,
+    )?;
+
+    flush_uploads(instance, device, data)
+}
+
+/// Converts the panorama to the faces of the cube map with a compute shader
+/// and generates its mip levels, waiting for the conversion to complete (so
+/// the resources only used by the conversion can be destroyed).
+unsafe fn convert_panorama(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    panorama: &HdrImage,
+) -> Result<()> {
+    let (width, height) = (panorama.width, panorama.height);
+
+    // Panorama
+
+    let pixels = panorama
+        .pixels
+        .iter()
+        .flat_map(|[r, g, b]| [*r, *g, *b, 1.0].map(f32_to_f16))
+        .collect::<Vec<_>>();
+
+    let mut panorama_image = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        ENVIRONMENT_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    cmd_transition_image_layout(
+        device,
+        get_upload_command_buffer(device, data)?,
+        panorama_image.image,
+        ENVIRONMENT_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+    )?;
+
+    upload_to_image(
+        instance,
+        device,
+        data,
+        &pixels,
+        panorama_image.image,
+        width,
+        height,
+        1,
+    )?;
+
+    panorama_image.view = create_image_view(
+        device,
+        panorama_image.image,
+        ENVIRONMENT_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    // The faces of the cube map are written through a 2D array view.
+    let faces_view = create_layered_image_view(
+        device,
+        data.environment.cubemap.image,
+        vk::ImageViewType::_2D_ARRAY,
+        ENVIRONMENT_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+        6,
+    )?;
+
+    // Descriptor Set
+
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+    ];
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let pool_sizes = [
+        vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .build(),
+        vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .build(),
+    ];
+
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(1);
+
+    let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(layouts);
+
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(panorama_image.view)
+        .sampler(*data.environment.sampler);
+
+    let image_info = &[info];
+    let panorama_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::GENERAL)
+        .image_view(faces_view);
+
+    let image_info = &[info];
+    let faces_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(
+        &[panorama_write, faces_write],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    // Pipeline
+
+    let set_layouts = &[descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    let pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let comp_shader_module = create_shader_module(device, shaders::EQUIRECT_TO_CUBE_COMP)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(comp_shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(pipeline_layout);
+
+    let pipeline = device
+        .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    device.destroy_shader_module(comp_shader_module, None);
+
+    // Convert
+
+    // The conversion runs on the graphics queue once the panorama has been
+    // uploaded.
+    let command_buffer = get_graphics_upload_command_buffer(device, data)?;
+
+    let color = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+
+    let faces = vk::ImageSubresourceRange {
+        layer_count: 6,
+        ..color
+    };
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .build();
+
+    let panorama_barrier = vk::ImageMemoryBarrier {
+        old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::SHADER_READ,
+        image: panorama_image.image,
+        subresource_range: color,
+        ..barrier
+    };
+
+    let faces_barrier = vk::ImageMemoryBarrier {
+        old_layout: vk::ImageLayout::UNDEFINED,
+        new_layout: vk::ImageLayout::GENERAL,
+        src_access_mask: vk::AccessFlags::empty(),
+        dst_access_mask: vk::AccessFlags::SHADER_WRITE,
+        image: data.environment.cubemap.image,
+        subresource_range: faces,
+        ..barrier
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[panorama_barrier, faces_barrier],
+    );
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+
+    let groups = data.environment.size.div_ceil(WORKGROUP_SIZE);
+    device.cmd_dispatch(command_buffer, groups, groups, 6);
+
+    // The other mip levels are generated from the first mip level.
+    let mip_levels = data.environment.mip_levels;
+
+    let level_barrier = vk::ImageMemoryBarrier {
+        old_layout: vk::ImageLayout::GENERAL,
+        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        src_access_mask: vk::AccessFlags::SHADER_WRITE,
+        dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+        image: data.environment.cubemap.image,
+        subresource_range: faces,
+        ..barrier
+    };
+
+    let levels_barrier = vk::ImageMemoryBarrier {
+        old_layout: vk::ImageLayout::UNDEFINED,
+        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        src_access_mask: vk::AccessFlags::empty(),
+        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        image: data.environment.cubemap.image,
+        subresource_range: vk::ImageSubresourceRange {
+            base_mip_level: 1,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            ..faces
+        },
+        ..barrier
+    };
+
+    let barriers = if mip_levels > 1 {
+        &[level_barrier, levels_barrier][..]
+    } else {
+        &[level_barrier][..]
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        barriers,
+    );
+
+    cmd_generate_mipmaps(
+        instance,
+        device,
+        data,
+        command_buffer,
+        data.environment.cubemap.image,
+        ENVIRONMENT_FORMAT,
+        data.environment.size,
+        data.environment.size,
+        mip_levels,
+        6,
+    )?;
+
+    flush_uploads(instance, device, data)?;
+    device.queue_wait_idle(data.graphics_queue)?;
+
+    // Cleanup
+
+    device.destroy_pipeline(pipeline, None);
+    device.destroy_pipeline_layout(pipeline_layout, None);
+    device.destroy_descriptor_pool(descriptor_pool, None);
+    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+    device.destroy_image_view(faces_view, None);
+
+    Ok(())
+}
+
+/// Creates the pipeline which draws the environment map behind the scene.
+///
+/// This does nothing unless there is an environment map in the options.
+pub unsafe fn create_skybox_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if data.options.environment.is_none() {
+        return Ok(());
+    }
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::SKYBOX_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::SKYBOX_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The environment is encoded like the rest of the scene.
+    let (encode_srgb, output_transfer) = get_scene_encoding(data);
+
+    let specialization_data = [encode_srgb as vk::Bool32, output_transfer as u32]
+        .map(u32::to_ne_bytes)
+        .concat();
+    let map_entries = &[
+        vk::SpecializationMapEntry::builder()
+            .constant_id(0)
+            .offset(0)
+            .size(size_of::<vk::Bool32>()),
+        vk::SpecializationMapEntry::builder()
+            .constant_id(1)
+            .offset(4)
+            .size(size_of::<u32>()),
+    ];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&specialization_data);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Vertex Input State
+
+    // The vertices of the triangle are generated by the vertex shader.
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    // The triangle is at the far plane, so it is only drawn where the depth
+    // buffer is still clear.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.environment.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.environment.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Returns a draw batch which draws the environment map behind the scene with
+/// the supplied view and projection matrices, if there is one.
+pub fn get_skybox_batch(data: &AppData, view: &Mat4, proj: &Mat4) -> Option<DrawBatch<'static>> {
+    let pipeline = *data.environment.pipeline;
+    if pipeline.is_null() {
+        return None;
+    }
+
+    let pipeline_layout = *data.environment.pipeline_layout;
+    let descriptor_set = data.environment.descriptor_set;
+    let inv_view_proj = glm::inverse(&(proj * view));
+
+    Some(DrawBatch {
+        name: "Skybox",
+        color: SKYBOX_LABEL_COLOR,
+        record: Box::new(move |device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            let push_constants = std::slice::from_raw_parts(
+                &inv_view_proj as *const Mat4 as *const u8,
+                size_of::<Mat4>(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                push_constants,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }),
+    })
+}
+
+/// Reads a line of the header of a Radiance HDR image (without the newline).
+fn read_line<'a>(bytes: &'a [u8], offset: &mut usize) -> Result<&'a str> {
+    let rest = bytes.get(*offset..).unwrap_or_default();
+    let length = rest
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(|| anyhow!("Unexpected end of file."))?;
+
+    *offset += length + 1;
+    Ok(str::from_utf8(&rest[..length])?.trim_end_matches('\r'))
+}
+
+fn read_byte(bytes: &[u8], offset: &mut usize) -> Result<u8> {
+    let byte = *bytes
+        .get(*offset)
+        .ok_or_else(|| anyhow!("Unexpected end of file."))?;
+    *offset += 1;
+    Ok(byte)
+}
+
+/// Reads a scanline of RGBE pixels, which is either flat or run-length
+/// encoded (with each of the four components encoded separately).
+fn read_scanline(bytes: &[u8], offset: &mut usize, scanline: &mut [[u8; 4]]) -> Result<()> {
+    let width = scanline.len();
+    let header = bytes.get(*offset..*offset + 4).unwrap_or_default();
+
+    // Run-length encoded scanlines start with 2, 2, and their width.
+    let encoded = (8..0x8000).contains(&width)
+        && header.len() == 4
+        && header[0] == 2
+        && header[1] == 2
+        && header[2] & 0x80 == 0;
+
+    if !encoded {
+        for pixel in scanline {
+            for component in pixel {
+                *component = read_byte(bytes, offset)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(anyhow!("Invalid scanline width."));
+    }
+
+    *offset += 4;
+
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            // A count above 128 is a run of a single value, otherwise it is a
+            // number of values which follow.
+            let count = read_byte(bytes, offset)? as usize;
+            let (run, count) = if count > 128 {
+                (true, count - 128)
+            } else {
+                (false, count)
+            };
+
+            if count == 0 || x + count > width {
+                return Err(anyhow!("Invalid run length."));
+            }
+
+            let value = if run { read_byte(bytes, offset)? } else { 0 };
+            for pixel in &mut scanline[x..x + count] {
+                pixel[component] = if run {
+                    value
+                } else {
+                    read_byte(bytes, offset)?
+                };
+            }
+
+            x += count;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the linear color of an RGBE pixel (whose components share an
+/// exponent).
+fn decode_rgbe([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+    if e == 0 {
+        return [0.0; 3];
+    }
+
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+    [r as f32 * scale, g as f32 * scale, b as f32 * scale]
+}
+
+/// Returns the bits of the half-precision float closest to a (non-negative)
+/// float, clamped to the largest finite half-precision float.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if exponent >= 31 {
+        sign | 0x7BFF
+    } else if exponent <= 0 {
+        // Subnormal (or too small to represent).
+        if exponent < -10 {
+            sign
+        } else {
+            sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16
+        }
+    } else {
+        sign | (exponent as u16) << 10 | (mantissa >> 13) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+
+        // A flat scanline and a run-length encoded scanline.
+        let mut bytes = header.to_vec();
+        for _ in 0..8 {
+            bytes.extend([128, 64, 0, 129]);
+        }
+        bytes.extend([2, 2, 0, 8]);
+        bytes.extend([136, 64]);
+        bytes.extend([136, 128]);
+        bytes.extend([4, 0, 0, 0, 0, 132, 32]);
+        bytes.extend([136, 130]);
+
+        let image = HdrImage::parse(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (8, 2));
+        assert_eq!(image.pixels[0], [1.0, 0.5, 0.0]);
+        assert_eq!(image.pixels[8], [1.0, 2.0, 0.0]);
+        assert_eq!(image.pixels[15], [1.0, 2.0, 0.5]);
+
+        // A truncated image is an error rather than a panic.
+        assert!(HdrImage::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7BFF);
+        assert_eq!(f32_to_f16(1.0e6), 0x7BFF);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+    }
+}
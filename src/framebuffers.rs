@@ -0,0 +1,175 @@
+//! Framebuffers and the color and depth attachments they render into.
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::device::RenderingPath;
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view};
+use crate::tonemap::HDR_FORMAT;
+
+pub unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> Result<()> {
+    // Dynamic rendering doesn't use framebuffers.
+    if data.rendering_path != RenderingPath::RenderPass {
+        return Ok(());
+    }
+
+    data.framebuffers = data
+        .swapchain
+        .image_views
+        .iter()
+        .map(|i| {
+            // With tone mapping, the scene is rendered to the intermediate
+            // target instead of the swapchain image.
+            let target = if data.options.tone_mapping.is_some() {
+                data.tone_map.image.view
+            } else {
+                *i
+            };
+            let attachments = if data.msaa_samples == vk::SampleCountFlags::_1 {
+                vec![target, data.depth_image.view]
+            } else {
+                vec![data.color_image.view, data.depth_image.view, target]
+            };
+            let create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(*data.render_pass)
+                .attachments(&attachments)
+                .width(data.swapchain.extent.width)
+                .height(data.swapchain.extent.height)
+                .layers(1);
+
+            let framebuffer = device.create_framebuffer(&create_info, None)?;
+            Ok(Owned::new(device, framebuffer))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+/// Returns the format of the color attachment of the main pass, which is the
+/// format of the HDR intermediate target with tone mapping (see `ToneMap`) or
+/// the format of the swapchain otherwise.
+pub fn get_color_format(data: &AppData) -> vk::Format {
+    if data.options.tone_mapping.is_some() {
+        HDR_FORMAT
+    } else {
+        data.swapchain.format
+    }
+}
+
+pub unsafe fn create_color_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Image + Image Memory
+
+    data.color_image = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain.extent.width,
+        data.swapchain.extent.height,
+        1,
+        data.msaa_samples,
+        get_color_format(data),
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Image View
+
+    data.color_image.view = create_image_view(
+        device,
+        data.color_image.image,
+        get_color_format(data),
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    Ok(())
+}
+
+pub unsafe fn create_depth_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Image + Image Memory
+
+    let format = get_depth_format(instance, data)?;
+    data.depth_format = format;
+
+    // The depth is read to build the Hi-Z pyramid with occlusion culling.
+    let usage = if data.options.occlusion_culling {
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+    } else {
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+    };
+
+    data.depth_image = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain.extent.width,
+        data.swapchain.extent.height,
+        1,
+        data.msaa_samples,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Image View
+
+    data.depth_image.view = create_image_view(
+        device,
+        data.depth_image.image,
+        format,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+    )?;
+
+    Ok(())
+}
+
+pub unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
+    let candidates = &[
+        vk::Format::D32_SFLOAT,
+        vk::Format::D32_SFLOAT_S8_UINT,
+        vk::Format::D24_UNORM_S8_UINT,
+    ];
+
+    get_supported_format(
+        instance,
+        data,
+        candidates,
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+    )
+}
+
+unsafe fn get_supported_format(
+    instance: &Instance,
+    data: &AppData,
+    candidates: &[vk::Format],
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> Result<vk::Format> {
+    candidates
+        .iter()
+        .cloned()
+        .find(|f| {
+            let properties =
+                instance.get_physical_device_format_properties(data.physical_device, *f);
+            match tiling {
+                vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+                vk::ImageTiling::OPTIMAL => properties.optimal_tiling_features.contains(features),
+                _ => false,
+            }
+        })
+        .ok_or_else(|| anyhow!("Failed to find supported format!"))
+}
@@ -0,0 +1,295 @@
+//! Golden-image regression testing, which renders a deterministic frame
+//! offscreen and compares it to a reference image.
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use crate::app::AppBuilder;
+use crate::screenshot::save_png;
+
+/// The size of the rendered frame.
+pub const WIDTH: u32 = 800;
+pub const HEIGHT: u32 = 600;
+
+/// The environment variable that makes the rendered frame the new reference
+/// image (`1`) like `--bless`.
+pub const BLESS_VAR: &str = "GOLDEN_BLESS";
+
+/// The time the animation is frozen at.
+const TIME: f32 = 0.5;
+
+/// The fraction of pixels which can differ by more than the tolerance, which
+/// allows for drivers rasterizing or resolving edges slightly differently.
+const MAX_DIFFERING_FRACTION: f64 = 0.001;
+
+/// The number of differing pixels which are reported individually.
+const MAX_REPORTED_PIXELS: usize = 10;
+
+/// The differences between two images of the same size.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels which differ by more than the tolerance, with their
+    /// coordinates and the largest difference between their channels.
+    pub differing: Vec<(u32, u32, u8)>,
+    /// The largest difference between the channels of any pixel.
+    pub max_difference: u8,
+}
+
+impl Comparison {
+    /// Compares two 8-bit RGBA images of the same size, ignoring differences
+    /// between channels of up to the supplied tolerance.
+    pub fn new(width: u32, actual: &[u8], expected: &[u8], tolerance: u8) -> Self {
+        let height = (actual.len() / 4 / width as usize) as u32;
+
+        let mut differing = vec![];
+        let mut max_difference = 0;
+        for (index, (a, e)) in actual
+            .chunks_exact(4)
+            .zip(expected.chunks_exact(4))
+            .enumerate()
+        {
+            let difference = a
+                .iter()
+                .zip(e)
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+
+            max_difference = max_difference.max(difference);
+            if difference > tolerance {
+                let index = index as u32;
+                differing.push((index % width, index / width, difference));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            differing,
+            max_difference,
+        }
+    }
+
+    /// The fraction of pixels which differ by more than the tolerance.
+    pub fn differing_fraction(&self) -> f64 {
+        self.differing.len() as f64 / (self.width as f64 * self.height as f64)
+    }
+
+    /// Returns an 8-bit RGBA image which dims the supplied image and highlights
+    /// the pixels which differ in red (brighter for larger differences).
+    pub fn diff_image(&self, actual: &[u8]) -> Vec<u8> {
+        let mut pixels = actual
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let luma = (p[0] as u32 * 2 + p[1] as u32 * 5 + p[2] as u32) / 8;
+                let luma = (luma / 4) as u8;
+                [luma, luma, luma, 255]
+            })
+            .collect::<Vec<_>>();
+
+        for (x, y, difference) in &self.differing {
+            let index = (*y * self.width + *x) as usize * 4;
+            pixels[index] = difference.saturating_mul(4).max(128);
+            pixels[index + 1] = 0;
+            pixels[index + 2] = 0;
+        }
+
+        pixels
+    }
+}
+
+/// Returns whether the rendered frame should replace the reference image,
+/// which is the case if `--bless` was passed or `BLESS_VAR` is set.
+pub fn bless_enabled(bless: bool) -> bool {
+    match env::var(BLESS_VAR).as_deref() {
+        Ok("1") | Ok("true") => true,
+        Ok("0") | Ok("false") | Err(_) => bless,
+        Ok(value) => {
+            warn!("Ignoring invalid `{}` value (`{}`).", BLESS_VAR, value);
+            bless
+        }
+    }
+}
+
+/// Renders a deterministic frame offscreen (without a window or a swapchain)
+/// and compares it to the supplied reference image, or makes the frame the
+/// new reference image if `bless` is set.
+///
+/// The frame is saved next to the reference image (as `<name>.actual.png`)
+/// and, if it differs, so is an image highlighting the differences (as
+/// `<name>.diff.png`). A missing reference image is an error, so a frame is
+/// never compared to itself.
+pub unsafe fn run(builder: AppBuilder, reference: &Path, tolerance: u8, bless: bool) -> Result<()> {
+    let actual_path = with_suffix(reference, "actual");
+    let actual = render(builder, &actual_path)?;
+
+    if bless {
+        info!("Saving rendered frame to `{}`.", reference.display());
+        if let Some(parent) = reference.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&actual_path, reference)?;
+        return Ok(());
+    }
+
+    if !reference.exists() {
+        return Err(anyhow!(
+            "Reference image `{}` doesn't exist (review `{}` and rerun with `--bless` to accept it).",
+            reference.display(),
+            actual_path.display()
+        ));
+    }
+
+    // Compare
+
+    let (width, height, expected) = load_png(reference)?;
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(anyhow!(
+            "Reference image is {}x{}, expected {}x{}.",
+            width,
+            height,
+            WIDTH,
+            HEIGHT
+        ));
+    }
+
+    let comparison = Comparison::new(WIDTH, &actual, &expected, tolerance);
+
+    info!(
+        "{} of {} pixels differ by more than {} (max difference {}).",
+        comparison.differing.len(),
+        WIDTH * HEIGHT,
+        tolerance,
+        comparison.max_difference,
+    );
+
+    for (x, y, difference) in comparison.differing.iter().take(MAX_REPORTED_PIXELS) {
+        let index = (y * WIDTH + x) as usize * 4;
+        info!(
+            "  ({}, {}): expected {:?}, got {:?} (difference {})",
+            x,
+            y,
+            &expected[index..index + 4],
+            &actual[index..index + 4],
+            difference,
+        );
+    }
+
+    if comparison.differing.is_empty() {
+        return Ok(());
+    }
+
+    let diff_path = with_suffix(reference, "diff");
+    save_png(&diff_path, WIDTH, HEIGHT, &comparison.diff_image(&actual))?;
+    info!("Saved differences to `{}`.", diff_path.display());
+
+    if comparison.differing_fraction() > MAX_DIFFERING_FRACTION {
+        return Err(anyhow!(
+            "Rendered frame doesn't match reference image `{}`.",
+            reference.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Renders a frame offscreen, saves it to the supplied path, and returns its
+/// pixels.
+unsafe fn render(builder: AppBuilder, path: &Path) -> Result<Vec<u8>> {
+    // A stale frame must not be mistaken for the one about to be rendered.
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let mut app = builder.time(TIME).size(WIDTH, HEIGHT).create_offscreen()?;
+    app.request_screenshot_to(path.into());
+    app.render_offscreen()?;
+
+    // Failing to save a screenshot is only logged by the app.
+    if !path.exists() {
+        return Err(anyhow!("Failed to capture rendered frame."));
+    }
+
+    let (width, height, pixels) = load_png(path)?;
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(anyhow!(
+            "Rendered frame is {}x{}, expected {}x{}.",
+            width,
+            height,
+            WIDTH,
+            HEIGHT
+        ));
+    }
+
+    Ok(pixels)
+}
+
+/// Loads an 8-bit RGBA PNG file.
+fn load_png(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+
+    if reader.info().color_type != png::ColorType::Rgba
+        || reader.info().bit_depth != png::BitDepth::Eight
+    {
+        return Err(anyhow!("`{}` must be 8-bit RGBA.", path.display()));
+    }
+
+    let mut pixels = vec![0; reader.info().raw_bytes()];
+    reader.next_frame(&mut pixels)?;
+
+    let (width, height) = reader.info().size();
+    Ok((width, height, pixels))
+}
+
+/// Returns the supplied path with a suffix added to its file stem (e.g.,
+/// `quad.png` becomes `quad.diff.png`).
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images() {
+        let pixels = [10, 20, 30, 255].repeat(4);
+        let comparison = Comparison::new(2, &pixels, &pixels, 0);
+        assert_eq!(comparison.height, 2);
+        assert!(comparison.differing.is_empty());
+        assert_eq!(comparison.max_difference, 0);
+    }
+
+    #[test]
+    fn differences_within_tolerance() {
+        let expected = [10, 20, 30, 255].repeat(4);
+        let mut actual = expected.clone();
+        actual[5] += 2;
+        actual[14] -= 5;
+
+        let comparison = Comparison::new(2, &actual, &expected, 2);
+        assert_eq!(comparison.differing, vec![(1, 1, 5)]);
+        assert_eq!(comparison.max_difference, 5);
+        assert_eq!(comparison.differing_fraction(), 0.25);
+    }
+
+    #[test]
+    fn diff_image_highlights_differences() {
+        let expected = [0, 0, 0, 255].repeat(4);
+        let mut actual = expected.clone();
+        actual[4] = 100;
+
+        let comparison = Comparison::new(2, &actual, &expected, 0);
+        let diff = comparison.diff_image(&actual);
+        assert_eq!(&diff[4..8], &[255, 0, 0, 255]);
+        assert_eq!(&diff[0..4], &[0, 0, 0, 255]);
+    }
+}
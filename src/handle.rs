@@ -0,0 +1,135 @@
+//! Handles created with the logical device which are destroyed when dropped.
+
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+
+use vulkanalia::prelude::v1_0::*;
+
+/// The command which destroys a handle created with the logical device.
+pub type DestroyCommand<H> =
+    unsafe extern "system" fn(vk::Device, H, *const vk::AllocationCallbacks);
+
+/// A type of handle created with the logical device.
+pub trait DeviceHandle: Handle + Copy + Default + fmt::Debug {
+    /// Returns the command which destroys handles of this type.
+    fn destroy_command(commands: &vk::DeviceCommands) -> DestroyCommand<Self>;
+}
+
+macro_rules! device_handles {
+    ($($handle:ident => $destroy:ident),* $(,)?) => {
+        $(
+            impl DeviceHandle for vk::$handle {
+                fn destroy_command(commands: &vk::DeviceCommands) -> DestroyCommand<Self> {
+                    commands.$destroy
+                }
+            }
+        )*
+    };
+}
+
+device_handles! {
+    CommandPool => destroy_command_pool,
+    DescriptorPool => destroy_descriptor_pool,
+    DescriptorSetLayout => destroy_descriptor_set_layout,
+    Fence => destroy_fence,
+    Framebuffer => destroy_framebuffer,
+    ImageView => destroy_image_view,
+    Pipeline => destroy_pipeline,
+    PipelineLayout => destroy_pipeline_layout,
+    QueryPool => destroy_query_pool,
+    RenderPass => destroy_render_pass,
+    Sampler => destroy_sampler,
+    Semaphore => destroy_semaphore,
+}
+
+/// A handle created with the logical device which is destroyed when dropped.
+///
+/// Only the logical device handle and the command which destroys the handle
+/// are kept (rather than a clone of the `Device`, which holds every device
+/// command). An owned handle must be dropped before the logical device is
+/// destroyed. A default owned handle is null and owns nothing.
+#[derive(Debug, Default)]
+pub struct Owned<H: DeviceHandle> {
+    device: vk::Device,
+    destroy: Option<DestroyCommand<H>>,
+    handle: H,
+}
+
+impl<H: DeviceHandle> Owned<H> {
+    /// Takes ownership of a handle created with the supplied logical device.
+    pub fn new(device: &Device, handle: H) -> Self {
+        Self {
+            device: device.handle(),
+            destroy: Some(H::destroy_command(device.commands())),
+            handle,
+        }
+    }
+}
+
+impl<H: DeviceHandle> Deref for Owned<H> {
+    type Target = H;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl<H: DeviceHandle> Drop for Owned<H> {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.device, self.handle, ptr::null()) };
+        }
+    }
+}
+
+/// Command buffers which are freed back to their command pool when dropped.
+///
+/// Like an owned handle, these must be dropped before their command pool is
+/// destroyed. Default command buffers are empty and own nothing.
+#[derive(Debug, Default)]
+pub struct OwnedCommandBuffers {
+    device: vk::Device,
+    free: Option<vk::PFN_vkFreeCommandBuffers>,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl OwnedCommandBuffers {
+    /// Takes ownership of command buffers allocated from a command pool.
+    pub fn new(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        command_buffers: Vec<vk::CommandBuffer>,
+    ) -> Self {
+        Self {
+            device: device.handle(),
+            free: Some(device.commands().free_command_buffers),
+            command_pool,
+            command_buffers,
+        }
+    }
+}
+
+impl Deref for OwnedCommandBuffers {
+    type Target = [vk::CommandBuffer];
+
+    fn deref(&self) -> &Self::Target {
+        &self.command_buffers
+    }
+}
+
+impl Drop for OwnedCommandBuffers {
+    fn drop(&mut self) {
+        if let (Some(free), false) = (self.free, self.command_buffers.is_empty()) {
+            unsafe {
+                free(
+                    self.device,
+                    self.command_pool,
+                    self.command_buffers.len() as u32,
+                    self.command_buffers.as_ptr(),
+                )
+            };
+        }
+    }
+}
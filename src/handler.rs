@@ -0,0 +1,274 @@
+//! The handlers of the events from the event loop, which create the window
+//! (and our Vulkan app) once the event loop has resumed.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::*;
+use nalgebra_glm as glm;
+use winit::application::ApplicationHandler;
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+use winit::window::{Window, WindowAttributes, WindowId};
+
+use crate::app::{App, AppBuilder};
+use crate::camera::Camera;
+
+/// The frame rate rendering is throttled to while the window is unfocused.
+const BACKGROUND_FPS: u32 = 10;
+
+/// What the render loop does while the window is in the background.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BackgroundMode {
+    /// Keep rendering as if the window were in the foreground.
+    #[default]
+    Render,
+    /// Render a few frames per second while the window is unfocused, and
+    /// nothing while it is occluded.
+    Throttle,
+    /// Render nothing while the window is unfocused or occluded.
+    Pause,
+}
+
+/// Runs our Vulkan app, rendering a frame whenever the event loop is about to
+/// wait for new events.
+#[derive(Debug)]
+pub struct AppHandler {
+    builder: AppBuilder,
+    /// Our Vulkan app and its window, which exist while the event loop is
+    /// resumed (our Vulkan app is dropped before the window).
+    state: Option<(App, Window)>,
+    minimized: bool,
+    /// Whether the window is hidden by other windows (as reported by the
+    /// platform, which not every platform does).
+    occluded: bool,
+    /// Whether any of the windows of our Vulkan app has the keyboard focus.
+    focused: bool,
+    /// When the next frame is rendered while rendering is throttled.
+    next_frame: Instant,
+    /// The error which stopped the event loop, if any.
+    error: Option<anyhow::Error>,
+}
+
+impl AppHandler {
+    pub fn new(builder: AppBuilder) -> Self {
+        Self {
+            builder,
+            state: None,
+            minimized: false,
+            occluded: false,
+            focused: true,
+            next_frame: Instant::now(),
+            error: None,
+        }
+    }
+
+    /// Returns the error which stopped the event loop, if any.
+    pub fn finish(self) -> Result<()> {
+        self.error.map_or(Ok(()), Err)
+    }
+
+    /// Stops the event loop because of an error.
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: anyhow::Error) {
+        error!("{}", error);
+        self.error = Some(error);
+        event_loop.exit();
+    }
+}
+
+impl ApplicationHandler for AppHandler {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.state.is_some() {
+            return;
+        }
+
+        let result = event_loop
+            .create_window(self.builder.window_attributes())
+            .map_err(|e| anyhow!(e))
+            .and_then(|window| {
+                let mut app = unsafe { self.builder.clone().create(&window)? };
+                unsafe { open_views(event_loop, &self.builder, &mut app)? };
+                Ok((app, window))
+            });
+
+        match result {
+            Ok(state) => self.state = Some(state),
+            Err(error) => self.fail(event_loop, error),
+        }
+    }
+
+    fn suspended(&mut self, _: &ActiveEventLoop) {
+        // The window (and its surface) can be destroyed while the event loop
+        // is suspended (e.g., on Android), so our Vulkan app is recreated with
+        // a new window when the event loop resumes.
+        self.state = None;
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
+        let Some((app, window)) = &mut self.state else {
+            return;
+        };
+
+        // Record the keyboard and mouse input, which is handled when the next
+        // frame is rendered (unless the UI of the window used it).
+        let used = id == window.id() && app.handle_ui_event(window, &event);
+
+        if !used {
+            app.input.handle_window_event(&event);
+        }
+
+        // The focus can move between the window and the views, and the window
+        // which loses it is notified before the window which gains it.
+        if let WindowEvent::Focused(focused) = event {
+            self.focused = focused;
+        }
+
+        // The other windows are views (which are closed without stopping the
+        // event loop).
+        if id != window.id() {
+            let result = match event {
+                WindowEvent::Resized(size) => {
+                    app.resize_view(id, size.width, size.height);
+                    Ok(())
+                }
+                WindowEvent::CloseRequested => unsafe { app.close_view(id) },
+                _ => Ok(()),
+            };
+
+            if let Err(error) = result {
+                self.fail(event_loop, error);
+            }
+
+            return;
+        }
+
+        match event {
+            // Mark the window as having been resized, or suspend rendering
+            // while it is minimized (i.e., has a zero-sized surface).
+            WindowEvent::Resized(size) => {
+                if size.width == 0 || size.height == 0 {
+                    self.minimized = true;
+                } else {
+                    self.minimized = false;
+                    app.resized = true;
+                }
+            }
+            WindowEvent::Occluded(occluded) => self.occluded = occluded,
+            // Stop the event loop.
+            WindowEvent::CloseRequested => event_loop.exit(),
+            _ => {}
+        }
+    }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        if let Some((app, _)) = &mut self.state {
+            app.input.handle_device_event(&event);
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some((app, window)) = &mut self.state else {
+            return;
+        };
+
+        // Wait for events instead of rendering while the window is in the
+        // background (if our Vulkan app was created to do so), rendering a
+        // frame only every so often if rendering is throttled.
+        let background = app.data.options.background;
+        let paused = (background != BackgroundMode::Render && self.occluded)
+            || (background == BackgroundMode::Pause && !self.focused);
+        let throttled = background == BackgroundMode::Throttle && !self.focused;
+
+        if paused {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        } else if throttled {
+            let now = Instant::now();
+            if now < self.next_frame {
+                event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+                return;
+            }
+
+            self.next_frame = now + Duration::from_secs(1) / BACKGROUND_FPS;
+            event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+        } else {
+            event_loop.set_control_flow(ControlFlow::Poll);
+        }
+
+        // Render a frame if our Vulkan app is not minimized.
+        if !self.minimized {
+            if let Err(error) = unsafe { app.render(window) } {
+                self.fail(event_loop, error);
+            }
+        }
+    }
+}
+
+/// Opens the views of our Vulkan app, each of which renders the scene from a
+/// quarter turn further around it than the last.
+unsafe fn open_views(
+    event_loop: &ActiveEventLoop,
+    builder: &AppBuilder,
+    app: &mut App,
+) -> Result<()> {
+    let options = builder.options();
+    for index in 1..options.windows {
+        let attributes = builder
+            .window_attributes()
+            .with_title(format!("{} (View {})", options.title, index));
+        let window = event_loop.create_window(attributes)?;
+
+        let turn = glm::quat_angle_axis(
+            index as f32 * glm::half_pi::<f32>(),
+            &glm::vec3(0.0, 0.0, 1.0),
+        );
+        let camera = Camera {
+            position: glm::quat_rotate_vec3(&turn, &app.data.camera.position),
+            rotation: turn * app.data.camera.rotation,
+            ..app.data.camera
+        };
+
+        app.open_view(window, Some(camera))?;
+    }
+
+    Ok(())
+}
+
+/// Calls a function with a window created once the event loop has resumed,
+/// then stops the event loop.
+pub struct WindowHandler<F> {
+    attributes: WindowAttributes,
+    function: Option<F>,
+    result: Result<()>,
+}
+
+impl<F: FnOnce(&Window) -> Result<()>> WindowHandler<F> {
+    pub fn new(attributes: WindowAttributes, function: F) -> Self {
+        Self {
+            attributes,
+            function: Some(function),
+            result: Ok(()),
+        }
+    }
+
+    /// Returns the result of the function.
+    pub fn finish(self) -> Result<()> {
+        self.result
+    }
+}
+
+impl<F: FnOnce(&Window) -> Result<()>> ApplicationHandler for WindowHandler<F> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if let Some(function) = self.function.take() {
+            self.result = event_loop
+                .create_window(self.attributes.clone())
+                .map_err(|e| anyhow!(e))
+                .and_then(|window| function(&window));
+        }
+
+        event_loop.exit();
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, _: WindowEvent) {}
+}
@@ -0,0 +1,597 @@
+//! Hierarchical-Z (Hi-Z) occlusion culling, which rejects the draw commands
+//! hidden behind the depth of the previous frame before GPU culling tests
+//! them against the view frustum (see `Culling`).
+//!
+//! After the main pass, the depth buffer is copied into the first level of a
+//! pyramid of depths (`hiz_depth.comp`, or `hiz_depth_ms.comp` with
+//! multisampling), and each further level is built from the farthest depths of
+//! the previous level (`hiz_reduce.comp`), so a texel of any level is at least
+//! as far as everything it covers. In the next frame, a compute shader
+//! (`hiz_cull.comp`) projects the bounding sphere of each draw command with the
+//! view-projection matrix of the previous frame and compares its nearest depth
+//! with the farthest depth of the level where its bounds cover at most 2x2
+//! texels. The instance counts of the draw commands which are entirely behind
+//! that depth are cleared, so frustum culling skips them.
+//!
+//! The pyramid lags a frame behind, so objects can appear a frame late when
+//! the camera moves quickly.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view, get_image_barrier, Image};
+use crate::indirect::IndirectDraws;
+use crate::pipeline::create_compute_pipeline;
+use crate::rendering::get_depth_aspect_mask;
+use crate::shaders;
+use crate::vertex::Mat4;
+
+/// The format of the levels of the pyramid.
+const HIZ_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+/// The size of the workgroups of the compute shaders which build the pyramid
+/// (in each dimension).
+const BUILD_WORKGROUP_SIZE: u32 = 8;
+
+/// The number of draw commands tested by a workgroup of the compute shader
+/// which culls them.
+const CULL_WORKGROUP_SIZE: u32 = 64;
+
+/// The push constants of the compute shader which culls the draw commands.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct HiZPushConstants {
+    view_proj: Mat4,
+    command_count: u32,
+}
+
+/// The Hi-Z pyramid and the Vulkan handles used to build it and cull the draw
+/// commands with it.
+///
+/// These are recreated with the swapchain.
+#[derive(Debug, Default)]
+pub struct HiZ {
+    /// The pyramid (with a view of every level).
+    pyramid: Image,
+    /// A view of each level of the pyramid.
+    levels: Vec<Owned<vk::ImageView>>,
+    sampler: Owned<vk::Sampler>,
+    /// The descriptor sets of the levels, which read the depth buffer or the
+    /// previous level at binding 0 and write the level at binding 1.
+    build_descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    build_descriptor_sets: Vec<vk::DescriptorSet>,
+    /// The descriptor sets of the culling pass (one per swapchain image).
+    cull_descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    cull_descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    depth_pipeline_layout: Owned<vk::PipelineLayout>,
+    depth_pipeline: Owned<vk::Pipeline>,
+    reduce_pipeline_layout: Owned<vk::PipelineLayout>,
+    reduce_pipeline: Owned<vk::Pipeline>,
+    cull_pipeline_layout: Owned<vk::PipelineLayout>,
+    cull_pipeline: Owned<vk::Pipeline>,
+    /// The view-projection matrix of the frame the pyramid was last built
+    /// from (if it has been built since it was created).
+    pub view_proj: Option<Mat4>,
+}
+
+impl HiZ {
+    /// Returns whether the draw commands are culled by occlusion.
+    pub fn is_enabled(&self) -> bool {
+        !self.cull_pipeline.is_null()
+    }
+}
+
+/// Returns the number of levels of a pyramid for a depth buffer of the
+/// supplied size (down to a single texel).
+pub fn get_hiz_levels(extent: vk::Extent2D) -> u32 {
+    32 - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+/// Returns the size of a level of a pyramid for a depth buffer of the
+/// supplied size.
+pub fn get_hiz_level_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
+
+/// Creates the pyramid and the compute pipelines which build it and cull the
+/// draw commands with it (unless the draw commands aren't culled on the GPU
+/// or occlusion culling is disabled in the options).
+pub unsafe fn create_hiz(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.options.occlusion_culling || !data.culling.is_enabled() {
+        return Ok(());
+    }
+
+    // Pyramid
+
+    let extent = data.swapchain.extent;
+    let level_count = get_hiz_levels(extent);
+
+    let mut pyramid = create_image(
+        instance,
+        device,
+        data,
+        extent.width,
+        extent.height,
+        level_count,
+        vk::SampleCountFlags::_1,
+        HIZ_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    pyramid.view = create_image_view(
+        device,
+        pyramid.image,
+        HIZ_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        level_count,
+    )?;
+
+    for level in 0..level_count {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(level)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(pyramid.image)
+            .view_type(vk::ImageViewType::_2D)
+            .format(HIZ_FORMAT)
+            .subresource_range(subresource_range);
+
+        let level = device.create_image_view(&info, None)?;
+        data.hiz.levels.push(Owned::new(device, level));
+    }
+
+    data.hiz.pyramid = pyramid;
+
+    // Sampler
+
+    // The levels are read texel by texel, and the culling pass reads the
+    // nearest texel of a level.
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .min_lod(0.0)
+        .max_lod(level_count as f32);
+
+    data.hiz.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Descriptor Set Layouts
+
+    let binding = |binding: u32, type_: vk::DescriptorType| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(type_)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    };
+
+    let bindings = &[
+        binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        binding(1, vk::DescriptorType::STORAGE_IMAGE),
+    ];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.hiz.build_descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // The draw commands, the cull commands, the stats, and the pyramid.
+    let bindings = &[
+        binding(0, vk::DescriptorType::STORAGE_BUFFER),
+        binding(1, vk::DescriptorType::STORAGE_BUFFER),
+        binding(2, vk::DescriptorType::STORAGE_BUFFER),
+        binding(3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+    ];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.hiz.cull_descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let images = data.swapchain.images.len() as u32;
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(level_count + images);
+
+    let storage_image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(level_count);
+
+    let storage_buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(images * 3);
+
+    let pool_sizes = &[sampler_size, storage_image_size, storage_buffer_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(level_count + images);
+
+    data.hiz.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Sets (Build)
+
+    let layouts = vec![*data.hiz.build_descriptor_set_layout; level_count as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.hiz.descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.hiz.build_descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // The first level reads the depth buffer, and each further level reads
+    // the previous level.
+    for (level, descriptor_set) in data.hiz.build_descriptor_sets.iter().enumerate() {
+        let read_info = if level == 0 {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(data.depth_image.view)
+                .sampler(*data.hiz.sampler)
+        } else {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(*data.hiz.levels[level - 1])
+                .sampler(*data.hiz.sampler)
+        };
+
+        let write_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(*data.hiz.levels[level]);
+
+        let read_infos = &[read_info];
+        let read = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(read_infos);
+
+        let write_infos = &[write_info];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(write_infos);
+
+        device.update_descriptor_sets(&[read, write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Descriptor Sets (Cull)
+
+    let layouts = vec![*data.hiz.cull_descriptor_set_layout; images as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.hiz.descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.hiz.cull_descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // The draw commands and cull commands are bound each frame (see
+    // `update_hiz_descriptor_set`).
+    for (descriptor_set, stats) in data
+        .hiz
+        .cull_descriptor_sets
+        .iter()
+        .zip(&data.culling.stats)
+    {
+        let stats_info = vk::DescriptorBufferInfo::builder()
+            .buffer(stats.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+
+        let stats_infos = &[stats_info];
+        let stats_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(2)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(stats_infos);
+
+        let pyramid_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(data.hiz.pyramid.view)
+            .sampler(*data.hiz.sampler);
+
+        let pyramid_infos = &[pyramid_info];
+        let pyramid_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(3)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(pyramid_infos);
+
+        device.update_descriptor_sets(
+            &[stats_write, pyramid_write],
+            &[] as &[vk::CopyDescriptorSet],
+        );
+    }
+
+    // Pipelines
+
+    // A multisampled depth buffer is read with a different type of sampler.
+    let depth_shader = if data.msaa_samples == vk::SampleCountFlags::_1 {
+        shaders::HIZ_DEPTH_COMP
+    } else {
+        shaders::HIZ_DEPTH_MS_COMP
+    };
+
+    (data.hiz.depth_pipeline_layout, data.hiz.depth_pipeline) = create_compute_pipeline(
+        device,
+        *data.hiz.build_descriptor_set_layout,
+        depth_shader,
+        0,
+    )?;
+
+    (data.hiz.reduce_pipeline_layout, data.hiz.reduce_pipeline) = create_compute_pipeline(
+        device,
+        *data.hiz.build_descriptor_set_layout,
+        shaders::HIZ_REDUCE_COMP,
+        0,
+    )?;
+
+    (data.hiz.cull_pipeline_layout, data.hiz.cull_pipeline) = create_compute_pipeline(
+        device,
+        *data.hiz.cull_descriptor_set_layout,
+        shaders::HIZ_CULL_COMP,
+        size_of::<HiZPushConstants>(),
+    )?;
+
+    Ok(())
+}
+
+/// Updates the descriptor set of the culling pass for the swapchain image at
+/// the supplied index to bind the supplied draw command and cull command
+/// buffers (if the draw commands are culled by occlusion).
+pub unsafe fn update_hiz_descriptor_set(
+    device: &Device,
+    data: &AppData,
+    image_index: usize,
+    buffers: [vk::Buffer; 2],
+) {
+    if !data.hiz.is_enabled() {
+        return;
+    }
+
+    let buffer_infos = buffers.map(|buffer| {
+        [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64)
+            .build()]
+    });
+
+    let writes = buffer_infos
+        .iter()
+        .enumerate()
+        .map(|(binding, buffer_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.hiz.cull_descriptor_sets[image_index])
+                .dst_binding(binding as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(buffer_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+}
+
+/// Records clearing the instance counts of the supplied draw commands which
+/// are occluded in the pyramid (if the draw commands are culled by occlusion
+/// and the pyramid has been built), after which they are ready to be culled
+/// against the view frustum.
+pub unsafe fn cmd_occlusion_cull(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    draws: &IndirectDraws,
+) {
+    let Some(view_proj) = data.hiz.view_proj.filter(|_| data.hiz.is_enabled()) else {
+        return;
+    };
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.hiz.cull_pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.hiz.cull_pipeline_layout,
+        0,
+        &[data.hiz.cull_descriptor_sets[image_index]],
+        &[],
+    );
+
+    let push_constants = HiZPushConstants {
+        view_proj,
+        command_count: draws.command_count,
+    };
+
+    let push_constants = std::slice::from_raw_parts(
+        &push_constants as *const HiZPushConstants as *const u8,
+        size_of::<HiZPushConstants>(),
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *data.hiz.cull_pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants,
+    );
+
+    let workgroups = draws.command_count.div_ceil(CULL_WORKGROUP_SIZE);
+    device.cmd_dispatch(command_buffer, workgroups, 1, 1);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Records building the pyramid from the depth buffer after the main pass,
+/// after which it is ready to be read by the culling pass of the next frame.
+///
+/// The depth buffer is left ready to be cleared by the next main pass.
+pub unsafe fn cmd_build_hiz(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    let depth_aspect_mask = get_depth_aspect_mask(data.depth_format);
+    let level_count = data.hiz.levels.len() as u32;
+
+    // The contents of the pyramid are discarded, but the culling pass has to
+    // be done with them.
+    let mut pyramid_barrier = get_image_barrier(
+        data.hiz.pyramid.image,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::GENERAL,
+        vk::AccessFlags::empty(),
+        vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+    );
+    pyramid_barrier.subresource_range.level_count = level_count;
+
+    let depth_barrier = get_image_barrier(
+        data.depth_image.image,
+        depth_aspect_mask,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        vk::AccessFlags::SHADER_READ,
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[pyramid_barrier, depth_barrier],
+    );
+
+    // Levels
+
+    for level in 0..level_count {
+        let (pipeline, pipeline_layout) = if level == 0 {
+            (*data.hiz.depth_pipeline, *data.hiz.depth_pipeline_layout)
+        } else {
+            (*data.hiz.reduce_pipeline, *data.hiz.reduce_pipeline_layout)
+        };
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &[data.hiz.build_descriptor_sets[level as usize]],
+            &[],
+        );
+
+        let extent = get_hiz_level_extent(data.swapchain.extent, level);
+        device.cmd_dispatch(
+            command_buffer,
+            extent.width.div_ceil(BUILD_WORKGROUP_SIZE),
+            extent.height.div_ceil(BUILD_WORKGROUP_SIZE),
+            1,
+        );
+
+        // Each level is read by the next level (and the last level by the
+        // culling pass of the next frame).
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+
+    // The depth buffer is returned to the layout the main pass leaves it in,
+    // so the next main pass waits for the pyramid to be built before clearing
+    // it.
+    let depth_barrier = get_image_barrier(
+        data.depth_image.image,
+        depth_aspect_mask,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        vk::AccessFlags::empty(),
+        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[depth_barrier],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let extent = vk::Extent2D {
+            width: 1280,
+            height: 720,
+        };
+
+        // 1280x720 is reduced down to 1x1 in 10 steps (the height reaches 1
+        // first and stays there).
+        assert_eq!(get_hiz_levels(extent), 11);
+        assert_eq!(
+            get_hiz_level_extent(extent, 3),
+            vk::Extent2D {
+                width: 160,
+                height: 90
+            }
+        );
+        assert_eq!(
+            get_hiz_level_extent(extent, 10),
+            vk::Extent2D {
+                width: 1,
+                height: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! Image-based lighting (IBL), which lights the scene with the environment map
+//! (see `Environment`) using the split sum approximation.
+//!
+//! The lighting is precomputed by compute shaders when the environment map is
+//! loaded:
+//!
+//! * the diffuse irradiance map (a small cube map of the cosine-weighted
+//!   average of the environment around each direction),
+//! * the prefiltered environment map (a cube map whose mip levels are the
+//!   environment convolved with the GGX specular lobe for increasing
+//!   roughness), and
+//! * the BRDF lookup table (the scale and bias of the specular reflectance
+//!   integrated over the hemisphere, by NdotV and roughness).
+//!
+//! These are bound as a second descriptor set (set 1) of the pipeline layout
+//! of the materials (see `create_pipeline`).
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::image::{create_layered_image, create_layered_image_view, Image};
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::upload::{flush_uploads, get_graphics_upload_command_buffer};
+
+/// The format of the irradiance map, the prefiltered environment map, and the
+/// BRDF lookup table (which, unlike two-component formats, can be written as
+/// a storage image without the extended storage image formats feature).
+const IBL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The size of the faces of the irradiance map (in pixels), which is smooth
+/// enough to be tiny.
+const IRRADIANCE_SIZE: u32 = 32;
+
+/// The size of the faces of the first mip level of the prefiltered
+/// environment map (in pixels).
+const PREFILTERED_SIZE: u32 = 128;
+
+/// The number of mip levels of the prefiltered environment map, from a
+/// roughness of zero to a roughness of one.
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+
+/// The size of the BRDF lookup table (in pixels).
+const BRDF_LUT_SIZE: u32 = 512;
+
+/// The size of the workgroups of the compute shaders (see `irradiance.comp`,
+/// `prefilter.comp`, and `brdf_lut.comp`).
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The precomputed image-based lighting and the descriptor set it is bound
+/// with.
+#[derive(Debug, Default)]
+pub struct Ibl {
+    pub irradiance: Image,
+    pub prefiltered: Image,
+    pub brdf_lut: Image,
+    lut_sampler: Owned<vk::Sampler>,
+    pub descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+/// The resources which are only used while the lighting is precomputed.
+#[derive(Debug, Default)]
+struct IblPasses {
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipelines: Vec<Owned<vk::Pipeline>>,
+    views: Vec<Owned<vk::ImageView>>,
+}
+
+/// Precomputes the image-based lighting from the environment map and creates
+/// the descriptor set it is bound with.
+pub unsafe fn create_ibl(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    // Images
+
+    data.ibl.irradiance = create_ibl_image(
+        instance,
+        device,
+        data,
+        IRRADIANCE_SIZE,
+        1,
+        vk::ImageViewType::CUBE,
+    )?;
+    data.ibl.prefiltered = create_ibl_image(
+        instance,
+        device,
+        data,
+        PREFILTERED_SIZE,
+        PREFILTERED_MIP_LEVELS,
+        vk::ImageViewType::CUBE,
+    )?;
+    data.ibl.brdf_lut = create_ibl_image(
+        instance,
+        device,
+        data,
+        BRDF_LUT_SIZE,
+        1,
+        vk::ImageViewType::_2D,
+    )?;
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.ibl.lut_sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Precompute
+
+    let mut passes = IblPasses::default();
+    let result = record_ibl_passes(device, data, &mut passes);
+
+    // The lighting has to be precomputed before the resources used by the
+    // passes are destroyed.
+    if result.is_ok() {
+        flush_uploads(instance, device, data)?;
+        device.queue_wait_idle(data.graphics_queue)?;
+    }
+
+    drop(passes);
+
+    result?;
+
+    // Descriptor Set
+
+    let bindings = (0..3)
+        .map(|i| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(i)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.ibl.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(3);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.ibl.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = &[*data.ibl.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.ibl.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.ibl.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    // The cube maps are sampled with the sampler of the environment map.
+    let images = [
+        (data.ibl.irradiance.view, *data.environment.sampler),
+        (data.ibl.prefiltered.view, *data.environment.sampler),
+        (data.ibl.brdf_lut.view, *data.ibl.lut_sampler),
+    ];
+
+    let image_infos = images
+        .iter()
+        .map(|(view, sampler)| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(*view)
+                .sampler(*sampler)
+                .build()]
+        })
+        .collect::<Vec<_>>();
+
+    let writes = image_infos
+        .iter()
+        .enumerate()
+        .map(|(i, image_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.ibl.descriptor_set)
+                .dst_binding(i as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/// Creates an image (and a view of it) which is written by the compute
+/// shaders and then sampled.
+unsafe fn create_ibl_image(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: u32,
+    mip_levels: u32,
+    view_type: vk::ImageViewType,
+) -> Result<Image> {
+    let (flags, array_layers) = if view_type == vk::ImageViewType::CUBE {
+        (vk::ImageCreateFlags::CUBE_COMPATIBLE, 6)
+    } else {
+        (vk::ImageCreateFlags::empty(), 1)
+    };
+
+    let mut image = create_layered_image(
+        instance,
+        device,
+        data,
+        size,
+        size,
+        mip_levels,
+        array_layers,
+        flags,
+        vk::SampleCountFlags::_1,
+        IBL_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image.view = create_layered_image_view(
+        device,
+        image.image,
+        view_type,
+        IBL_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        mip_levels,
+        array_layers,
+    )?;
+
+    Ok(image)
+}
+
+/// Records the compute passes which precompute the lighting, adding the
+/// resources they use to `passes` (so they can be destroyed even if this
+/// fails).
+unsafe fn record_ibl_passes(
+    device: &Device,
+    data: &mut AppData,
+    passes: &mut IblPasses,
+) -> Result<()> {
+    // Descriptor Sets
+
+    // Every pass samples the environment map (binding 0) and writes a storage
+    // image (binding 1), although the BRDF lookup table doesn't depend on the
+    // environment (so its shader ignores binding 0).
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+    ];
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    passes.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // One set for the irradiance map, one for each mip level of the
+    // prefiltered environment map, and one for the BRDF lookup table.
+    let sets = PREFILTERED_MIP_LEVELS + 2;
+
+    let pool_sizes = [
+        vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(sets)
+            .build(),
+        vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(sets)
+            .build(),
+    ];
+
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(sets);
+
+    passes.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = vec![*passes.descriptor_set_layout; sets as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*passes.descriptor_pool)
+        .set_layouts(&layouts);
+
+    let descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // The storage images are written through 2D array views of single mip
+    // levels.
+    let mut targets = vec![(data.ibl.irradiance.image, 0, 6)];
+    targets.extend((0..PREFILTERED_MIP_LEVELS).map(|i| (data.ibl.prefiltered.image, i, 6)));
+    targets.push((data.ibl.brdf_lut.image, 0, 1));
+
+    for ((image, mip_level, layers), descriptor_set) in targets.into_iter().zip(&descriptor_sets) {
+        let view_type = if layers == 1 {
+            vk::ImageViewType::_2D
+        } else {
+            vk::ImageViewType::_2D_ARRAY
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(mip_level)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(layers);
+
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(view_type)
+            .format(IBL_FORMAT)
+            .subresource_range(subresource_range);
+
+        let view = device.create_image_view(&info, None)?;
+        passes.views.push(Owned::new(device, view));
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(view);
+
+        let image_info = &[info];
+        let storage_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(image_info);
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.environment.cubemap.view)
+            .sampler(*data.environment.sampler);
+
+        let image_info = &[info];
+        let environment_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+
+        device.update_descriptor_sets(
+            &[environment_write, storage_write],
+            &[] as &[vk::CopyDescriptorSet],
+        );
+    }
+
+    // Pipelines
+
+    // The prefilter pass has the roughness of the mip level as a push
+    // constant.
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(4);
+
+    let set_layouts = &[*passes.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    passes.pipeline_layout = Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    for shader in [
+        shaders::IRRADIANCE_COMP,
+        shaders::PREFILTER_COMP,
+        shaders::BRDF_LUT_COMP,
+    ] {
+        let comp_shader_module = create_shader_module(device, shader)?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(comp_shader_module)
+            .name(b"main\0");
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(*passes.pipeline_layout);
+
+        let result = device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None);
+        device.destroy_shader_module(comp_shader_module, None);
+        passes.pipelines.push(Owned::new(device, result?.0));
+    }
+
+    // Record
+
+    let command_buffer = get_graphics_upload_command_buffer(device, data)?;
+    let images = [
+        (data.ibl.irradiance.image, 6),
+        (data.ibl.prefiltered.image, 6),
+        (data.ibl.brdf_lut.image, 1),
+    ];
+
+    let barriers = images.map(|(image, layers)| {
+        vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(get_ibl_subresource_range(layers))
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .build()
+    });
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+
+    // Irradiance
+
+    let dispatch = |pipeline: vk::Pipeline, descriptor_set, size: u32, layers| {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *passes.pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        let groups = size.div_ceil(WORKGROUP_SIZE);
+        device.cmd_dispatch(command_buffer, groups, groups, layers);
+    };
+
+    dispatch(*passes.pipelines[0], descriptor_sets[0], IRRADIANCE_SIZE, 6);
+
+    // Prefiltered
+
+    for i in 0..PREFILTERED_MIP_LEVELS {
+        let roughness = i as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32;
+        device.cmd_push_constants(
+            command_buffer,
+            *passes.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &roughness.to_ne_bytes(),
+        );
+
+        let size = (PREFILTERED_SIZE >> i).max(1);
+        dispatch(
+            *passes.pipelines[1],
+            descriptor_sets[1 + i as usize],
+            size,
+            6,
+        );
+    }
+
+    // BRDF Lookup Table
+
+    dispatch(
+        *passes.pipelines[2],
+        descriptor_sets[sets as usize - 1],
+        BRDF_LUT_SIZE,
+        1,
+    );
+
+    let barriers = images.map(|(image, layers)| {
+        vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(get_ibl_subresource_range(layers))
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build()
+    });
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+
+    Ok(())
+}
+
+/// Returns the subresource range of every mip level of an image with the
+/// supplied number of array layers.
+fn get_ibl_subresource_range(array_layers: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(vk::REMAINING_MIP_LEVELS)
+        .base_array_layer(0)
+        .layer_count(array_layers)
+        .build()
+}
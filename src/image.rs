@@ -0,0 +1,279 @@
+//! Helpers shared by everything that creates or transitions images.
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::get_memory_type_index;
+use crate::memory::{get_allocator, Allocation, Allocator};
+
+/// An image, its backing memory and an optional view which are destroyed
+/// when dropped.
+///
+/// A default image owns no Vulkan handles.
+#[derive(Debug, Default)]
+pub struct Image {
+    allocator: Option<Allocator>,
+    pub image: vk::Image,
+    pub allocation: Allocation,
+    pub view: vk::ImageView,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Some(allocator) = &self.allocator {
+            unsafe {
+                let device = allocator.device();
+                device.destroy_image_view(self.view, None);
+                device.destroy_image(self.image, None);
+                allocator.free(&mut self.allocation);
+            }
+        }
+    }
+}
+
+/// Creates an image and its backing memory (but not a view).
+pub unsafe fn create_image(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<Image> {
+    create_layered_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        1,
+        vk::ImageCreateFlags::empty(),
+        samples,
+        format,
+        tiling,
+        usage,
+        properties,
+    )
+}
+
+/// Creates an image with array layers (e.g., the faces of a cube map) and its
+/// backing memory (but not a view).
+pub unsafe fn create_layered_image(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    array_layers: u32,
+    flags: vk::ImageCreateFlags,
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<Image> {
+    // Image
+
+    let info = vk::ImageCreateInfo::builder()
+        .flags(flags)
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(array_layers)
+        .format(format)
+        .tiling(tiling)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .samples(samples)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    let allocator = get_allocator(data)?;
+    let mut image = Image {
+        allocator: Some(allocator.clone()),
+        image: device.create_image(&info, None)?,
+        allocation: Allocation::default(),
+        view: vk::ImageView::null(),
+    };
+
+    // Memory
+
+    let requirements = device.get_image_memory_requirements(image.image);
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
+
+    image.allocation = allocator.allocate(
+        requirements,
+        memory_type_index,
+        tiling == vk::ImageTiling::LINEAR,
+    )?;
+
+    device.bind_image_memory(
+        image.image,
+        image.allocation.memory,
+        image.allocation.offset,
+    )?;
+
+    Ok(image)
+}
+
+pub unsafe fn create_image_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    mip_levels: u32,
+) -> Result<vk::ImageView> {
+    create_layered_image_view(
+        device,
+        image,
+        vk::ImageViewType::_2D,
+        format,
+        aspects,
+        mip_levels,
+        1,
+    )
+}
+
+/// Creates a view of every mip level and array layer of an image.
+pub unsafe fn create_layered_image_view(
+    device: &Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    mip_levels: u32,
+    array_layers: u32,
+) -> Result<vk::ImageView> {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspects)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(array_layers);
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    Ok(device.create_image_view(&info, None)?)
+}
+
+pub unsafe fn cmd_transition_image_layout(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    mip_levels: u32,
+) -> Result<()> {
+    cmd_transition_image_layers(
+        device,
+        command_buffer,
+        image,
+        format,
+        old_layout,
+        new_layout,
+        mip_levels,
+        1,
+    )
+}
+
+/// Transitions every mip level and array layer of an image between layouts.
+pub unsafe fn cmd_transition_image_layers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    mip_levels: u32,
+    array_layers: u32,
+) -> Result<()> {
+    let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
+        match (old_layout, new_layout) {
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+            (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+            _ => return Err(anyhow!("Unsupported image layout transition!")),
+        };
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(array_layers);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    Ok(())
+}
+
+/// Returns a barrier which transitions an image between layouts.
+pub fn get_image_barrier(
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build()
+}
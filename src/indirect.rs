@@ -0,0 +1,619 @@
+//! Multi-draw indirect rendering, which draws the whole scene with an
+//! indirect draw call per material (and opacity).
+//!
+//! The vertices and indices of every mesh are consolidated into a shared
+//! vertex buffer and a shared index buffer when the meshes are created, so
+//! the draws don't have to bind the buffers of their meshes. Every frame, the
+//! draw commands (`vk::DrawIndexedIndirectCommand`) of the draws are written
+//! to an indirect buffer on the CPU, grouped by material, along with the model
+//! matrix of each draw, which is read from an instance buffer like the
+//! attributes of instances (see `InstanceData`): the first instance of each
+//! draw command is the index of its draw.
+//!
+//! Without the `multiDrawIndirect` feature, each draw command is drawn by its
+//! own indirect draw call instead. Without `drawIndirectFirstInstance`, the
+//! scene is drawn as usual.
+//!
+//! With GPU culling (see `Culling`), the draw commands are culled and
+//! compacted on the GPU before they are drawn.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::Range;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_2::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::culling::{get_bounding_sphere, update_culling_descriptor_set, CullCommand};
+use crate::handle::Owned;
+use crate::hiz::update_hiz_descriptor_set;
+use crate::instancing::InstanceData;
+use crate::material::MATERIAL_PUSH_CONSTANTS_SIZE;
+use crate::occlusion::{cmd_begin_predicate, cmd_end_predicate};
+use crate::pipeline::create_material_pipeline;
+use crate::resources::{MaterialHandle, MeshHandle};
+use crate::scene::Draw;
+use crate::upload::upload_to_buffer;
+use crate::vertex::Vertex;
+
+/// Where the vertices and indices of a mesh start in the shared vertex and
+/// index buffers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MeshOffset {
+    pub vertex_offset: i32,
+    pub first_index: u32,
+}
+
+/// The shared vertex and index buffers, the indirect and instance buffers of
+/// each swapchain image, and the pipelines of the materials.
+#[derive(Debug, Default)]
+pub struct Indirect {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    offsets: HashMap<MeshHandle, MeshOffset>,
+    frames: Vec<IndirectFrame>,
+    /// The pipeline of each material which draws the draw commands (which
+    /// are recreated with the swapchain).
+    pipelines: HashMap<MaterialHandle, Owned<vk::Pipeline>>,
+}
+
+impl Indirect {
+    /// Returns whether the meshes are in the shared buffers.
+    pub fn is_enabled(&self) -> bool {
+        !self.offsets.is_empty()
+    }
+
+    /// Returns whether the supplied draw is drawn indirectly, which requires
+    /// its mesh to be in the shared buffers and its material to have a
+    /// pipeline (materials created after the swapchain don't until it is
+    /// recreated).
+    pub fn contains(&self, draw: &Draw) -> bool {
+        self.offsets.contains_key(&draw.mesh)
+            && self.pipelines.contains_key(&draw.material.material)
+    }
+}
+
+/// The indirect and instance buffers of a swapchain image, which grow to fit
+/// the draws.
+#[derive(Debug, Default)]
+struct IndirectFrame {
+    commands: Buffer,
+    instances: Buffer,
+    /// The buffers read and written by GPU culling (see `cmd_cull_draws`),
+    /// which are only created if it is enabled.
+    cull_commands: Buffer,
+    culled_commands: Buffer,
+    counts: Buffer,
+    /// The number of draw commands (and instances) the buffers can hold.
+    capacity: usize,
+}
+
+/// The draw commands of a material (and opacity).
+#[derive(Clone, Debug)]
+struct IndirectBatch {
+    pipeline: vk::Pipeline,
+    descriptor_set: vk::DescriptorSet,
+    push_constants: [u8; MATERIAL_PUSH_CONSTANTS_SIZE],
+    commands: Range<u32>,
+}
+
+/// The handles needed to draw the draw commands written for a frame, which
+/// can be sent to the threads recording draw commands.
+#[derive(Clone, Debug)]
+pub struct IndirectDraws {
+    vertex_buffer: vk::Buffer,
+    index_buffer: vk::Buffer,
+    commands: vk::Buffer,
+    instances: vk::Buffer,
+    batches: Vec<IndirectBatch>,
+    multi_draw: bool,
+    /// The number of draw commands.
+    pub command_count: u32,
+    /// The culled draw commands and the number of draw commands which
+    /// survived in each batch, which are drawn instead of the draw commands
+    /// (if the draw commands are culled).
+    pub culled_commands: vk::Buffer,
+    pub counts: vk::Buffer,
+    /// Whether the draw calls of the batches are predicated on their counts
+    /// with conditional rendering (if the draw commands are culled).
+    conditional_rendering: bool,
+}
+
+impl IndirectDraws {
+    /// Returns the number of indirect draw calls.
+    pub fn draw_calls(&self) -> usize {
+        if self.multi_draw || self.is_culled() {
+            self.batches.len()
+        } else {
+            self.batches.iter().map(|b| b.commands.len()).sum()
+        }
+    }
+
+    /// Returns whether the draw commands are culled on the GPU before they
+    /// are drawn.
+    pub fn is_culled(&self) -> bool {
+        !self.counts.is_null()
+    }
+
+    /// Records binding the shared buffers and drawing the draw commands of
+    /// each material with the supplied pipeline layout (see `create_pipeline`)
+    /// and image-based lighting descriptor set.
+    pub unsafe fn draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        ibl_descriptor_set: vk::DescriptorSet,
+    ) {
+        device.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[self.vertex_buffer, self.instances],
+            &[0, 0],
+        );
+        device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
+
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        for (index, batch) in self.batches.iter().enumerate() {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                batch.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[batch.descriptor_set, ibl_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                72,
+                &batch.push_constants,
+            );
+
+            // The culled draw commands of a batch are compacted to the start
+            // of its range, and the number which survived is read from the
+            // count buffer.
+            //
+            // With conditional rendering, the draw call of a batch whose draw
+            // commands were all culled is discarded before the draw commands
+            // are fetched.
+            if self.is_culled() {
+                let offset = batch.commands.start as u64 * stride as u64;
+                let count_offset = (index * size_of::<u32>()) as u64;
+                let predicate = self
+                    .conditional_rendering
+                    .then_some((self.counts, count_offset));
+                cmd_begin_predicate(device, command_buffer, predicate);
+                device.cmd_draw_indexed_indirect_count(
+                    command_buffer,
+                    self.culled_commands,
+                    offset,
+                    self.counts,
+                    count_offset,
+                    batch.commands.len() as u32,
+                    stride,
+                );
+                cmd_end_predicate(device, command_buffer, predicate);
+            } else if self.multi_draw {
+                let offset = batch.commands.start as u64 * stride as u64;
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    self.commands,
+                    offset,
+                    batch.commands.len() as u32,
+                    stride,
+                );
+            } else {
+                for command in batch.commands.clone() {
+                    let offset = command as u64 * stride as u64;
+                    device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        self.commands,
+                        offset,
+                        1,
+                        stride,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returns the draw commands which draw the supplied submeshes of a mesh at
+/// the supplied offset in the shared buffers as the supplied instance.
+pub fn get_draw_commands(
+    offset: MeshOffset,
+    submeshes: &[Range<u32>],
+    instance: u32,
+) -> Vec<vk::DrawIndexedIndirectCommand> {
+    submeshes
+        .iter()
+        .map(|s| vk::DrawIndexedIndirectCommand {
+            index_count: s.len() as u32,
+            instance_count: 1,
+            first_index: offset.first_index + s.start,
+            vertex_offset: offset.vertex_offset,
+            first_instance: instance,
+        })
+        .collect()
+}
+
+/// Consolidates the vertices and indices of the meshes into the shared
+/// vertex and index buffers (which does nothing unless indirect rendering is
+/// enabled in the options).
+pub unsafe fn create_indirect(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.indirect {
+        return Ok(());
+    }
+
+    // The draw commands select their model matrices with their first
+    // instances.
+    if !data.features.draw_indirect_first_instance {
+        warn!("Indirect rendering isn't supported by the device.");
+        return Ok(());
+    }
+
+    // Geometry
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut offsets = HashMap::new();
+
+    let handles = data
+        .resources
+        .meshes
+        .iter()
+        .map(|(h, _)| h)
+        .collect::<Vec<_>>();
+    for handle in handles {
+        let geometry = data
+            .resources
+            .meshes
+            .get_mut(handle)
+            .and_then(|m| m.geometry.take());
+        if let Some((mesh_vertices, mesh_indices)) = geometry {
+            let offset = MeshOffset {
+                vertex_offset: vertices.len() as i32,
+                first_index: indices.len() as u32,
+            };
+
+            offsets.insert(handle, offset);
+            vertices.extend(mesh_vertices);
+            indices.extend(mesh_indices);
+        }
+    }
+
+    if indices.is_empty() {
+        return Ok(());
+    }
+
+    // Buffers
+
+    let vertex_buffer = create_buffer(
+        instance,
+        device,
+        data,
+        (size_of::<Vertex>() * vertices.len()) as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    upload_to_buffer(instance, device, data, &vertices, vertex_buffer.buffer)?;
+
+    let index_buffer = create_buffer(
+        instance,
+        device,
+        data,
+        (size_of::<u32>() * indices.len()) as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    upload_to_buffer(instance, device, data, &indices, index_buffer.buffer)?;
+
+    data.indirect = Indirect {
+        vertex_buffer,
+        index_buffer,
+        offsets,
+        ..Default::default()
+    };
+
+    Ok(())
+}
+
+/// Creates the pipelines of the materials which draw the draw commands (if
+/// there are shared buffers).
+pub unsafe fn create_indirect_pipelines(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.indirect.is_enabled() {
+        return Ok(());
+    }
+
+    let materials = data
+        .resources
+        .materials
+        .iter()
+        .map(|(h, m)| (h, m.info))
+        .collect::<Vec<_>>();
+    for (handle, info) in materials {
+        let pipeline =
+            create_material_pipeline(instance, device, data, &info, vk::PolygonMode::FILL, true)?;
+        data.indirect.pipelines.insert(handle, pipeline);
+    }
+
+    Ok(())
+}
+
+/// Writes the draw commands and model matrices of the supplied draws whose
+/// meshes are in the shared buffers to the buffers of the swapchain image at
+/// the supplied index, returning the handles needed to draw them (if there
+/// are any).
+pub unsafe fn prepare_indirect_draws(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    draws: &[Draw],
+) -> Result<Option<IndirectDraws>> {
+    if !data.indirect.is_enabled() {
+        return Ok(None);
+    }
+
+    // Commands
+
+    // The draws are grouped by material (and opacity), so each pipeline and
+    // descriptor set is only bound once.
+    let mut groups = HashMap::<_, Vec<&Draw>>::new();
+    let mut keys = vec![];
+    for draw in draws.iter().filter(|d| data.indirect.contains(d)) {
+        let Some(material) = data.resources.material(draw.material.material) else {
+            continue;
+        };
+
+        let key = (
+            draw.material.material,
+            draw.material.push_constants(&material.factors),
+        );
+        groups
+            .entry(key)
+            .or_insert_with(|| {
+                keys.push(key);
+                vec![]
+            })
+            .push(draw);
+    }
+
+    let mut commands = vec![];
+    let mut cull_commands = vec![];
+    let mut instances = vec![];
+    let mut batches = vec![];
+    for key in keys {
+        let (handle, push_constants) = key;
+        let Some(material) = data.resources.material(handle) else {
+            continue;
+        };
+
+        let start = commands.len() as u32;
+        for draw in &groups[&key] {
+            let Some(mesh) = data.resources.mesh(draw.mesh) else {
+                continue;
+            };
+
+            let submeshes = mesh
+                .submeshes
+                .iter()
+                .map(|s| s.indices.clone())
+                .collect::<Vec<_>>();
+            let offset = data.indirect.offsets[&draw.mesh];
+            commands.extend(get_draw_commands(
+                offset,
+                &submeshes,
+                instances.len() as u32,
+            ));
+
+            // The submeshes are culled with the bounds of the whole mesh.
+            let cull_command = CullCommand {
+                sphere: get_bounding_sphere(&mesh.bounds, &draw.model),
+                batch: batches.len() as u32,
+                batch_start: start,
+                _padding: [0; 2],
+            };
+            cull_commands.resize(commands.len(), cull_command);
+
+            instances.push(InstanceData {
+                model: draw.model,
+                color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            });
+        }
+
+        batches.push(IndirectBatch {
+            pipeline: *data.indirect.pipelines[&handle],
+            descriptor_set: material.descriptor_sets[image_index],
+            push_constants,
+            commands: start..commands.len() as u32,
+        });
+    }
+
+    if commands.is_empty() {
+        return Ok(None);
+    }
+
+    // Buffers
+
+    // The buffers of a swapchain image are no longer in use once it is being
+    // rendered to again, so they can be recreated if they are too small.
+    let count = data.swapchain.images.len();
+    data.indirect.frames.resize_with(count, Default::default);
+
+    let culling = data.culling.is_enabled();
+    let capacity = commands.len().max(instances.len());
+    if data.indirect.frames[image_index].capacity < capacity {
+        let capacity = capacity.next_power_of_two();
+        let commands_size = (size_of::<vk::DrawIndexedIndirectCommand>() * capacity) as u64;
+
+        let commands = create_buffer(
+            instance,
+            device,
+            data,
+            commands_size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let instances = create_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<InstanceData>() * capacity) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let mut frame = IndirectFrame {
+            commands,
+            instances,
+            capacity,
+            ..Default::default()
+        };
+
+        // There are at most as many batches as draw commands, so the count
+        // buffer has the same capacity.
+        if culling {
+            frame.cull_commands = create_buffer(
+                instance,
+                device,
+                data,
+                (size_of::<CullCommand>() * capacity) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            )?;
+
+            frame.culled_commands = create_buffer(
+                instance,
+                device,
+                data,
+                commands_size,
+                vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+
+            let mut usage = vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST;
+            if data.features.conditional_rendering {
+                usage |= vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT;
+            }
+
+            frame.counts = create_buffer(
+                instance,
+                device,
+                data,
+                (size_of::<u32>() * capacity) as u64,
+                usage,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+        }
+
+        data.indirect.frames[image_index] = frame;
+    }
+
+    let frame = &data.indirect.frames[image_index];
+
+    let memory = frame.commands.allocation.map()?;
+    memcpy(
+        commands.as_ptr(),
+        memory.cast::<vk::DrawIndexedIndirectCommand>(),
+        commands.len(),
+    );
+
+    let memory = frame.instances.allocation.map()?;
+    memcpy(
+        instances.as_ptr(),
+        memory.cast::<InstanceData>(),
+        instances.len(),
+    );
+
+    if culling {
+        let memory = frame.cull_commands.allocation.map()?;
+        memcpy(
+            cull_commands.as_ptr(),
+            memory.cast::<CullCommand>(),
+            cull_commands.len(),
+        );
+
+        update_culling_descriptor_set(
+            device,
+            data,
+            image_index,
+            [
+                frame.commands.buffer,
+                frame.cull_commands.buffer,
+                frame.culled_commands.buffer,
+                frame.counts.buffer,
+            ],
+        );
+        update_hiz_descriptor_set(
+            device,
+            data,
+            image_index,
+            [frame.commands.buffer, frame.cull_commands.buffer],
+        );
+    }
+
+    Ok(Some(IndirectDraws {
+        vertex_buffer: data.indirect.vertex_buffer.buffer,
+        index_buffer: data.indirect.index_buffer.buffer,
+        commands: frame.commands.buffer,
+        instances: frame.instances.buffer,
+        batches,
+        multi_draw: data.features.multi_draw_indirect,
+        command_count: commands.len() as u32,
+        culled_commands: frame.culled_commands.buffer,
+        counts: frame.counts.buffer,
+        conditional_rendering: data.features.conditional_rendering,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_commands() {
+        let offset = MeshOffset {
+            vertex_offset: 100,
+            first_index: 600,
+        };
+
+        let commands = get_draw_commands(offset, &[0..6, 6..12], 3);
+        assert_eq!(commands.len(), 2);
+
+        // The indices of the submeshes are relative to the start of the mesh
+        // in the shared index buffer, and every command draws the same
+        // instance.
+        assert_eq!(commands[1].index_count, 6);
+        assert_eq!(commands[1].first_index, 606);
+        assert_eq!(commands[1].vertex_offset, 100);
+        assert_eq!(commands[1].instance_count, 1);
+        assert_eq!(commands[0].first_instance, 3);
+        assert_eq!(commands[1].first_instance, 3);
+    }
+}
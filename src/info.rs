@@ -0,0 +1,176 @@
+//! A `vulkaninfo`-style report of the Vulkan implementation.
+
+use anyhow::{anyhow, Result};
+use vulkanalia::loader::{LibloadingLoader, LIBRARY};
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSurfaceExtension;
+use vulkanalia::Version;
+use winit::window::Window;
+
+use crate::app::{AppData, AppOptions};
+use crate::instance::{create_instance, create_surface};
+
+/// Prints the instance layers and extensions and the properties of every
+/// physical device (including their support for the surface of the supplied
+/// window).
+pub unsafe fn print_info(window: &Window) -> Result<()> {
+    let loader = LibloadingLoader::new(LIBRARY)?;
+    let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+
+    // Instance
+
+    println!("Instance");
+    println!("  Version: {}", entry.version()?);
+
+    println!("  Layers:");
+    for layer in entry.enumerate_instance_layer_properties()? {
+        println!(
+            "    {} ({}): {}",
+            layer.layer_name,
+            Version::from(layer.spec_version),
+            layer.description
+        );
+    }
+
+    println!("  Extensions:");
+    for extension in entry.enumerate_instance_extension_properties(None)? {
+        println!(
+            "    {} (revision {})",
+            extension.extension_name, extension.spec_version
+        );
+    }
+
+    // The report shouldn't include validation messages.
+    let mut data = AppData {
+        options: AppOptions {
+            validation: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let instance = create_instance(Some(window), &entry, &mut data)?;
+    let surface = create_surface(window, &instance, &mut data)?;
+
+    // Physical Devices
+
+    for (index, physical_device) in instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .enumerate()
+    {
+        println!();
+        print_physical_device(&instance, surface.surface, index, physical_device)?;
+    }
+
+    Ok(())
+}
+
+unsafe fn print_physical_device(
+    instance: &Instance,
+    surface: vk::SurfaceKHR,
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+) -> Result<()> {
+    let properties = instance.get_physical_device_properties(physical_device);
+
+    println!("Physical Device {}: {}", index, properties.device_name);
+    println!("  Type: {:?}", properties.device_type);
+    println!("  API version: {}", Version::from(properties.api_version));
+    println!("  Driver version: {:#x}", properties.driver_version);
+    println!(
+        "  Vendor / device ID: {:#06x} / {:#06x}",
+        properties.vendor_id, properties.device_id
+    );
+
+    // Queue Families
+
+    println!("  Queue Families:");
+    let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+    for (index, family) in queue_families.iter().enumerate() {
+        let present = instance.get_physical_device_surface_support_khr(
+            physical_device,
+            index as u32,
+            surface,
+        )?;
+        println!(
+            "    {}: {:?} x{} (present: {})",
+            index, family.queue_flags, family.queue_count, present
+        );
+    }
+
+    // Memory
+
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+
+    println!("  Memory Heaps:");
+    for (index, heap) in memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+    {
+        println!("    {}: {} MiB {:?}", index, heap.size >> 20, heap.flags);
+    }
+
+    println!("  Memory Types:");
+    for (index, type_) in memory.memory_types[..memory.memory_type_count as usize]
+        .iter()
+        .enumerate()
+    {
+        println!(
+            "    {}: heap {} {:?}",
+            index, type_.heap_index, type_.property_flags
+        );
+    }
+
+    // Limits
+
+    let limits = properties.limits;
+    println!("  Limits:");
+    println!(
+        "    max_image_dimension_2d: {}",
+        limits.max_image_dimension_2d
+    );
+    println!(
+        "    max_image_array_layers: {}",
+        limits.max_image_array_layers
+    );
+    println!(
+        "    max_push_constants_size: {}",
+        limits.max_push_constants_size
+    );
+    println!(
+        "    max_uniform_buffer_range: {}",
+        limits.max_uniform_buffer_range
+    );
+    println!(
+        "    max_bound_descriptor_sets: {}",
+        limits.max_bound_descriptor_sets
+    );
+    println!(
+        "    max_sampler_anisotropy: {}",
+        limits.max_sampler_anisotropy
+    );
+    println!(
+        "    framebuffer_color_sample_counts: {:?}",
+        limits.framebuffer_color_sample_counts
+    );
+    println!(
+        "    framebuffer_depth_sample_counts: {:?}",
+        limits.framebuffer_depth_sample_counts
+    );
+    println!("    timestamp_period: {} ns", limits.timestamp_period);
+
+    // Surface
+
+    println!("  Surface Formats:");
+    for format in instance.get_physical_device_surface_formats_khr(physical_device, surface)? {
+        println!("    {:?} / {:?}", format.format, format.color_space);
+    }
+
+    println!("  Present Modes:");
+    for mode in instance.get_physical_device_surface_present_modes_khr(physical_device, surface)? {
+        println!("    {:?}", mode);
+    }
+
+    Ok(())
+}
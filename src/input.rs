@@ -0,0 +1,465 @@
+//! Keyboard, mouse, and (with the `gamepad` feature) gamepad input, which is
+//! tracked per frame and mapped to named actions so the rest of our Vulkan app
+//! doesn't handle window events itself.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button, EventType, Gilrs};
+#[cfg(feature = "gamepad")]
+use log::*;
+use winit::event::{
+    DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent,
+};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// The distance a gamepad axis must be pushed by before it counts as held,
+/// which hides the drift of sticks which aren't quite centered.
+#[cfg(feature = "gamepad")]
+const DEAD_ZONE: f32 = 0.15;
+
+/// A key or button an action can be bound to.
+///
+/// Keys are bound by their physical location (e.g., `KeyW` is the key where
+/// `W` is on a US keyboard), so the bindings don't move with the layout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    /// A key pressed while a modifier key is held (e.g., `Enter` while `Alt`
+    /// is held).
+    Chord(KeyCode, KeyCode),
+    Mouse(MouseButton),
+    #[cfg(feature = "gamepad")]
+    GamepadButton(Button),
+    /// A direction of a gamepad axis (e.g., pushing a stick left).
+    #[cfg(feature = "gamepad")]
+    GamepadAxis(Axis, AxisDirection),
+}
+
+#[cfg(feature = "gamepad")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Negative,
+    Positive,
+}
+
+/// The keys and mouse buttons bound to each action.
+pub type Bindings = HashMap<String, Vec<Binding>>;
+
+/// Returns the bindings of the actions used by our Vulkan app.
+pub fn default_bindings() -> Bindings {
+    use Binding::*;
+    use KeyCode as K;
+
+    [
+        // Camera
+        ("move_forward", vec![Key(K::KeyW)]),
+        ("move_back", vec![Key(K::KeyS)]),
+        ("move_left", vec![Key(K::KeyA)]),
+        ("move_right", vec![Key(K::KeyD)]),
+        ("move_up", vec![Key(K::Space)]),
+        ("move_down", vec![Key(K::KeyC)]),
+        ("sprint", vec![Key(K::ShiftLeft), Key(K::ShiftRight)]),
+        ("look", vec![Mouse(MouseButton::Right)]),
+        ("look_left", vec![Key(K::ArrowLeft)]),
+        ("look_right", vec![Key(K::ArrowRight)]),
+        ("look_up", vec![Key(K::ArrowUp)]),
+        ("look_down", vec![Key(K::ArrowDown)]),
+        ("orbit", vec![Mouse(MouseButton::Left)]),
+        ("pan", vec![Mouse(MouseButton::Middle)]),
+        ("toggle_camera", vec![Key(K::Tab)]),
+        ("release_cursor", vec![Key(K::Escape)]),
+        // App
+        ("toggle_srgb", vec![Key(K::KeyG)]),
+        ("screenshot", vec![Key(K::F12)]),
+        ("toggle_stats", vec![Key(K::F3)]),
+        ("toggle_parallax", vec![Key(K::KeyP)]),
+        ("toggle_wireframe", vec![Key(K::KeyF)]),
+        ("cycle_debug_view", vec![Key(K::KeyV)]),
+        ("toggle_clustering", vec![Key(K::KeyL)]),
+        ("cycle_tone_mapping", vec![Key(K::KeyT)]),
+        ("increase_exposure", vec![Key(K::Equal)]),
+        ("decrease_exposure", vec![Key(K::Minus)]),
+        (
+            "toggle_fullscreen",
+            vec![Chord(K::AltLeft, K::Enter), Chord(K::AltRight, K::Enter)],
+        ),
+    ]
+    .into_iter()
+    .map(|(action, bindings)| (action.into(), bindings))
+    .collect()
+}
+
+/// Returns the bindings of the actions used by our Vulkan app to the buttons
+/// and axes of a gamepad (which are added to the default bindings).
+#[cfg(feature = "gamepad")]
+pub fn default_gamepad_bindings() -> Vec<(&'static str, Binding)> {
+    use AxisDirection::*;
+    use Binding::*;
+
+    vec![
+        // Camera
+        ("move_forward", GamepadAxis(Axis::LeftStickY, Positive)),
+        ("move_back", GamepadAxis(Axis::LeftStickY, Negative)),
+        ("move_left", GamepadAxis(Axis::LeftStickX, Negative)),
+        ("move_right", GamepadAxis(Axis::LeftStickX, Positive)),
+        ("move_up", GamepadButton(Button::RightTrigger)),
+        ("move_down", GamepadButton(Button::LeftTrigger)),
+        ("sprint", GamepadButton(Button::LeftThumb)),
+        ("look_left", GamepadAxis(Axis::RightStickX, Negative)),
+        ("look_right", GamepadAxis(Axis::RightStickX, Positive)),
+        ("look_up", GamepadAxis(Axis::RightStickY, Positive)),
+        ("look_down", GamepadAxis(Axis::RightStickY, Negative)),
+        ("toggle_camera", GamepadButton(Button::Select)),
+        // App
+        ("toggle_srgb", GamepadButton(Button::North)),
+        ("screenshot", GamepadButton(Button::Start)),
+    ]
+}
+
+/// The keys or mouse buttons which are held, and which were pressed or
+/// released since the last frame.
+#[derive(Clone, Debug)]
+struct States<T> {
+    held: HashSet<T>,
+    pressed: HashSet<T>,
+    released: HashSet<T>,
+}
+
+impl<T> Default for States<T> {
+    fn default() -> Self {
+        Self {
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> States<T> {
+    fn update(&mut self, value: T, state: ElementState) {
+        // Held keys repeatedly send pressed events, which don't count as
+        // presses.
+        match state {
+            ElementState::Pressed if self.held.insert(value) => {
+                self.pressed.insert(value);
+            }
+            ElementState::Released if self.held.remove(&value) => {
+                self.released.insert(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn release_all(&mut self) {
+        self.released.extend(self.held.drain());
+    }
+
+    fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+}
+
+/// The buttons and axes of the connected gamepads (which are combined as if
+/// they were one gamepad).
+#[cfg(feature = "gamepad")]
+#[derive(Debug, Default)]
+struct Gamepads {
+    /// The context gamepad events are polled from, if gamepads are supported
+    /// by the platform.
+    gilrs: Option<Gilrs>,
+    buttons: States<Button>,
+    axes: HashMap<Axis, f32>,
+}
+
+/// The input received since the last frame.
+#[derive(Debug)]
+pub struct Input {
+    pub bindings: Bindings,
+    keys: States<KeyCode>,
+    buttons: States<MouseButton>,
+    /// The raw mouse motion (which isn't affected by the cursor reaching the
+    /// edges of the window) and the lines scrolled.
+    mouse_delta: (f64, f64),
+    scroll: f32,
+    #[cfg(feature = "gamepad")]
+    gamepads: Gamepads,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut bindings = default_bindings();
+
+        #[cfg(feature = "gamepad")]
+        for (action, binding) in default_gamepad_bindings() {
+            bindings.entry(action.into()).or_default().push(binding);
+        }
+
+        Self::new(bindings)
+    }
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            keys: States::default(),
+            buttons: States::default(),
+            mouse_delta: (0.0, 0.0),
+            scroll: 0.0,
+            #[cfg(feature = "gamepad")]
+            gamepads: Gamepads {
+                gilrs: Gilrs::new()
+                    .map_err(|e| warn!("Gamepads are not supported: {}", e))
+                    .ok(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Records the input from the gamepad events received since the last
+    /// poll.
+    #[cfg(feature = "gamepad")]
+    pub fn poll_gamepads(&mut self) {
+        let gamepads = &mut self.gamepads;
+        if let Some(gilrs) = &mut gamepads.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                match event.event {
+                    EventType::ButtonPressed(button, _) => {
+                        gamepads.buttons.update(button, ElementState::Pressed)
+                    }
+                    EventType::ButtonReleased(button, _) => {
+                        gamepads.buttons.update(button, ElementState::Released)
+                    }
+                    EventType::AxisChanged(axis, value, _) => {
+                        gamepads.axes.insert(axis, value);
+                    }
+                    // A disconnected gamepad doesn't send the events which
+                    // would release its buttons and center its axes.
+                    EventType::Disconnected => {
+                        gamepads.buttons.release_all();
+                        gamepads.axes.clear();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Records the input from a window event (other events are ignored).
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => self.key(*key, *state),
+            WindowEvent::MouseInput { state, button, .. } => self.mouse_button(*button, *state),
+            WindowEvent::MouseWheel { delta, .. } => self.mouse_wheel(*delta),
+            // The window stops receiving the events which would release the
+            // held keys and buttons when it loses focus.
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => {}
+        }
+    }
+
+    /// Records the input from a device event (other events are ignored).
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_motion(*delta);
+        }
+    }
+
+    pub fn key(&mut self, key: KeyCode, state: ElementState) {
+        self.keys.update(key, state);
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        self.buttons.update(button, state);
+    }
+
+    pub fn mouse_motion(&mut self, delta: (f64, f64)) {
+        self.mouse_delta.0 += delta.0;
+        self.mouse_delta.1 += delta.1;
+    }
+
+    pub fn mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        self.scroll += match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            // Touchpads scroll by pixels, roughly 20 of which make a line.
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+        };
+    }
+
+    /// Releases every held key and button.
+    pub fn release_all(&mut self) {
+        self.keys.release_all();
+        self.buttons.release_all();
+        #[cfg(feature = "gamepad")]
+        self.gamepads.buttons.release_all();
+    }
+
+    /// Clears the input received since the last frame (but not the held keys
+    /// and mouse buttons).
+    pub fn end_frame(&mut self) {
+        self.keys.end_frame();
+        self.buttons.end_frame();
+        #[cfg(feature = "gamepad")]
+        self.gamepads.buttons.end_frame();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll = 0.0;
+    }
+
+    pub fn is_key_held(&self, key: KeyCode) -> bool {
+        self.keys.held.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys.pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: KeyCode) -> bool {
+        self.keys.released.contains(&key)
+    }
+
+    pub fn is_button_held(&self, button: MouseButton) -> bool {
+        self.buttons.held.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons.pressed.contains(&button)
+    }
+
+    pub fn is_button_released(&self, button: MouseButton) -> bool {
+        self.buttons.released.contains(&button)
+    }
+
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.mouse_delta
+    }
+
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    /// Returns how far an action is held, from `0` to `1` (keys and buttons
+    /// are either held or not, while gamepad axes can be partially held).
+    pub fn value(&self, action: &str) -> f32 {
+        self.bindings.get(action).map_or(0.0, |bindings| {
+            bindings
+                .iter()
+                .map(|b| self.binding_value(*b))
+                .fold(0.0, f32::max)
+        })
+    }
+
+    /// Returns whether any of the bindings of an action are held (at least
+    /// halfway for gamepad axes).
+    pub fn is_held(&self, action: &str) -> bool {
+        self.value(action) >= 0.5
+    }
+
+    /// Returns whether any of the bindings of an action were pressed since
+    /// the last frame.
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.any(action, |b| match b {
+            Binding::Key(key) => self.is_key_pressed(key),
+            Binding::Chord(modifier, key) => self.is_key_held(modifier) && self.is_key_pressed(key),
+            Binding::Mouse(button) => self.is_button_pressed(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => self.gamepads.buttons.pressed.contains(&button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(..) => false,
+        })
+    }
+
+    /// Returns whether any of the bindings of an action were released since
+    /// the last frame.
+    pub fn is_released(&self, action: &str) -> bool {
+        self.any(action, |b| match b {
+            Binding::Key(key) => self.is_key_released(key),
+            Binding::Chord(modifier, key) => {
+                self.is_key_held(modifier) && self.is_key_released(key)
+            }
+            Binding::Mouse(button) => self.is_button_released(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => self.gamepads.buttons.released.contains(&button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(..) => false,
+        })
+    }
+
+    /// Returns a value from `-1` to `1` for an axis controlled by two
+    /// actions.
+    pub fn axis(&self, negative: &str, positive: &str) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+
+    fn binding_value(&self, binding: Binding) -> f32 {
+        match binding {
+            Binding::Key(key) => self.is_key_held(key) as i32 as f32,
+            Binding::Chord(modifier, key) => {
+                (self.is_key_held(modifier) && self.is_key_held(key)) as i32 as f32
+            }
+            Binding::Mouse(button) => self.is_button_held(button) as i32 as f32,
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => {
+                self.gamepads.buttons.held.contains(&button) as i32 as f32
+            }
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(axis, direction) => {
+                let value = self.gamepads.axes.get(&axis).copied().unwrap_or(0.0);
+                let value = match direction {
+                    AxisDirection::Negative => -value,
+                    AxisDirection::Positive => value,
+                };
+
+                // The value is rescaled so it starts from zero at the edge of
+                // the dead zone.
+                ((value - DEAD_ZONE) / (1.0 - DEAD_ZONE)).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    fn any(&self, action: &str, f: impl Fn(Binding) -> bool) -> bool {
+        self.bindings
+            .get(action)
+            .is_some_and(|bindings| bindings.iter().any(|b| f(*b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions() {
+        let mut input = Input::default();
+
+        // Pressing a key is only a press for the frame it is pressed in, even
+        // if the key repeats.
+        input.key(KeyCode::ShiftLeft, ElementState::Pressed);
+        assert!(input.is_pressed("sprint") && input.is_held("sprint"));
+        input.end_frame();
+        input.key(KeyCode::ShiftLeft, ElementState::Pressed);
+        assert!(!input.is_pressed("sprint") && input.is_held("sprint"));
+
+        // Either binding holds the action.
+        input.key(KeyCode::ShiftRight, ElementState::Pressed);
+        input.key(KeyCode::ShiftLeft, ElementState::Released);
+        assert!(input.is_released("sprint") && input.is_held("sprint"));
+
+        // Releasing everything (e.g., on losing focus) releases the action.
+        input.end_frame();
+        input.release_all();
+        assert!(input.is_released("sprint") && !input.is_held("sprint"));
+        assert!(!input.is_held("unbound"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Instance creation and the validation layer debug messenger.
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::CStr;
+use std::ops::Deref;
+use std::os::raw::c_void;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::ExtDebugUtilsExtension;
+use vulkanalia::vk::KhrSurfaceExtension;
+use vulkanalia::window as vk_window;
+use vulkanalia::Version;
+use winit::window::Window;
+
+use crate::app::AppData;
+
+/// The Vulkan SDK version that started requiring the portability subset extension for macOS.
+pub const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
+
+/// The newest Vulkan version our Vulkan app uses if it is available.
+pub const MAX_API_VERSION: Version = Version::new(1, 3, 0);
+
+/// Whether the validation layers should be enabled if not overridden by
+/// `VALIDATION_VAR`.
+pub const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
+/// The name of the validation layers.
+pub const VALIDATION_LAYER: vk::ExtensionName =
+    vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
+
+/// The environment variable that enables (`1`) or disables (`0`) the
+/// validation layers.
+pub const VALIDATION_VAR: &str = "VK_TUTORIAL_VALIDATION";
+/// The environment variable that sets the least severe debug message that is
+/// reported (`verbose`, `info`, `warning`, or `error`).
+pub const SEVERITY_VAR: &str = "VK_TUTORIAL_SEVERITY";
+
+/// Returns whether the validation layers should be enabled.
+pub fn validation_enabled() -> bool {
+    match env::var(VALIDATION_VAR).as_deref() {
+        Ok("1") | Ok("true") => true,
+        Ok("0") | Ok("false") => false,
+        Ok(value) => {
+            warn!("Ignoring invalid `{}` value (`{}`).", VALIDATION_VAR, value);
+            VALIDATION_ENABLED
+        }
+        Err(_) => VALIDATION_ENABLED,
+    }
+}
+
+/// Returns the debug message severities that should be reported.
+pub fn debug_severity() -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    type Severity = vk::DebugUtilsMessageSeverityFlagsEXT;
+
+    let minimum = match env::var(SEVERITY_VAR).as_deref() {
+        Ok("verbose") | Err(_) => Severity::VERBOSE,
+        Ok("info") => Severity::INFO,
+        Ok("warning") => Severity::WARNING,
+        Ok("error") => Severity::ERROR,
+        Ok(value) => {
+            warn!("Ignoring invalid `{}` value (`{}`).", SEVERITY_VAR, value);
+            Severity::VERBOSE
+        }
+    };
+
+    [
+        Severity::VERBOSE,
+        Severity::INFO,
+        Severity::WARNING,
+        Severity::ERROR,
+    ]
+    .iter()
+    .cloned()
+    .filter(|s| *s >= minimum)
+    .collect()
+}
+
+/// An instance and its debug messenger which are destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedInstance {
+    instance: Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl Deref for OwnedInstance {
+    type Target = Instance;
+
+    fn deref(&self) -> &Self::Target {
+        &self.instance
+    }
+}
+
+impl Drop for OwnedInstance {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.messenger.is_null() {
+                self.instance
+                    .destroy_debug_utils_messenger_ext(self.messenger, None);
+            }
+
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// A window surface which is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedSurface {
+    instance: Instance,
+    pub surface: vk::SurfaceKHR,
+}
+
+impl Drop for OwnedSurface {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_surface_khr(self.surface, None) };
+    }
+}
+
+/// Creates an instance which can create surfaces for the supplied window, or
+/// which can only render offscreen if there is no window.
+pub unsafe fn create_instance(
+    window: Option<&Window>,
+    entry: &Entry,
+    data: &mut AppData,
+) -> Result<OwnedInstance> {
+    // API Version
+
+    // Newer Vulkan versions are only used by physical devices which support
+    // them (e.g., for dynamic rendering), so request the newest one available.
+    let version = entry.version()?.min(MAX_API_VERSION);
+    data.api_version = vk::make_version(version.major, version.minor, 0);
+
+    // Application Info
+
+    let application_info = vk::ApplicationInfo::builder()
+        .application_name(b"Vulkan Tutorial (Rust)\0")
+        .application_version(vk::make_version(1, 0, 0))
+        .engine_name(b"No Engine\0")
+        .engine_version(vk::make_version(1, 0, 0))
+        .api_version(data.api_version);
+
+    // Layers
+
+    let available_layers = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .map(|l| l.layer_name)
+        .collect::<HashSet<_>>();
+
+    if data.options.validation && !available_layers.contains(&VALIDATION_LAYER) {
+        return Err(anyhow!("Validation layer requested but not supported."));
+    }
+
+    let layers = if data.options.validation {
+        vec![VALIDATION_LAYER.as_ptr()]
+    } else {
+        Vec::new()
+    };
+
+    // Extensions
+
+    let mut extensions = window
+        .map(|w| vk_window::get_required_instance_extensions(w))
+        .unwrap_or(&[])
+        .iter()
+        .map(|e| e.as_ptr())
+        .collect::<Vec<_>>();
+
+    // Required by Vulkan SDK on macOS since 1.3.216.
+    let flags = if cfg!(target_os = "macos") && entry.version()? >= PORTABILITY_MACOS_VERSION {
+        info!("Enabling extensions for macOS portability.");
+        extensions.push(
+            vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION
+                .name
+                .as_ptr(),
+        );
+        extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } else {
+        vk::InstanceCreateFlags::empty()
+    };
+
+    if data.options.validation {
+        extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
+    }
+
+    // Surfaces only report HDR color spaces with this extension enabled.
+    if data.options.hdr && window.is_some() {
+        let available_extensions = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .map(|e| e.extension_name)
+            .collect::<HashSet<_>>();
+
+        if available_extensions.contains(&vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name) {
+            extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name.as_ptr());
+        } else {
+            warn!("HDR requested but swapchain color spaces are not supported.");
+        }
+    }
+
+    // Create
+
+    let mut info = vk::InstanceCreateInfo::builder()
+        .application_info(&application_info)
+        .enabled_layer_names(&layers)
+        .enabled_extension_names(&extensions)
+        .flags(flags);
+
+    let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(data.options.debug_severity)
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .user_callback(Some(debug_callback));
+
+    if data.options.validation {
+        info = info.push_next(&mut debug_info);
+    }
+
+    let mut instance = OwnedInstance {
+        instance: entry.create_instance(&info, None)?,
+        messenger: vk::DebugUtilsMessengerEXT::null(),
+    };
+
+    // Messenger
+
+    if data.options.validation {
+        instance.messenger = instance.create_debug_utils_messenger_ext(&debug_info, None)?;
+    }
+
+    Ok(instance)
+}
+
+pub unsafe fn create_surface(
+    window: &Window,
+    instance: &Instance,
+    data: &mut AppData,
+) -> Result<OwnedSurface> {
+    let surface = vk_window::create_surface(instance, &window, &window)?;
+    data.surface = surface;
+    Ok(OwnedSurface {
+        instance: instance.clone(),
+        surface,
+    })
+}
+
+extern "system" fn debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    type_: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _: *mut c_void,
+) -> vk::Bool32 {
+    let data = unsafe { *data };
+    let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
+
+    if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
+        error!("({:?}) {}", type_, message);
+    } else if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING {
+        warn!("({:?}) {}", type_, message);
+    } else if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::INFO {
+        debug!("({:?}) {}", type_, message);
+    } else {
+        trace!("({:?}) {}", type_, message);
+    }
+
+    vk::FALSE
+}
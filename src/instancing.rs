@@ -0,0 +1,207 @@
+//! Instanced rendering, which draws a grid of copies of the first mesh with
+//! the first material using a single draw call per submesh.
+//!
+//! The model matrix and color of each copy are read from an instance buffer
+//! bound as a second vertex buffer, whose attributes advance once per
+//! instance rather than once per vertex (see `InstanceData`). The copies are
+//! drawn by a variant of the pipeline of the material with an instanced
+//! vertex shader (`instanced.vert`), which tints them with their colors.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::handle::Owned;
+use crate::mesh::Aabb;
+use crate::pipeline::create_material_pipeline;
+use crate::resources::{MaterialHandle, MeshHandle};
+use crate::upload::upload_to_buffer;
+use crate::vertex::{Mat4, Vec3, Vec4};
+
+/// The attributes of a copy of the mesh.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct InstanceData {
+    pub model: Mat4,
+    pub color: Vec4,
+}
+
+impl InstanceData {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<InstanceData>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    /// Returns the attributes of an instance, which follow the attributes of
+    /// a vertex (see `Vertex::attribute_descriptions`).
+    ///
+    /// A matrix attribute takes a location per column.
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 5] {
+        let attribute = |location: u32, offset: usize| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(location)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset as u32)
+                .build()
+        };
+
+        [
+            attribute(5, 0),
+            attribute(6, size_of::<Vec4>()),
+            attribute(7, size_of::<Vec4>() * 2),
+            attribute(8, size_of::<Vec4>() * 3),
+            attribute(9, size_of::<Mat4>()),
+        ]
+    }
+}
+
+/// The instance buffer and the mesh, material, and pipeline its copies are
+/// drawn with.
+#[derive(Debug, Default)]
+pub struct Instances {
+    pub buffer: Buffer,
+    pub count: u32,
+    pub mesh: Option<MeshHandle>,
+    pub material: Option<MaterialHandle>,
+    /// The pipeline which draws the copies (which is recreated with the
+    /// swapchain).
+    pub pipeline: Owned<vk::Pipeline>,
+}
+
+/// Returns the attributes of the supplied number of copies of a mesh with the
+/// supplied bounds, which are laid out in a square grid below the scene (so
+/// they don't overlap it) and colored with hues spread around the color wheel.
+pub fn get_instances(count: u32, bounds: &Aabb) -> Vec<InstanceData> {
+    let size = bounds.max - bounds.min;
+    let spacing = size.max().max(0.1) * 1.5;
+    let columns = (count as f32).sqrt().ceil().max(1.0) as u32;
+    let offset = (columns - 1) as f32 * spacing / 2.0;
+
+    (0..count)
+        .map(|i| {
+            let x = (i % columns) as f32 * spacing - offset;
+            let y = (i / columns) as f32 * spacing - offset;
+            let translation = glm::vec3(x, y, -spacing) - bounds.center();
+            let model = glm::translate(&Mat4::identity(), &translation);
+
+            let hue = i as f32 / count as f32;
+            let color = get_hue_color(hue);
+
+            InstanceData {
+                model,
+                color: glm::vec4(color.x, color.y, color.z, 1.0),
+            }
+        })
+        .collect()
+}
+
+/// Returns the fully saturated color of the supplied hue (from 0 to 1).
+fn get_hue_color(hue: f32) -> Vec3 {
+    let channel = |offset: f32| {
+        let k = (hue * 6.0 + offset) % 6.0;
+        1.0 - (k.min(4.0 - k).clamp(0.0, 1.0))
+    };
+
+    glm::vec3(channel(5.0), channel(3.0), channel(1.0))
+}
+
+/// Creates the instance buffer with the number of copies in the options
+/// (which does nothing if there are none).
+pub unsafe fn create_instances(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let count = data.options.instances;
+    if count == 0 {
+        return Ok(());
+    }
+
+    let mesh = data.resources.meshes.iter().map(|(h, _)| h).next();
+    let material = data.resources.materials.iter().map(|(h, _)| h).next();
+    let (Some(mesh), Some(material)) = (mesh, material) else {
+        return Ok(());
+    };
+
+    let Some(bounds) = data.resources.mesh(mesh).map(|m| m.bounds) else {
+        return Ok(());
+    };
+
+    let instances = get_instances(count, &bounds);
+
+    let buffer = create_buffer(
+        instance,
+        device,
+        data,
+        (size_of::<InstanceData>() * instances.len()) as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    upload_to_buffer(instance, device, data, &instances, buffer.buffer)?;
+
+    data.instances = Instances {
+        buffer,
+        count,
+        mesh: Some(mesh),
+        material: Some(material),
+        pipeline: Owned::default(),
+    };
+
+    Ok(())
+}
+
+/// Creates the pipeline which draws the copies (if there are any).
+pub unsafe fn create_instanced_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let Some(material) = data.instances.material else {
+        return Ok(());
+    };
+
+    let Some(info) = data.resources.material(material).map(|m| m.info) else {
+        return Ok(());
+    };
+
+    data.instances.pipeline =
+        create_material_pipeline(instance, device, data, &info, vk::PolygonMode::FILL, true)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances() {
+        let bounds = Aabb {
+            min: glm::vec3(-0.5, -0.5, 0.0),
+            max: glm::vec3(0.5, 0.5, 0.0),
+        };
+
+        let instances = get_instances(10, &bounds);
+        assert_eq!(instances.len(), 10);
+
+        // The copies are laid out in a 4x4 grid (which is centered).
+        let positions = instances
+            .iter()
+            .map(|i| glm::vec3(i.model[(0, 3)], i.model[(1, 3)], i.model[(2, 3)]))
+            .collect::<Vec<_>>();
+        assert_eq!(positions[0], glm::vec3(-2.25, -2.25, -1.5));
+        assert_eq!(positions[5], glm::vec3(-0.75, -0.75, -1.5));
+
+        // The first copy is red.
+        assert_eq!(instances[0].color, glm::vec4(1.0, 0.0, 0.0, 1.0));
+    }
+}
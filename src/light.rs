@@ -0,0 +1,355 @@
+//! Dynamic lights, which are copied to a storage buffer for each swapchain
+//! image every frame and shaded by the materials (see `Shading`).
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::create_buffer;
+use crate::shadows::get_shadowed_point_lights;
+use crate::vertex::{Vec3, Vec4};
+
+/// The maximum number of lights shaded in a frame (any further lights are
+/// ignored).
+pub const MAX_LIGHTS: usize = 512;
+
+/// The distance at which the lights spawned without a range stop lighting.
+pub const DEFAULT_RANGE: f32 = 10.0;
+
+/// The distance at which the lights scattered by `scatter_lights` stop
+/// lighting (which is small, so clustering culls most of them).
+pub const SCATTERED_RANGE: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightKind {
+    /// A light infinitely far away in a direction (e.g., the sun).
+    Directional,
+    /// A light which shines equally in every direction from a position.
+    Point,
+    /// A light which shines in a cone from a position.
+    Spot,
+}
+
+/// A light in the scene.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// The position of a point or spot light.
+    pub position: Vec3,
+    /// The direction a directional or spot light shines in.
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    /// The distance at which a point or spot light stops lighting.
+    pub range: f32,
+    /// The angles (in radians) from the direction of a spot light at which
+    /// its light starts to fall off and at which it stops.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    /// Whether a directional or point light casts shadows (only the first
+    /// directional light which does is rendered into the shadow map, and only
+    /// the first `MAX_POINT_SHADOWS` point lights into point light shadow
+    /// maps, see `ShadowMap`).
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: Vec3::zeros(),
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: Vec3::zeros(),
+            color,
+            intensity,
+            range,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            cast_shadows: false,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range,
+            inner_angle,
+            outer_angle,
+            cast_shadows: false,
+        }
+    }
+
+    /// Returns this light as it is laid out in the light buffer.
+    pub fn to_gpu(self) -> GpuLight {
+        let kind = match self.kind {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        };
+
+        GpuLight {
+            position: glm::vec4(self.position.x, self.position.y, self.position.z, kind),
+            direction: glm::vec4(
+                self.direction.x,
+                self.direction.y,
+                self.direction.z,
+                self.range,
+            ),
+            color: glm::vec4(
+                self.color.x * self.intensity,
+                self.color.y * self.intensity,
+                self.color.z * self.intensity,
+                0.0,
+            ),
+            cone: glm::vec4(
+                self.inner_angle.cos(),
+                self.outer_angle.cos(),
+                (self.cast_shadows && self.kind != LightKind::Spot) as u32 as f32,
+                0.0,
+            ),
+        }
+    }
+}
+
+/// A light as it is laid out in the light buffer (see `Light` in
+/// `shader.frag`).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GpuLight {
+    /// The position and, in W, the kind of the light.
+    pub position: Vec4,
+    /// The direction and, in W, the range of the light.
+    pub direction: Vec4,
+    /// The color multiplied by the intensity of the light.
+    pub color: Vec4,
+    /// The cosines of the inner and outer angles of a spot light and, in Z,
+    /// whether the light is shadowed by a shadow map and, in W, the index of
+    /// the shadow map of a point light.
+    pub cone: Vec4,
+}
+
+/// The contents of a light buffer (a `std430` storage buffer).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LightBufferObject {
+    pub count: u32,
+    _padding: [u32; 3],
+    pub lights: [GpuLight; MAX_LIGHTS],
+}
+
+impl LightBufferObject {
+    /// Returns the contents of a light buffer with the first `MAX_LIGHTS` of
+    /// the supplied lights.
+    pub fn new(lights: &[Light]) -> Self {
+        let mut object = Self {
+            count: lights.len().min(MAX_LIGHTS) as u32,
+            _padding: [0; 3],
+            lights: [GpuLight::default(); MAX_LIGHTS],
+        };
+
+        for (gpu, light) in object.lights.iter_mut().zip(lights) {
+            *gpu = light.to_gpu();
+        }
+
+        // Point lights without a shadow map are unshadowed.
+        let shadowed = get_shadowed_point_lights(lights).collect::<Vec<_>>();
+        for (gpu, light) in object.lights.iter_mut().zip(lights) {
+            if light.kind == LightKind::Point && light.cast_shadows {
+                match shadowed.iter().position(|l| std::ptr::eq(*l, light)) {
+                    Some(index) => gpu.cone.w = index as f32,
+                    None => gpu.cone.z = 0.0,
+                }
+            }
+        }
+
+        object
+    }
+}
+
+/// Creates the lights of the scene rendered by our Vulkan app: the sun and two
+/// colored point lights which orbit the quads (which all cast shadows), and a
+/// spot light above them, followed by the lights scattered around the quads
+/// (see `AppOptions::lights`).
+pub fn create_lights(data: &mut AppData) {
+    data.lights = vec![
+        Light {
+            cast_shadows: true,
+            ..Light::directional(glm::vec3(-0.5, -0.3, -1.0), glm::vec3(1.0, 0.95, 0.9), 3.0)
+        },
+        Light {
+            cast_shadows: true,
+            ..Light::point(
+                glm::vec3(1.5, 0.0, 0.5),
+                glm::vec3(1.0, 0.2, 0.1),
+                4.0,
+                DEFAULT_RANGE,
+            )
+        },
+        Light {
+            cast_shadows: true,
+            ..Light::point(
+                glm::vec3(-1.5, 0.0, 0.5),
+                glm::vec3(0.1, 0.3, 1.0),
+                4.0,
+                DEFAULT_RANGE,
+            )
+        },
+        Light::spot(
+            glm::vec3(0.0, 0.0, 3.0),
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(1.0, 0.8, 0.5),
+            10.0,
+            DEFAULT_RANGE,
+            glm::radians(&glm::vec1(15.0))[0],
+            glm::radians(&glm::vec1(25.0))[0],
+        ),
+    ];
+
+    data.lights.extend(scatter_lights(data.options.lights));
+}
+
+/// Returns the supplied number of small point lights scattered around the
+/// quads in a spiral (whose colors cycle around the hue wheel).
+pub fn scatter_lights(count: usize) -> Vec<Light> {
+    // Successive lights are a golden angle apart, so they are spread evenly
+    // over the disk regardless of their number.
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    let third = 2.0 * std::f32::consts::FRAC_PI_3;
+
+    (0..count)
+        .map(|i| {
+            let angle = i as f32 * golden_angle;
+            let radius = 3.0 * ((i as f32 + 0.5) / count as f32).sqrt();
+            let position = glm::vec3(
+                radius * angle.cos(),
+                radius * angle.sin(),
+                0.25 + 0.25 * (angle / 2.0).sin(),
+            );
+
+            let color = glm::vec3(
+                0.5 + 0.5 * angle.cos(),
+                0.5 + 0.5 * (angle + third).cos(),
+                0.5 + 0.5 * (angle + 2.0 * third).cos(),
+            );
+
+            Light::point(position, color, 0.5, SCATTERED_RANGE)
+        })
+        .collect()
+}
+
+/// Creates a light buffer for each swapchain image.
+pub unsafe fn create_light_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.light_buffers.clear();
+
+    for _ in 0..data.swapchain.images.len() {
+        let light_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<LightBufferObject>() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.light_buffers.push(light_buffer);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadows::MAX_POINT_SHADOWS;
+
+    #[test]
+    fn light_buffer() {
+        // The lights start after the count (aligned like a `vec4`).
+        assert_eq!(size_of::<GpuLight>(), 64);
+        assert_eq!(size_of::<LightBufferObject>(), 16 + 64 * MAX_LIGHTS);
+
+        let spot = Light::spot(
+            glm::vec3(1.0, 2.0, 3.0),
+            glm::vec3(0.0, 0.0, -2.0),
+            glm::vec3(1.0, 0.5, 0.25),
+            2.0,
+            5.0,
+            0.0,
+            std::f32::consts::FRAC_PI_2,
+        );
+        let gpu = spot.to_gpu();
+        assert_eq!(gpu.position, glm::vec4(1.0, 2.0, 3.0, 2.0));
+        assert_eq!(gpu.direction, glm::vec4(0.0, 0.0, -1.0, 5.0));
+        assert_eq!(gpu.color, glm::vec4(2.0, 1.0, 0.5, 0.0));
+        assert_eq!(gpu.cone.x, 1.0);
+        assert!(gpu.cone.y.abs() < 1e-6);
+        assert_eq!(gpu.cone.z, 0.0);
+
+        // Only the first `MAX_LIGHTS` lights are shaded.
+        let object = LightBufferObject::new(&vec![spot; MAX_LIGHTS + 1]);
+        assert_eq!(object.count, MAX_LIGHTS as u32);
+        assert_eq!(object.lights[MAX_LIGHTS - 1], gpu);
+    }
+
+    #[test]
+    fn point_shadows() {
+        let point = Light {
+            cast_shadows: true,
+            ..Light::point(Vec3::zeros(), glm::vec3(1.0, 1.0, 1.0), 1.0, DEFAULT_RANGE)
+        };
+
+        // Only the first `MAX_POINT_SHADOWS` point lights are shadowed.
+        let object = LightBufferObject::new(&vec![point; MAX_POINT_SHADOWS + 1]);
+        for (index, gpu) in object.lights[..MAX_POINT_SHADOWS].iter().enumerate() {
+            assert_eq!(gpu.cone.z, 1.0);
+            assert_eq!(gpu.cone.w, index as f32);
+        }
+        assert_eq!(object.lights[MAX_POINT_SHADOWS].cone.z, 0.0);
+    }
+
+    #[test]
+    fn scattered_lights() {
+        let lights = scatter_lights(100);
+        assert_eq!(lights.len(), 100);
+
+        for light in &lights {
+            assert_eq!(light.kind, LightKind::Point);
+            assert_eq!(light.range, SCATTERED_RANGE);
+            assert!(light.position.xy().norm() <= 3.0);
+            assert!((0.0..=0.5).contains(&light.position.z));
+        }
+    }
+}
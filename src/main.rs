@@ -1,3 +1,247 @@
-fn main() {
-    println!("Hello, world!");
+#![allow(
+    dead_code,
+    unused_variables,
+    clippy::too_many_arguments,
+    clippy::unnecessary_wraps
+)]
+
+mod app;
+mod args;
+mod bloom;
+mod buffers;
+mod camera;
+mod clock;
+mod clusters;
+mod commands;
+mod container;
+mod controller;
+mod culling;
+mod debug;
+mod debug_view;
+mod deferred;
+mod descriptors;
+mod device;
+mod display;
+#[cfg(feature = "ecs")]
+mod ecs;
+mod environment;
+mod framebuffers;
+mod golden;
+mod handle;
+mod handler;
+mod hiz;
+mod ibl;
+mod image;
+mod indirect;
+mod info;
+mod input;
+mod instance;
+mod instancing;
+mod light;
+mod material;
+mod memory;
+mod mesh;
+mod model;
+mod motion_blur;
+mod occlusion;
+mod overlay;
+mod particles;
+mod ping_pong;
+mod pipeline;
+mod post_process;
+mod profiling;
+mod queries;
+mod recorder;
+mod render_graph;
+mod render_target;
+mod rendering;
+mod resources;
+mod scene;
+mod screenshot;
+#[cfg(feature = "sdf-text")]
+mod sdf_text;
+mod shadows;
+mod swapchain;
+mod sync;
+mod text;
+mod texture;
+mod tonemap;
+#[cfg(feature = "basis")]
+mod transcode;
+mod ui;
+#[cfg(feature = "egui")]
+mod ui_egui;
+#[cfg(feature = "imgui")]
+mod ui_imgui;
+mod upload;
+mod vertex;
+mod view;
+
+use anyhow::Result;
+use clap::Parser;
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::Window;
+
+use app::App;
+use args::Args;
+use handler::{AppHandler, WindowHandler};
+
+/// The SPIR-V shaders compiled from `shaders/` by the build script.
+mod shaders {
+    pub const VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shader.vert.spv"));
+    pub const VERT_BDA: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shader_bda.vert.spv"));
+    pub const FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shader.frag.spv"));
+    pub const BLINN_PHONG_FRAG: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/blinn_phong.frag.spv"));
+    pub const PARTICLES_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/particles.comp.spv"));
+    pub const PARTICLE_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/particle.vert.spv"));
+    pub const PARTICLE_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/particle.frag.spv"));
+    pub const UI_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ui.vert.spv"));
+    pub const UI_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ui.frag.spv"));
+    pub const SDF_TEXT_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sdf_text.vert.spv"));
+    pub const SDF_TEXT_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sdf_text.frag.spv"));
+    pub const EQUIRECT_TO_CUBE_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/equirect_to_cube.comp.spv"));
+    pub const SKYBOX_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/skybox.vert.spv"));
+    pub const SKYBOX_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/skybox.frag.spv"));
+    pub const IRRADIANCE_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/irradiance.comp.spv"));
+    pub const PREFILTER_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/prefilter.comp.spv"));
+    pub const BRDF_LUT_COMP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/brdf_lut.comp.spv"));
+    pub const SHADOW_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shadow.vert.spv"));
+    pub const POINT_SHADOW_VERT: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/point_shadow.vert.spv"));
+    pub const POINT_SHADOW_FRAG: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/point_shadow.frag.spv"));
+    pub const GBUFFER_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/gbuffer.frag.spv"));
+    pub const LIGHTING_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/lighting.vert.spv"));
+    pub const LIGHTING_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/lighting.frag.spv"));
+    pub const CLUSTER_LIGHTS_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/cluster_lights.comp.spv"));
+    pub const TONEMAP_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tonemap.frag.spv"));
+    pub const VELOCITY_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/velocity.vert.spv"));
+    pub const VELOCITY_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/velocity.frag.spv"));
+    pub const MOTION_BLUR_FRAG: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/motion_blur.frag.spv"));
+    pub const VIGNETTE_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vignette.frag.spv"));
+    pub const BLOOM_THRESHOLD_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/bloom_threshold.comp.spv"));
+    pub const BLOOM_BLUR_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/bloom_blur.comp.spv"));
+    pub const DEBUG_VIEW_FRAG: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/debug_view.frag.spv"));
+    pub const INSTANCED_VERT: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/instanced.vert.spv"));
+    pub const BLOOM_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bloom.frag.spv"));
+    pub const CULL_DRAWS_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/cull_draws.comp.spv"));
+    pub const HIZ_DEPTH_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/hiz_depth.comp.spv"));
+    pub const HIZ_DEPTH_MS_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/hiz_depth_ms.comp.spv"));
+    pub const HIZ_REDUCE_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/hiz_reduce.comp.spv"));
+    pub const HIZ_CULL_COMP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/hiz_cull.comp.spv"));
+    pub const OCCLUSION_BOX_VERT: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/occlusion_box.vert.spv"));
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    profiling::start();
+
+    let args = Args::parse();
+
+    let mut builder = App::builder()
+        .present_mode(args.present_mode)
+        .hdr(args.hdr)
+        .particles(args.particles)
+        .recording_threads(args.recording_threads)
+        .model(args.model.clone())
+        .texture(args.texture.clone())
+        .font(args.font.clone())
+        .sdf_font(args.sdf_font.clone())
+        .environment(args.environment.clone())
+        .shading(args.shading)
+        .deferred(args.deferred)
+        .clustered(args.clustered)
+        .lights(args.lights)
+        .instances(args.instances)
+        .indirect(args.indirect)
+        .gpu_culling(args.gpu_culling)
+        .occlusion_culling(args.occlusion_culling)
+        .occlusion_queries(args.occlusion_queries)
+        .tone_mapping(args.tone_mapping)
+        .exposure(args.exposure)
+        .motion_blur(args.motion_blur)
+        .motion_blur_samples(args.motion_blur_samples)
+        .shutter(args.shutter)
+        .bloom(args.bloom)
+        .vignette(args.vignette)
+        .height_map(args.height_map.clone())
+        .parallax_layers(args.parallax_min_layers, args.parallax_max_layers)
+        .shadow_bias(args.shadow_bias, args.shadow_slope_bias)
+        .camera_speed(args.camera_speed)
+        .tick_rate(args.tick_rate)
+        .windows(args.windows)
+        .display_mode(args.fullscreen)
+        .background(args.background)
+        .software(args.software)
+        .dynamic_rendering(!args.render_pass)
+        .memory_allocator(args.memory_allocator);
+    if let Some(gpu) = args.gpu {
+        builder = builder.gpu(gpu);
+    }
+    if let Some(max_fps) = args.max_fps {
+        builder = builder.max_fps(max_fps);
+    }
+    if let Some(seconds) = args.record {
+        builder = builder.record(seconds);
+    }
+    if let Some(monitor) = args.monitor.clone() {
+        builder = builder.monitor(monitor);
+    }
+    if let Some(video_mode) = args.video_mode {
+        builder = builder.video_mode(video_mode);
+    }
+
+    // Golden Image
+
+    // The frame is rendered offscreen, so no event loop (or window system) is
+    // needed.
+    if let Some(reference) = &args.golden {
+        let bless = golden::bless_enabled(args.bless);
+        return unsafe { golden::run(builder, reference, args.tolerance, bless) };
+    }
+
+    let event_loop = EventLoop::new()?;
+    event_loop.set_control_flow(ControlFlow::Poll);
+
+    // Reports
+
+    if args.info || args.list_gpus || args.list_monitors {
+        // A window is needed to query surface support, but it is never shown.
+        let attributes = Window::default_attributes().with_visible(false);
+        let mut handler = WindowHandler::new(attributes, |window| unsafe {
+            if args.info {
+                info::print_info(window)
+            } else if args.list_monitors {
+                display::list_monitors(window)
+            } else {
+                builder.list_gpus(window)
+            }
+        });
+        event_loop.run_app(&mut handler)?;
+        return handler.finish();
+    }
+
+    // App
+
+    // The window (and our Vulkan app) are created once the event loop has
+    // resumed, and our Vulkan app is dropped before the window.
+    let mut handler = AppHandler::new(builder);
+    event_loop.run_app(&mut handler)?;
+    handler.finish()
 }
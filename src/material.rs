@@ -0,0 +1,301 @@
+//! Materials, which bundle the shaders, pipeline state, and descriptor sets
+//! used to draw meshes, and the instances of materials which supply the
+//! parameters for each object.
+//!
+//! Materials are physically based (with the metallic-roughness model of
+//! glTF): the fragment shader (`shader.frag`) shades the surface with a
+//! Cook-Torrance BRDF from its textures and factors, lit by the lights of the
+//! scene (see `Light`) and the image-based lighting of the environment (see
+//! `Ibl`). The same materials can instead be shaded with the simpler
+//! Blinn-Phong model (see `Shading`).
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::resources::{load_texture, MaterialHandle, TextureHandle};
+use crate::shaders;
+use crate::texture::{create_data_texture, create_texture_from_pixels};
+use crate::vertex::{Vec3, Vec4};
+
+/// The size of the fragment shader push constants (see
+/// `MaterialInstance::push_constants`).
+pub const MATERIAL_PUSH_CONSTANTS_SIZE: usize = 52;
+
+/// The depth of the height map supplied in the options (relative to the
+/// texture coordinates).
+pub const HEIGHT_MAP_SCALE: f32 = 0.05;
+
+/// The shaders and pipeline state of a material.
+///
+/// Every material draws meshes of vertices (see `Vertex`), so the vertex
+/// shader is chosen by the pipeline (see `create_pipeline`).
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialInfo {
+    pub name: &'static str,
+    pub fragment_shader: &'static [u8],
+    pub cull_mode: vk::CullModeFlags,
+    /// Whether the output of the fragment shader is blended with the color
+    /// attachment using its alpha.
+    pub blend: bool,
+}
+
+/// How the materials of our Vulkan app are shaded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Shading {
+    /// A Cook-Torrance BRDF with image-based lighting (`shader.frag`).
+    #[default]
+    Pbr,
+    /// Blinn-Phong diffuse and specular reflection with a constant ambient
+    /// term (`blinn_phong.frag`).
+    BlinnPhong,
+}
+
+impl Shading {
+    /// Returns the shaders and pipeline state of materials shaded this way.
+    pub fn material_info(self) -> MaterialInfo {
+        match self {
+            Self::Pbr => PBR_MATERIAL,
+            Self::BlinnPhong => BLINN_PHONG_MATERIAL,
+        }
+    }
+}
+
+/// The textures of a material, which are bound in this order after the
+/// uniform buffer (bindings 1 to 6).
+///
+/// Materials without one of the textures use one of the default textures
+/// instead (see `create_default_textures`), which don't change the factors.
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialTextures {
+    /// The base color (sRGB) and alpha.
+    pub albedo: TextureHandle,
+    /// The tangent space normal.
+    pub normal: TextureHandle,
+    /// The roughness (G) and metalness (B).
+    pub metallic_roughness: TextureHandle,
+    /// The ambient occlusion (R).
+    pub occlusion: TextureHandle,
+    /// The emitted color (sRGB).
+    pub emissive: TextureHandle,
+    /// The height of the surface (R, with white at the top), which displaces
+    /// the texture coordinates with parallax occlusion mapping.
+    pub height: TextureHandle,
+}
+
+impl MaterialTextures {
+    pub fn handles(&self) -> [TextureHandle; 6] {
+        [
+            self.albedo,
+            self.normal,
+            self.metallic_roughness,
+            self.occlusion,
+            self.emissive,
+            self.height,
+        ]
+    }
+}
+
+/// The factors of a material, which its textures are multiplied by.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialFactors {
+    pub base_color: Vec4,
+    pub emissive: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// The scale of the X and Y components of the normals.
+    pub normal_scale: f32,
+    /// How much of the ambient occlusion is applied.
+    pub occlusion_strength: f32,
+    /// The depth of the height map (relative to the texture coordinates), or
+    /// zero if the texture coordinates aren't displaced.
+    pub height_scale: f32,
+}
+
+impl Default for MaterialFactors {
+    /// Returns the default factors of glTF materials.
+    fn default() -> Self {
+        Self {
+            base_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            emissive: Vec3::zeros(),
+            metallic: 1.0,
+            roughness: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            height_scale: 0.0,
+        }
+    }
+}
+
+/// A material and the Vulkan objects created for it.
+///
+/// The pipeline and descriptor sets (one per swapchain image) are recreated
+/// with the swapchain.
+#[derive(Debug)]
+pub struct Material {
+    pub info: MaterialInfo,
+    pub textures: MaterialTextures,
+    pub factors: MaterialFactors,
+    pub pipeline: Owned<vk::Pipeline>,
+    /// The pipeline which draws the material as a wireframe (or a null handle
+    /// if the device doesn't support drawing lines).
+    pub wireframe_pipeline: Owned<vk::Pipeline>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+/// The per-object parameters of a material, which are supplied as push
+/// constants when an object is drawn.
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialInstance {
+    pub material: MaterialHandle,
+    pub opacity: f32,
+}
+
+impl MaterialInstance {
+    /// Returns the fragment shader push constants for this instance of a
+    /// material with the supplied factors.
+    pub fn push_constants(&self, factors: &MaterialFactors) -> [u8; MATERIAL_PUSH_CONSTANTS_SIZE] {
+        let [r, g, b, a] = factors.base_color.into();
+        let [er, eg, eb] = factors.emissive.into();
+        let values = [
+            self.opacity,
+            factors.metallic,
+            r,
+            g,
+            b,
+            a,
+            er,
+            eg,
+            eb,
+            factors.roughness,
+            factors.normal_scale,
+            factors.occlusion_strength,
+            factors.height_scale,
+        ];
+
+        let mut bytes = [0; MATERIAL_PUSH_CONSTANTS_SIZE];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+
+        bytes
+    }
+}
+
+/// Creates the materials used by our Vulkan app (without their pipelines or
+/// descriptor sets).
+pub unsafe fn create_materials(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let defaults = create_default_textures(instance, device, data)?;
+    data.resources.default_textures = Some(defaults);
+
+    let path = data
+        .options
+        .texture
+        .clone()
+        .unwrap_or_else(|| "resources/texture.png".into());
+    let albedo = load_texture(instance, device, data, &path)?;
+
+    // The fragment shader samples a single 2D image.
+    if data.resources.texture(albedo).map_or(1, |t| t.array_layers) != 1 {
+        return Err(anyhow!(
+            "Texture `{}` has more than one array layer.",
+            path.display()
+        ));
+    }
+
+    // The texture coordinates are only displaced if there is a height map.
+    let (height, height_scale) = match data.options.height_map.clone() {
+        Some(path) => {
+            let texture = create_data_texture(instance, device, data, &path)?;
+            (data.resources.textures.insert(texture), HEIGHT_MAP_SCALE)
+        }
+        None => (defaults.height, 0.0),
+    };
+
+    data.resources.materials.insert(Material {
+        info: data.options.shading.material_info(),
+        textures: MaterialTextures {
+            albedo,
+            height,
+            ..defaults
+        },
+        factors: MaterialFactors {
+            metallic: 0.0,
+            roughness: 0.5,
+            height_scale,
+            ..Default::default()
+        },
+        pipeline: Owned::default(),
+        wireframe_pipeline: Owned::default(),
+        descriptor_sets: vec![],
+    });
+
+    Ok(())
+}
+
+/// The shaders and pipeline state of the physically based materials (of the
+/// quads, the OBJ model, and the glTF models).
+pub const PBR_MATERIAL: MaterialInfo = MaterialInfo {
+    name: "PBR",
+    fragment_shader: shaders::FRAG,
+    cull_mode: vk::CullModeFlags::BACK,
+    blend: true,
+};
+
+/// The shaders and pipeline state of the materials when they are shaded with
+/// the Blinn-Phong model, which uses the albedo, normal, occlusion, and
+/// emissive textures (and derives the shininess from the roughness).
+pub const BLINN_PHONG_MATERIAL: MaterialInfo = MaterialInfo {
+    name: "Blinn-Phong",
+    fragment_shader: shaders::BLINN_PHONG_FRAG,
+    ..PBR_MATERIAL
+};
+
+/// Creates the 1x1 textures used for the textures a material doesn't have: a
+/// white texture (which doesn't change the factors it is multiplied by) and a
+/// flat normal map.
+unsafe fn create_default_textures(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<MaterialTextures> {
+    let white = create_texture_from_pixels(
+        instance,
+        device,
+        data,
+        "White".into(),
+        &[255, 255, 255, 255],
+        1,
+        1,
+        vk::Format::R8G8B8A8_SRGB,
+    )?;
+    let white = data.resources.textures.insert(white);
+
+    let normal = create_texture_from_pixels(
+        instance,
+        device,
+        data,
+        "Flat Normal".into(),
+        &[128, 128, 255, 255],
+        1,
+        1,
+        vk::Format::R8G8B8A8_UNORM,
+    )?;
+    let normal = data.resources.textures.insert(normal);
+
+    Ok(MaterialTextures {
+        albedo: white,
+        normal,
+        metallic_roughness: white,
+        occlusion: white,
+        emissive: white,
+        height: white,
+    })
+}
@@ -0,0 +1,795 @@
+//! Device memory allocation for buffers and images.
+//!
+//! Rather than allocating a `vk::DeviceMemory` per buffer or image, buffers
+//! and images share large blocks of memory, which keeps the number of
+//! allocations under `maxMemoryAllocationCount`. The blocks are managed by
+//! either:
+//!
+//! * the `gpu-allocator` crate, which is built on `ash` rather than
+//!   `vulkanalia`, so it is handed `ash` function pointers loaded for the
+//!   instance and logical device created with `vulkanalia` (which share the
+//!   same Vulkan loader)
+//! * our own arenas, one per memory type, which hand out aligned ranges of
+//!   their blocks and keep track of the ranges that are free in a free list
+
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use ash::vk::Handle as _;
+use clap::ValueEnum;
+use gpu_allocator::vulkan::{
+    AllocationCreateDesc, AllocationScheme, Allocator as GpuAllocator, AllocatorCreateDesc,
+};
+use gpu_allocator::{AllocatorReport, MemoryLocation};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+/// The size of the blocks allocated by an arena (unless its memory heap is
+/// small or a larger block is needed).
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// What manages the blocks of memory buffers and images are allocated from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MemoryAllocator {
+    /// The `gpu-allocator` crate.
+    #[default]
+    GpuAllocator,
+    /// Our own arenas (see `Arena`), which are simpler to follow.
+    Arena,
+}
+
+/// A range of device memory allocated for a buffer or image.
+///
+/// A default allocation owns no memory.
+#[derive(Debug, Default)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    /// The start of the range in the address space of our Vulkan app, if the
+    /// memory is host-visible.
+    mapped: Option<NonNull<u8>>,
+    memory_type_index: u32,
+    /// The allocation made by `gpu-allocator`, which is handed back to it to
+    /// be freed.
+    allocation: Option<gpu_allocator::vulkan::Allocation>,
+}
+
+impl Allocation {
+    /// Returns a pointer to the start of this allocation.
+    ///
+    /// Host-visible memory stays mapped for as long as it is allocated, so
+    /// there is nothing to unmap.
+    pub fn map(&self) -> Result<*mut u8> {
+        self.mapped
+            .map(NonNull::as_ptr)
+            .ok_or_else(|| anyhow!("Memory is not host-visible."))
+    }
+}
+
+/// Allocates the device memory for buffers and images.
+///
+/// Like a `Device`, an allocator can be cloned cheaply, which lets buffers and
+/// images free their memory when they are dropped. The blocks of memory are
+/// freed once every clone has been dropped.
+#[derive(Clone, Debug)]
+pub struct Allocator {
+    inner: Rc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    device: Device,
+    backend: Backend,
+}
+
+#[derive(Debug)]
+enum Backend {
+    GpuAllocator {
+        allocator: Box<RefCell<GpuAllocator>>,
+        /// The properties of each memory type and the number of bytes
+        /// allocated from it, which `gpu-allocator` doesn't report.
+        memory_types: Vec<(vk::MemoryPropertyFlags, Cell<vk::DeviceSize>)>,
+    },
+    Arenas(Arenas),
+}
+
+#[derive(Debug)]
+struct Arenas {
+    /// Whether memory has to be allocated with `DEVICE_ADDRESS` so that
+    /// buffers with `SHADER_DEVICE_ADDRESS` usage can be bound to it.
+    device_address: bool,
+    /// The alignment which keeps buffers and linear images from sharing a
+    /// "page" of memory with optimal images.
+    granularity: vk::DeviceSize,
+    /// The number of `vk::DeviceMemory` objects that can exist at once, which
+    /// can be as low as 4096.
+    max_allocations: u32,
+    allocations: Cell<u32>,
+    /// The arena for each memory type.
+    arenas: RefCell<Vec<Arena>>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // `gpu-allocator` frees its blocks when it is dropped.
+        if let Backend::Arenas(arenas) = &mut self.backend {
+            for arena in arenas.arenas.get_mut() {
+                for block in arena.blocks.drain(..) {
+                    unsafe { self.device.free_memory(block.memory, None) };
+                }
+            }
+        }
+    }
+}
+
+impl Allocator {
+    pub unsafe fn new(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let backend = match data.options.memory_allocator {
+            MemoryAllocator::GpuAllocator => create_gpu_allocator(instance, device, data)?,
+            MemoryAllocator::Arena => create_arenas(instance, data),
+        };
+
+        Ok(Self {
+            inner: Rc::new(Inner {
+                device: device.clone(),
+                backend,
+            }),
+        })
+    }
+
+    /// The logical device the memory is allocated from.
+    pub fn device(&self) -> &Device {
+        &self.inner.device
+    }
+
+    /// Allocates memory of the supplied type which satisfies the supplied
+    /// requirements for a buffer or linear image (`linear`) or an optimal
+    /// image.
+    ///
+    /// The memory is mapped if its type is `HOST_VISIBLE`.
+    pub unsafe fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+        linear: bool,
+    ) -> Result<Allocation> {
+        match &self.inner.backend {
+            Backend::GpuAllocator {
+                allocator,
+                memory_types,
+            } => {
+                // The memory type has already been picked, so `gpu-allocator`
+                // is only allowed to use that one.
+                let requirements = ash::vk::MemoryRequirements {
+                    size: requirements.size,
+                    alignment: requirements.alignment,
+                    memory_type_bits: 1 << memory_type_index,
+                };
+
+                let allocation = allocator.borrow_mut().allocate(&AllocationCreateDesc {
+                    name: if linear { "Buffer" } else { "Image" },
+                    requirements,
+                    location: MemoryLocation::Unknown,
+                    linear,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                })?;
+
+                let used = &memory_types[memory_type_index as usize].1;
+                used.set(used.get() + allocation.size());
+
+                Ok(Allocation {
+                    memory: vk::DeviceMemory::from_raw(allocation.memory().as_raw()),
+                    offset: allocation.offset(),
+                    size: allocation.size(),
+                    mapped: allocation.mapped_ptr().map(NonNull::cast),
+                    memory_type_index,
+                    allocation: Some(allocation),
+                })
+            }
+            Backend::Arenas(arenas) => {
+                arenas.allocate(&self.inner.device, requirements, memory_type_index)
+            }
+        }
+    }
+
+    /// Frees an allocation (which may be a default allocation), leaving a
+    /// default allocation in its place.
+    ///
+    /// Blocks which no longer have any allocations are freed.
+    pub unsafe fn free(&self, allocation: &mut Allocation) {
+        let allocation = mem::take(allocation);
+        if allocation.memory.is_null() {
+            return;
+        }
+
+        match &self.inner.backend {
+            Backend::GpuAllocator {
+                allocator,
+                memory_types,
+            } => {
+                let used = &memory_types[allocation.memory_type_index as usize].1;
+                used.set(used.get() - allocation.size);
+
+                if let Some(allocation) = allocation.allocation {
+                    if let Err(e) = allocator.borrow_mut().free(allocation) {
+                        error!("Failed to free memory: {}", e);
+                    }
+                }
+            }
+            Backend::Arenas(arenas) => arenas.free(&self.inner.device, &allocation),
+        }
+    }
+
+    /// Returns the memory blocks that have been allocated and the memory types
+    /// they were allocated from.
+    ///
+    /// Only the arenas keep track of their blocks, so this is empty for
+    /// `gpu-allocator`.
+    pub fn blocks(&self) -> Vec<(u32, vk::DeviceMemory)> {
+        match &self.inner.backend {
+            Backend::GpuAllocator { .. } => vec![],
+            Backend::Arenas(arenas) => arenas
+                .arenas
+                .borrow()
+                .iter()
+                .flat_map(|a| a.blocks.iter().map(|b| (a.memory_type_index, b.memory)))
+                .collect(),
+        }
+    }
+
+    /// Returns the combined statistics of the arenas for the memory types with
+    /// the supplied properties (e.g., `DEVICE_LOCAL` for video memory).
+    ///
+    /// The report of `gpu-allocator` doesn't say which memory type each of its
+    /// blocks is, so only the memory used is known for `gpu-allocator` (see
+    /// `Allocator::log_stats` for its blocks).
+    pub fn stats(&self, properties: vk::MemoryPropertyFlags) -> ArenaStats {
+        let arenas = match &self.inner.backend {
+            Backend::GpuAllocator { memory_types, .. } => {
+                let used = memory_types
+                    .iter()
+                    .filter(|(p, _)| p.contains(properties))
+                    .map(|(_, used)| used.get())
+                    .sum();
+                return ArenaStats {
+                    used,
+                    ..Default::default()
+                };
+            }
+            Backend::Arenas(arenas) => arenas.arenas.borrow(),
+        };
+
+        let mut stats = ArenaStats::default();
+
+        for arena in arenas.iter() {
+            if !arena.properties.contains(properties) {
+                continue;
+            }
+
+            let arena = arena.stats();
+            stats.blocks += arena.blocks;
+            stats.capacity += arena.capacity;
+            stats.used += arena.used;
+            stats.free += arena.free;
+            stats.free_ranges += arena.free_ranges;
+            stats.largest_free_range = stats.largest_free_range.max(arena.largest_free_range);
+        }
+
+        stats
+    }
+
+    /// Logs statistics about the memory in every arena that has allocated
+    /// memory blocks (or in every block allocated by `gpu-allocator`).
+    pub fn log_stats(&self) {
+        let arenas = match &self.inner.backend {
+            Backend::GpuAllocator { allocator, .. } => {
+                let stats = get_report_stats(&allocator.borrow().generate_report());
+                info!(
+                    "Memory (all heaps): {} KiB of {} KiB used in {} block(s), {} free range(s) ({:.0}% fragmented).",
+                    stats.used / 1024,
+                    stats.capacity / 1024,
+                    stats.blocks,
+                    stats.free_ranges,
+                    stats.fragmentation() * 100.0,
+                );
+                return;
+            }
+            Backend::Arenas(arenas) => arenas.arenas.borrow(),
+        };
+
+        for arena in arenas.iter() {
+            let stats = arena.stats();
+            if stats.blocks == 0 {
+                continue;
+            }
+
+            info!(
+                "Memory type {}: {} KiB of {} KiB used in {} block(s), {} free range(s) ({:.0}% fragmented).",
+                arena.memory_type_index,
+                stats.used / 1024,
+                stats.capacity / 1024,
+                stats.blocks,
+                stats.free_ranges,
+                stats.fragmentation() * 100.0,
+            );
+        }
+    }
+}
+
+/// Returns the allocator for the logical device.
+pub fn get_allocator(data: &AppData) -> Result<&Allocator> {
+    data.allocator
+        .as_ref()
+        .ok_or_else(|| anyhow!("Allocator has not been created."))
+}
+
+/// Creates a `gpu-allocator` allocator for the logical device.
+unsafe fn create_gpu_allocator(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+) -> Result<Backend> {
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+    let memory_types = memory.memory_types[..memory.memory_type_count as usize]
+        .iter()
+        .map(|t| (t.property_flags, Cell::new(0)))
+        .collect();
+
+    // `ash` loads its own function pointers for the handles created with
+    // `vulkanalia` (from the Vulkan loader our Vulkan app already loaded).
+    let entry = ash::Entry::load()?;
+    let instance = ash::Instance::load(
+        entry.static_fn(),
+        ash::vk::Instance::from_raw(instance.handle().as_raw() as u64),
+    );
+    let device = ash::Device::load(
+        instance.fp_v1_0(),
+        ash::vk::Device::from_raw(device.handle().as_raw() as u64),
+    );
+    let physical_device = ash::vk::PhysicalDevice::from_raw(data.physical_device.as_raw() as u64);
+
+    let allocator = GpuAllocator::new(&AllocatorCreateDesc {
+        instance,
+        device,
+        physical_device,
+        debug_settings: Default::default(),
+        buffer_device_address: data.features.buffer_device_address,
+        allocation_sizes: Default::default(),
+    })?;
+
+    Ok(Backend::GpuAllocator {
+        allocator: Box::new(RefCell::new(allocator)),
+        memory_types,
+    })
+}
+
+/// Returns the statistics of every block in a `gpu-allocator` report, whose
+/// free ranges are the gaps between its allocations.
+fn get_report_stats(report: &AllocatorReport) -> ArenaStats {
+    let mut stats = ArenaStats {
+        blocks: report.blocks.len(),
+        capacity: report.total_capacity_bytes,
+        used: report.total_allocated_bytes,
+        ..Default::default()
+    };
+
+    for block in &report.blocks {
+        let mut allocations = report.allocations[block.allocations.clone()]
+            .iter()
+            .map(|a| (a.offset, a.size))
+            .collect::<Vec<_>>();
+        allocations.sort_unstable();
+
+        let mut offset = 0;
+        for (start, size) in allocations.into_iter().chain([(block.size, 0)]) {
+            if start > offset {
+                stats.free += start - offset;
+                stats.free_ranges += 1;
+                stats.largest_free_range = stats.largest_free_range.max(start - offset);
+            }
+            offset = offset.max(start + size);
+        }
+    }
+
+    stats
+}
+
+/// Creates an arena for each memory type.
+unsafe fn create_arenas(instance: &Instance, data: &AppData) -> Backend {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+
+    let arenas = memory.memory_types[..memory.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(index, memory_type)| {
+            // A block shouldn't take up too much of a small heap (e.g., the
+            // 256 MiB of device-local memory visible to the host without
+            // resizable BAR).
+            let heap = memory.memory_heaps[memory_type.heap_index as usize];
+            Arena::new(
+                index as u32,
+                memory_type.property_flags,
+                BLOCK_SIZE.min(heap.size / 8),
+            )
+        })
+        .collect();
+
+    Backend::Arenas(Arenas {
+        device_address: data.features.buffer_device_address,
+        granularity: properties.limits.buffer_image_granularity,
+        max_allocations: properties.limits.max_memory_allocation_count,
+        allocations: Cell::new(0),
+        arenas: RefCell::new(arenas),
+    })
+}
+
+impl Arenas {
+    unsafe fn allocate(
+        &self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> Result<Allocation> {
+        let mut arenas = self.arenas.borrow_mut();
+        let arena = &mut arenas[memory_type_index as usize];
+
+        // Every allocation is aligned to (and padded to a multiple of) the
+        // granularity, which keeps buffers and images apart without tracking
+        // which kind of resource each range was allocated for.
+        let alignment = requirements.alignment.max(self.granularity);
+        let size = align_up(requirements.size, self.granularity);
+
+        if let Some(allocation) = arena.suballocate(size, alignment) {
+            return Ok(allocation);
+        }
+
+        // Block
+
+        let allocations = self.allocations.get();
+        if allocations >= self.max_allocations {
+            return Err(anyhow!(
+                "Exceeded maximum number of memory allocations ({}).",
+                self.max_allocations
+            ));
+        }
+
+        let block = Block::new(
+            device,
+            memory_type_index,
+            arena.properties,
+            arena.block_size.max(size),
+            self.device_address,
+        )?;
+
+        self.allocations.set(allocations + 1);
+        arena.blocks.push(block);
+
+        arena
+            .suballocate(size, alignment)
+            .ok_or_else(|| anyhow!("Failed to suballocate new memory block."))
+    }
+
+    unsafe fn free(&self, device: &Device, allocation: &Allocation) {
+        let mut arenas = self.arenas.borrow_mut();
+        let arena = &mut arenas[allocation.memory_type_index as usize];
+
+        let index = match arena
+            .blocks
+            .iter()
+            .position(|b| b.memory == allocation.memory)
+        {
+            Some(index) => index,
+            None => return,
+        };
+
+        let block = &mut arena.blocks[index];
+        block.free.free(allocation.offset, allocation.size);
+
+        if block.free.is_empty() {
+            let block = arena.blocks.swap_remove(index);
+            device.free_memory(block.memory, None);
+            self.allocations.set(self.allocations.get() - 1);
+        }
+    }
+}
+
+/// Suballocates blocks of device memory of a single memory type.
+#[derive(Debug)]
+pub struct Arena {
+    memory_type_index: u32,
+    properties: vk::MemoryPropertyFlags,
+    block_size: vk::DeviceSize,
+    blocks: Vec<Block>,
+}
+
+impl Arena {
+    fn new(
+        memory_type_index: u32,
+        properties: vk::MemoryPropertyFlags,
+        block_size: vk::DeviceSize,
+    ) -> Self {
+        Self {
+            memory_type_index,
+            properties,
+            block_size,
+            blocks: vec![],
+        }
+    }
+
+    /// Allocates a range from the first block with a free range large enough.
+    fn suballocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<Allocation> {
+        self.blocks.iter_mut().find_map(|block| {
+            let offset = block.free.allocate(size, alignment)?;
+            Some(Allocation {
+                memory: block.memory,
+                offset,
+                size,
+                mapped: block
+                    .mapped
+                    .map(|p| unsafe { NonNull::new_unchecked(p.as_ptr().add(offset as usize)) }),
+                memory_type_index: self.memory_type_index,
+                allocation: None,
+            })
+        })
+    }
+
+    /// Returns statistics about the memory in this arena.
+    pub fn stats(&self) -> ArenaStats {
+        let mut stats = ArenaStats {
+            blocks: self.blocks.len(),
+            ..Default::default()
+        };
+
+        for block in &self.blocks {
+            stats.capacity += block.free.size;
+            for (_, size) in &block.free.ranges {
+                stats.free += size;
+                stats.free_ranges += 1;
+                stats.largest_free_range = stats.largest_free_range.max(*size);
+            }
+        }
+
+        stats.used = stats.capacity - stats.free;
+        stats
+    }
+}
+
+/// Statistics about the memory in an arena.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ArenaStats {
+    pub blocks: usize,
+    /// The size of the blocks.
+    pub capacity: vk::DeviceSize,
+    pub used: vk::DeviceSize,
+    pub free: vk::DeviceSize,
+    pub free_ranges: usize,
+    pub largest_free_range: vk::DeviceSize,
+}
+
+impl ArenaStats {
+    /// The fraction of the free memory which is not part of the largest free
+    /// range (i.e., 0 if the free memory could be allocated all at once).
+    pub fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_range as f64 / self.free as f64
+        }
+    }
+}
+
+/// A block of device memory allocated by an arena.
+#[derive(Debug)]
+struct Block {
+    memory: vk::DeviceMemory,
+    mapped: Option<NonNull<u8>>,
+    free: FreeList,
+}
+
+impl Block {
+    unsafe fn new(
+        device: &Device,
+        memory_type_index: u32,
+        properties: vk::MemoryPropertyFlags,
+        size: vk::DeviceSize,
+        device_address: bool,
+    ) -> Result<Self> {
+        // Allocate
+
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::builder().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+
+        let mut info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+
+        if device_address {
+            info = info.push_next(&mut flags_info);
+        }
+
+        let memory = device.allocate_memory(&info, None)?;
+
+        // Map
+
+        // A block is mapped once, since the same memory can't be mapped more
+        // than once at a time.
+        let mapped = if properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            match device.map_memory(
+                memory,
+                0,
+                vk::WHOLE_SIZE as u64,
+                vk::MemoryMapFlags::empty(),
+            ) {
+                Ok(pointer) => NonNull::new(pointer.cast()),
+                Err(error) => {
+                    device.free_memory(memory, None);
+                    return Err(error.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            memory,
+            mapped,
+            free: FreeList::new(size),
+        })
+    }
+}
+
+/// The free ranges of a block, which are kept sorted by offset and merged
+/// with their neighbors when freed.
+#[derive(Clone, Debug)]
+struct FreeList {
+    size: vk::DeviceSize,
+    /// The offsets and sizes of the free ranges.
+    ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl FreeList {
+    fn new(size: vk::DeviceSize) -> Self {
+        Self {
+            size,
+            ranges: vec![(0, size)],
+        }
+    }
+
+    /// Whether the entire block is free.
+    fn is_empty(&self) -> bool {
+        self.ranges == [(0, self.size)]
+    }
+
+    /// Allocates a range from the first free range which is large enough once
+    /// its start has been aligned, returning its offset.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let (index, offset, aligned, free) =
+            self.ranges
+                .iter()
+                .enumerate()
+                .find_map(|(index, &(offset, free))| {
+                    let aligned = align_up(offset, alignment);
+                    (free >= aligned - offset + size).then_some((index, offset, aligned, free))
+                })?;
+
+        // The padding before the allocation and the remainder after it are
+        // still free.
+        let padding = aligned - offset;
+        let remainder = free - padding - size;
+
+        self.ranges.remove(index);
+        if remainder != 0 {
+            self.ranges.insert(index, (aligned + size, remainder));
+        }
+        if padding != 0 {
+            self.ranges.insert(index, (offset, padding));
+        }
+
+        Some(aligned)
+    }
+
+    /// Frees a range, merging it with the free ranges next to it.
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let mut index = self.ranges.partition_point(|r| r.0 < offset);
+        self.ranges.insert(index, (offset, size));
+
+        if index + 1 < self.ranges.len() && offset + size == self.ranges[index + 1].0 {
+            self.ranges[index].1 += self.ranges.remove(index + 1).1;
+        }
+
+        if index > 0 && self.ranges[index - 1].0 + self.ranges[index - 1].1 == offset {
+            self.ranges[index - 1].1 += self.ranges.remove(index).1;
+            index -= 1;
+        }
+
+        debug_assert!(self.ranges[index].0 + self.ranges[index].1 <= self.size);
+    }
+}
+
+/// Rounds a value up to a multiple of an alignment (a power of two).
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_aligned() {
+        let mut list = FreeList::new(1024);
+        assert_eq!(list.allocate(100, 64), Some(0));
+        assert_eq!(list.allocate(100, 64), Some(128));
+        assert_eq!(list.ranges, vec![(100, 28), (228, 796)]);
+        assert_eq!(list.allocate(2048, 64), None);
+    }
+
+    #[test]
+    fn free_merges_neighbors() {
+        let mut list = FreeList::new(1024);
+        let a = list.allocate(256, 256).unwrap();
+        let b = list.allocate(256, 256).unwrap();
+        let c = list.allocate(256, 256).unwrap();
+
+        list.free(a, 256);
+        list.free(c, 256);
+        assert_eq!(list.ranges, vec![(0, 256), (512, 512)]);
+
+        list.free(b, 256);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn fragmentation() {
+        let stats = ArenaStats {
+            free: 300,
+            largest_free_range: 200,
+            ..Default::default()
+        };
+        assert!((stats.fragmentation() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(ArenaStats::default().fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn report_stats() {
+        use gpu_allocator::{AllocationReport, MemoryBlockReport};
+
+        let allocation = |offset, size| AllocationReport {
+            name: "Buffer".into(),
+            offset,
+            size,
+        };
+
+        let report = AllocatorReport {
+            allocations: vec![allocation(256, 256), allocation(0, 128)],
+            blocks: vec![MemoryBlockReport {
+                size: 1024,
+                allocations: 0..2,
+            }],
+            total_allocated_bytes: 384,
+            total_capacity_bytes: 1024,
+        };
+
+        let stats = get_report_stats(&report);
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.used, 384);
+        assert_eq!(stats.free, 640);
+        assert_eq!(stats.free_ranges, 2);
+        assert_eq!(stats.largest_free_range, 512);
+    }
+}
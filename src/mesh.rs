@@ -0,0 +1,450 @@
+//! Meshes, which own the vertex and index buffers of the geometry they draw.
+
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_2::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::model::is_gltf_path;
+use crate::resources::load_mesh;
+use crate::upload::upload_to_buffer;
+use crate::vertex::{Vec3, Vec4, Vertex, INDICES, VERTICES};
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Returns the smallest bounding box containing the supplied points (or an
+    /// empty bounding box at the origin if there are none).
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(None, |aabb: Option<Self>, p| {
+                Some(match aabb {
+                    Some(aabb) => Self {
+                        min: glm::min2(&aabb.min, &p),
+                        max: glm::max2(&aabb.max, &p),
+                    },
+                    None => Self { min: p, max: p },
+                })
+            })
+            .unwrap_or(Self {
+                min: Vec3::zeros(),
+                max: Vec3::zeros(),
+            })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+}
+
+/// A range of the indices of a mesh which is drawn with a single draw call
+/// (e.g., one of the objects in an OBJ file).
+#[derive(Clone, Debug)]
+pub struct Submesh {
+    pub name: String,
+    pub indices: Range<u32>,
+}
+
+/// The vertex and index buffers of a mesh and the bounding box and submeshes
+/// of its geometry.
+///
+/// The indices of every mesh are 32-bit and are relative to the first vertex
+/// of the mesh.
+#[derive(Debug)]
+pub struct Mesh {
+    pub name: String,
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub bounds: Aabb,
+    pub submeshes: Vec<Submesh>,
+    /// The vertices and indices of the mesh, which are only kept (with
+    /// indirect rendering) until they are copied into the shared vertex and
+    /// index buffers (see `Indirect`).
+    pub geometry: Option<(Vec<Vertex>, Vec<u32>)>,
+    /// Whether the vertex buffer is bound when the mesh is drawn (rather than
+    /// read through its address by the vertex shader).
+    bind_vertex_buffer: bool,
+}
+
+impl Mesh {
+    /// Creates a mesh with a single submesh from the supplied vertices and
+    /// indices, uploading them with the pending uploads.
+    pub unsafe fn from_data(
+        instance: &Instance,
+        device: &Device,
+        data: &mut AppData,
+        name: impl Into<String>,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Result<Self> {
+        let name = name.into();
+        let submeshes = vec![Submesh {
+            name: name.clone(),
+            indices: 0..indices.len() as u32,
+        }];
+        Self::from_submeshes(instance, device, data, name, vertices, indices, submeshes)
+    }
+
+    /// Creates a mesh from an OBJ file, with a submesh for each of the objects
+    /// in the file.
+    pub unsafe fn from_obj(
+        instance: &Instance,
+        device: &Device,
+        data: &mut AppData,
+        path: &Path,
+    ) -> Result<Self> {
+        let options = tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        };
+
+        let (models, _) = tobj::load_obj(path, &options)
+            .map_err(|e| anyhow!("Failed to load `{}`: {}", path.display(), e))?;
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut submeshes = vec![];
+
+        for model in &models {
+            let mesh = &model.mesh;
+            let base_vertex = vertices.len() as u32;
+            let first_index = indices.len() as u32;
+
+            for i in 0..mesh.positions.len() / 3 {
+                let pos = glm::vec3(
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                );
+
+                // OBJ texture coordinates have their origin at the bottom
+                // left, Vulkan texture coordinates at the top left.
+                let tex_coord = if mesh.texcoords.is_empty() {
+                    glm::vec2(0.0, 0.0)
+                } else {
+                    glm::vec2(mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1])
+                };
+
+                let normal = if mesh.normals.is_empty() {
+                    Vec3::zeros()
+                } else {
+                    glm::vec3(
+                        mesh.normals[i * 3],
+                        mesh.normals[i * 3 + 1],
+                        mesh.normals[i * 3 + 2],
+                    )
+                };
+
+                vertices.push(Vertex::new(
+                    pos,
+                    glm::vec3(1.0, 1.0, 1.0),
+                    tex_coord,
+                    normal,
+                    Vec4::zeros(),
+                ));
+            }
+
+            indices.extend(mesh.indices.iter().map(|i| base_vertex + i));
+
+            // The tangents (and the normals, if the object doesn't have any)
+            // are generated from the faces.
+            let object_indices = &indices[first_index as usize..];
+            if mesh.normals.is_empty() {
+                generate_normals(&mut vertices, object_indices);
+            }
+            generate_tangents(&mut vertices, object_indices);
+
+            submeshes.push(Submesh {
+                name: model.name.clone(),
+                indices: first_index..indices.len() as u32,
+            });
+        }
+
+        if indices.is_empty() {
+            return Err(anyhow!("`{}` doesn't contain any faces.", path.display()));
+        }
+
+        let name = path
+            .file_stem()
+            .map_or_else(|| "Model".into(), |s| s.to_string_lossy().into_owned());
+        Self::from_submeshes(instance, device, data, name, &vertices, &indices, submeshes)
+    }
+
+    unsafe fn from_submeshes(
+        instance: &Instance,
+        device: &Device,
+        data: &mut AppData,
+        name: String,
+        vertices: &[Vertex],
+        indices: &[u32],
+        submeshes: Vec<Submesh>,
+    ) -> Result<Self> {
+        // Vertex Buffer
+
+        // With buffer device addresses, the vertex shader reads the vertices
+        // through a pointer instead of from vertex attributes.
+        let mut usage = vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER;
+        if data.features.buffer_device_address {
+            usage |=
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
+        let vertex_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size_of_val(vertices) as u64,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        upload_to_buffer(instance, device, data, vertices, vertex_buffer.buffer)?;
+
+        // Index Buffer
+
+        let index_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size_of_val(indices) as u64,
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        upload_to_buffer(instance, device, data, indices, index_buffer.buffer)?;
+
+        Ok(Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            bounds: Aabb::from_points(vertices.iter().map(|v| v.pos)),
+            submeshes,
+            geometry: data
+                .options
+                .indirect
+                .then(|| (vertices.to_vec(), indices.to_vec())),
+            bind_vertex_buffer: !data.features.buffer_device_address,
+        })
+    }
+
+    /// Returns the handles needed to draw this mesh, which (unlike the mesh)
+    /// can be sent to the threads recording draw commands.
+    pub fn handles(&self) -> MeshHandles {
+        MeshHandles {
+            vertex_buffer: self.vertex_buffer.buffer,
+            vertex_buffer_address: self.vertex_buffer.address,
+            index_buffer: self.index_buffer.buffer,
+            submeshes: self.submeshes.iter().map(|s| s.indices.clone()).collect(),
+            bind_vertex_buffer: self.bind_vertex_buffer,
+        }
+    }
+
+    /// Records the commands which bind the buffers of this mesh and draw each
+    /// of its submeshes.
+    ///
+    /// With buffer device addresses, the address of the vertex buffer has to
+    /// be pushed by the caller (see `MeshHandles::vertex_buffer_address`).
+    pub unsafe fn draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        self.handles().draw(device, command_buffer);
+    }
+}
+
+/// The handles needed to draw a mesh.
+#[derive(Clone, Debug)]
+pub struct MeshHandles {
+    pub vertex_buffer: vk::Buffer,
+    pub vertex_buffer_address: vk::DeviceAddress,
+    pub index_buffer: vk::Buffer,
+    pub submeshes: Vec<Range<u32>>,
+    bind_vertex_buffer: bool,
+}
+
+impl MeshHandles {
+    /// See `Mesh::draw`.
+    pub unsafe fn draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.bind_vertex_buffer {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
+        }
+
+        device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
+
+        for indices in &self.submeshes {
+            device.cmd_draw_indexed(command_buffer, indices.len() as u32, 1, indices.start, 0, 0);
+        }
+    }
+
+    /// Records the commands which bind the buffers of this mesh and the
+    /// supplied instance buffer and draw the supplied number of instances of
+    /// each of its submeshes (see `InstanceData`).
+    ///
+    /// The vertices of instances are always read from vertex attributes.
+    pub unsafe fn draw_instanced(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        instance_buffer: vk::Buffer,
+        instances: u32,
+    ) {
+        device.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[self.vertex_buffer, instance_buffer],
+            &[0, 0],
+        );
+        device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
+
+        for indices in &self.submeshes {
+            device.cmd_draw_indexed(
+                command_buffer,
+                indices.len() as u32,
+                instances,
+                indices.start,
+                0,
+                0,
+            );
+        }
+    }
+}
+
+/// Sets the normals of the vertices of the supplied triangles to the average
+/// of the normals of the triangles they are part of (weighted by area).
+pub fn generate_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let normal =
+            (vertices[b].pos - vertices[a].pos).cross(&(vertices[c].pos - vertices[a].pos));
+        for i in [a, b, c] {
+            normals[i] += normal;
+        }
+    }
+
+    for i in indices.iter().map(|i| *i as usize) {
+        vertices[i].normal = normals[i].try_normalize(f32::EPSILON).unwrap_or(Vec3::z());
+    }
+}
+
+/// Sets the tangents of the vertices of the supplied triangles from the
+/// directions their texture coordinates increase along, which are made
+/// orthogonal to the normals of the vertices.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::zeros(); vertices.len()];
+    let mut bitangents = vec![Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let (edge1, edge2) = (
+            vertices[b].pos - vertices[a].pos,
+            vertices[c].pos - vertices[a].pos,
+        );
+        let (delta1, delta2) = (
+            vertices[b].tex_coord - vertices[a].tex_coord,
+            vertices[c].tex_coord - vertices[a].tex_coord,
+        );
+
+        // Triangles without texture coordinates don't have a tangent space.
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+        let bitangent = (edge2 * delta1.x - edge1 * delta2.x) / determinant;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for i in indices.iter().map(|i| *i as usize) {
+        let normal = vertices[i].normal;
+
+        // Any direction orthogonal to the normal will do without a tangent.
+        let tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+        let tangent = tangent.try_normalize(f32::EPSILON).unwrap_or_else(|| {
+            let axis = if normal.x.abs() < 0.9 {
+                Vec3::x()
+            } else {
+                Vec3::y()
+            };
+            axis.cross(&normal).normalize()
+        });
+
+        let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertices[i].tangent = glm::vec4(tangent.x, tangent.y, tangent.z, handedness);
+    }
+}
+
+/// Creates the meshes rendered by our Vulkan app, which are either the
+/// textured quads or the OBJ model supplied in the options (the meshes of glTF
+/// models are created with their scene by `create_scene`).
+pub unsafe fn create_meshes(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if let Some(path) = data.options.model.clone() {
+        if !is_gltf_path(&path) {
+            load_mesh(instance, device, data, path)?;
+        }
+    } else {
+        let indices = INDICES.iter().map(|i| *i as u32).collect::<Vec<_>>();
+        let mesh = Mesh::from_data(instance, device, data, "Quads", &VERTICES, &indices)?;
+        data.resources.meshes.insert(mesh);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tangents() {
+        // The quads (whose tangents are known).
+        let mut vertices = VERTICES.clone();
+        vertices.iter_mut().for_each(|v| {
+            v.normal = Vec3::zeros();
+            v.tangent = Vec4::zeros();
+        });
+
+        let indices = INDICES.iter().map(|i| *i as u32).collect::<Vec<_>>();
+        generate_normals(&mut vertices, &indices);
+        generate_tangents(&mut vertices, &indices);
+
+        for (vertex, expected) in vertices.iter().zip(VERTICES.iter()) {
+            assert!(glm::distance(&vertex.normal, &expected.normal) < 1e-6);
+            assert!(glm::distance(&vertex.tangent, &expected.tangent) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let aabb = Aabb::from_points([
+            glm::vec3(-0.5, 1.0, 0.0),
+            glm::vec3(0.5, -1.0, 2.0),
+            glm::vec3(0.0, 0.0, -2.0),
+        ]);
+        assert_eq!(aabb.min, glm::vec3(-0.5, -1.0, -2.0));
+        assert_eq!(aabb.max, glm::vec3(0.5, 1.0, 2.0));
+        assert_eq!(aabb.center(), glm::vec3(0.0, 0.0, 0.0));
+    }
+}
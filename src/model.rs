@@ -0,0 +1,367 @@
+//! Loading glTF models (`.gltf` or `.glb` files) into a scene, with a mesh for
+//! each of their primitives and a material for each of their materials.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use gltf::image::Format;
+use gltf::mesh::Mode;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::material::{Material, MaterialFactors, MaterialInfo, MaterialInstance};
+use crate::mesh::{generate_normals, generate_tangents, Mesh};
+use crate::resources::{MaterialHandle, MeshHandle, TextureHandle};
+use crate::scene::{NodeId, Scene, Transform};
+use crate::texture::create_texture_from_pixels;
+use crate::vertex::{Vec3, Vec4, Vertex};
+
+/// Returns whether the supplied path is a glTF model (rather than an OBJ
+/// model).
+pub fn is_gltf_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("gltf") || e.eq_ignore_ascii_case("glb"))
+}
+
+/// The resources created for a glTF model, which are indexed like the
+/// corresponding glTF objects.
+struct Model<'a> {
+    path: &'a Path,
+    images: Vec<gltf::image::Data>,
+    /// The textures created for the images (in sRGB or linear formats).
+    textures: HashMap<(usize, bool), TextureHandle>,
+    materials: HashMap<Option<usize>, MaterialHandle>,
+    /// The meshes created for the primitives of each mesh, and the materials
+    /// they are drawn with.
+    meshes: Vec<Vec<(MeshHandle, MaterialHandle)>>,
+}
+
+/// Loads a glTF model, creating its textures, materials, and meshes, and
+/// returns a scene with the nodes of its default scene.
+pub unsafe fn load_gltf(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Scene> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| anyhow!("Failed to load `{}`: {}", path.display(), e))?;
+
+    let mut model = Model {
+        path,
+        images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: vec![],
+    };
+
+    // Meshes
+
+    for mesh in document.meshes() {
+        let mut primitives = vec![];
+        for primitive in mesh.primitives() {
+            let name = format!("{} #{}", mesh.name().unwrap_or("Mesh"), primitive.index());
+
+            let (vertices, indices) = read_primitive(&primitive, &buffers)
+                .map_err(|e| anyhow!("Failed to load `{}` ({}): {}", path.display(), name, e))?;
+            let handle = Mesh::from_data(instance, device, data, name, &vertices, &indices)
+                .map(|m| data.resources.meshes.insert(m))?;
+
+            let material = load_material(instance, device, data, &mut model, primitive.material())?;
+            primitives.push((handle, material));
+        }
+
+        model.meshes.push(primitives);
+    }
+
+    // Nodes
+
+    let gltf_scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("Model `{}` has no scenes.", path.display()))?;
+
+    let mut scene = Scene::default();
+    let root = scene.root();
+    for node in gltf_scene.nodes() {
+        add_node(&mut scene, &model, &node, root);
+    }
+
+    Ok(scene)
+}
+
+/// Reads the vertices and indices of a primitive, generating the normals and
+/// tangents it doesn't have.
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<(Vec<Vertex>, Vec<u32>)> {
+    if primitive.mode() != Mode::Triangles {
+        return Err(anyhow!("Only triangle primitives are supported."));
+    }
+
+    let reader = primitive.reader(|b| buffers.get(b.index()).map(|d| &d.0[..]));
+
+    let positions = reader
+        .read_positions()
+        .ok_or_else(|| anyhow!("Primitive has no positions."))?;
+
+    let mut vertices = positions
+        .map(|p| {
+            Vertex::new(
+                p.into(),
+                glm::vec3(1.0, 1.0, 1.0),
+                glm::vec2(0.0, 0.0),
+                Vec3::zeros(),
+                Vec4::zeros(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+
+    if let Some(colors) = reader.read_colors(0) {
+        vertices
+            .iter_mut()
+            .zip(colors.into_rgb_f32())
+            .for_each(|(v, c)| v.color = c.into());
+    }
+
+    // glTF texture coordinates have their origin at the top left, like Vulkan
+    // texture coordinates.
+    if let Some(tex_coords) = reader.read_tex_coords(0) {
+        vertices
+            .iter_mut()
+            .zip(tex_coords.into_f32())
+            .for_each(|(v, t)| v.tex_coord = t.into());
+    }
+
+    match reader.read_normals() {
+        Some(normals) => vertices
+            .iter_mut()
+            .zip(normals)
+            .for_each(|(v, n)| v.normal = n.into()),
+        None => generate_normals(&mut vertices, &indices),
+    }
+
+    match reader.read_tangents() {
+        Some(tangents) => vertices
+            .iter_mut()
+            .zip(tangents)
+            .for_each(|(v, t)| v.tangent = t.into()),
+        None => generate_tangents(&mut vertices, &indices),
+    }
+
+    Ok((vertices, indices))
+}
+
+/// Creates a material for a glTF material (or returns the material previously
+/// created for it).
+unsafe fn load_material(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    model: &mut Model,
+    material: gltf::Material,
+) -> Result<MaterialHandle> {
+    if let Some(handle) = model.materials.get(&material.index()) {
+        return Ok(*handle);
+    }
+
+    let defaults = data
+        .resources
+        .default_textures
+        .ok_or_else(|| anyhow!("The default textures have not been created."))?;
+
+    let pbr = material.pbr_metallic_roughness();
+
+    let mut textures = defaults;
+    if let Some(info) = pbr.base_color_texture() {
+        textures.albedo = load_image(instance, device, data, model, info.texture(), true)?;
+    }
+    if let Some(info) = material.normal_texture() {
+        textures.normal = load_image(instance, device, data, model, info.texture(), false)?;
+    }
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        textures.metallic_roughness =
+            load_image(instance, device, data, model, info.texture(), false)?;
+    }
+    if let Some(info) = material.occlusion_texture() {
+        textures.occlusion = load_image(instance, device, data, model, info.texture(), false)?;
+    }
+    if let Some(info) = material.emissive_texture() {
+        textures.emissive = load_image(instance, device, data, model, info.texture(), true)?;
+    }
+
+    let factors = MaterialFactors {
+        base_color: pbr.base_color_factor().into(),
+        emissive: material.emissive_factor().into(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        normal_scale: material.normal_texture().map_or(1.0, |t| t.scale()),
+        occlusion_strength: material.occlusion_texture().map_or(1.0, |t| t.strength()),
+        ..Default::default()
+    };
+
+    // Both sides of double-sided materials are drawn.
+    let info = data.options.shading.material_info();
+    let info = if material.double_sided() {
+        MaterialInfo {
+            cull_mode: vk::CullModeFlags::NONE,
+            ..info
+        }
+    } else {
+        info
+    };
+
+    let handle = data.resources.materials.insert(Material {
+        info,
+        textures,
+        factors,
+        pipeline: Owned::default(),
+        wireframe_pipeline: Owned::default(),
+        descriptor_sets: vec![],
+    });
+    model.materials.insert(material.index(), handle);
+
+    Ok(handle)
+}
+
+/// Creates a texture for the image of a glTF texture in an sRGB or a linear
+/// format (or returns the texture previously created for it).
+unsafe fn load_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    model: &mut Model,
+    texture: gltf::Texture,
+    srgb: bool,
+) -> Result<TextureHandle> {
+    let index = texture.source().index();
+    if let Some(handle) = model.textures.get(&(index, srgb)) {
+        return Ok(*handle);
+    }
+
+    let image = &model.images[index];
+    let (channels, channel_size) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        format => {
+            return Err(anyhow!(
+                "Image #{} of `{}` has an unsupported format ({:?}).",
+                index,
+                model.path.display(),
+                format
+            ))
+        }
+    };
+
+    let pixels = to_rgba8(&image.pixels, channels, channel_size);
+    let format = if srgb {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    };
+
+    let name = format!("{} #{}", model.path.display(), index);
+    let texture = create_texture_from_pixels(
+        instance,
+        device,
+        data,
+        name,
+        &pixels,
+        image.width,
+        image.height,
+        format,
+    )?;
+    let handle = data.resources.textures.insert(texture);
+    model.textures.insert((index, srgb), handle);
+
+    Ok(handle)
+}
+
+/// Converts pixels with the supplied number of (little-endian) components of
+/// the supplied size to 8-bit RGBA pixels.
+///
+/// Missing color components are zero (except for grayscale pixels, whose
+/// component is used for all colors) and missing alpha components are opaque.
+fn to_rgba8(pixels: &[u8], channels: usize, channel_size: usize) -> Vec<u8> {
+    pixels
+        .chunks_exact(channels * channel_size)
+        .flat_map(|p| {
+            // The most significant byte of each component.
+            let c = |i: usize| p[i * channel_size + channel_size - 1];
+            match channels {
+                1 => [c(0), c(0), c(0), 255],
+                2 => [c(0), c(1), 0, 255],
+                3 => [c(0), c(1), c(2), 255],
+                _ => [c(0), c(1), c(2), c(3)],
+            }
+        })
+        .collect()
+}
+
+/// Adds a glTF node (and its descendants) to a scene, with a child node for
+/// each of the primitives of its mesh.
+fn add_node(scene: &mut Scene, model: &Model, node: &gltf::Node, parent: NodeId) {
+    let name = node
+        .name()
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| format!("Node #{}", node.index()));
+    let id = scene.add_node(name.clone(), parent);
+
+    let (translation, rotation, scale) = node.transform().decomposed();
+    scene.node_mut(id).transform = Transform {
+        translation: translation.into(),
+        rotation: glm::quat(rotation[0], rotation[1], rotation[2], rotation[3]),
+        scale: scale.into(),
+    };
+
+    if let Some(mesh) = node.mesh() {
+        for (i, (mesh, material)) in model.meshes[mesh.index()].iter().enumerate() {
+            let primitive = scene.add_node(format!("{} #{}", name, i), id);
+            scene.node_mut(primitive).mesh = Some(*mesh);
+            scene.node_mut(primitive).material = Some(MaterialInstance {
+                material: *material,
+                opacity: 1.0,
+            });
+        }
+    }
+
+    for child in node.children() {
+        add_node(scene, model, &child, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba8() {
+        assert_eq!(
+            to_rgba8(&[10, 20], 1, 1),
+            [10, 10, 10, 255, 20, 20, 20, 255]
+        );
+        assert_eq!(to_rgba8(&[1, 2, 3], 3, 1), [1, 2, 3, 255]);
+        assert_eq!(
+            to_rgba8(&[0x00, 0x80, 0xFF, 0x40], 2, 2),
+            [0x80, 0x40, 0, 255]
+        );
+        assert!(is_gltf_path(Path::new("models/box.GLB")));
+        assert!(!is_gltf_path(Path::new("models/viking_room.obj")));
+    }
+}
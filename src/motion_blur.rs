@@ -0,0 +1,600 @@
+//! Motion blur, which blurs the HDR intermediate target of tone mapping along
+//! the screen space motion of each pixel since the previous frame before it
+//! is tone mapped.
+//!
+//! The motion is rendered into a velocity buffer by a velocity pass before
+//! the main pass, which transforms the draws with the view projection and
+//! model matrices of both this frame and the previous frame (so objects which
+//! move are blurred as well as everything moving with the camera). The draws
+//! are matched to those of the previous frame by their order. Where nothing
+//! was drawn, the background is reprojected with the camera instead.
+//!
+//! The blur pass then writes the blurred scene into a second HDR target,
+//! which the post-processing stack reads instead of the target the main pass
+//! rendered to (see `create_post_process`). Both are offscreen render targets
+//! (see `RenderTarget`), so both passes always use a render pass.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::pipeline::create_shader_module;
+use crate::render_target::{cmd_begin_render_target, create_render_target, RenderTarget};
+use crate::scene::Draw;
+use crate::shaders;
+use crate::tonemap::HDR_FORMAT;
+use crate::vertex::{Mat4, Vertex};
+
+/// The format of the velocity buffer (the motion in texture coordinates).
+const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+/// The format of the depth attachment of the velocity buffer (which is
+/// sampled to find the background).
+const VELOCITY_DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// The number of samples along the motion of each pixel by default.
+pub const DEFAULT_MOTION_BLUR_SAMPLES: u32 = 8;
+
+/// The velocity buffer, the blurred HDR target, and the passes which render
+/// them.
+///
+/// The images are the size of the swapchain images, so they are recreated
+/// with the swapchain.
+#[derive(Debug, Default)]
+pub struct MotionBlur {
+    /// The velocity buffer (with a depth image).
+    velocity: RenderTarget,
+    /// The blurred scene, which is post-processed.
+    pub output: RenderTarget,
+    sampler: Owned<vk::Sampler>,
+    velocity_pipeline_layout: Owned<vk::PipelineLayout>,
+    velocity_pipeline: Owned<vk::Pipeline>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+    /// The view projection matrix of the previous frame (as of the last
+    /// `update_previous_frame`), if there was one.
+    previous_view_proj: Option<Mat4>,
+    /// The model matrices of the draws of the previous frame.
+    previous_models: Vec<Mat4>,
+}
+
+/// Creates the velocity buffer and the blurred HDR target, the render passes
+/// and framebuffers which render to them, and their pipelines.
+///
+/// This does nothing unless motion blur is enabled in the options (which
+/// requires tone mapping, so this has to be called after `create_tone_map`).
+pub unsafe fn create_motion_blur(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.motion_blur || data.options.tone_mapping.is_none() {
+        return Ok(());
+    }
+
+    let extent = data.swapchain.extent;
+
+    // Render Targets
+
+    data.motion_blur.velocity = create_render_target(
+        instance,
+        device,
+        data,
+        extent,
+        VELOCITY_FORMAT,
+        Some(VELOCITY_DEPTH_FORMAT),
+        vk::SampleCountFlags::_1,
+    )?;
+
+    data.motion_blur.output = create_render_target(
+        instance,
+        device,
+        data,
+        extent,
+        HDR_FORMAT,
+        None,
+        vk::SampleCountFlags::_1,
+    )?;
+
+    // Sampler
+
+    // The HDR target is sampled between texels along the motion (the
+    // velocity buffer and its depth are read texel by texel).
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.motion_blur.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Descriptor Set Layout
+
+    // The HDR target, the velocity buffer, and its depth.
+    let bindings = (0..3)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.motion_blur.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(bindings.len() as u32);
+
+    let pool_sizes = &[sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.motion_blur.descriptor_pool =
+        Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Set
+
+    let layouts = &[*data.motion_blur.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.motion_blur.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.motion_blur.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let views = [
+        data.tone_map.image.view,
+        data.motion_blur.velocity.view(),
+        data.motion_blur.velocity.depth_view().unwrap_or_default(),
+    ];
+
+    let image_infos = views.map(|v| {
+        [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(v)
+            .sampler(*data.motion_blur.sampler)
+            .build()]
+    });
+
+    let writes = image_infos
+        .iter()
+        .enumerate()
+        .map(|(i, image_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.motion_blur.descriptor_set)
+                .dst_binding(i as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    // Pipelines
+
+    create_velocity_pipeline(device, data)?;
+    create_blur_pipeline(device, data)?;
+
+    Ok(())
+}
+
+/// Creates the pipeline which renders the velocity of meshes.
+unsafe fn create_velocity_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    // Layout
+
+    // The view projection matrix multiplied by the model matrix of this frame
+    // and of the previous frame.
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<[Mat4; 2]>() as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info =
+        vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(push_constant_ranges);
+
+    data.motion_blur.velocity_pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::VELOCITY_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::VELOCITY_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Vertex Input State
+
+    // Only the positions of the vertices are read (like the shadow pass).
+    let binding_descriptions = &[Vertex::binding_description()];
+    let attribute_descriptions = &[Vertex::attribute_descriptions()[0]];
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(attribute_descriptions);
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // Both sides of the quads are visible.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // Depth Stencil State
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G)
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.motion_blur.velocity_pipeline_layout)
+        .render_pass(*data.motion_blur.velocity.render_pass)
+        .subpass(0);
+
+    data.motion_blur.velocity_pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Creates the pipeline which blurs the HDR target.
+unsafe fn create_blur_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    // Layout
+
+    // The reprojection matrix of the background, the number of samples, and
+    // the shutter fraction.
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size((size_of::<Mat4>() + size_of::<u32>() + size_of::<f32>()) as u32);
+
+    let set_layouts = &[*data.motion_blur.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.motion_blur.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Stages
+
+    // The lighting pass of deferred rendering covers the screen with a
+    // triangle too.
+    let vert_shader_module = create_shader_module(device, shaders::LIGHTING_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::MOTION_BLUR_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Vertex Input State
+
+    // The vertices of the triangle are generated by the vertex shader.
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.motion_blur.pipeline_layout)
+        .render_pass(*data.motion_blur.output.render_pass)
+        .subpass(0);
+
+    data.motion_blur.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Returns the view projection matrix and the model matrices of the previous
+/// frame, or the supplied matrices (so nothing moves) if there was no
+/// previous frame or its draws don't match the supplied draws.
+fn get_previous_frame<'a>(
+    data: &'a AppData,
+    draws: &'a [Draw],
+    view_proj: &Mat4,
+) -> (Mat4, Vec<&'a Mat4>) {
+    let view_proj = data.motion_blur.previous_view_proj.unwrap_or(*view_proj);
+
+    let models = if data.motion_blur.previous_models.len() == draws.len() {
+        data.motion_blur.previous_models.iter().collect()
+    } else {
+        draws.iter().map(|d| &d.model).collect()
+    };
+
+    (view_proj, models)
+}
+
+/// Records the velocity pass, which renders the motion of the supplied draws
+/// since the previous frame with the supplied view projection matrix into
+/// the velocity buffer.
+pub unsafe fn cmd_velocity_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    draws: &[Draw],
+    view_proj: &Mat4,
+) {
+    // Pixels where nothing is drawn have no velocity.
+    cmd_begin_render_target(
+        device,
+        &data.motion_blur.velocity,
+        command_buffer,
+        [0.0, 0.0, 0.0, 0.0],
+    );
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.motion_blur.velocity_pipeline,
+    );
+
+    let (previous_view_proj, previous_models) = get_previous_frame(data, draws, view_proj);
+
+    for (draw, previous_model) in draws.iter().zip(previous_models) {
+        let mesh = match data.resources.mesh(draw.mesh) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+
+        let matrices = [view_proj * draw.model, previous_view_proj * previous_model];
+        let bytes =
+            std::slice::from_raw_parts(matrices.as_ptr() as *const u8, size_of::<[Mat4; 2]>());
+        device.cmd_push_constants(
+            command_buffer,
+            *data.motion_blur.velocity_pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            bytes,
+        );
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer.buffer], &[0]);
+        device.cmd_bind_index_buffer(
+            command_buffer,
+            mesh.index_buffer.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
+
+        for submesh in &mesh.submeshes {
+            let indices = &submesh.indices;
+            device.cmd_draw_indexed(command_buffer, indices.len() as u32, 1, indices.start, 0, 0);
+        }
+    }
+
+    device.cmd_end_render_pass(command_buffer);
+}
+
+/// Records blurring the HDR target into the blurred target with the sample
+/// count and shutter fraction in the options, where the background is
+/// reprojected from the supplied view projection matrix.
+pub unsafe fn cmd_motion_blur(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    view_proj: &Mat4,
+) {
+    // Every pixel of the blurred target is written.
+    cmd_begin_render_target(
+        device,
+        &data.motion_blur.output,
+        command_buffer,
+        [0.0, 0.0, 0.0, 1.0],
+    );
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.motion_blur.pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.motion_blur.pipeline_layout,
+        0,
+        &[data.motion_blur.descriptor_set],
+        &[],
+    );
+
+    // NDC of this frame are transformed back to world space and then to the
+    // clip space of the previous frame.
+    let previous_view_proj = data.motion_blur.previous_view_proj.unwrap_or(*view_proj);
+    let reprojection = previous_view_proj * glm::inverse(view_proj);
+
+    let push_constants = [
+        std::slice::from_raw_parts(&reprojection as *const Mat4 as *const u8, size_of::<Mat4>()),
+        &data.options.motion_blur_samples.to_ne_bytes()[..],
+        &data.options.shutter.to_ne_bytes()[..],
+    ]
+    .concat();
+    device.cmd_push_constants(
+        command_buffer,
+        *data.motion_blur.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        &push_constants,
+    );
+
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    device.cmd_end_render_pass(command_buffer);
+}
+
+/// Remembers the supplied draws and view projection matrix as those of the
+/// previous frame (for the next frame).
+pub fn update_previous_frame(data: &mut AppData, draws: &[Draw], view_proj: &Mat4) {
+    data.motion_blur.previous_view_proj = Some(*view_proj);
+    data.motion_blur.previous_models = draws.iter().map(|d| d.model).collect();
+}
@@ -0,0 +1,597 @@
+//! Hardware occlusion queries, which skip drawing the heavy objects that were
+//! entirely hidden in a previous frame.
+//!
+//! After the scene is drawn in the main pass, the bounding box of each heavy
+//! object (see `OCCLUSION_QUERY_MIN_INDICES`) is drawn against its depth
+//! without writing to any attachment, within an occlusion query which counts
+//! the samples that pass the depth test. Once the last frame rendered to a
+//! swapchain image has completed, the queries of that frame are read back and
+//! the objects which passed no samples are skipped until a later query of
+//! their bounding boxes passes samples again (their boxes are still drawn).
+//!
+//! Each swapchain image has its own range of queries, so the queries of the
+//! frames in flight don't overwrite each other. The range is reset before the
+//! main pass (outside of the render pass) after it has been read back.
+//!
+//! Where `VK_EXT_conditional_rendering` is supported, the results are never
+//! read back. Instead, the results of each frame are copied into a predicate
+//! buffer after the main pass, and the heavy objects queried in that frame are
+//! drawn in the next frame with conditional rendering, which discards their
+//! draw calls on the GPU if their predicates (sample counts) are zero.
+
+use std::collections::HashSet;
+use std::mem::size_of;
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::ExtConditionalRenderingExtension;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::commands::{begin_single_time_commands, end_single_time_commands, DrawBatch};
+use crate::debug::OCCLUSION_LABEL_COLOR;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::pipeline::create_shader_module;
+use crate::resources::MeshHandle;
+use crate::scene::Draw;
+use crate::shaders;
+use crate::vertex::{Mat4, Vec3};
+
+/// The number of indices above which an object is heavy enough to be worth
+/// querying.
+pub const OCCLUSION_QUERY_MIN_INDICES: u32 = 4096;
+
+/// The number of queries per swapchain image (the number of heavy objects
+/// which can be queried per frame).
+const MAX_OCCLUSION_QUERIES: u32 = 256;
+
+/// An object in the draws of a frame (its index and mesh).
+type OcclusionObject = (usize, MeshHandle);
+
+/// The occlusion query pool and the pipeline which draws the bounding boxes.
+///
+/// These are recreated with the swapchain.
+#[derive(Debug, Default)]
+pub struct OcclusionQueries {
+    query_pool: Owned<vk::QueryPool>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+    /// The objects queried in the last frame rendered to each swapchain image
+    /// (in the order of their queries).
+    frames: Vec<Vec<OcclusionObject>>,
+    /// The objects whose last query passed no samples.
+    occluded: HashSet<OcclusionObject>,
+    /// The results of the queries of the last frame recorded, copied on the
+    /// GPU (if conditional rendering is supported).
+    predicates: Buffer,
+    /// The objects queried in the last frame recorded (in the order of their
+    /// predicates).
+    predicate_objects: Vec<OcclusionObject>,
+}
+
+impl OcclusionQueries {
+    /// Returns whether heavy objects are skipped when they are occluded.
+    pub fn is_enabled(&self) -> bool {
+        !self.pipeline.is_null()
+    }
+
+    /// Returns whether the supplied draw (at the supplied index in the draws
+    /// of the frame) was occluded in a previous frame.
+    pub fn is_occluded(&self, index: usize, draw: &Draw) -> bool {
+        self.occluded.contains(&(index, draw.mesh))
+    }
+
+    /// Returns the predicate buffer and the offset of the predicate of the
+    /// supplied draw (at the supplied index in the draws of the frame), if it
+    /// is drawn with conditional rendering.
+    pub fn get_predicate(&self, index: usize, draw: &Draw) -> Option<(vk::Buffer, u64)> {
+        if self.predicates.buffer.is_null() {
+            return None;
+        }
+
+        self.predicate_objects
+            .iter()
+            .position(|o| *o == (index, draw.mesh))
+            .map(|p| (self.predicates.buffer, (p * size_of::<u32>()) as u64))
+    }
+
+    /// Returns the number of objects queried in the frame being rendered to
+    /// the swapchain image at the supplied index.
+    pub fn query_count(&self, image_index: usize) -> usize {
+        self.frames.get(image_index).map_or(0, |f| f.len())
+    }
+}
+
+/// Returns whether the supplied point is inside the supplied bounding box
+/// after it is transformed by the supplied model matrix.
+///
+/// The box is grown by a margin, so boxes which would be clipped by the near
+/// plane are treated as containing the camera.
+fn contains_point(min: &Vec3, max: &Vec3, model: &Mat4, point: &Vec3) -> bool {
+    let point = glm::inverse(model) * glm::vec4(point.x, point.y, point.z, 1.0);
+    let margin = glm::vec3(0.1, 0.1, 0.1);
+    let (min, max) = (min - margin, max + margin);
+    (0..3).all(|i| point[i] >= min[i] && point[i] <= max[i])
+}
+
+/// Creates the occlusion query pool and the pipeline which draws the bounding
+/// boxes (unless occlusion queries are disabled in the options).
+pub unsafe fn create_occlusion_queries(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.occlusion_queries {
+        return Ok(());
+    }
+
+    // The bounding boxes are drawn in the main pass, which doesn't have the
+    // depth of the scene with deferred rendering.
+    if data.options.deferred {
+        warn!("Occlusion queries aren't supported with deferred rendering.");
+        return Ok(());
+    }
+
+    // Query Pool
+
+    let images = data.swapchain.images.len();
+    let count = MAX_OCCLUSION_QUERIES * images as u32;
+
+    let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::OCCLUSION)
+        .query_count(count);
+
+    data.occlusion.query_pool = Owned::new(device, device.create_query_pool(&info, None)?);
+    data.occlusion.frames = vec![vec![]; images];
+
+    // Predicates
+
+    let size = (size_of::<u32>() as u32 * MAX_OCCLUSION_QUERIES) as u64;
+    if data.features.conditional_rendering {
+        data.occlusion.predicates = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+    }
+
+    // Reset
+
+    // The objects are drawn until they have been queried (and the queries
+    // have to be reset before their results can be retrieved).
+    let command_buffer = begin_single_time_commands(device, data)?;
+    device.cmd_reset_query_pool(command_buffer, *data.occlusion.query_pool, 0, count);
+    if data.features.conditional_rendering {
+        device.cmd_fill_buffer(command_buffer, data.occlusion.predicates.buffer, 0, size, 1);
+    }
+    end_single_time_commands(device, data, command_buffer)?;
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<Mat4>() as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info =
+        vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(push_constant_ranges);
+
+    data.occlusion.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Stages
+
+    // The bounding boxes are only depth tested, so there is no fragment
+    // shader.
+    let vert_shader_module = create_shader_module(device, shaders::OCCLUSION_BOX_VERT)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // Vertex Input State
+
+    // The corners of the boxes are generated by the vertex shader.
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // Both sides of the boxes are drawn, so a box is visible from inside it.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::empty())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.occlusion.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.occlusion.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+
+    Ok(())
+}
+
+/// Reads the queries of the last frame rendered to the swapchain image at the
+/// supplied index, updating which objects are occluded.
+///
+/// This must only be called once the last frame rendered to the swapchain
+/// image has completed and before its queries are reset.
+pub unsafe fn read_occlusion_queries(
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+) -> Result<()> {
+    // With conditional rendering, the results are only copied on the GPU
+    // (see `cmd_copy_occlusion_predicates`).
+    if !data.occlusion.predicates.buffer.is_null() {
+        return Ok(());
+    }
+
+    let Some(objects) = data.occlusion.frames.get_mut(image_index) else {
+        return Ok(());
+    };
+
+    if objects.is_empty() {
+        return Ok(());
+    }
+
+    let objects = std::mem::take(objects);
+    let mut samples = vec![0u64; objects.len()];
+    let bytes = std::slice::from_raw_parts_mut(
+        samples.as_mut_ptr() as *mut u8,
+        size_of::<u64>() * samples.len(),
+    );
+
+    let result = device.get_query_pool_results(
+        *data.occlusion.query_pool,
+        MAX_OCCLUSION_QUERIES * image_index as u32,
+        objects.len() as u32,
+        bytes,
+        size_of::<u64>() as u64,
+        vk::QueryResultFlags::_64,
+    )?;
+
+    // The frame has completed, so this only happens if it was never submitted
+    // (in which case the objects are left as they were).
+    if result == vk::SuccessCode::NOT_READY {
+        return Ok(());
+    }
+
+    for (object, samples) in objects.into_iter().zip(samples) {
+        if samples == 0 {
+            data.occlusion.occluded.insert(object);
+        } else {
+            data.occlusion.occluded.remove(&object);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resets the queries for the swapchain image at the supplied index.
+pub unsafe fn cmd_reset_occlusion_queries(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    if data.occlusion.is_enabled() {
+        let first = MAX_OCCLUSION_QUERIES * image_index as u32;
+        device.cmd_reset_query_pool(
+            command_buffer,
+            *data.occlusion.query_pool,
+            first,
+            MAX_OCCLUSION_QUERIES,
+        );
+    }
+}
+
+/// Records copying the results of the queries for the swapchain image at the
+/// supplied index into the predicate buffer after the main pass (if
+/// conditional rendering is supported), after which they are ready to
+/// predicate the draws of the next frame.
+pub unsafe fn cmd_copy_occlusion_predicates(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    let count = data.occlusion.query_count(image_index) as u32;
+    if data.occlusion.predicates.buffer.is_null() || count == 0 {
+        return;
+    }
+
+    // The predicates of the last frame have to be read before they are
+    // overwritten.
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    // The copy waits for the queries to complete on the GPU.
+    device.cmd_copy_query_pool_results(
+        command_buffer,
+        *data.occlusion.query_pool,
+        MAX_OCCLUSION_QUERIES * image_index as u32,
+        count,
+        data.occlusion.predicates.buffer,
+        0,
+        size_of::<u32>() as u64,
+        vk::QueryResultFlags::WAIT,
+    );
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::CONDITIONAL_RENDERING_READ_EXT);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Records beginning conditional rendering with the supplied predicate (see
+/// `OcclusionQueries::get_predicate`), if there is one.
+pub unsafe fn cmd_begin_predicate(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    predicate: Option<(vk::Buffer, u64)>,
+) {
+    if let Some((buffer, offset)) = predicate {
+        let info = vk::ConditionalRenderingBeginInfoEXT::builder()
+            .buffer(buffer)
+            .offset(offset);
+        device.cmd_begin_conditional_rendering_ext(command_buffer, &info);
+    }
+}
+
+/// Records ending conditional rendering with the supplied predicate, if there
+/// is one.
+pub unsafe fn cmd_end_predicate(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    predicate: Option<(vk::Buffer, u64)>,
+) {
+    if predicate.is_some() {
+        device.cmd_end_conditional_rendering_ext(command_buffer);
+    }
+}
+
+/// Returns a draw batch which queries the bounding boxes of the heavy objects
+/// in the supplied draws with the supplied view and projection matrices (if
+/// occlusion queries are enabled and there are any heavy objects).
+///
+/// The objects are recorded as queried for the swapchain image at the
+/// supplied index, so their queries are read back before the next frame
+/// rendered to it (see `read_occlusion_queries`).
+pub fn get_occlusion_batch(
+    data: &mut AppData,
+    image_index: usize,
+    draws: &[Draw],
+    view: &Mat4,
+    proj: &Mat4,
+) -> Option<DrawBatch<'static>> {
+    if !data.occlusion.is_enabled() {
+        return None;
+    }
+
+    let inv_view = glm::inverse(view);
+    let eye = glm::vec3(inv_view[(0, 3)], inv_view[(1, 3)], inv_view[(2, 3)]);
+    let view_proj = proj * view;
+
+    let mut objects = vec![];
+    let mut transforms = vec![];
+    for (index, draw) in draws.iter().enumerate() {
+        if objects.len() == MAX_OCCLUSION_QUERIES as usize {
+            break;
+        }
+
+        let Some(mesh) = data.resources.mesh(draw.mesh) else {
+            continue;
+        };
+
+        let indices = mesh
+            .submeshes
+            .iter()
+            .map(|s| s.indices.len() as u32)
+            .sum::<u32>();
+        if indices < OCCLUSION_QUERY_MIN_INDICES {
+            continue;
+        }
+
+        // The box of an object around the camera would be clipped by the near
+        // plane, so the object is drawn without being queried.
+        let (min, max) = (mesh.bounds.min, mesh.bounds.max);
+        if contains_point(&min, &max, &draw.model, &eye) {
+            data.occlusion.occluded.remove(&(index, draw.mesh));
+            continue;
+        }
+
+        let transform = glm::translate(&draw.model, &min);
+        let transform = glm::scale(&transform, &(max - min));
+        objects.push((index, draw.mesh));
+        transforms.push(view_proj * transform);
+    }
+
+    data.occlusion.predicate_objects = objects.clone();
+    data.occlusion.frames[image_index] = objects;
+    if transforms.is_empty() {
+        return None;
+    }
+
+    let query_pool = *data.occlusion.query_pool;
+    let pipeline_layout = *data.occlusion.pipeline_layout;
+    let pipeline = *data.occlusion.pipeline;
+    let first = MAX_OCCLUSION_QUERIES * image_index as u32;
+
+    Some(DrawBatch {
+        name: "Occlusion Queries",
+        color: OCCLUSION_LABEL_COLOR,
+        record: Box::new(move |device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            for (query, transform) in transforms.iter().enumerate() {
+                let push_constants = std::slice::from_raw_parts(
+                    transform as *const Mat4 as *const u8,
+                    size_of::<Mat4>(),
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    push_constants,
+                );
+
+                let query = first + query as u32;
+                device.cmd_begin_query(
+                    command_buffer,
+                    query_pool,
+                    query,
+                    vk::QueryControlFlags::empty(),
+                );
+                device.cmd_draw(command_buffer, 36, 1, 0, 0);
+                device.cmd_end_query(command_buffer, query_pool, query);
+            }
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains() {
+        let min = glm::vec3(-1.0, -1.0, -1.0);
+        let max = glm::vec3(1.0, 1.0, 1.0);
+        let model = glm::translate(&Mat4::identity(), &glm::vec3(10.0, 0.0, 0.0));
+
+        assert!(contains_point(
+            &min,
+            &max,
+            &model,
+            &glm::vec3(10.5, 0.0, 0.0)
+        ));
+        assert!(!contains_point(
+            &min,
+            &max,
+            &model,
+            &glm::vec3(0.0, 0.0, 0.0)
+        ));
+
+        // The box is grown by a margin.
+        assert!(contains_point(
+            &min,
+            &max,
+            &model,
+            &glm::vec3(11.05, 0.0, 0.0)
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+//! An overlay with statistics about the frames rendered (a graph of the CPU
+//! and GPU frame times, the number of draw calls, and the video memory used),
+//! which is toggled with F3 and drawn with the egui UI.
+
+use std::collections::VecDeque;
+
+#[cfg(not(feature = "egui"))]
+use log::*;
+
+#[cfg(feature = "egui")]
+use crate::memory::ArenaStats;
+#[cfg(feature = "egui")]
+use crate::queries::FrameStats;
+
+/// The number of frames shown in the frame time graph.
+const HISTORY: usize = 240;
+
+/// The frame time (in milliseconds) the frame time graph is scaled to at least
+/// (i.e., 60 FPS).
+const MIN_GRAPH_TIME: f32 = 1000.0 / 60.0;
+
+/// The frame times of the last frames and whether the overlay is shown.
+#[derive(Clone, Debug, Default)]
+pub struct StatsOverlay {
+    pub visible: bool,
+    /// The CPU and GPU frame times (in milliseconds) of the last frames.
+    frame_times: VecDeque<(f32, f32)>,
+}
+
+impl StatsOverlay {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+
+        #[cfg(not(feature = "egui"))]
+        if self.visible {
+            warn!("The stats overlay is only drawn when built with the `egui` feature.");
+        }
+    }
+
+    /// Records the CPU frame time (the time between frames) and GPU frame
+    /// time (in milliseconds) of a frame.
+    pub fn record(&mut self, cpu: f32, gpu: f32) {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+
+        self.frame_times.push_back((cpu, gpu));
+    }
+
+    /// Shows the overlay in the top right corner of the window.
+    #[cfg(feature = "egui")]
+    pub fn show(&self, ctx: &egui::Context, stats: &FrameStats, memory: &ArenaStats) {
+        use egui::{pos2, vec2, Align2, Color32, Sense, Shape, Stroke};
+
+        const MIB: f64 = 1024.0 * 1024.0;
+
+        let cpu_color = Color32::from_rgb(100, 180, 255);
+        let gpu_color = Color32::from_rgb(120, 220, 100);
+
+        egui::Window::new("Stats")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(Align2::RIGHT_TOP, vec2(-8.0, 8.0))
+            .show(ctx, |ui| {
+                let (cpu, gpu) = self.frame_times.back().copied().unwrap_or_default();
+                ui.colored_label(
+                    cpu_color,
+                    format!("CPU: {:.2} ms ({:.0} FPS)", cpu, 1000.0 / cpu.max(0.001)),
+                );
+                ui.colored_label(gpu_color, format!("GPU: {:.2} ms", gpu));
+                for (name, time) in &stats.passes {
+                    ui.label(format!("  {}: {:.2} ms", name, time));
+                }
+
+                // Graph
+
+                let size = vec2(HISTORY as f32, 60.0);
+                let (response, painter) = ui.allocate_painter(size, Sense::hover());
+                let rect = response.rect;
+                painter.rect_filled(rect, 2.0, Color32::from_black_alpha(96));
+
+                // The graph is scaled to the slowest frame (and 60 FPS at
+                // least, so a steady frame rate looks steady).
+                let max = self
+                    .frame_times
+                    .iter()
+                    .map(|(cpu, gpu)| cpu.max(*gpu))
+                    .fold(MIN_GRAPH_TIME, f32::max);
+
+                let line = |time: fn(&(f32, f32)) -> f32, color: Color32| {
+                    let points = self
+                        .frame_times
+                        .iter()
+                        .enumerate()
+                        .map(|(i, t)| {
+                            let x = rect.left() + i as f32 * rect.width() / (HISTORY - 1) as f32;
+                            let y = rect.bottom() - time(t).min(max) / max * rect.height();
+                            pos2(x, y)
+                        })
+                        .collect();
+                    Shape::line(points, Stroke::new(1.0, color))
+                };
+
+                painter.add(line(|t| t.0, cpu_color));
+                painter.add(line(|t| t.1, gpu_color));
+                ui.small(format!("{:.1} ms", max));
+
+                // Frame
+
+                ui.label(format!("Draw calls: {}", stats.draw_calls));
+                if let Some(culling) = &stats.culling {
+                    ui.label(format!(
+                        "Culled draws: {} (frustum), {} (occlusion)",
+                        culling.frustum_culled, culling.occlusion_culled,
+                    ));
+                }
+                // Only the arenas know how much memory their blocks hold.
+                if memory.blocks == 0 {
+                    ui.label(format!("VRAM: {:.1} MiB used", memory.used as f64 / MIB));
+                } else {
+                    ui.label(format!(
+                        "VRAM: {:.1} MiB used of {:.1} MiB allocated",
+                        memory.used as f64 / MIB,
+                        memory.capacity as f64 / MIB,
+                    ));
+                }
+            });
+    }
+}
@@ -0,0 +1,518 @@
+//! A particle system which is updated by a compute shader on an async compute
+//! queue while the graphics queue renders, then drawn as points.
+//!
+//! Each frame in flight has a particle buffer. The compute shader reads the
+//! particles from the buffer of the previous frame and writes the updated
+//! particles to the buffer of the current frame, signaling a semaphore which
+//! the graphics queue waits on before reading the particles as vertices. The
+//! buffers are shared by the compute and graphics queue families, so their
+//! ownership is never transferred between them.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::buffers::{create_shared_buffer, Buffer};
+use crate::commands::DrawBatch;
+use crate::debug::{begin_queue_label, end_queue_label, PARTICLES_LABEL_COLOR};
+use crate::device::{QueueFamilyIndices, RenderingPath};
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::vertex::Vec2;
+
+/// The number of particles.
+pub const PARTICLE_COUNT: u32 = 8192;
+
+/// The number of particles updated by a workgroup of the compute shader.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// A particle as it is laid out in the particle buffers (the particles are
+/// only ever created by the compute shader).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Particle {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub color: glm::Vec4,
+}
+
+impl Particle {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Particle>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(0)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<Vec2>() + size_of::<Vec2>()) as u32)
+            .build();
+        [position, color]
+    }
+}
+
+/// The Vulkan handles used to update and draw the particles.
+#[derive(Debug, Default)]
+pub struct Particles {
+    /// The particle buffer for each frame in flight.
+    pub buffers: Vec<Buffer>,
+    // Compute
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+    command_pool: Owned<vk::CommandPool>,
+    /// Freed along with their command pool.
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Signaled once the particles for each frame in flight have been updated.
+    pub semaphores: Vec<Owned<vk::Semaphore>>,
+    /// Whether the particles have been initialized by the first update.
+    initialized: bool,
+    // Graphics
+    pub graphics_pipeline_layout: Owned<vk::PipelineLayout>,
+    pub graphics_pipeline: Owned<vk::Pipeline>,
+}
+
+/// Creates the particle buffers and the compute pipeline which updates them.
+///
+/// This does nothing unless particles are enabled.
+pub unsafe fn create_particles(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.particles {
+        return Ok(());
+    }
+
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    // Buffers
+
+    let mut queue_family_indices = vec![indices.graphics];
+    if indices.compute != indices.graphics {
+        queue_family_indices.push(indices.compute);
+    }
+
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let buffer = create_shared_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<Particle>() * PARTICLE_COUNT as usize) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &queue_family_indices,
+        )?;
+
+        data.particles.buffers.push(buffer);
+    }
+
+    // Descriptor Set Layout
+
+    let bindings = [0, 1].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    });
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.particles.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(2 * MAX_FRAMES_IN_FLIGHT as u32);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+
+    data.particles.descriptor_pool =
+        Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Sets
+
+    let layouts = vec![*data.particles.descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.particles.descriptor_pool)
+        .set_layouts(&layouts);
+
+    data.particles.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for i in 0..MAX_FRAMES_IN_FLIGHT {
+        let previous = (i + MAX_FRAMES_IN_FLIGHT - 1) % MAX_FRAMES_IN_FLIGHT;
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.particles.buffers[previous].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+
+        let buffer_info = &[info];
+        let in_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.particles.descriptor_sets[i])
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.particles.buffers[i].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+
+        let buffer_info = &[info];
+        let out_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.particles.descriptor_sets[i])
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        device.update_descriptor_sets(&[in_write, out_write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Pipeline
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(8 /* 4 byte float delta + 4 byte initialize flag */);
+
+    let set_layouts = &[*data.particles.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.particles.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    let comp_shader_module = create_shader_module(device, shaders::PARTICLES_COMP)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(comp_shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(*data.particles.pipeline_layout);
+
+    data.particles.pipeline = Owned::new(
+        device,
+        device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    device.destroy_shader_module(comp_shader_module, None);
+
+    // Command Buffers
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(indices.compute);
+
+    data.particles.command_pool = Owned::new(device, device.create_command_pool(&info, None)?);
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*data.particles.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32);
+
+    data.particles.command_buffers = device.allocate_command_buffers(&info)?;
+
+    // Semaphores
+
+    let info = vk::SemaphoreCreateInfo::builder();
+
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let semaphore = device.create_semaphore(&info, None)?;
+        data.particles
+            .semaphores
+            .push(Owned::new(device, semaphore));
+    }
+
+    data.particles.initialized = false;
+
+    Ok(())
+}
+
+/// Creates the pipeline which draws the particles as points in the main pass.
+///
+/// This does nothing unless particles are enabled.
+pub unsafe fn create_particle_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !data.options.particles {
+        return Ok(());
+    }
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::PARTICLE_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::PARTICLE_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Vertex Input State
+
+    let binding_descriptions = &[Particle::binding_description()];
+    let attribute_descriptions = Particle::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::POINT_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    // The particles are drawn on top of everything else.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Layout
+
+    let layout_info = vk::PipelineLayoutCreateInfo::builder();
+
+    data.particles.graphics_pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.particles.graphics_pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.particles.graphics_pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Submits an update of the particles for the frame in flight at the supplied
+/// index to the compute queue, which advances them by the supplied time (in
+/// seconds).
+///
+/// The command buffer for the frame in flight has to have completed, and the
+/// graphics queue has to wait on the semaphore for the frame in flight that
+/// the update signals.
+pub unsafe fn submit_particle_update(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+    delta: f32,
+) -> Result<()> {
+    let command_buffer = data.particles.command_buffers[frame];
+
+    device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    // The particles written by the previous update are read by this one.
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let initialize = !data.particles.initialized as u32;
+    let push_constants = [delta.to_ne_bytes(), initialize.to_ne_bytes()].concat();
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.particles.pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        *data.particles.pipeline_layout,
+        0,
+        &[data.particles.descriptor_sets[frame]],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *data.particles.pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        &push_constants,
+    );
+    device.cmd_dispatch(
+        command_buffer,
+        PARTICLE_COUNT.div_ceil(WORKGROUP_SIZE),
+        1,
+        1,
+    );
+
+    device.end_command_buffer(command_buffer)?;
+
+    // Submit
+
+    let queue = data.compute_queue;
+    begin_queue_label(instance, data, queue, "Particles", PARTICLES_LABEL_COLOR)?;
+
+    let command_buffers = &[command_buffer];
+    let signal_semaphores = &[*data.particles.semaphores[frame]];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers)
+        .signal_semaphores(signal_semaphores);
+
+    device.queue_submit(queue, &[info], vk::Fence::null())?;
+
+    end_queue_label(instance, data, queue);
+
+    data.particles.initialized = true;
+
+    Ok(())
+}
+
+/// Returns a draw batch which draws the particles updated for the frame in
+/// flight at the supplied index.
+pub fn get_particles_batch(data: &AppData, frame: usize) -> DrawBatch<'static> {
+    let pipeline = *data.particles.graphics_pipeline;
+    let buffer = data.particles.buffers[frame].buffer;
+
+    DrawBatch {
+        name: "Particles",
+        color: PARTICLES_LABEL_COLOR,
+        record: Box::new(move |device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+            device.cmd_draw(command_buffer, PARTICLE_COUNT, 1, 0, 0);
+        }),
+    }
+}
@@ -0,0 +1,275 @@
+//! Ping-pong images for iterative compute passes (like blurs or
+//! simulations), where each iteration reads the image the previous iteration
+//! wrote and writes the other.
+//!
+//! Each iteration is bound to a descriptor set which reads one image (as a
+//! sampled image, so it can be filtered) and writes the other (as a storage
+//! image). Between the iterations, `cmd_swap_ping_pong` transitions the image
+//! which was written to be read and the image which was read to be written.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view, get_image_barrier, Image};
+
+/// A pair of images which iterations of a compute pass alternately read and
+/// write.
+#[derive(Debug, Default)]
+pub struct PingPong {
+    pub extent: vk::Extent2D,
+    pub images: [Image; 2],
+    /// The layout of the descriptor sets of the iterations, which bind the
+    /// image which is read (a combined image sampler) at binding 0 and the
+    /// image which is written (a storage image) at binding 1.
+    pub descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl PingPong {
+    /// Returns the descriptor set of the supplied iteration (counting from 0),
+    /// which reads the first image in even iterations and the second image in
+    /// odd iterations.
+    pub fn descriptor_set(&self, iteration: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[iteration % 2]
+    }
+
+    /// Returns the image written by the last of the supplied number of
+    /// iterations (the first image if there were none).
+    pub fn output(&self, iterations: usize) -> &Image {
+        &self.images[iterations % 2]
+    }
+}
+
+/// Creates a pair of images of the supplied size and format and the
+/// descriptor sets which read them with the supplied sampler.
+pub unsafe fn create_ping_pong(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    sampler: vk::Sampler,
+) -> Result<PingPong> {
+    let mut ping_pong = PingPong {
+        extent,
+        ..Default::default()
+    };
+
+    // Images
+
+    for image in &mut ping_pong.images {
+        *image = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            vk::SampleCountFlags::_1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.view =
+            create_image_view(device, image.image, format, vk::ImageAspectFlags::COLOR, 1)?;
+    }
+
+    // Descriptor Set Layout
+
+    let bindings = [
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        vk::DescriptorType::STORAGE_IMAGE,
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(binding, type_)| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding as u32)
+            .descriptor_type(type_)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    })
+    .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    ping_pong.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(2);
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(2);
+
+    let pool_sizes = &[sampler_size, storage_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(2);
+
+    ping_pong.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Descriptor Sets
+
+    let layouts = &[*ping_pong.descriptor_set_layout; 2];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*ping_pong.descriptor_pool)
+        .set_layouts(layouts);
+
+    ping_pong.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (i, descriptor_set) in ping_pong.descriptor_sets.iter().enumerate() {
+        let read_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(ping_pong.images[i].view)
+            .sampler(sampler);
+
+        let write_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(ping_pong.images[1 - i].view);
+
+        let image_infos = [[read_info], [write_info]];
+        let writes = image_infos
+            .iter()
+            .zip(&bindings)
+            .map(|(image_info, binding)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(binding.descriptor_type)
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    Ok(ping_pong)
+}
+
+/// Records discarding the contents of both images and transitioning them to
+/// be written by compute shaders (after anything which read them before).
+///
+/// The first image is then written with something to iterate on before the
+/// first iteration.
+pub unsafe fn cmd_begin_ping_pong(
+    device: &Device,
+    ping_pong: &PingPong,
+    command_buffer: vk::CommandBuffer,
+) {
+    let barriers = ping_pong
+        .images
+        .iter()
+        .map(|i| {
+            get_image_barrier(
+                i.image,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_WRITE,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+}
+
+/// Records the transitions before the supplied iteration (counting from 0),
+/// after which the image written by the previous iteration (or before the
+/// first iteration) is ready to be read and the other image is ready to be
+/// written.
+pub unsafe fn cmd_swap_ping_pong(
+    device: &Device,
+    ping_pong: &PingPong,
+    command_buffer: vk::CommandBuffer,
+    iteration: usize,
+) {
+    let read = &ping_pong.images[iteration % 2];
+    let write = &ping_pong.images[(iteration + 1) % 2];
+
+    // The image which is written was last read, except before the first
+    // iteration.
+    let write_layout = if iteration == 0 {
+        vk::ImageLayout::GENERAL
+    } else {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    };
+
+    let barriers = [
+        get_image_barrier(
+            read.image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        ),
+        get_image_barrier(
+            write.image,
+            vk::ImageAspectFlags::COLOR,
+            write_layout,
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags::SHADER_READ,
+            vk::AccessFlags::SHADER_WRITE,
+        ),
+    ];
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+}
+
+/// Records transitioning the image written by the last of the supplied
+/// number of iterations to be read by fragment and compute shaders.
+pub unsafe fn cmd_end_ping_pong(
+    device: &Device,
+    ping_pong: &PingPong,
+    command_buffer: vk::CommandBuffer,
+    iterations: usize,
+) {
+    let barrier = get_image_barrier(
+        ping_pong.output(iterations).image,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::AccessFlags::SHADER_WRITE,
+        vk::AccessFlags::SHADER_READ,
+    );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
@@ -0,0 +1,570 @@
+//! The render pass, descriptor set layout and graphics pipeline.
+
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::deferred::GBUFFER_FORMATS;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::instancing::InstanceData;
+use crate::material::{MaterialInfo, MATERIAL_PUSH_CONSTANTS_SIZE};
+use crate::shaders;
+use crate::shadows::MAX_POINT_SHADOWS;
+use crate::swapchain::get_scene_encoding;
+use crate::vertex::Vertex;
+
+pub unsafe fn create_render_pass(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Dynamic rendering doesn't use render passes.
+    if data.rendering_path != RenderingPath::RenderPass {
+        return Ok(());
+    }
+
+    // Without multisampling, the swapchain image (or the intermediate target
+    // with tone mapping) is rendered to directly instead of being resolved to.
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
+    // With tone mapping, the intermediate target is left ready to be read by
+    // the tone mapping pass instead of being presented.
+    let color_format = get_color_format(data);
+    let target_layout = if data.options.tone_mapping.is_some() {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    } else {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    };
+
+    // Attachments
+
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(color_format)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(if multisampled {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            target_layout
+        });
+
+    // The depth is kept for the Hi-Z pyramid with occlusion culling.
+    let depth_store_op = if data.options.occlusion_culling {
+        vk::AttachmentStoreOp::STORE
+    } else {
+        vk::AttachmentStoreOp::DONT_CARE
+    };
+
+    let depth_stencil_attachment = vk::AttachmentDescription::builder()
+        .format(get_depth_format(instance, data)?)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(depth_store_op)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_resolve_attachment = vk::AttachmentDescription::builder()
+        .format(color_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(target_layout);
+
+    // Subpasses
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(2)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let resolve_attachments = &[color_resolve_attachment_ref];
+    let mut subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments)
+        .depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+    if multisampled {
+        subpass = subpass.resolve_attachments(resolve_attachments);
+    }
+
+    // Dependencies
+
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+
+    // Create
+
+    let attachments = &[
+        color_attachment,
+        depth_stencil_attachment,
+        color_resolve_attachment,
+    ];
+    let attachments = if multisampled {
+        &attachments[..]
+    } else {
+        &attachments[..2]
+    };
+    let subpasses = &[subpass];
+    let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.render_pass = Owned::new(device, device.create_render_pass(&info, None)?);
+
+    Ok(())
+}
+
+pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    // The fragment shader shades from the position of the camera.
+    let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+    // The textures of the material (see `MaterialTextures`).
+    let sampler_bindings = (1..=6).map(|i| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(i)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()
+    });
+
+    // The lights of the scene (see `LightBufferObject`).
+    let light_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(7)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    // The shadow map (see `ShadowMap`).
+    let shadow_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(8)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    // The shadow maps of the point lights (see `PointShadow`).
+    let point_shadow_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(9)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(MAX_POINT_SHADOWS as u32)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    // The lights which reach each cluster (see `Clusters`).
+    let cluster_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(10)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = [ubo_binding.build()]
+        .into_iter()
+        .chain(sampler_bindings)
+        .chain([
+            light_binding.build(),
+            shadow_binding.build(),
+            point_shadow_binding.build(),
+            cluster_binding.build(),
+        ])
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    Ok(())
+}
+
+pub unsafe fn create_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Layout
+
+    let vert_push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(
+            72, /* 16 × 4 byte floats + 8 byte vertex buffer address */
+        );
+
+    let frag_push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(72)
+        .size(
+            MATERIAL_PUSH_CONSTANTS_SIZE as u32 + 4, /* + 4 byte debug view (see `DebugView`) */
+        );
+
+    // The image-based lighting is bound as a second descriptor set (see
+    // `Ibl`).
+    let set_layouts = &[*data.descriptor_set_layout, *data.ibl.descriptor_set_layout];
+    let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.pipeline_layout = Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Materials
+
+    let materials = data
+        .resources
+        .materials
+        .iter()
+        .map(|(h, m)| (h, m.info))
+        .collect::<Vec<_>>();
+    for (handle, info) in materials {
+        let pipeline =
+            create_material_pipeline(instance, device, data, &info, vk::PolygonMode::FILL, false)?;
+
+        // Lines can only be drawn with `fillModeNonSolid`.
+        let wireframe_pipeline = if data.features.fill_mode_non_solid {
+            create_material_pipeline(instance, device, data, &info, vk::PolygonMode::LINE, false)?
+        } else {
+            Owned::default()
+        };
+
+        if let Some(material) = data.resources.materials.get_mut(handle) {
+            material.pipeline = pipeline;
+            material.wireframe_pipeline = wireframe_pipeline;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the graphics pipeline for a material which draws its triangles
+/// with the supplied polygon mode, or which draws instances of a mesh whose
+/// attributes are read from an instance buffer (see `InstanceData`).
+///
+/// With deferred rendering, the materials are drawn into the G-buffer by the
+/// same fragment shader (`gbuffer.frag`) instead, without blending or
+/// multisampling.
+pub unsafe fn create_material_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    material: &MaterialInfo,
+    polygon_mode: vk::PolygonMode,
+    instanced: bool,
+) -> Result<Owned<vk::Pipeline>> {
+    // Stages
+
+    // With buffer device addresses, the vertex shader reads the vertices
+    // itself (see `Mesh::from_data`), except for instances, whose vertices
+    // are read from vertex attributes along with the attributes of the
+    // instances.
+    let vert_shader = if instanced {
+        shaders::INSTANCED_VERT
+    } else if data.features.buffer_device_address {
+        shaders::VERT_BDA
+    } else {
+        shaders::VERT
+    };
+
+    let deferred = data.options.deferred;
+    let frag_shader = if deferred {
+        shaders::GBUFFER_FRAG
+    } else {
+        material.fragment_shader
+    };
+
+    let vert_shader_module = create_shader_module(device, vert_shader)?;
+    let frag_shader_module = create_shader_module(device, frag_shader)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The fragment shader encodes SDR output as sRGB itself if sRGB output is
+    // enabled but the swapchain format doesn't support it, and encodes HDR
+    // output for the color space of the swapchain (unless the scene is tone
+    // mapped). Instances are tinted with their colors.
+    let (encode_srgb, output_transfer) = get_scene_encoding(data);
+
+    let specialization_data = [
+        encode_srgb as vk::Bool32,
+        output_transfer as u32,
+        instanced as vk::Bool32,
+    ]
+    .iter()
+    .flat_map(|c| c.to_ne_bytes())
+    .collect::<Vec<_>>();
+    let map_entries = &[
+        vk::SpecializationMapEntry::builder()
+            .constant_id(0)
+            .offset(0)
+            .size(size_of::<vk::Bool32>()),
+        vk::SpecializationMapEntry::builder()
+            .constant_id(1)
+            .offset(size_of::<vk::Bool32>() as u32)
+            .size(size_of::<u32>()),
+        vk::SpecializationMapEntry::builder()
+            .constant_id(2)
+            .offset((size_of::<vk::Bool32>() + size_of::<u32>()) as u32)
+            .size(size_of::<vk::Bool32>()),
+    ];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&specialization_data);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Vertex Input State
+
+    let binding_descriptions = &[
+        Vertex::binding_description(),
+        InstanceData::binding_description(),
+    ];
+    let attribute_descriptions = Vertex::attribute_descriptions()
+        .into_iter()
+        .chain(InstanceData::attribute_descriptions())
+        .collect::<Vec<_>>();
+
+    // The instance buffer is the second vertex buffer.
+    let bindings = if instanced { 2 } else { 1 };
+    let attributes = if instanced {
+        attribute_descriptions.len()
+    } else {
+        Vertex::attribute_descriptions().len()
+    };
+
+    let mut vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+    if instanced || !data.features.buffer_device_address {
+        vertex_input_state = vertex_input_state
+            .vertex_binding_descriptions(&binding_descriptions[..bindings])
+            .vertex_attribute_descriptions(&attribute_descriptions[..attributes]);
+    }
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(polygon_mode)
+        .line_width(1.0)
+        .cull_mode(material.cull_mode)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let samples = if deferred {
+        vk::SampleCountFlags::_1
+    } else {
+        data.msaa_samples
+    };
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(samples);
+
+    // Depth Stencil State
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(material.blend && !deferred)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = if deferred {
+        vec![attachment.build(); GBUFFER_FORMATS.len()]
+    } else {
+        vec![attachment.build()]
+    };
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(&attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Rendering
+
+    // Without a render pass, the formats of the attachments are supplied
+    // when the pipeline is created instead.
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.pipeline_layout)
+        .render_pass(if deferred {
+            *data.gbuffer.render_pass
+        } else {
+            *data.render_pass
+        })
+        .subpass(0);
+
+    // The geometry pass always uses a render pass.
+    if data.rendering_path != RenderingPath::RenderPass && !deferred {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let result = device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None);
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(Owned::new(device, result?.0))
+}
+
+/// Creates a compute pipeline (and its layout) with the supplied descriptor
+/// set layout, compute shader, and push constants of the supplied size (if
+/// any).
+pub unsafe fn create_compute_pipeline(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    compute_shader: &[u8],
+    push_constants_size: usize,
+) -> Result<(Owned<vk::PipelineLayout>, Owned<vk::Pipeline>)> {
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(push_constants_size as u32);
+
+    let set_layouts = &[descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let mut layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    if push_constants_size > 0 {
+        layout_info = layout_info.push_constant_ranges(push_constant_ranges);
+    }
+
+    let pipeline_layout = Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    let comp_shader_module = create_shader_module(device, compute_shader)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(comp_shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(*pipeline_layout);
+
+    let result = device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None);
+
+    device.destroy_shader_module(comp_shader_module, None);
+
+    Ok((pipeline_layout, Owned::new(device, result?.0)))
+}
+
+pub unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
+    // SPIR-V is a stream of 32-bit words, which are copied out of the bytes
+    // since the bytes may not be aligned for `u32`.
+    if !bytecode.len().is_multiple_of(4) {
+        return Err(anyhow!("Shader bytecode is not a multiple of 4 bytes."));
+    }
+
+    let code = bytecode
+        .chunks_exact(4)
+        .map(|w| u32::from_ne_bytes([w[0], w[1], w[2], w[3]]))
+        .collect::<Vec<_>>();
+
+    let info = vk::ShaderModuleCreateInfo::builder()
+        .code_size(bytecode.len())
+        .code(&code);
+
+    Ok(device.create_shader_module(&info, None)?)
+}
@@ -0,0 +1,646 @@
+//! A post-processing stack, which applies an ordered chain of full-screen
+//! effects (see `PostProcess`) to the HDR intermediate target and writes the
+//! result of the last effect to the swapchain image.
+//!
+//! Each effect reads the output of the effect before it (the first reads the
+//! HDR intermediate target, or the blurred target with motion blur) and
+//! writes an intermediate target which the stack creates in the format the
+//! effect negotiates (see `PostProcess::output_format`), except the last
+//! effect, which writes the swapchain image. Effects after tone mapping work
+//! on colors already encoded for the swapchain, and effects before it (like
+//! bloom) on the HDR colors.
+//!
+//! Like the shadow pass, every stage uses a render pass. The last stage leaves
+//! the swapchain image ready to be presented, so the UI is drawn over it
+//! unaffected by the effects.
+
+use std::fmt;
+use std::mem::size_of;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::bloom::BloomEffect;
+use crate::debug::{begin_command_label, end_command_label, POST_PROCESS_LABEL_COLOR};
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view, Image};
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::tonemap::{ToneMapEffect, HDR_FORMAT};
+
+/// A full-screen effect applied by the post-processing stack.
+pub trait PostProcess: fmt::Debug {
+    /// The name of the effect (which labels its pass).
+    fn name(&self) -> &'static str;
+
+    /// Returns the format the effect writes when it reads the supplied format
+    /// (which is ignored for the last effect, which writes the swapchain
+    /// image).
+    fn output_format(&self, _data: &AppData, input: vk::Format) -> vk::Format {
+        input
+    }
+
+    /// Creates the pipeline (and anything else) which applies the effect in
+    /// the supplied stage.
+    unsafe fn create(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        stage: &Stage,
+    ) -> Result<()>;
+
+    /// Records anything the effect does before the render pass of its stage
+    /// (like compute passes over its input).
+    unsafe fn cmd_prepare(
+        &self,
+        _device: &Device,
+        _data: &AppData,
+        _command_buffer: vk::CommandBuffer,
+    ) {
+    }
+
+    /// Records applying the effect (in the render pass of its stage).
+    unsafe fn cmd_apply(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer);
+}
+
+/// What an effect reads and writes in the post-processing stack.
+#[derive(Copy, Clone, Debug)]
+pub struct Stage {
+    /// The output of the previous effect (ready to be read by the fragment
+    /// shader).
+    pub input: vk::ImageView,
+    /// The render pass the effect is applied in.
+    pub render_pass: vk::RenderPass,
+    /// A sampler which filters the input linearly (clamped to its edges).
+    pub sampler: vk::Sampler,
+}
+
+/// The effects of the post-processing stack, the intermediate targets between
+/// them, and the render passes and framebuffers which write them.
+///
+/// The intermediate targets are the size of the swapchain images, so they are
+/// recreated with the swapchain.
+#[derive(Debug, Default)]
+pub struct PostProcessStack {
+    effects: Vec<Box<dyn PostProcess>>,
+    /// The intermediate target of each effect but the last.
+    targets: Vec<Image>,
+    sampler: Owned<vk::Sampler>,
+    /// The render pass of each effect.
+    render_passes: Vec<Owned<vk::RenderPass>>,
+    /// The framebuffer of each effect (one per swapchain image for the last).
+    framebuffers: Vec<Vec<Owned<vk::Framebuffer>>>,
+}
+
+/// Returns the effects in the options, in the order they are applied.
+pub fn get_post_process_effects(data: &AppData) -> Vec<Box<dyn PostProcess>> {
+    let mut effects: Vec<Box<dyn PostProcess>> = Vec::new();
+
+    // Bloom is added to the HDR colors (before they are tone mapped).
+    if data.options.bloom > 0.0 {
+        effects.push(Box::<BloomEffect>::default());
+    }
+
+    if data.options.tone_mapping.is_some() {
+        effects.push(Box::<ToneMapEffect>::default());
+    }
+
+    if data.options.vignette > 0.0 {
+        effects.push(Box::<VignetteEffect>::default());
+    }
+
+    effects
+}
+
+/// Creates the effects in the options, the intermediate targets between them,
+/// and the render passes and framebuffers which write them.
+///
+/// This does nothing unless tone mapping is enabled in the options (without
+/// which the scene is rendered to the swapchain image directly), and has to
+/// be called after `create_tone_map` and `create_motion_blur`.
+pub unsafe fn create_post_process(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if data.options.tone_mapping.is_none() {
+        return Ok(());
+    }
+
+    let extent = data.swapchain.extent;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.post_process.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Stages
+
+    // The blurred target replaces the HDR intermediate target with motion
+    // blur.
+    let mut input = if data.options.motion_blur {
+        data.motion_blur.output.view()
+    } else {
+        data.tone_map.image.view
+    };
+    let mut input_format = HDR_FORMAT;
+
+    let mut effects = get_post_process_effects(data);
+    let count = effects.len();
+
+    for (index, effect) in effects.iter_mut().enumerate() {
+        let last = index + 1 == count;
+
+        let output_format = if last {
+            data.swapchain.format
+        } else {
+            effect.output_format(data, input_format)
+        };
+
+        let render_pass = create_stage_render_pass(device, output_format, last)?;
+        data.post_process
+            .render_passes
+            .push(Owned::new(device, render_pass));
+
+        let views = if last {
+            data.swapchain.image_views.clone()
+        } else {
+            let mut target = create_image(
+                instance,
+                device,
+                data,
+                extent.width,
+                extent.height,
+                1,
+                vk::SampleCountFlags::_1,
+                output_format,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+
+            target.view = create_image_view(
+                device,
+                target.image,
+                output_format,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )?;
+
+            let view = target.view;
+            data.post_process.targets.push(target);
+            vec![view]
+        };
+
+        let framebuffers = views
+            .iter()
+            .map(|v| {
+                let attachments = &[*v];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+
+                let framebuffer = device.create_framebuffer(&info, None)?;
+                Ok(Owned::new(device, framebuffer))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        data.post_process.framebuffers.push(framebuffers);
+
+        let stage = Stage {
+            input,
+            render_pass,
+            sampler: *data.post_process.sampler,
+        };
+
+        effect.create(instance, device, data, &stage)?;
+
+        input = views[0];
+        input_format = output_format;
+    }
+
+    data.post_process.effects = effects;
+
+    Ok(())
+}
+
+/// Creates the render pass of a stage which writes a target in the supplied
+/// format (or the swapchain image if it is the last stage).
+unsafe fn create_stage_render_pass(
+    device: &Device,
+    format: vk::Format,
+    last: bool,
+) -> Result<vk::RenderPass> {
+    // Every pixel of the target is written, so its contents are discarded.
+    let final_layout = if last {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    } else {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    };
+
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(final_layout);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    // The previous pass has to be done writing the input before it is read,
+    // and the next pass has to wait for the target to be written (and the
+    // input to be read, before the input is written again).
+    let dependencies = &[
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    Ok(device.create_render_pass(&info, None)?)
+}
+
+/// Records applying the effects to the HDR intermediate target, the last of
+/// which writes the swapchain image at the supplied index.
+pub unsafe fn cmd_post_process(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) -> Result<()> {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain.extent);
+
+    let stack = &data.post_process;
+    for (index, effect) in stack.effects.iter().enumerate() {
+        let framebuffers = &stack.framebuffers[index];
+        let framebuffer = *framebuffers[image_index.min(framebuffers.len() - 1)];
+
+        begin_command_label(
+            instance,
+            data,
+            command_buffer,
+            effect.name(),
+            POST_PROCESS_LABEL_COLOR,
+        )?;
+
+        effect.cmd_prepare(device, data, command_buffer);
+
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*stack.render_passes[index])
+            .framebuffer(framebuffer)
+            .render_area(render_area);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        effect.cmd_apply(device, data, command_buffer);
+        device.cmd_end_render_pass(command_buffer);
+
+        end_command_label(instance, data, command_buffer);
+    }
+
+    Ok(())
+}
+
+/// The descriptor set which binds the input of a stage to an effect and the
+/// full-screen pipeline which applies the effect.
+#[derive(Debug, Default)]
+pub struct EffectPipeline {
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    pub pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+}
+
+impl EffectPipeline {
+    /// Creates a pipeline for the supplied stage which covers the screen with
+    /// the supplied fragment shader, which reads the supplied images (like the
+    /// input of the stage) at bindings 0 and up and receives push constants of
+    /// the supplied size.
+    pub unsafe fn create(
+        device: &Device,
+        data: &AppData,
+        stage: &Stage,
+        views: &[vk::ImageView],
+        fragment_shader: &[u8],
+        push_constants_size: usize,
+        specialization_info: Option<&vk::SpecializationInfo>,
+    ) -> Result<Self> {
+        let mut effect = Self::default();
+
+        // Descriptor Set Layout
+
+        let bindings = (0..views.len())
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        effect.descriptor_set_layout =
+            Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+        // Descriptor Pool
+
+        let sampler_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(views.len() as u32);
+
+        let pool_sizes = &[sampler_size];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+
+        effect.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+        // Descriptor Set
+
+        let layouts = &[*effect.descriptor_set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(*effect.descriptor_pool)
+            .set_layouts(layouts);
+
+        effect.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+        let image_infos = views
+            .iter()
+            .map(|v| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(*v)
+                    .sampler(stage.sampler)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let writes = image_infos
+            .iter()
+            .zip(&bindings)
+            .map(|(image_info, binding)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(effect.descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(binding.descriptor_type)
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        // Layout
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(push_constants_size as u32);
+
+        let set_layouts = &[*effect.descriptor_set_layout];
+        let push_constant_ranges = &[push_constant_range];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+
+        effect.pipeline_layout =
+            Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+        // Stages
+
+        // The lighting pass of deferred rendering covers the screen with a
+        // triangle too.
+        let vert_shader_module = create_shader_module(device, shaders::LIGHTING_VERT)?;
+        let frag_shader_module = create_shader_module(device, fragment_shader)?;
+
+        let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vert_shader_module)
+            .name(b"main\0");
+
+        let mut frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(frag_shader_module)
+            .name(b"main\0");
+
+        if let Some(specialization_info) = specialization_info {
+            frag_stage = frag_stage.specialization_info(specialization_info);
+        }
+
+        // Vertex Input State
+
+        // The vertices of the triangle are generated by the vertex shader.
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        // Input Assembly State
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        // Viewport State
+
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(data.swapchain.extent.width as f32)
+            .height(data.swapchain.extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(data.swapchain.extent);
+
+        let viewports = &[viewport];
+        let scissors = &[scissor];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(viewports)
+            .scissors(scissors);
+
+        // Rasterization State
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_bias_enable(false);
+
+        // Multisample State
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::_1);
+
+        // Color Blend State
+
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::all())
+            .blend_enable(false);
+
+        let attachments = &[attachment];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(attachments);
+
+        // Create
+
+        let stages = &[vert_stage, frag_stage];
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .layout(*effect.pipeline_layout)
+            .render_pass(stage.render_pass)
+            .subpass(0);
+
+        effect.pipeline = Owned::new(
+            device,
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+                .0,
+        );
+
+        // Cleanup
+
+        device.destroy_shader_module(vert_shader_module, None);
+        device.destroy_shader_module(frag_shader_module, None);
+
+        Ok(effect)
+    }
+
+    /// Records covering the screen with the pipeline after pushing the
+    /// supplied push constants.
+    pub unsafe fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        push_constants: &[u8],
+    ) {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            *self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    }
+}
+
+/// An effect which darkens the corners of the screen by the strength in the
+/// options.
+#[derive(Debug, Default)]
+pub struct VignetteEffect {
+    pipeline: EffectPipeline,
+}
+
+impl PostProcess for VignetteEffect {
+    fn name(&self) -> &'static str {
+        "Vignette"
+    }
+
+    unsafe fn create(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        stage: &Stage,
+    ) -> Result<()> {
+        // The strength of the vignette.
+        self.pipeline = EffectPipeline::create(
+            device,
+            data,
+            stage,
+            &[stage.input],
+            shaders::VIGNETTE_FRAG,
+            size_of::<f32>(),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    unsafe fn cmd_apply(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+        let push_constants = data.options.vignette.to_ne_bytes();
+        self.pipeline
+            .cmd_draw(device, command_buffer, &push_constants);
+    }
+}
@@ -0,0 +1,170 @@
+//! CPU and GPU profiling zones which are emitted to Tracy when the `profiling`
+//! feature is enabled and compiled out otherwise.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+#[cfg(feature = "profiling")]
+use crate::commands::{begin_single_time_commands, end_single_time_commands};
+use crate::queries::Timestamps;
+
+/// Profiles the rest of the enclosing scope as a CPU zone with the supplied name.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        let _zone = tracy_client::span!($name);
+    };
+}
+
+pub(crate) use profile_scope;
+
+/// Starts the profiler.
+pub fn start() {
+    #[cfg(feature = "profiling")]
+    tracy_client::Client::start();
+}
+
+/// Marks the end of a frame.
+pub fn frame_mark() {
+    #[cfg(feature = "profiling")]
+    if let Some(client) = tracy_client::Client::running() {
+        client.frame_mark();
+    }
+}
+
+/// Emits GPU zones from the timestamps written for each swapchain image.
+#[derive(Default)]
+pub struct GpuProfiler {
+    #[cfg(feature = "profiling")]
+    context: Option<tracy_client::GpuContext>,
+    /// The zones recorded for each swapchain image, with the indices of the
+    /// timestamps they begin and end at, which are waiting for their
+    /// timestamps.
+    #[cfg(feature = "profiling")]
+    pending: Vec<Vec<(tracy_client::GpuSpan, u32, u32)>>,
+}
+
+impl std::fmt::Debug for GpuProfiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("GpuProfiler").finish_non_exhaustive()
+    }
+}
+
+impl GpuProfiler {
+    /// Creates a GPU profiler which is calibrated with a timestamp written to
+    /// the query pool (which is reset afterwards).
+    ///
+    /// This must be called after the query pool has been created.
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        #[cfg(feature = "profiling")]
+        if let Some(client) = tracy_client::Client::running() {
+            if data.query_pool.is_null() {
+                return Ok(Self::default());
+            }
+
+            let command_buffer = begin_single_time_commands(device, data)?;
+            device.cmd_reset_query_pool(command_buffer, *data.query_pool, 0, 1);
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                *data.query_pool,
+                0,
+            );
+            end_single_time_commands(device, data, command_buffer)?;
+
+            let mut timestamp = [0u8; 8];
+            device.get_query_pool_results(
+                *data.query_pool,
+                0,
+                1,
+                &mut timestamp,
+                8,
+                vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT,
+            )?;
+
+            let command_buffer = begin_single_time_commands(device, data)?;
+            device.cmd_reset_query_pool(command_buffer, *data.query_pool, 0, 1);
+            end_single_time_commands(device, data, command_buffer)?;
+
+            let timestamp = u64::from_ne_bytes(timestamp) & data.timestamp_mask;
+            let context = client.new_gpu_context(
+                Some("Graphics Queue"),
+                tracy_client::GpuContextType::Vulkan,
+                timestamp as i64,
+                data.timestamp_period,
+            )?;
+
+            return Ok(Self {
+                context: Some(context),
+                pending: vec![],
+            });
+        }
+
+        Ok(Self::default())
+    }
+
+    /// Begins a GPU zone for the swapchain image at the supplied index which
+    /// starts at the supplied timestamp (e.g., `FRAME_BEGIN`).
+    ///
+    /// This should be called right next to where the timestamp is written.
+    pub fn begin_zone(&mut self, image_index: usize, name: &str, timestamp: u32) {
+        #[cfg(feature = "profiling")]
+        if let Some(context) = &self.context {
+            if let Ok(span) = context.span_alloc(name, "", file!(), line!()) {
+                if self.pending.len() <= image_index {
+                    self.pending.resize_with(image_index + 1, Vec::new);
+                }
+
+                self.pending[image_index].push((span, timestamp, timestamp));
+            }
+        }
+    }
+
+    /// Ends the innermost GPU zone for the swapchain image at the supplied
+    /// index which hasn't been ended yet at the supplied timestamp.
+    ///
+    /// This should be called right next to where the timestamp is written.
+    pub fn end_zone(&mut self, image_index: usize, timestamp: u32) {
+        #[cfg(feature = "profiling")]
+        if let Some(zones) = self.pending.get_mut(image_index) {
+            if let Some((span, _, end)) = zones.iter_mut().rev().find(|(_, b, e)| b == e) {
+                span.end_zone();
+                *end = timestamp;
+            }
+        }
+    }
+
+    /// Supplies the timestamps for the GPU zones of the last frame rendered to
+    /// the swapchain image at the supplied index.
+    pub fn upload(&mut self, data: &AppData, image_index: usize, timestamps: &Timestamps) {
+        #[cfg(feature = "profiling")]
+        if let Some(zones) = self.pending.get_mut(image_index) {
+            // The timestamps have to be supplied in increasing order, which is
+            // the order they are written in.
+            let mut events = zones
+                .iter()
+                .flat_map(|(span, begin, end)| [(*begin, span, true), (*end, span, false)])
+                .collect::<Vec<_>>();
+            events.sort_by_key(|(timestamp, _, _)| *timestamp);
+
+            for (timestamp, span, start) in events {
+                let value = (timestamps[timestamp as usize] & data.timestamp_mask) as i64;
+                if start {
+                    span.upload_timestamp_start(value);
+                } else {
+                    span.upload_timestamp_end(value);
+                }
+            }
+
+            zones.clear();
+        }
+    }
+
+    /// Discards the GPU zones waiting for timestamps (e.g., when the swapchain
+    /// and the timestamp query pool are recreated).
+    pub fn discard(&mut self) {
+        #[cfg(feature = "profiling")]
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,181 @@
+//! GPU timestamp queries and the frame statistics computed from them.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::commands::{begin_single_time_commands, end_single_time_commands};
+use crate::culling::CullingStats;
+use crate::device::QueueFamilyIndices;
+use crate::handle::Owned;
+
+/// The timestamp written at the start of a frame.
+pub const FRAME_BEGIN: u32 = 0;
+/// The timestamp written before the main render pass.
+pub const MAIN_PASS_BEGIN: u32 = 1;
+/// The timestamp written after the main render pass.
+pub const MAIN_PASS_END: u32 = 2;
+/// The timestamp written at the end of a frame.
+pub const FRAME_END: u32 = 3;
+
+/// The number of timestamps written per frame.
+const TIMESTAMPS: u32 = 4;
+
+/// The names of the timed passes and the timestamps they begin and end at.
+const PASSES: &[(&str, u32, u32)] = &[("Main Pass", MAIN_PASS_BEGIN, MAIN_PASS_END)];
+
+/// The GPU timings of a frame.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    /// The GPU time spent on the whole frame (in milliseconds).
+    pub gpu_frame_time: f32,
+    /// The names of the passes of the frame and the GPU time spent on each
+    /// (in milliseconds).
+    pub passes: Vec<(&'static str, f32)>,
+    /// The number of draw calls recorded for the frame.
+    pub draw_calls: usize,
+    /// The number of draw commands culled on the GPU (if they are).
+    pub culling: Option<CullingStats>,
+}
+
+/// Creates a timestamp query pool with a range of queries per swapchain image.
+///
+/// The query pool is left null (and the other functions in this module do
+/// nothing) if the graphics queue doesn't support timestamps.
+pub unsafe fn create_query_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Support
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+
+    let valid_bits = families[indices.graphics as usize].timestamp_valid_bits;
+    if valid_bits == 0 {
+        warn!("Timestamp queries not supported by the graphics queue.");
+        return Ok(());
+    }
+
+    data.timestamp_period = properties.limits.timestamp_period;
+    data.timestamp_mask = if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << valid_bits) - 1
+    };
+
+    // Create
+
+    let count = TIMESTAMPS * data.swapchain.images.len() as u32;
+
+    let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(count);
+
+    data.query_pool = Owned::new(device, device.create_query_pool(&info, None)?);
+
+    // Reset
+
+    // Queries have to be reset before their results can be retrieved, even
+    // if the results are not available yet.
+    let command_buffer = begin_single_time_commands(device, data)?;
+    device.cmd_reset_query_pool(command_buffer, *data.query_pool, 0, count);
+    end_single_time_commands(device, data, command_buffer)?;
+
+    Ok(())
+}
+
+/// Resets the timestamps for the swapchain image at the supplied index.
+pub unsafe fn cmd_reset_timestamps(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    if !data.query_pool.is_null() {
+        let first = TIMESTAMPS * image_index as u32;
+        device.cmd_reset_query_pool(command_buffer, *data.query_pool, first, TIMESTAMPS);
+    }
+}
+
+/// Writes a timestamp (e.g., `FRAME_BEGIN`) for the swapchain image at the
+/// supplied index once the supplied stage has completed.
+pub unsafe fn cmd_write_timestamp(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    timestamp: u32,
+    stage: vk::PipelineStageFlags,
+) {
+    if !data.query_pool.is_null() {
+        let query = TIMESTAMPS * image_index as u32 + timestamp;
+        device.cmd_write_timestamp(command_buffer, stage, *data.query_pool, query);
+    }
+}
+
+/// The timestamps written per frame.
+pub type Timestamps = [u64; TIMESTAMPS as usize];
+
+impl FrameStats {
+    /// Computes the GPU timings of a frame from its timestamps.
+    pub fn new(data: &AppData, timestamps: &Timestamps) -> Self {
+        let milliseconds = |begin: u32, end: u32| {
+            let begin = timestamps[begin as usize] & data.timestamp_mask;
+            let end = timestamps[end as usize] & data.timestamp_mask;
+            let ticks = end.wrapping_sub(begin) & data.timestamp_mask;
+            (ticks as f64 * data.timestamp_period as f64 / 1_000_000.0) as f32
+        };
+
+        Self {
+            gpu_frame_time: milliseconds(FRAME_BEGIN, FRAME_END),
+            passes: PASSES
+                .iter()
+                .map(|(name, begin, end)| (*name, milliseconds(*begin, *end)))
+                .collect(),
+            draw_calls: 0,
+            culling: None,
+        }
+    }
+}
+
+/// Returns the timestamps of the last frame rendered to the swapchain image at
+/// the supplied index, if they are available.
+///
+/// This must only be called once the last frame rendered to the swapchain
+/// image has completed and before its timestamps are reset.
+pub unsafe fn get_timestamps(
+    device: &Device,
+    data: &AppData,
+    image_index: usize,
+) -> Result<Option<Timestamps>> {
+    if data.query_pool.is_null() {
+        return Ok(None);
+    }
+
+    let mut timestamps = [0u64; TIMESTAMPS as usize];
+    let bytes =
+        std::slice::from_raw_parts_mut(timestamps.as_mut_ptr() as *mut u8, size_of::<Timestamps>());
+
+    let result = device.get_query_pool_results(
+        *data.query_pool,
+        TIMESTAMPS * image_index as u32,
+        TIMESTAMPS,
+        bytes,
+        size_of::<u64>() as u64,
+        vk::QueryResultFlags::_64,
+    )?;
+
+    // The timestamps of a swapchain image that hasn't been rendered to yet
+    // are never written.
+    if result == vk::SuccessCode::NOT_READY {
+        Ok(None)
+    } else {
+        Ok(Some(timestamps))
+    }
+}
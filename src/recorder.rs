@@ -0,0 +1,299 @@
+//! Capturing every rendered frame to a numbered sequence of PNG files.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::image::get_image_barrier;
+use crate::screenshot::{convert_to_rgba, is_copyable_format, save_png};
+
+/// The directory the frames are saved to.
+const DIRECTORY: &str = "recording";
+
+/// The number of frames that can be waiting to be saved before rendering
+/// waits for the frames to be saved.
+const MAX_QUEUED_FRAMES: usize = 16;
+
+/// A captured frame which is waiting to be saved.
+struct Frame {
+    number: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Captures every frame rendered for a period of time.
+///
+/// Each swapchain image has a readback buffer which the image is copied to at
+/// the end of its command buffer. The buffer is only read once the command
+/// buffer is next recorded (i.e., once the frame has completed) and the frame
+/// is then saved on another thread, so capturing frames doesn't stall the GPU.
+#[derive(Debug)]
+pub struct Recorder {
+    duration: Duration,
+    start: Option<Instant>,
+    frames: u32,
+    finished: bool,
+    /// The readback buffer for each swapchain image and the format and extent
+    /// of the swapchain images they were created for.
+    buffers: Vec<Buffer>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    /// The number of the frame captured in each readback buffer that hasn't
+    /// been read yet.
+    pending: Vec<Option<u32>>,
+    sender: Option<SyncSender<Frame>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Creates a recorder which captures the frames rendered for the supplied
+    /// number of seconds, starting with the first frame rendered.
+    pub fn new(seconds: u32) -> Result<Self> {
+        fs::create_dir_all(DIRECTORY)?;
+
+        let (sender, receiver) = mpsc::sync_channel::<Frame>(MAX_QUEUED_FRAMES);
+        let writer = thread::spawn(move || {
+            for frame in receiver {
+                let path = PathBuf::from(DIRECTORY).join(format!("frame-{:05}.png", frame.number));
+                if let Err(e) = save_png(&path, frame.width, frame.height, &frame.pixels) {
+                    error!("Failed to save `{}`: {}", path.display(), e);
+                }
+            }
+        });
+
+        Ok(Self {
+            duration: Duration::from_secs(seconds as u64),
+            start: None,
+            frames: 0,
+            finished: false,
+            buffers: vec![],
+            format: vk::Format::UNDEFINED,
+            extent: vk::Extent2D::default(),
+            pending: vec![],
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Creates a readback buffer for each swapchain image, reading the frames
+    /// captured in the previous readback buffers first.
+    ///
+    /// This must be called whenever the swapchain is (re)created, while the
+    /// device is idle.
+    pub unsafe fn create_buffers(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.flush()?;
+
+        if !is_copyable_format(data.swapchain.format)
+            || !data
+                .swapchain
+                .usage
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(anyhow!(
+                "Recording requires 8-bit swapchain images which can be copied from."
+            ));
+        }
+
+        let vk::Extent2D { width, height } = data.swapchain.extent;
+        let size = width as u64 * height as u64 * 4;
+
+        self.buffers = data
+            .swapchain
+            .images
+            .iter()
+            .map(|_| {
+                create_buffer(
+                    instance,
+                    device,
+                    data,
+                    size,
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+                )
+            })
+            .collect::<Result<_>>()?;
+        self.format = data.swapchain.format;
+        self.extent = data.swapchain.extent;
+        self.pending = vec![None; self.buffers.len()];
+
+        Ok(())
+    }
+
+    /// Records a copy of the swapchain image at the supplied index to its
+    /// readback buffer if frames are still being captured.
+    ///
+    /// This must be called after the render pass has ended.
+    pub unsafe fn cmd_capture(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        if self.finished {
+            return;
+        }
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        if start.elapsed() > self.duration {
+            info!("Recorded {} frames to `{}`.", self.frames, DIRECTORY);
+            self.finished = true;
+            return;
+        }
+
+        let image = data.swapchain.images[image_index];
+        let buffer = self.buffers[image_index].buffer;
+
+        let barrier = get_image_barrier(
+            image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: data.swapchain.extent.width,
+                height: data.swapchain.extent.height,
+                depth: 1,
+            });
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+
+        // The image has to be presentable again and the copy has to be
+        // visible to the host once the frame has completed.
+        let image_barrier = get_image_barrier(
+            image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::empty(),
+        );
+
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE as u64);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[buffer_barrier],
+            &[image_barrier],
+        );
+
+        self.pending[image_index] = Some(self.frames);
+        self.frames += 1;
+    }
+
+    /// Reads the frame captured in the readback buffer for the swapchain image
+    /// at the supplied index, if any, and queues it to be saved.
+    ///
+    /// This must only be called once the last frame rendered to the swapchain
+    /// image has completed.
+    pub unsafe fn read_frame(&mut self, image_index: usize) -> Result<()> {
+        let number = match self.pending.get_mut(image_index).and_then(Option::take) {
+            Some(number) => number,
+            None => return Ok(()),
+        };
+
+        let vk::Extent2D { width, height } = self.extent;
+        let size = width as u64 * height as u64 * 4;
+        let pointer = self.buffers[image_index].allocation.map()?;
+        let mut pixels = std::slice::from_raw_parts(pointer, size as usize).to_vec();
+
+        convert_to_rgba(self.format, &mut pixels);
+
+        let frame = Frame {
+            number,
+            width,
+            height,
+            pixels,
+        };
+
+        if let Some(sender) = &self.sender {
+            sender.send(frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// Destroys the readback buffers, discarding the frames captured in them
+    /// (e.g., when the logical device is lost).
+    pub fn destroy_buffers(&mut self) {
+        self.buffers.clear();
+        self.pending.clear();
+    }
+
+    /// Reads the frames captured in every readback buffer.
+    ///
+    /// This must only be called while the device is idle.
+    pub unsafe fn flush(&mut self) -> Result<()> {
+        for image_index in 0..self.pending.len() {
+            self.read_frame(image_index)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    /// Waits for the frames which have been read to be saved.
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
@@ -0,0 +1,804 @@
+//! A render graph, in which passes declare the images and buffers they read
+//! and write instead of recording barriers themselves.
+//!
+//! Compiling a graph computes the barriers (including image layout
+//! transitions) needed before each pass from the order of the passes, and the
+//! barriers which leave the exported images and buffers ready for their next
+//! use. Transient images, which only exist for the passes which use them, are
+//! created when the graph is compiled and share memory whenever the passes
+//! which use them don't overlap.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::get_memory_type_index;
+use crate::image::create_image_view;
+use crate::memory::{get_allocator, Allocation, Allocator};
+
+/// The way a pass uses an image or buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    FragmentShaderRead,
+    ComputeShaderRead,
+    ComputeShaderWrite,
+    TransferRead,
+    TransferWrite,
+    VertexBuffer,
+    IndexBuffer,
+    Present,
+}
+
+impl Access {
+    /// Returns the state an image or buffer is in while it is used this way.
+    pub fn state(self) -> State {
+        let (stages, access, layout) = match self {
+            Self::ColorAttachment => (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthAttachment => (
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            Self::FragmentShaderRead => (
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::ComputeShaderRead => (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::GENERAL,
+            ),
+            Self::ComputeShaderWrite => (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::ImageLayout::GENERAL,
+            ),
+            Self::TransferRead => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            Self::TransferWrite => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+            Self::VertexBuffer => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                vk::ImageLayout::UNDEFINED,
+            ),
+            Self::IndexBuffer => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::INDEX_READ,
+                vk::ImageLayout::UNDEFINED,
+            ),
+            // Presentation is synchronized with a semaphore, so this only
+            // transitions the image.
+            Self::Present => (
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::empty(),
+                vk::ImageLayout::PRESENT_SRC_KHR,
+            ),
+        };
+
+        State {
+            stages,
+            access,
+            layout,
+        }
+    }
+}
+
+/// The pipeline stages and memory accesses of the last use of an image or
+/// buffer, and the layout of an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    pub layout: vk::ImageLayout,
+}
+
+impl State {
+    /// Returns the state of an image or buffer whose contents can be
+    /// discarded, which was last used by the supplied pipeline stages.
+    pub fn discarded(stages: vk::PipelineStageFlags) -> Self {
+        Self {
+            stages,
+            access: vk::AccessFlags::empty(),
+            layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    /// Returns the writes among the memory accesses of this state.
+    fn writes(&self) -> vk::AccessFlags {
+        self.access
+            & (vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                | vk::AccessFlags::TRANSFER_WRITE
+                | vk::AccessFlags::HOST_WRITE
+                | vk::AccessFlags::MEMORY_WRITE)
+    }
+}
+
+/// An image in a render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+/// A buffer in a render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(usize);
+
+/// A pass in a render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+/// The properties of a transient image.
+#[derive(Copy, Clone, Debug)]
+pub struct TransientImageInfo {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect_mask: vk::ImageAspectFlags,
+}
+
+#[derive(Debug)]
+struct GraphImage {
+    name: String,
+    /// The image (which is null for transient images until they are created).
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    /// The state of the image before the first pass (which is ignored for
+    /// transient images, whose contents are always discarded).
+    initial: State,
+    /// The use the image is left ready for after the last pass.
+    export: Option<Access>,
+    transient: Option<TransientImageInfo>,
+}
+
+#[derive(Debug)]
+struct GraphBuffer {
+    name: String,
+    buffer: vk::Buffer,
+    initial: State,
+    export: Option<Access>,
+}
+
+#[derive(Debug)]
+struct Pass {
+    name: String,
+    images: Vec<(ImageId, Access)>,
+    buffers: Vec<(BufferId, Access)>,
+}
+
+/// The passes of a frame (or part of a frame) and the images and buffers they
+/// use.
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an image created outside of the graph, which is in the supplied
+    /// state before the first pass.
+    pub fn import_image(
+        &mut self,
+        name: impl Into<String>,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        initial: State,
+    ) -> ImageId {
+        self.images.push(GraphImage {
+            name: name.into(),
+            image,
+            aspect_mask,
+            initial,
+            export: None,
+            transient: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Adds a transient image, which is created when the graph is compiled and
+    /// whose contents are discarded before the first pass which uses it.
+    pub fn create_image(&mut self, name: impl Into<String>, info: TransientImageInfo) -> ImageId {
+        self.images.push(GraphImage {
+            name: name.into(),
+            image: vk::Image::null(),
+            aspect_mask: info.aspect_mask,
+            initial: State::discarded(vk::PipelineStageFlags::TOP_OF_PIPE),
+            export: None,
+            transient: Some(info),
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Adds a buffer created outside of the graph, which is in the supplied
+    /// state before the first pass.
+    pub fn import_buffer(
+        &mut self,
+        name: impl Into<String>,
+        buffer: vk::Buffer,
+        initial: State,
+    ) -> BufferId {
+        self.buffers.push(GraphBuffer {
+            name: name.into(),
+            buffer,
+            initial,
+            export: None,
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// Leaves an imported image ready for the supplied use after the last pass.
+    pub fn export_image(&mut self, image: ImageId, access: Access) {
+        self.images[image.0].export = Some(access);
+    }
+
+    /// Leaves an imported buffer ready for the supplied use after the last
+    /// pass.
+    pub fn export_buffer(&mut self, buffer: BufferId, access: Access) {
+        self.buffers[buffer.0].export = Some(access);
+    }
+
+    /// Adds a pass, which is executed after the passes added before it.
+    pub fn add_pass(&mut self, name: impl Into<String>) -> PassId {
+        self.passes.push(Pass {
+            name: name.into(),
+            images: vec![],
+            buffers: vec![],
+        });
+        PassId(self.passes.len() - 1)
+    }
+
+    /// Declares that a pass uses an image in the supplied way.
+    pub fn use_image(&mut self, pass: PassId, image: ImageId, access: Access) {
+        self.passes[pass.0].images.push((image, access));
+    }
+
+    /// Declares that a pass uses a buffer in the supplied way.
+    pub fn use_buffer(&mut self, pass: PassId, buffer: BufferId, access: Access) {
+        self.passes[pass.0].buffers.push((buffer, access));
+    }
+
+    /// Creates the transient images and computes the barriers for every pass.
+    pub unsafe fn compile(
+        self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<CompiledGraph> {
+        // `CompiledGraph` implements `Drop`, so it can't be built with the
+        // struct update syntax.
+        let mut compiled = CompiledGraph::default();
+        compiled.images = self.images.iter().map(|i| i.image).collect();
+        compiled.views = vec![vk::ImageView::null(); self.images.len()];
+        compiled.buffers = self.buffers.iter().map(|b| b.buffer).collect();
+
+        // Transient Images
+
+        let transients = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(i, image)| image.transient.map(|info| (i, info)))
+            .collect::<Vec<_>>();
+
+        if !transients.is_empty() {
+            compiled.allocator = Some(get_allocator(data)?.clone());
+        }
+
+        let mut requirements = vec![];
+        for (i, info) in &transients {
+            let create_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::_2D)
+                .extent(vk::Extent3D {
+                    width: info.extent.width,
+                    height: info.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(info.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(info.usage)
+                .samples(info.samples)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+            let image = device.create_image(&create_info, None)?;
+            compiled.images[*i] = image;
+            compiled.transients.push(*i);
+            requirements.push(device.get_image_memory_requirements(image));
+        }
+
+        // Aliasing
+
+        let lifetimes = transients
+            .iter()
+            .zip(&requirements)
+            .map(|((i, _), r)| {
+                let lifetime = self.get_lifetime(ImageId(*i)).ok_or_else(|| {
+                    anyhow!(
+                        "Transient image `{}` is not used by any pass.",
+                        self.images[*i].name
+                    )
+                })?;
+                Ok((lifetime, r.memory_type_bits))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let assignments = assign_slots(&lifetimes);
+        let slot_count = assignments.iter().map(|s| s + 1).max().unwrap_or(0);
+
+        for slot in 0..slot_count {
+            let members = (0..transients.len())
+                .filter(|t| assignments[*t] == slot)
+                .collect::<Vec<_>>();
+
+            let slot_requirements = vk::MemoryRequirements {
+                size: members.iter().map(|t| requirements[*t].size).max().unwrap(),
+                alignment: members
+                    .iter()
+                    .map(|t| requirements[*t].alignment)
+                    .max()
+                    .unwrap(),
+                memory_type_bits: members
+                    .iter()
+                    .fold(!0, |b, t| b & requirements[*t].memory_type_bits),
+            };
+
+            let memory_type_index = get_memory_type_index(
+                instance,
+                data,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                slot_requirements,
+            )?;
+
+            let allocation =
+                get_allocator(data)?.allocate(slot_requirements, memory_type_index, false)?;
+
+            for t in members {
+                let (i, info) = transients[t];
+                device.bind_image_memory(
+                    compiled.images[i],
+                    allocation.memory,
+                    allocation.offset,
+                )?;
+                compiled.views[i] = create_image_view(
+                    device,
+                    compiled.images[i],
+                    info.format,
+                    info.aspect_mask,
+                    1,
+                )?;
+            }
+
+            compiled.allocations.push(allocation);
+        }
+
+        // Barriers
+
+        let mut slots = vec![None; self.images.len()];
+        for ((i, _), slot) in transients.iter().zip(&assignments) {
+            slots[*i] = Some(*slot);
+        }
+
+        compiled.barriers = self.plan(&slots);
+        compiled.aspect_masks = self.images.iter().map(|i| i.aspect_mask).collect();
+
+        Ok(compiled)
+    }
+
+    /// Returns the indices of the first and last passes which use an image.
+    fn get_lifetime(&self, image: ImageId) -> Option<RangeInclusive<usize>> {
+        let mut passes = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.images.iter().any(|(i, _)| *i == image))
+            .map(|(i, _)| i);
+        let first = passes.next()?;
+        Some(first..=passes.next_back().unwrap_or(first))
+    }
+
+    /// Computes the barriers recorded before each pass and after the last
+    /// pass, given the memory slot of each transient image.
+    fn plan(&self, slots: &[Option<usize>]) -> Vec<Barriers> {
+        let mut image_states = self
+            .images
+            .iter()
+            .map(|i| i.transient.is_none().then_some(i.initial))
+            .collect::<Vec<_>>();
+        let mut buffer_states = self.buffers.iter().map(|b| b.initial).collect::<Vec<_>>();
+
+        // The state of the last transient image which used each memory slot.
+        let mut slot_states = HashMap::<usize, State>::new();
+
+        let mut barriers = vec![];
+
+        for pass in &self.passes {
+            let mut pass_barriers = Barriers::default();
+
+            for (image, access) in &pass.images {
+                // The contents of a transient image are undefined before its
+                // first use, but the image may share memory with another
+                // transient image which has to be done with it first.
+                let current = image_states[image.0].unwrap_or_else(|| {
+                    let previous = slots[image.0].and_then(|s| slot_states.get(&s));
+                    State {
+                        stages: previous.map_or(vk::PipelineStageFlags::TOP_OF_PIPE, |s| s.stages),
+                        access: previous.map_or(vk::AccessFlags::empty(), |s| s.access),
+                        layout: vk::ImageLayout::UNDEFINED,
+                    }
+                });
+
+                let state = pass_barriers.add_image(*image, current, access.state());
+                image_states[image.0] = Some(state);
+                if let Some(slot) = slots[image.0] {
+                    slot_states.insert(slot, state);
+                }
+            }
+
+            for (buffer, access) in &pass.buffers {
+                let current = buffer_states[buffer.0];
+                buffer_states[buffer.0] =
+                    pass_barriers.add_buffer(*buffer, current, access.state());
+            }
+
+            barriers.push(pass_barriers);
+        }
+
+        // Exports
+
+        let mut final_barriers = Barriers::default();
+
+        for (i, image) in self.images.iter().enumerate() {
+            if let (Some(access), Some(current)) = (image.export, image_states[i]) {
+                final_barriers.add_image(ImageId(i), current, access.state());
+            }
+        }
+
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if let Some(access) = buffer.export {
+                final_barriers.add_buffer(BufferId(i), buffer_states[i], access.state());
+            }
+        }
+
+        barriers.push(final_barriers);
+        barriers
+    }
+}
+
+/// The barriers recorded before a pass (or after the last pass).
+#[derive(Clone, Debug, Default)]
+struct Barriers {
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    images: Vec<ImageBarrier>,
+    buffers: Vec<BufferBarrier>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct ImageBarrier {
+    image: ImageId,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct BufferBarrier {
+    buffer: BufferId,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+impl Barriers {
+    fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.buffers.is_empty()
+    }
+
+    /// Adds a barrier between two states of an image if one is needed,
+    /// returning the state of the image afterwards.
+    fn add_image(&mut self, image: ImageId, current: State, next: State) -> State {
+        match self.add(current, next, current.layout != next.layout) {
+            Some(state) => state,
+            None => {
+                self.images.push(ImageBarrier {
+                    image,
+                    old_layout: current.layout,
+                    new_layout: next.layout,
+                    src_access: current.writes(),
+                    dst_access: next.access,
+                });
+                next
+            }
+        }
+    }
+
+    /// Adds a barrier between two states of a buffer if one is needed,
+    /// returning the state of the buffer afterwards.
+    fn add_buffer(&mut self, buffer: BufferId, current: State, next: State) -> State {
+        match self.add(current, next, false) {
+            Some(state) => state,
+            None => {
+                self.buffers.push(BufferBarrier {
+                    buffer,
+                    src_access: current.writes(),
+                    dst_access: next.access,
+                });
+                next
+            }
+        }
+    }
+
+    /// Returns the combined state if no barrier is needed between two states
+    /// (i.e., if both only read and no layout transition is needed), or adds
+    /// the stages to synchronize otherwise.
+    fn add(&mut self, current: State, next: State, transition: bool) -> Option<State> {
+        if !transition && current.writes().is_empty() && next.writes().is_empty() {
+            return Some(State {
+                stages: current.stages | next.stages,
+                access: current.access | next.access,
+                layout: current.layout,
+            });
+        }
+
+        self.src_stages |= current.stages;
+        self.dst_stages |= next.stages;
+        None
+    }
+}
+
+/// Assigns the transient images with the supplied lifetimes (the indices of
+/// the first and last passes which use them) and memory type bits to memory
+/// slots, which are shared by images whose lifetimes don't overlap.
+fn assign_slots(images: &[(RangeInclusive<usize>, u32)]) -> Vec<usize> {
+    let mut slots: Vec<(Vec<RangeInclusive<usize>>, u32)> = vec![];
+
+    images
+        .iter()
+        .map(|(lifetime, memory_type_bits)| {
+            let slot = slots.iter().position(|(lifetimes, bits)| {
+                bits & memory_type_bits != 0
+                    && lifetimes
+                        .iter()
+                        .all(|l| l.end() < lifetime.start() || lifetime.end() < l.start())
+            });
+
+            match slot {
+                Some(slot) => {
+                    slots[slot].0.push(lifetime.clone());
+                    slots[slot].1 &= memory_type_bits;
+                    slot
+                }
+                None => {
+                    slots.push((vec![lifetime.clone()], *memory_type_bits));
+                    slots.len() - 1
+                }
+            }
+        })
+        .collect()
+}
+
+/// A render graph whose transient images have been created and whose
+/// barriers have been computed.
+///
+/// The transient images (and the memory they share) are destroyed when this
+/// is dropped, so a graph with transient images should be compiled once and
+/// reused (e.g., until the swapchain is recreated) rather than every frame.
+#[derive(Debug, Default)]
+pub struct CompiledGraph {
+    allocator: Option<Allocator>,
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    aspect_masks: Vec<vk::ImageAspectFlags>,
+    buffers: Vec<vk::Buffer>,
+    /// The indices of the transient images.
+    transients: Vec<usize>,
+    allocations: Vec<Allocation>,
+    barriers: Vec<Barriers>,
+}
+
+impl CompiledGraph {
+    /// Returns an image (which was created by the graph if it is transient).
+    pub fn image(&self, image: ImageId) -> vk::Image {
+        self.images[image.0]
+    }
+
+    /// Returns the view of a transient image.
+    pub fn image_view(&self, image: ImageId) -> vk::ImageView {
+        self.views[image.0]
+    }
+
+    /// Replaces an imported image (e.g., with the next swapchain image), which
+    /// has to be used in the same way as the image it replaces.
+    pub fn set_image(&mut self, image: ImageId, handle: vk::Image) {
+        assert!(!self.transients.contains(&image.0));
+        self.images[image.0] = handle;
+    }
+
+    /// Replaces an imported buffer, which has to be used in the same way as
+    /// the buffer it replaces.
+    pub fn set_buffer(&mut self, buffer: BufferId, handle: vk::Buffer) {
+        self.buffers[buffer.0] = handle;
+    }
+
+    /// Records the barriers needed before a pass.
+    pub unsafe fn cmd_barriers(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pass: PassId,
+    ) {
+        self.cmd_pipeline_barrier(device, command_buffer, &self.barriers[pass.0]);
+    }
+
+    /// Records the barriers which leave the exported images and buffers ready
+    /// for their next use, which have to be recorded after the last pass.
+    pub unsafe fn cmd_final_barriers(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if let Some(barriers) = self.barriers.last() {
+            self.cmd_pipeline_barrier(device, command_buffer, barriers);
+        }
+    }
+
+    unsafe fn cmd_pipeline_barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        barriers: &Barriers,
+    ) {
+        if barriers.is_empty() {
+            return;
+        }
+
+        let image_barriers = barriers
+            .images
+            .iter()
+            .map(|b| {
+                let subresource = vk::ImageSubresourceRange::builder()
+                    .aspect_mask(self.aspect_masks[b.image.0])
+                    .base_mip_level(0)
+                    .level_count(vk::REMAINING_MIP_LEVELS)
+                    .base_array_layer(0)
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS);
+
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(b.old_layout)
+                    .new_layout(b.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(self.images[b.image.0])
+                    .subresource_range(subresource)
+                    .src_access_mask(b.src_access)
+                    .dst_access_mask(b.dst_access)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_barriers = barriers
+            .buffers
+            .iter()
+            .map(|b| {
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(b.src_access)
+                    .dst_access_mask(b.dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(self.buffers[b.buffer.0])
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE as u64)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            barriers.src_stages,
+            barriers.dst_stages,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+}
+
+impl Drop for CompiledGraph {
+    fn drop(&mut self) {
+        if let Some(allocator) = &self.allocator {
+            unsafe {
+                let device = allocator.device();
+                for i in &self.transients {
+                    device.destroy_image_view(self.views[*i], None);
+                    device.destroy_image(self.images[*i], None);
+                }
+                self.allocations.iter_mut().for_each(|a| allocator.free(a));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn barriers() {
+        let mut graph = RenderGraph::new();
+        let color = graph.import_image(
+            "Color",
+            vk::Image::null(),
+            vk::ImageAspectFlags::COLOR,
+            State::discarded(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT),
+        );
+        let draw = graph.add_pass("Draw");
+        graph.use_image(draw, color, Access::ColorAttachment);
+        let sample = graph.add_pass("Sample");
+        graph.use_image(sample, color, Access::FragmentShaderRead);
+        let sample_again = graph.add_pass("Sample Again");
+        graph.use_image(sample_again, color, Access::FragmentShaderRead);
+        graph.export_image(color, Access::Present);
+
+        let barriers = graph.plan(&[None]);
+        assert_eq!(barriers.len(), 4);
+
+        // Transition from undefined (the contents are discarded).
+        assert_eq!(barriers[0].images[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(barriers[0].images[0].src_access, vk::AccessFlags::empty());
+
+        // Read after write.
+        let barrier = barriers[1].images[0];
+        assert_eq!(barrier.src_access, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(barrier.dst_access, vk::AccessFlags::SHADER_READ);
+        assert_eq!(
+            barrier.new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+
+        // Read after read.
+        assert!(barriers[2].is_empty());
+
+        // Export.
+        assert_eq!(
+            barriers[3].images[0].new_layout,
+            vk::ImageLayout::PRESENT_SRC_KHR
+        );
+        assert_eq!(
+            barriers[3].src_stages,
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+        );
+    }
+
+    #[test]
+    fn aliasing() {
+        let slots = assign_slots(&[(0..=1, 0b11), (1..=2, 0b11), (2..=3, 0b01), (3..=3, 0b10)]);
+        assert_eq!(slots, vec![0, 1, 0, 1]);
+
+        // Incompatible memory types are never aliased.
+        let slots = assign_slots(&[(0..=0, 0b01), (1..=1, 0b10)]);
+        assert_eq!(slots, vec![0, 1]);
+    }
+}
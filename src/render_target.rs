@@ -0,0 +1,291 @@
+//! Offscreen render targets, which are rendered to in their own render pass
+//! and then sampled as textures by later passes.
+//!
+//! A render target has a color image and optionally a depth image, which are
+//! both left ready to be sampled by fragment shaders (multisampled targets
+//! are resolved into a single sampled color image).
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::image::{create_image, create_image_view, Image};
+
+/// An offscreen color (and depth) target with its own render pass and
+/// framebuffer.
+#[derive(Debug, Default)]
+pub struct RenderTarget {
+    pub extent: vk::Extent2D,
+    pub color: Image,
+    pub depth: Option<Image>,
+    /// The image the color image is resolved into (if it is multisampled).
+    resolve: Option<Image>,
+    /// The render pass which renders the target, which pipelines which render
+    /// to it have to be created for.
+    pub render_pass: Owned<vk::RenderPass>,
+    framebuffer: Owned<vk::Framebuffer>,
+}
+
+impl RenderTarget {
+    /// Returns the view of the color image which is sampled (the resolved
+    /// image if the target is multisampled).
+    pub fn view(&self) -> vk::ImageView {
+        self.resolve.as_ref().unwrap_or(&self.color).view
+    }
+
+    /// Returns the view of the depth image (if the target has one).
+    pub fn depth_view(&self) -> Option<vk::ImageView> {
+        self.depth.as_ref().map(|d| d.view)
+    }
+}
+
+/// Creates a render target of the supplied size with a color image in the
+/// supplied format, a depth image in the supplied format (if any), and the
+/// supplied number of samples.
+pub unsafe fn create_render_target(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+    samples: vk::SampleCountFlags,
+) -> Result<RenderTarget> {
+    let multisampled = samples != vk::SampleCountFlags::_1;
+
+    let mut target = RenderTarget {
+        extent,
+        ..Default::default()
+    };
+
+    // Images
+
+    let create = |format, samples, usage, aspects| -> Result<Image> {
+        let mut image = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            samples,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.view = create_image_view(device, image.image, format, aspects, 1)?;
+
+        Ok(image)
+    };
+
+    // A multisampled color image is only sampled once it is resolved.
+    let color_usage = if multisampled {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+    };
+
+    target.color = create(format, samples, color_usage, vk::ImageAspectFlags::COLOR)?;
+
+    if let Some(depth_format) = depth_format {
+        target.depth = Some(create(
+            depth_format,
+            samples,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::DEPTH,
+        )?);
+    }
+
+    if multisampled {
+        target.resolve = Some(create(
+            format,
+            vk::SampleCountFlags::_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+        )?);
+    }
+
+    // Render Pass
+
+    // The color image is only kept if it is sampled (rather than resolved).
+    let (color_store_op, color_layout) = if multisampled {
+        (
+            vk::AttachmentStoreOp::DONT_CARE,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    } else {
+        (
+            vk::AttachmentStoreOp::STORE,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    };
+
+    let mut attachments = vec![vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(color_store_op)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(color_layout)
+        .build()];
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let depth_attachment_ref = depth_format.map(|depth_format| {
+        attachments.push(
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(samples)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        );
+
+        vk::AttachmentReference::builder()
+            .attachment(attachments.len() as u32 - 1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+    });
+
+    let resolve_attachment_ref = multisampled.then(|| {
+        attachments.push(
+            vk::AttachmentDescription::builder()
+                .format(format)
+                .samples(vk::SampleCountFlags::_1)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        );
+
+        vk::AttachmentReference::builder()
+            .attachment(attachments.len() as u32 - 1)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+    });
+
+    let color_attachments = &[color_attachment_ref];
+    let mut subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    if let Some(depth_attachment_ref) = &depth_attachment_ref {
+        subpass = subpass.depth_stencil_attachment(depth_attachment_ref);
+    }
+
+    if let Some(resolve_attachment_ref) = &resolve_attachment_ref {
+        subpass = subpass.resolve_attachments(std::slice::from_ref(resolve_attachment_ref));
+    }
+
+    // Whatever was rendered (or sampled) before has to be done before the
+    // target is rendered, and whatever comes after has to wait for it (so it
+    // can be sampled).
+    let stages = vk::PipelineStageFlags::FRAGMENT_SHADER
+        | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+    let src_access =
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    let dst_access = src_access | vk::AccessFlags::SHADER_READ;
+
+    let dependencies =
+        [(vk::SUBPASS_EXTERNAL, 0), (0, vk::SUBPASS_EXTERNAL)].map(|(src_subpass, dst_subpass)| {
+            vk::SubpassDependency::builder()
+                .src_subpass(src_subpass)
+                .dst_subpass(dst_subpass)
+                .src_stage_mask(stages)
+                .src_access_mask(src_access)
+                .dst_stage_mask(stages)
+                .dst_access_mask(dst_access)
+                .build()
+        });
+
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(subpasses)
+        .dependencies(&dependencies);
+
+    target.render_pass = Owned::new(device, device.create_render_pass(&info, None)?);
+
+    // Framebuffer
+
+    let attachments = [
+        Some(&target.color),
+        target.depth.as_ref(),
+        target.resolve.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|i| i.view)
+    .collect::<Vec<_>>();
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(*target.render_pass)
+        .attachments(&attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+
+    target.framebuffer = Owned::new(device, device.create_framebuffer(&info, None)?);
+
+    Ok(target)
+}
+
+/// Records beginning the render pass of the supplied render target, which
+/// clears the color image to the supplied color (and the depth image to 1).
+///
+/// The render pass is ended with `cmd_end_render_pass`.
+pub unsafe fn cmd_begin_render_target(
+    device: &Device,
+    target: &RenderTarget,
+    command_buffer: vk::CommandBuffer,
+    clear_color: [f32; 4],
+) {
+    // The resolve image isn't cleared, but has a clear value to keep the
+    // clear values in the order of the attachments.
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: clear_color,
+        },
+    };
+
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    };
+
+    let clear_values = [
+        Some(color_clear_value),
+        target.depth.as_ref().map(|_| depth_clear_value),
+        target.resolve.as_ref().map(|_| color_clear_value),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(target.extent);
+
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*target.render_pass)
+        .framebuffer(*target.framebuffer)
+        .render_area(render_area)
+        .clear_values(&clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+}
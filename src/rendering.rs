@@ -0,0 +1,244 @@
+//! Beginning and ending the main pass with either a render pass or dynamic
+//! rendering.
+
+use vulkanalia::prelude::v1_2::*;
+use vulkanalia::vk::DeviceV1_3;
+use vulkanalia::vk::KhrDynamicRenderingExtension;
+
+use anyhow::Result;
+
+use crate::app::AppData;
+use crate::device::RenderingPath;
+use crate::render_graph::{Access, CompiledGraph, PassId, RenderGraph, State};
+
+/// The render graph of the main pass for a swapchain image, which computes the
+/// layout transitions of the attachments when rendering dynamically.
+#[derive(Debug)]
+pub struct MainPassGraph {
+    pub graph: CompiledGraph,
+    pub pass: PassId,
+}
+
+pub unsafe fn create_main_pass_graphs(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Render passes transition their own attachments.
+    if data.rendering_path == RenderingPath::RenderPass {
+        return Ok(());
+    }
+
+    data.main_pass_graphs = data
+        .swapchain
+        .images
+        .iter()
+        .map(|i| {
+            let mut graph = RenderGraph::new();
+
+            // The contents of the attachments from the previous frame are
+            // discarded, but the previous frame has to be done with them.
+            //
+            // With tone mapping, the scene is rendered to the intermediate
+            // target (which the previous frame read in the tone mapping pass)
+            // and left ready to be read instead of presented.
+            let (target, export) = if data.options.tone_mapping.is_some() {
+                let image = graph.import_image(
+                    "HDR Image",
+                    data.tone_map.image.image,
+                    vk::ImageAspectFlags::COLOR,
+                    State::discarded(
+                        vk::PipelineStageFlags::FRAGMENT_SHADER
+                            | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    ),
+                );
+                (image, Access::FragmentShaderRead)
+            } else {
+                let image = graph.import_image(
+                    "Swapchain Image",
+                    *i,
+                    vk::ImageAspectFlags::COLOR,
+                    State::discarded(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT),
+                );
+                (image, Access::Present)
+            };
+
+            let color_image = (data.msaa_samples != vk::SampleCountFlags::_1).then(|| {
+                graph.import_image(
+                    "Color Image",
+                    data.color_image.image,
+                    vk::ImageAspectFlags::COLOR,
+                    State {
+                        access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        ..State::discarded(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    },
+                )
+            });
+
+            let depth_image = graph.import_image(
+                "Depth Image",
+                data.depth_image.image,
+                get_depth_aspect_mask(data.depth_format),
+                State {
+                    access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    ..State::discarded(
+                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    )
+                },
+            );
+
+            let pass = graph.add_pass("Main Pass");
+            graph.use_image(pass, target, Access::ColorAttachment);
+            if let Some(color_image) = color_image {
+                graph.use_image(pass, color_image, Access::ColorAttachment);
+            }
+            graph.use_image(pass, depth_image, Access::DepthAttachment);
+            graph.export_image(target, export);
+
+            let graph = graph.compile(instance, device, data)?;
+            Ok(MainPassGraph { graph, pass })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(())
+}
+
+/// Begins the main pass, which renders to the swapchain image at the supplied
+/// index after clearing it and the depth image to the supplied values.
+///
+/// The contents of the main pass are either recorded inline or executed from
+/// secondary command buffers (see `record_secondary_command_buffers`).
+pub unsafe fn cmd_begin_main_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    color_clear_value: vk::ClearValue,
+    depth_clear_value: vk::ClearValue,
+    contents: vk::SubpassContents,
+) {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain.extent);
+
+    // Render Pass
+
+    if data.rendering_path == RenderingPath::RenderPass {
+        let clear_values = &[color_clear_value, depth_clear_value];
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*data.render_pass)
+            .framebuffer(*data.framebuffers[image_index])
+            .render_area(render_area)
+            .clear_values(clear_values);
+
+        device.cmd_begin_render_pass(command_buffer, &info, contents);
+        return;
+    }
+
+    // Dynamic Rendering
+
+    // Without a render pass, the attachments are transitioned explicitly.
+    let main_pass = &data.main_pass_graphs[image_index];
+    main_pass
+        .graph
+        .cmd_barriers(device, command_buffer, main_pass.pass);
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
+    // The multisampled color image is resolved to the swapchain image (or the
+    // intermediate target with tone mapping), which is rendered to directly
+    // without multisampling.
+    let target = if data.options.tone_mapping.is_some() {
+        data.tone_map.image.view
+    } else {
+        data.swapchain.image_views[image_index]
+    };
+
+    let mut color_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(color_clear_value);
+
+    color_attachment = if multisampled {
+        color_attachment
+            .image_view(data.color_image.view)
+            .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+            .resolve_image_view(target)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+    } else {
+        color_attachment.image_view(target)
+    };
+
+    // The depth is kept for the Hi-Z pyramid with occlusion culling.
+    let depth_store_op = if data.options.occlusion_culling {
+        vk::AttachmentStoreOp::STORE
+    } else {
+        vk::AttachmentStoreOp::DONT_CARE
+    };
+
+    let depth_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(data.depth_image.view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(depth_store_op)
+        .clear_value(depth_clear_value);
+
+    let flags = if contents == vk::SubpassContents::SECONDARY_COMMAND_BUFFERS {
+        vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+    } else {
+        vk::RenderingFlags::empty()
+    };
+
+    let color_attachments = &[color_attachment];
+    let info = vk::RenderingInfo::builder()
+        .flags(flags)
+        .render_area(render_area)
+        .layer_count(1)
+        .color_attachments(color_attachments)
+        .depth_attachment(&depth_attachment);
+
+    if data.rendering_path == RenderingPath::DynamicRendering {
+        device.cmd_begin_rendering(command_buffer, &info);
+    } else {
+        device.cmd_begin_rendering_khr(command_buffer, &info);
+    }
+}
+
+/// Ends the main pass, leaving the swapchain image at the supplied index ready
+/// to be presented (or the intermediate target ready to be tone mapped).
+pub unsafe fn cmd_end_main_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    // Render Pass
+
+    if data.rendering_path == RenderingPath::RenderPass {
+        device.cmd_end_render_pass(command_buffer);
+        return;
+    }
+
+    // Dynamic Rendering
+
+    if data.rendering_path == RenderingPath::DynamicRendering {
+        device.cmd_end_rendering(command_buffer);
+    } else {
+        device.cmd_end_rendering_khr(command_buffer);
+    }
+
+    data.main_pass_graphs[image_index]
+        .graph
+        .cmd_final_barriers(device, command_buffer);
+}
+
+/// Returns the aspects of a depth format (which may include stencil).
+pub fn get_depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
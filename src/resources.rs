@@ -0,0 +1,318 @@
+//! The textures, meshes, and materials used by our Vulkan app, which are
+//! referred to with generational handles.
+//!
+//! A handle stays valid until the resource it refers to is removed. Removed
+//! resources may still be used by frames in flight, so they are only destroyed
+//! once those frames have completed (see `Resources::begin_frame`).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::material::{Material, MaterialTextures};
+use crate::mesh::Mesh;
+use crate::texture::{create_texture, Texture};
+
+pub type TextureHandle = Handle<Texture>;
+pub type MeshHandle = Handle<Mesh>;
+pub type MaterialHandle = Handle<Material>;
+
+/// A handle to a resource in a `Pool`.
+///
+/// The generation of the handle has to match the generation of the slot it
+/// refers to, so a handle to a removed resource never refers to the resource
+/// which reuses its slot.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+// These are implemented manually since deriving them would require `T` to
+// implement them as well.
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// A collection of resources of a single type which reuses the slots of
+/// removed resources.
+#[derive(Debug)]
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some(value);
+
+        Handle {
+            index,
+            generation: slot.generation,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|s| s.generation == handle.generation)
+            .and_then(|s| s.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|s| s.generation == handle.generation)
+            .and_then(|s| s.value.as_mut())
+    }
+
+    /// Removes a resource, invalidating every handle to it.
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|s| s.generation == handle.generation)?;
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    /// Removes every resource, invalidating every handle.
+    pub fn drain(&mut self) -> Vec<T> {
+        let handles = self.iter().map(|(h, _)| h).collect::<Vec<_>>();
+        handles.into_iter().filter_map(|h| self.remove(h)).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, s)| {
+            let handle = Handle {
+                index: i as u32,
+                generation: s.generation,
+                marker: PhantomData,
+            };
+            s.value.as_ref().map(|v| (handle, v))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(i, s)| {
+            let handle = Handle {
+                index: i as u32,
+                generation: s.generation,
+                marker: PhantomData,
+            };
+            s.value.as_mut().map(|v| (handle, v))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A removed resource which is waiting for the frames in flight to complete
+/// (and which is destroyed when dropped).
+#[derive(Debug)]
+enum Garbage {
+    Texture(Texture),
+    Mesh(Box<Mesh>),
+    Material(Material),
+}
+
+/// The textures, meshes, and materials used by our Vulkan app.
+#[derive(Debug, Default)]
+pub struct Resources {
+    pub textures: Pool<Texture>,
+    pub meshes: Pool<Mesh>,
+    pub materials: Pool<Material>,
+    /// The textures used for the textures a material doesn't have.
+    pub default_textures: Option<MaterialTextures>,
+    /// The resources loaded from files, which are only loaded once.
+    texture_paths: HashMap<PathBuf, TextureHandle>,
+    mesh_paths: HashMap<PathBuf, MeshHandle>,
+    /// The number of frames which have begun.
+    frame: u64,
+    /// The removed resources and the frame they were removed during.
+    garbage: Vec<(u64, Garbage)>,
+}
+
+impl Resources {
+    pub fn texture(&self, handle: TextureHandle) -> Option<&Texture> {
+        self.textures.get(handle)
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> Option<&Mesh> {
+        self.meshes.get(handle)
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle)
+    }
+
+    pub fn remove_texture(&mut self, handle: TextureHandle) {
+        self.texture_paths.retain(|_, h| *h != handle);
+        if let Some(texture) = self.textures.remove(handle) {
+            self.garbage.push((self.frame, Garbage::Texture(texture)));
+        }
+    }
+
+    pub fn remove_mesh(&mut self, handle: MeshHandle) {
+        self.mesh_paths.retain(|_, h| *h != handle);
+        if let Some(mesh) = self.meshes.remove(handle) {
+            self.garbage
+                .push((self.frame, Garbage::Mesh(Box::new(mesh))));
+        }
+    }
+
+    pub fn remove_material(&mut self, handle: MaterialHandle) {
+        if let Some(material) = self.materials.remove(handle) {
+            self.garbage.push((self.frame, Garbage::Material(material)));
+        }
+    }
+
+    /// Destroys the removed resources which are no longer used by any frame
+    /// in flight.
+    ///
+    /// This must be called at the start of every frame, once the frame which
+    /// last used the resources for the current frame has completed.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        self.garbage
+            .retain(|(f, _)| f + MAX_FRAMES_IN_FLIGHT as u64 > frame);
+    }
+}
+
+/// Loads a texture from a PNG, KTX2, or DDS file, or returns the texture previously loaded
+/// from the file.
+pub unsafe fn load_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: impl AsRef<Path>,
+) -> Result<TextureHandle> {
+    let path = path.as_ref();
+    if let Some(handle) = data.resources.texture_paths.get(path) {
+        return Ok(*handle);
+    }
+
+    let texture = create_texture(instance, device, data, path)?;
+    let handle = data.resources.textures.insert(texture);
+    data.resources
+        .texture_paths
+        .insert(path.to_path_buf(), handle);
+
+    Ok(handle)
+}
+
+/// Loads a mesh from an OBJ file, or returns the mesh previously loaded from
+/// the file.
+pub unsafe fn load_mesh(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: impl AsRef<Path>,
+) -> Result<MeshHandle> {
+    let path = path.as_ref();
+    if let Some(handle) = data.resources.mesh_paths.get(path) {
+        return Ok(*handle);
+    }
+
+    let mesh = Mesh::from_obj(instance, device, data, path)?;
+    let handle = data.resources.meshes.insert(mesh);
+    data.resources.mesh_paths.insert(path.to_path_buf(), handle);
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles() {
+        let mut pool = Pool::default();
+        let a = pool.insert("a");
+        let b = pool.insert("b");
+        assert_eq!(pool.get(a), Some(&"a"));
+        assert_eq!(pool.len(), 2);
+
+        // A removed resource's slot is reused, but not its handles.
+        assert_eq!(pool.remove(a), Some("a"));
+        assert_eq!(pool.remove(a), None);
+        let c = pool.insert("c");
+        assert_eq!(pool.get(a), None);
+        assert_eq!(pool.get(c), Some(&"c"));
+        assert_ne!(a, c);
+
+        assert_eq!(pool.drain(), vec!["c", "b"]);
+        assert_eq!(pool.get(b), None);
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+//! A scene graph of nodes with hierarchical transforms, which is flattened
+//! into a list of draws every frame.
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::material::MaterialInstance;
+use crate::model::{is_gltf_path, load_gltf};
+use crate::resources::MeshHandle;
+use crate::vertex::{Mat4, Vec3};
+
+/// A translation, rotation, and (non-uniform) scale.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: glm::Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::zeros(),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    /// Returns the matrix which scales, then rotates, then translates.
+    pub fn matrix(&self) -> Mat4 {
+        glm::translation(&self.translation)
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale)
+    }
+}
+
+/// A node in a scene.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    /// The transform of this node relative to its parent.
+    pub transform: Transform,
+    /// The mesh drawn at this node and the material it is drawn with.
+    pub mesh: Option<MeshHandle>,
+    pub material: Option<MaterialInstance>,
+    /// The transform of this node relative to the scene (as of the last
+    /// `Scene::update_transforms`).
+    world: Mat4,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn world(&self) -> &Mat4 {
+        &self.world
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// A mesh to draw, the material to draw it with, and its model matrix.
+#[derive(Copy, Clone, Debug)]
+pub struct Draw {
+    pub mesh: MeshHandle,
+    pub material: MaterialInstance,
+    pub model: Mat4,
+}
+
+/// A tree of nodes, whose root is created with the scene.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    nodes: Vec<Node>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                name: "Root".into(),
+                transform: Transform::default(),
+                mesh: None,
+                material: None,
+                world: glm::identity(),
+                parent: None,
+                children: vec![],
+            }],
+        }
+    }
+}
+
+impl Scene {
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Adds an empty node as the last child of the supplied node.
+    pub fn add_node(&mut self, name: impl Into<String>, parent: NodeId) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.into(),
+            transform: Transform::default(),
+            mesh: None,
+            material: None,
+            world: glm::identity(),
+            parent: Some(parent),
+            children: vec![],
+        });
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// Moves a node (and its descendants) to the end of the children of
+    /// another node, which can't be one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: NodeId) -> Result<()> {
+        let mut ancestor = Some(parent);
+        while let Some(a) = ancestor {
+            if a == id {
+                return Err(anyhow!(
+                    "Node `{}` can't be a descendant of itself.",
+                    self.nodes[id.0].name
+                ));
+            }
+            ancestor = self.nodes[a.0].parent;
+        }
+
+        if let Some(old) = self.nodes[id.0].parent {
+            self.nodes[old.0].children.retain(|c| *c != id);
+        }
+
+        self.nodes[id.0].parent = Some(parent);
+        self.nodes[parent.0].children.push(id);
+        Ok(())
+    }
+
+    /// Computes the world transform of every node from the local transforms.
+    pub fn update_transforms(&mut self) {
+        let mut stack = vec![(self.root(), glm::identity())];
+        while let Some((id, parent)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            node.world = parent * node.transform.matrix();
+            let world = node.world;
+            stack.extend(node.children.iter().rev().map(|c| (*c, world)));
+        }
+    }
+
+    /// Returns the draws for the nodes with both a mesh and a material, in
+    /// depth-first order.
+    pub fn get_draws(&self) -> Vec<Draw> {
+        let mut draws = vec![];
+        let mut stack = vec![self.root()];
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id.0];
+            if let (Some(mesh), Some(material)) = (node.mesh, node.material) {
+                draws.push(Draw {
+                    mesh,
+                    material,
+                    model: node.world,
+                });
+            }
+            stack.extend(node.children.iter().rev());
+        }
+        draws
+    }
+}
+
+/// Creates the scene rendered by our Vulkan app, which is either the scene of
+/// the glTF model supplied in the options or a scene which draws every mesh
+/// with the first material at a quarter opacity.
+pub unsafe fn create_scene(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if let Some(path) = data.options.model.clone().filter(|p| is_gltf_path(p)) {
+        data.scene = load_gltf(instance, device, data, &path)?;
+        return Ok(());
+    }
+
+    let material = data
+        .resources
+        .materials
+        .iter()
+        .map(|(h, _)| h)
+        .next()
+        .ok_or_else(|| anyhow!("There are no materials to draw the meshes with."))?;
+
+    let mut scene = Scene::default();
+    for (mesh, m) in data.resources.meshes.iter() {
+        let node = scene.add_node(m.name.clone(), scene.root());
+        scene.node_mut(node).mesh = Some(mesh);
+        scene.node_mut(node).material = Some(MaterialInstance {
+            material,
+            opacity: 0.25,
+        });
+    }
+
+    data.scene = scene;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms() {
+        let mut scene = Scene::default();
+        let parent = scene.add_node("Parent", scene.root());
+        let child = scene.add_node("Child", parent);
+
+        scene.node_mut(parent).transform.translation = glm::vec3(1.0, 0.0, 0.0);
+        scene.node_mut(parent).transform.scale = glm::vec3(2.0, 2.0, 2.0);
+        scene.node_mut(child).transform.translation = glm::vec3(0.0, 1.0, 0.0);
+        scene.update_transforms();
+
+        // The child is translated in the scaled space of its parent.
+        let origin = scene.node(child).world() * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(origin, glm::vec4(1.0, 2.0, 0.0, 1.0));
+
+        // Cycles are rejected.
+        assert!(scene.set_parent(parent, child).is_err());
+        assert!(scene.set_parent(child, scene.root()).is_ok());
+        assert_eq!(scene.node(scene.root()).children(), &[parent, child]);
+        assert_eq!(scene.node(parent).children(), &[]);
+    }
+}
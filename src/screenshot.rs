@@ -0,0 +1,390 @@
+//! Reading back rendered swapchain images and saving them as PNG files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::image::{create_image, get_image_barrier, Image};
+use crate::swapchain::is_srgb_format;
+
+/// The resource a screenshot is read back through.
+#[derive(Debug)]
+enum Readback {
+    /// A host-visible linear image the swapchain image is blitted to (which
+    /// converts it to RGBA).
+    Image(Image),
+    /// A host-visible buffer the swapchain image is copied to as is (which is
+    /// converted on the CPU).
+    Buffer(Buffer),
+}
+
+/// A screenshot which is captured in the command buffer of a frame.
+///
+/// The swapchain image is copied to a readback resource at the end of the
+/// command buffer, while the frame still owns the image (i.e., before it is
+/// presented), and the copy is only read once the frame has completed.
+#[derive(Debug)]
+pub struct Screenshot {
+    path: PathBuf,
+    readback: Readback,
+    format: vk::Format,
+    extent: vk::Extent2D,
+}
+
+impl Screenshot {
+    /// Creates the readback resource for a screenshot of the swapchain images
+    /// which is saved to the supplied PNG file.
+    ///
+    /// The swapchain images are blitted to a linear image (which converts them
+    /// to RGBA) if the device supports it, otherwise they are copied to a
+    /// buffer as is and converted on the CPU.
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        path: PathBuf,
+    ) -> Result<Self> {
+        if !data
+            .swapchain
+            .usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(anyhow!("Swapchain images can't be copied from."));
+        }
+
+        let vk::Extent2D { width, height } = data.swapchain.extent;
+
+        let readback = if supports_blit(instance, data) {
+            if data.swapchain.format == vk::Format::R16G16B16A16_SFLOAT
+                || data.swapchain.format == vk::Format::A2B10G10R10_UNORM_PACK32
+            {
+                warn!("HDR swapchain images are clamped to 8-bit in screenshots.");
+            }
+
+            Readback::Image(create_image(
+                instance,
+                device,
+                data,
+                width,
+                height,
+                1,
+                vk::SampleCountFlags::_1,
+                get_blit_format(data),
+                vk::ImageTiling::LINEAR,
+                vk::ImageUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            )?)
+        } else if is_copyable_format(data.swapchain.format) {
+            Readback::Buffer(create_buffer(
+                instance,
+                device,
+                data,
+                width as u64 * height as u64 * 4,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            )?)
+        } else {
+            return Err(anyhow!(
+                "Unsupported swapchain format ({:?}).",
+                data.swapchain.format
+            ));
+        };
+
+        Ok(Self {
+            path,
+            readback,
+            format: data.swapchain.format,
+            extent: data.swapchain.extent,
+        })
+    }
+
+    /// Records a copy of the swapchain image at the supplied index to the
+    /// readback resource.
+    ///
+    /// This must be called after the last pass which renders to the swapchain
+    /// image has ended (i.e., once it is in `PRESENT_SRC_KHR`).
+    pub unsafe fn cmd_capture(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let image = data.swapchain.images[image_index];
+        let vk::Extent2D { width, height } = self.extent;
+
+        let destination = match &self.readback {
+            Readback::Image(destination) => destination.image,
+            Readback::Buffer(_) => vk::Image::null(),
+        };
+
+        cmd_transition_for_read(device, command_buffer, image, destination);
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        match &self.readback {
+            Readback::Image(destination) => {
+                let offsets = [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: width as i32,
+                        y: height as i32,
+                        z: 1,
+                    },
+                ];
+
+                let blit = vk::ImageBlit::builder()
+                    .src_offsets(offsets)
+                    .src_subresource(subresource)
+                    .dst_offsets(offsets)
+                    .dst_subresource(subresource);
+
+                device.cmd_blit_image(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    destination.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::NEAREST,
+                );
+            }
+            Readback::Buffer(buffer) => {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(0)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    });
+
+                device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer.buffer,
+                    &[region],
+                );
+            }
+        }
+
+        cmd_transition_after_read(device, command_buffer, image, destination);
+    }
+
+    /// Reads the captured frame back and saves it, returning the path it was
+    /// saved to.
+    ///
+    /// This must only be called once the frame the screenshot was captured
+    /// in has completed.
+    pub unsafe fn save(self, device: &Device) -> Result<PathBuf> {
+        let vk::Extent2D { width, height } = self.extent;
+        let row = width as usize * 4;
+
+        let pixels = match &self.readback {
+            Readback::Image(destination) => {
+                // The rows of a linear image may be padded.
+                let subresource = vk::ImageSubresource::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .array_layer(0);
+                let layout = device.get_image_subresource_layout(destination.image, &subresource);
+
+                let memory = destination.allocation.map()?;
+
+                let mut pixels = Vec::with_capacity(row * height as usize);
+                for y in 0..height as usize {
+                    let offset = layout.offset as usize + y * layout.row_pitch as usize;
+                    let source = std::slice::from_raw_parts(memory.add(offset), row);
+                    pixels.extend_from_slice(source);
+                }
+
+                set_opaque(&mut pixels);
+                pixels
+            }
+            Readback::Buffer(buffer) => {
+                let memory = buffer.allocation.map()?;
+                let mut pixels = std::slice::from_raw_parts(memory, row * height as usize).to_vec();
+                convert_to_rgba(self.format, &mut pixels);
+                pixels
+            }
+        };
+
+        save_png(&self.path, width, height, &pixels)?;
+
+        Ok(self.path)
+    }
+}
+
+/// Returns a path for a screenshot in the working directory which is named
+/// after the current time.
+pub fn get_screenshot_path() -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from(format!("screenshot-{}.png", timestamp.as_millis()))
+}
+
+/// Saves 8-bit RGBA pixels to a PNG file.
+pub fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
+
+/// Returns whether images with the supplied format can be copied to a buffer
+/// and converted to 8-bit RGBA on the CPU.
+pub fn is_copyable_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+    )
+}
+
+/// Converts the pixels of an image with a copyable format to opaque 8-bit
+/// RGBA in place.
+pub fn convert_to_rgba(format: vk::Format, pixels: &mut [u8]) {
+    if matches!(
+        format,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+    ) {
+        pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+    }
+
+    set_opaque(pixels);
+}
+
+/// The format swapchain images are blitted to, which has the same transfer
+/// function so that the blit doesn't encode or decode sRGB.
+fn get_blit_format(data: &AppData) -> vk::Format {
+    if is_srgb_format(data.swapchain.format) {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    }
+}
+
+/// Returns whether swapchain images can be blitted to a linear image.
+unsafe fn supports_blit(instance: &Instance, data: &AppData) -> bool {
+    let source =
+        instance.get_physical_device_format_properties(data.physical_device, data.swapchain.format);
+    let destination =
+        instance.get_physical_device_format_properties(data.physical_device, get_blit_format(data));
+
+    source
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::BLIT_SRC)
+        && destination
+            .linear_tiling_features
+            .contains(vk::FormatFeatureFlags::BLIT_DST)
+}
+
+/// Transitions a swapchain image which has been rendered to so it can be read
+/// from and, if it isn't null, the image it is blitted to so it can be
+/// written to.
+unsafe fn cmd_transition_for_read(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    source: vk::Image,
+    destination: vk::Image,
+) {
+    let mut barriers = vec![get_image_barrier(
+        source,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        vk::AccessFlags::TRANSFER_READ,
+    )];
+
+    if !destination.is_null() {
+        barriers.push(get_image_barrier(
+            destination,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+        ));
+    }
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+}
+
+/// Transitions a swapchain image that has been read from back so it can be
+/// presented and, if it isn't null, the image it was blitted to so it can be
+/// mapped (and makes the copy visible to the host once the frame has
+/// completed).
+unsafe fn cmd_transition_after_read(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    source: vk::Image,
+    destination: vk::Image,
+) {
+    let mut barriers = vec![get_image_barrier(
+        source,
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::AccessFlags::TRANSFER_READ,
+        vk::AccessFlags::empty(),
+    )];
+
+    if !destination.is_null() {
+        barriers.push(get_image_barrier(
+            destination,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::HOST_READ,
+        ));
+    }
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+}
+
+/// The swapchain is composited as opaque, so the alpha the fragment shader
+/// writes isn't what is displayed.
+fn set_opaque(pixels: &mut [u8]) {
+    pixels.chunks_exact_mut(4).for_each(|p| p[3] = 255);
+}
@@ -0,0 +1,894 @@
+//! Signed distance field (SDF) text, which is drawn in the scene and stays
+//! crisp at any scale or angle since the glyphs are drawn from the distance to
+//! their edges rather than from their coverage.
+//!
+//! The printable ASCII glyphs of a TrueType or OpenType font are rasterized
+//! with fontdue, their signed distance fields are computed with a distance
+//! transform and packed into a font atlas, and strings are laid out as quads
+//! (see `BitmapFont`) which are drawn in the main pass with an outline and
+//! softness of their edges.
+
+use std::fs;
+use std::mem::{self, size_of};
+use std::path::Path;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::commands::DrawBatch;
+use crate::debug::TEXT_LABEL_COLOR;
+use crate::device::RenderingPath;
+use crate::framebuffers::{get_color_format, get_depth_format};
+use crate::handle::Owned;
+use crate::image::{cmd_transition_image_layout, create_image, create_image_view, Image};
+use crate::pipeline::create_shader_module;
+use crate::shaders;
+use crate::swapchain::get_scene_encoding;
+use crate::text::{BitmapFont, Glyph};
+use crate::upload::{
+    get_graphics_upload_command_buffer, get_upload_command_buffer, upload_to_image,
+};
+use crate::vertex::{Mat4, Vec2, Vec3, Vec4};
+
+/// The size the glyphs are rasterized at (in pixels).
+const SDF_FONT_SIZE: f32 = 48.0;
+
+/// The largest distance from the edges of the glyphs in the font atlas (in
+/// pixels at `SDF_FONT_SIZE`), which is also the padding around the glyphs.
+const SDF_SPREAD: usize = 8;
+
+/// The width of the font atlas (in pixels).
+const SDF_ATLAS_WIDTH: usize = 512;
+
+/// The format of the font atlas, whose distances must not be decoded as sRGB.
+const SDF_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+/// The smallest number of vertices the vertex buffers are created for.
+const MIN_SDF_BUFFER_LEN: usize = 1024;
+
+/// The size of the push constants (the model-view-projection matrix, the
+/// colors, the outline width, and the softness).
+const PUSH_CONSTANTS_SIZE: u32 = 104;
+
+/// A vertex of a glyph quad.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SdfVertex {
+    pub position: Vec3,
+    pub uv: Vec2,
+}
+
+impl SdfVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<SdfVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let uv = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+        [position, uv]
+    }
+}
+
+/// How SDF text is drawn.
+#[derive(Copy, Clone, Debug)]
+pub struct TextStyle {
+    /// The color of the glyphs (linear, with straight alpha).
+    pub color: Vec4,
+    pub outline_color: Vec4,
+    /// The width of the outline (from 0 to 0.5, where 0.5 is `SDF_SPREAD`
+    /// pixels at `SDF_FONT_SIZE`).
+    pub outline_width: f32,
+    /// How far the edges are softened (e.g., for a glow), in the same units.
+    pub softness: f32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            outline_color: glm::vec4(0.0, 0.0, 0.0, 1.0),
+            outline_width: 0.0,
+            softness: 0.0,
+        }
+    }
+}
+
+/// A font atlas of the signed distance fields of the glyphs of a font and the
+/// metrics of the glyphs.
+#[derive(Clone, Debug, Default)]
+pub struct SdfFont {
+    pub metrics: BitmapFont,
+    /// The distances (from 0 to 255, where 128 is the edge of a glyph) of the
+    /// font atlas, row by row.
+    pub pixels: Vec<u8>,
+}
+
+impl SdfFont {
+    /// Generates a font atlas for the printable ASCII characters of a TrueType
+    /// or OpenType font.
+    pub fn new(bytes: &[u8]) -> Result<Self> {
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|e| anyhow!("Failed to load font: {}", e))?;
+        let line_metrics = font
+            .horizontal_line_metrics(SDF_FONT_SIZE)
+            .ok_or_else(|| anyhow!("Font has no horizontal line metrics."))?;
+
+        let chars = (' '..='~').collect::<Vec<_>>();
+
+        // Glyphs
+
+        let glyphs = chars
+            .iter()
+            .map(|c| {
+                let (metrics, coverage) = font.rasterize(*c, SDF_FONT_SIZE);
+                let sdf = get_sdf(&coverage, metrics.width, metrics.height);
+                (*c, metrics, sdf)
+            })
+            .collect::<Vec<_>>();
+
+        // Packing
+
+        // The glyphs are packed into rows, left to right.
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        let mut positions = vec![];
+        for (_, metrics, _) in &glyphs {
+            let width = metrics.width + 2 * SDF_SPREAD;
+            let height = metrics.height + 2 * SDF_SPREAD;
+            if x + width > SDF_ATLAS_WIDTH {
+                (x, y, row_height) = (0, y + row_height, 0);
+            }
+
+            positions.push((x, y));
+            x += width;
+            row_height = row_height.max(height);
+        }
+
+        let atlas_height = (y + row_height).next_power_of_two();
+
+        // Atlas
+
+        let mut pixels = vec![0; SDF_ATLAS_WIDTH * atlas_height];
+        let mut metrics = BitmapFont::new(
+            line_metrics.new_line_size,
+            SDF_ATLAS_WIDTH as u32,
+            atlas_height as u32,
+        );
+
+        for ((char, glyph_metrics, sdf), (x, y)) in glyphs.iter().zip(positions) {
+            let width = glyph_metrics.width + 2 * SDF_SPREAD;
+            let height = glyph_metrics.height + 2 * SDF_SPREAD;
+            for row in 0..height {
+                let destination = (y + row) * SDF_ATLAS_WIDTH + x;
+                pixels[destination..destination + width]
+                    .copy_from_slice(&sdf[row * width..(row + 1) * width]);
+            }
+
+            // fontdue measures the glyphs up from the baseline, while bitmap
+            // fonts measure them down from the top of the line.
+            let top = glyph_metrics.ymin as f32 + glyph_metrics.height as f32;
+            let glyph = Glyph {
+                x: x as u32,
+                y: y as u32,
+                width: width as u32,
+                height: height as u32,
+                offset: Vec2::new(
+                    glyph_metrics.xmin as f32 - SDF_SPREAD as f32,
+                    line_metrics.ascent - top - SDF_SPREAD as f32,
+                ),
+                advance: glyph_metrics.advance_width,
+                page: 0,
+            };
+
+            metrics.add_glyph(*char, glyph);
+        }
+
+        // Kerning
+
+        for first in &chars {
+            for second in &chars {
+                match font.horizontal_kern(*first, *second, SDF_FONT_SIZE) {
+                    Some(amount) if amount != 0.0 => metrics.add_kerning(*first, *second, amount),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self { metrics, pixels })
+    }
+}
+
+/// A draw of a string.
+#[derive(Copy, Clone, Debug)]
+struct SdfDraw {
+    model: Mat4,
+    style: TextStyle,
+    first_vertex: u32,
+    vertex_count: u32,
+}
+
+/// The vertices of the text drawn to a swapchain image.
+#[derive(Debug, Default)]
+struct SdfBuffers {
+    vertex_buffer: Buffer,
+    capacity: usize,
+    draws: Vec<SdfDraw>,
+}
+
+/// The SDF font, the text drawn with it in the next frame, and the Vulkan
+/// handles used to draw it.
+#[derive(Debug, Default)]
+pub struct SdfText {
+    /// The font, which outlives the logical device.
+    font: Option<SdfFont>,
+    vertices: Vec<SdfVertex>,
+    draws: Vec<SdfDraw>,
+    image: Image,
+    sampler: Owned<vk::Sampler>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    // Swapchain
+    pipeline: Owned<vk::Pipeline>,
+    buffers: Vec<SdfBuffers>,
+}
+
+impl SdfText {
+    /// Draws a string in the next frame, with the top left corner of its first
+    /// line at the origin of the supplied model matrix and its lines extending
+    /// along the X axis and stacked down the Y axis. The supplied size is the
+    /// size of the font (in units of the model matrix).
+    ///
+    /// Nothing is drawn if there isn't a font.
+    pub fn draw(&mut self, text: &str, model: Mat4, size: f32, style: TextStyle) {
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        let metrics = &font.metrics;
+        let scale = size / SDF_FONT_SIZE;
+        let atlas = Vec2::new(metrics.width as f32, metrics.height as f32);
+        let first_vertex = self.vertices.len() as u32;
+
+        metrics.layout(text, |glyph, pen, _| {
+            let size = Vec2::new(glyph.width as f32, glyph.height as f32);
+            let min = (pen + glyph.offset) * scale;
+            let max = min + size * scale;
+            let uv_min = Vec2::new(glyph.x as f32, glyph.y as f32).component_div(&atlas);
+            let uv_max = uv_min + size.component_div(&atlas);
+
+            // The layout is top down, while the Y axis points up.
+            let vertex = |x: f32, y: f32, u: f32, v: f32| SdfVertex {
+                position: glm::vec3(x, -y, 0.0),
+                uv: Vec2::new(u, v),
+            };
+
+            let top_left = vertex(min.x, min.y, uv_min.x, uv_min.y);
+            let top_right = vertex(max.x, min.y, uv_max.x, uv_min.y);
+            let bottom_right = vertex(max.x, max.y, uv_max.x, uv_max.y);
+            let bottom_left = vertex(min.x, max.y, uv_min.x, uv_max.y);
+
+            self.vertices.extend([
+                top_left,
+                bottom_left,
+                bottom_right,
+                bottom_right,
+                top_right,
+                top_left,
+            ]);
+        });
+
+        let vertex_count = self.vertices.len() as u32 - first_vertex;
+        if vertex_count > 0 {
+            self.draws.push(SdfDraw {
+                model,
+                style,
+                first_vertex,
+                vertex_count,
+            });
+        }
+    }
+
+    /// Takes the font (which outlives the logical device), leaving everything
+    /// used to draw SDF text to be destroyed when the rest is dropped.
+    pub fn take_persistent(&mut self) -> Self {
+        Self {
+            font: self.font.take(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Creates the font atlas (generating it the first time) and the descriptor
+/// set and pipeline layout used to draw SDF text.
+///
+/// This does nothing unless there is an SDF font in the options.
+pub unsafe fn create_sdf_text(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let Some(path) = data.options.sdf_font.clone() else {
+        return Ok(());
+    };
+
+    if data.sdf_text.font.is_none() {
+        data.sdf_text.font = Some(load_sdf_font(&path)?);
+    }
+
+    // Create
+
+    let font = data.sdf_text.font.as_ref().unwrap();
+    let (width, height) = (font.metrics.width, font.metrics.height);
+    let pixels = font.pixels.clone();
+
+    let mut atlas = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        SDF_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Transition + Upload
+
+    cmd_transition_image_layout(
+        device,
+        get_upload_command_buffer(device, data)?,
+        atlas.image,
+        SDF_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+    )?;
+
+    upload_to_image(
+        instance,
+        device,
+        data,
+        &pixels,
+        atlas.image,
+        width,
+        height,
+        1,
+    )?;
+
+    cmd_transition_image_layout(
+        device,
+        get_graphics_upload_command_buffer(device, data)?,
+        atlas.image,
+        SDF_FORMAT,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+    )?;
+
+    atlas.view = create_image_view(
+        device,
+        atlas.image,
+        SDF_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    data.sdf_text.image = atlas;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .max_lod(0.0);
+
+    data.sdf_text.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Descriptor Set
+
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.sdf_text.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.sdf_text.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = &[*data.sdf_text.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.sdf_text.descriptor_pool)
+        .set_layouts(layouts);
+
+    data.sdf_text.descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.sdf_text.image.view)
+        .sampler(*data.sdf_text.sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.sdf_text.descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(PUSH_CONSTANTS_SIZE);
+
+    let set_layouts = &[*data.sdf_text.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.sdf_text.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    Ok(())
+}
+
+/// Creates the pipeline which draws SDF text in the main pass.
+///
+/// This does nothing unless there is an SDF font in the options.
+pub unsafe fn create_sdf_text_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.sdf_text.buffers = (0..data.swapchain.images.len())
+        .map(|_| SdfBuffers::default())
+        .collect();
+
+    if data.options.sdf_font.is_none() {
+        return Ok(());
+    }
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::SDF_TEXT_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::SDF_TEXT_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The text is encoded like the rest of the scene.
+    let (encode_srgb, output_transfer) = get_scene_encoding(data);
+
+    let specialization_data = [encode_srgb as vk::Bool32, output_transfer as u32]
+        .map(u32::to_ne_bytes)
+        .concat();
+    let map_entries = &[
+        vk::SpecializationMapEntry::builder()
+            .constant_id(0)
+            .offset(0)
+            .size(size_of::<vk::Bool32>()),
+        vk::SpecializationMapEntry::builder()
+            .constant_id(1)
+            .offset(4)
+            .size(size_of::<u32>()),
+    ];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&specialization_data);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Vertex Input State
+
+    let binding_descriptions = &[SdfVertex::binding_description()];
+    let attribute_descriptions = SdfVertex::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain.extent.width as f32)
+        .height(data.swapchain.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain.extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // The text can be read from behind (mirrored).
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Depth Stencil State
+
+    // The text is hidden by the scene, but it doesn't hide anything drawn
+    // after it (since its quads are mostly transparent).
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    // The colors have premultiplied alpha.
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[get_color_format(data)];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats)
+        .depth_attachment_format(get_depth_format(instance, data)?);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*data.sdf_text.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.sdf_text.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Copies the text drawn since the last frame to the vertex buffer for the
+/// swapchain image at the supplied index (growing it if needed).
+///
+/// The command buffer for the swapchain image has to have completed.
+pub unsafe fn prepare_sdf_text(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+) -> Result<()> {
+    let vertices = mem::take(&mut data.sdf_text.vertices);
+    let draws = mem::take(&mut data.sdf_text.draws);
+
+    if data.sdf_text.buffers[image_index].capacity < vertices.len() {
+        let capacity = vertices.len().next_power_of_two().max(MIN_SDF_BUFFER_LEN);
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            (capacity * size_of::<SdfVertex>()) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let buffers = &mut data.sdf_text.buffers[image_index];
+        buffers.vertex_buffer = buffer;
+        buffers.capacity = capacity;
+    }
+
+    let buffers = &mut data.sdf_text.buffers[image_index];
+    if !vertices.is_empty() {
+        let memory = buffers.vertex_buffer.allocation.map()?;
+        memcpy(vertices.as_ptr(), memory.cast(), vertices.len());
+    }
+
+    buffers.draws = draws;
+
+    Ok(())
+}
+
+/// Returns a draw batch which draws the text prepared for the swapchain image
+/// at the supplied index with the supplied view and projection matrices, if
+/// there is any.
+pub fn get_sdf_text_batch(
+    data: &AppData,
+    image_index: usize,
+    view: &Mat4,
+    proj: &Mat4,
+) -> Option<DrawBatch<'static>> {
+    let buffers = &data.sdf_text.buffers[image_index];
+    if buffers.draws.is_empty() {
+        return None;
+    }
+
+    let draws = buffers
+        .draws
+        .iter()
+        .map(|d| {
+            let mvp = proj * view * d.model;
+            let push_constants = mvp
+                .iter()
+                .chain(d.style.color.iter())
+                .chain(d.style.outline_color.iter())
+                .chain([d.style.outline_width, d.style.softness].iter())
+                .flat_map(|f| f.to_ne_bytes())
+                .collect::<Vec<_>>();
+            (push_constants, d.first_vertex, d.vertex_count)
+        })
+        .collect::<Vec<_>>();
+
+    let pipeline = *data.sdf_text.pipeline;
+    let pipeline_layout = *data.sdf_text.pipeline_layout;
+    let descriptor_set = data.sdf_text.descriptor_set;
+    let vertex_buffer = buffers.vertex_buffer.buffer;
+
+    Some(DrawBatch {
+        name: "Text",
+        color: TEXT_LABEL_COLOR,
+        record: Box::new(move |device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            for (push_constants, first_vertex, vertex_count) in &draws {
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    push_constants,
+                );
+                device.cmd_draw(command_buffer, *vertex_count, 1, *first_vertex, 0);
+            }
+        }),
+    })
+}
+
+/// Loads a font file and generates its font atlas.
+fn load_sdf_font(path: &Path) -> Result<SdfFont> {
+    let bytes =
+        fs::read(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+    SdfFont::new(&bytes)
+}
+
+/// Returns the signed distance field of a glyph from its coverage (row by
+/// row), padded by `SDF_SPREAD` pixels on every side.
+///
+/// The distances are encoded so that 128 is the edge of the glyph, 255 is
+/// `SDF_SPREAD` pixels inside of it, and 0 is `SDF_SPREAD` pixels outside of
+/// it.
+fn get_sdf(coverage: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let padded_width = width + 2 * SDF_SPREAD;
+    let padded_height = height + 2 * SDF_SPREAD;
+
+    let inside = (0..padded_width * padded_height)
+        .map(|i| {
+            let (x, y) = (i % padded_width, i / padded_width);
+            let (x, y) = (x.wrapping_sub(SDF_SPREAD), y.wrapping_sub(SDF_SPREAD));
+            x < width && y < height && coverage[y * width + x] >= 128
+        })
+        .collect::<Vec<_>>();
+    let outside = inside.iter().map(|i| !i).collect::<Vec<_>>();
+
+    let to_inside = get_distances(&inside, padded_width, padded_height);
+    let to_outside = get_distances(&outside, padded_width, padded_height);
+
+    (0..inside.len())
+        .map(|i| {
+            // The edge is between the pixels inside and outside of the glyph.
+            let distance = if inside[i] {
+                to_outside[i] - 0.5
+            } else {
+                0.5 - to_inside[i]
+            };
+
+            let value = 0.5 + distance / (2.0 * SDF_SPREAD as f32);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Returns the distance (in pixels) from every pixel of an image to the
+/// nearest pixel in a set of pixels (which is 0 for the pixels in the set).
+///
+/// This is the 8SSEDT algorithm, which propagates the offset to the nearest
+/// pixel in the set from the neighbors of each pixel in two passes over the
+/// image (down and up).
+fn get_distances(set: &[bool], width: usize, height: usize) -> Vec<f32> {
+    // Far enough from every pixel that the squared distance doesn't overflow.
+    const FAR: (i32, i32) = (9999, 9999);
+
+    let mut offsets = set
+        .iter()
+        .map(|s| if *s { (0, 0) } else { FAR })
+        .collect::<Vec<_>>();
+
+    let length = |(x, y): (i32, i32)| x * x + y * y;
+    let compare = |offsets: &mut [(i32, i32)], x: usize, y: usize, dx: i32, dy: i32| {
+        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+            return;
+        }
+
+        let (ox, oy) = offsets[ny as usize * width + nx as usize];
+        let offset = (ox + dx, oy + dy);
+        if length(offset) < length(offsets[y * width + x]) {
+            offsets[y * width + x] = offset;
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            compare(&mut offsets, x, y, -1, 0);
+            compare(&mut offsets, x, y, 0, -1);
+            compare(&mut offsets, x, y, -1, -1);
+            compare(&mut offsets, x, y, 1, -1);
+        }
+        for x in (0..width).rev() {
+            compare(&mut offsets, x, y, 1, 0);
+        }
+    }
+
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            compare(&mut offsets, x, y, 1, 0);
+            compare(&mut offsets, x, y, 0, 1);
+            compare(&mut offsets, x, y, -1, 1);
+            compare(&mut offsets, x, y, 1, 1);
+        }
+        for x in 0..width {
+            compare(&mut offsets, x, y, -1, 0);
+        }
+    }
+
+    offsets
+        .into_iter()
+        .map(|o| (length(o) as f32).sqrt())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        // A single pixel in the set in the middle of a 5x5 image.
+        let mut set = vec![false; 25];
+        set[12] = true;
+
+        let distances = get_distances(&set, 5, 5);
+        assert_eq!(distances[12], 0.0);
+        assert_eq!(distances[13], 1.0);
+        assert_eq!(distances[2], 2.0);
+        assert_eq!(distances[0], 8.0f32.sqrt());
+    }
+
+    #[test]
+    fn sdf() {
+        // A 2x2 glyph which is entirely inside.
+        let sdf = get_sdf(&[255; 4], 2, 2);
+        let width = 2 + 2 * SDF_SPREAD;
+        assert_eq!(sdf.len(), width * width);
+
+        // The pixels next to the edge are half a pixel from it.
+        let inside = sdf[SDF_SPREAD * width + SDF_SPREAD];
+        let outside = sdf[SDF_SPREAD * width + SDF_SPREAD - 1];
+        assert!(inside > 128 && outside < 128);
+        assert_eq!(inside as i32 - 128, 127 - outside as i32);
+
+        // The corners of the padding are the farthest outside.
+        assert_eq!(sdf[0], 0);
+    }
+}
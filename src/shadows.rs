@@ -0,0 +1,835 @@
+//! A shadow map for the directional light which casts shadows, which is
+//! rendered by a depth-only pass before the main pass every frame.
+//!
+//! Point lights which cast shadows are rendered into cube maps instead (one
+//! per light, up to `MAX_POINT_SHADOWS`), whose faces store the distance from
+//! the light (relative to its range) rather than the depth of a projection,
+//! so they can be compared with the distance of any point around the light.
+//!
+//! The shadow pass always uses a render pass (which every device supports),
+//! whose subpass dependencies order it after the main pass of the previous
+//! frame (which samples the shadow map) and before the main pass of the
+//! current frame.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::handle::Owned;
+use crate::image::{
+    create_image, create_image_view, create_layered_image, create_layered_image_view, Image,
+};
+use crate::light::{Light, LightKind, MAX_LIGHTS};
+use crate::mesh::Aabb;
+use crate::pipeline::create_shader_module;
+use crate::scene::Draw;
+use crate::shaders;
+use crate::vertex::{Mat4, Vec3, Vec4, Vertex};
+
+/// The width and height of the shadow map.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// The format of the shadow map.
+const SHADOW_MAP_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// The width and height of the faces of the point light shadow maps.
+pub const POINT_SHADOW_SIZE: u32 = 512;
+
+/// The maximum number of point lights which cast shadows in a frame (any
+/// further point lights are unshadowed).
+pub const MAX_POINT_SHADOWS: usize = 4;
+
+/// The distance (relative to the range of the light) closer than which the
+/// point light shadow maps don't render anything.
+const POINT_SHADOW_NEAR: f32 = 0.01;
+
+/// The depth bias applied to the shadow map by default (a constant factor and
+/// a factor of the slope of the triangles), which keeps surfaces from
+/// shadowing themselves ("shadow acne").
+pub const DEFAULT_SHADOW_BIAS: (f32, f32) = (1.25, 1.75);
+
+/// The shadow map and the pipeline which renders it.
+#[derive(Debug, Default)]
+pub struct ShadowMap {
+    pub image: Image,
+    /// A sampler which compares the depth of the shadow map (with linear
+    /// filtering of the results).
+    pub sampler: Owned<vk::Sampler>,
+    render_pass: Owned<vk::RenderPass>,
+    framebuffer: Owned<vk::Framebuffer>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    pipeline: Owned<vk::Pipeline>,
+    /// The matrix which transforms world space to the clip space of the light
+    /// (as of the last `update_light_space`).
+    pub light_space: Mat4,
+    /// The shadow maps of the point lights (which are all bound, even if
+    /// fewer point lights cast shadows).
+    pub point_shadows: Vec<PointShadow>,
+    point_pipeline_layout: Owned<vk::PipelineLayout>,
+    point_pipeline: Owned<vk::Pipeline>,
+    /// The positions and, in W, the ranges of the point lights which cast
+    /// shadows (as of the last `update_light_space`).
+    point_lights: Vec<Vec4>,
+}
+
+/// A cube map shadow map of a point light.
+#[derive(Debug, Default)]
+pub struct PointShadow {
+    /// The cube map (whose view is a cube view).
+    pub image: Image,
+    /// A 2D view and framebuffer for each face of the cube map.
+    face_views: Vec<Owned<vk::ImageView>>,
+    framebuffers: Vec<Owned<vk::Framebuffer>>,
+}
+
+/// Creates the shadow map, the render pass and framebuffer which render to it,
+/// and the pipeline which renders to it.
+pub unsafe fn create_shadow_map(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Image
+
+    let mut image = create_image(
+        instance,
+        device,
+        data,
+        SHADOW_MAP_SIZE,
+        SHADOW_MAP_SIZE,
+        1,
+        vk::SampleCountFlags::_1,
+        SHADOW_MAP_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image.view = create_image_view(
+        device,
+        image.image,
+        SHADOW_MAP_FORMAT,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+    )?;
+
+    data.shadow_map.image = image;
+
+    // Sampler
+
+    // Everything outside of the shadow map is lit.
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.shadow_map.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Render Pass
+
+    let depth_attachment = vk::AttachmentDescription::builder()
+        .format(SHADOW_MAP_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .depth_stencil_attachment(&depth_attachment_ref);
+
+    // The previous frame has to be done sampling the shadow map before it is
+    // cleared, and the main pass has to wait for it to be rendered.
+    let dependencies = &[
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let attachments = &[depth_attachment];
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.shadow_map.render_pass = Owned::new(device, device.create_render_pass(&info, None)?);
+
+    // Framebuffer
+
+    let attachments = &[data.shadow_map.image.view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(*data.shadow_map.render_pass)
+        .attachments(attachments)
+        .width(SHADOW_MAP_SIZE)
+        .height(SHADOW_MAP_SIZE)
+        .layers(1);
+
+    data.shadow_map.framebuffer = Owned::new(device, device.create_framebuffer(&info, None)?);
+
+    // Point Shadows
+
+    for _ in 0..MAX_POINT_SHADOWS {
+        let point_shadow = create_point_shadow(instance, device, data)?;
+        data.shadow_map.point_shadows.push(point_shadow);
+    }
+
+    // Pipelines
+
+    let (pipeline_layout, pipeline) =
+        create_shadow_pipeline(device, data, shaders::SHADOW_VERT, None, SHADOW_MAP_SIZE)?;
+
+    data.shadow_map.pipeline_layout = pipeline_layout;
+    data.shadow_map.pipeline = pipeline;
+
+    let (pipeline_layout, pipeline) = create_shadow_pipeline(
+        device,
+        data,
+        shaders::POINT_SHADOW_VERT,
+        Some(shaders::POINT_SHADOW_FRAG),
+        POINT_SHADOW_SIZE,
+    )?;
+
+    data.shadow_map.point_pipeline_layout = pipeline_layout;
+    data.shadow_map.point_pipeline = pipeline;
+
+    Ok(())
+}
+
+/// Creates a cube map shadow map for a point light and the framebuffers which
+/// render to its faces (with the render pass of the shadow map, whose
+/// attachment is compatible).
+unsafe fn create_point_shadow(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+) -> Result<PointShadow> {
+    let mut image = create_layered_image(
+        instance,
+        device,
+        data,
+        POINT_SHADOW_SIZE,
+        POINT_SHADOW_SIZE,
+        1,
+        6,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::SampleCountFlags::_1,
+        SHADOW_MAP_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image.view = create_layered_image_view(
+        device,
+        image.image,
+        vk::ImageViewType::CUBE,
+        SHADOW_MAP_FORMAT,
+        vk::ImageAspectFlags::DEPTH,
+        1,
+        6,
+    )?;
+
+    let mut point_shadow = PointShadow {
+        image,
+        ..Default::default()
+    };
+
+    for face in 0..6 {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(face)
+            .layer_count(1);
+
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(point_shadow.image.image)
+            .view_type(vk::ImageViewType::_2D)
+            .format(SHADOW_MAP_FORMAT)
+            .subresource_range(subresource_range);
+
+        let face_view = device.create_image_view(&info, None)?;
+        point_shadow.face_views.push(Owned::new(device, face_view));
+
+        let attachments = &[face_view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(*data.shadow_map.render_pass)
+            .attachments(attachments)
+            .width(POINT_SHADOW_SIZE)
+            .height(POINT_SHADOW_SIZE)
+            .layers(1);
+
+        let framebuffer = device.create_framebuffer(&info, None)?;
+        point_shadow
+            .framebuffers
+            .push(Owned::new(device, framebuffer));
+    }
+
+    Ok(point_shadow)
+}
+
+/// Creates a pipeline (and its layout) which renders the depth of meshes into
+/// a shadow map of the supplied size.
+///
+/// The pipelines of point light shadow maps have a fragment shader which
+/// writes the distance from the light, and receive the matrix of a face of the
+/// cube map along with the light model matrix (see `cmd_point_shadow_pass`).
+unsafe fn create_shadow_pipeline(
+    device: &Device,
+    data: &AppData,
+    vertex_shader: &[u8],
+    fragment_shader: Option<&[u8]>,
+    size: u32,
+) -> Result<(Owned<vk::PipelineLayout>, Owned<vk::Pipeline>)> {
+    // Layout
+
+    // The light space matrix multiplied by the model matrix (or the face and
+    // light model matrices).
+    let push_constants_size = if fragment_shader.is_some() {
+        2 * size_of::<Mat4>()
+    } else {
+        size_of::<Mat4>()
+    };
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(push_constants_size as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info =
+        vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(push_constant_ranges);
+
+    let pipeline_layout = Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Stages
+
+    // Without a fragment shader only the depth of the triangles is written.
+    let vert_shader_module = create_shader_module(device, vertex_shader)?;
+    let frag_shader_module = match fragment_shader {
+        Some(bytecode) => Some(create_shader_module(device, bytecode)?),
+        None => None,
+    };
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = frag_shader_module.map(|module| {
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(module)
+            .name(b"main\0")
+    });
+
+    // Vertex Input State
+
+    // Only the positions of the vertices are read (even with buffer device
+    // addresses, since vertex buffers can always be bound).
+    let binding_descriptions = &[Vertex::binding_description()];
+    let attribute_descriptions = &[Vertex::attribute_descriptions()[0]];
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(attribute_descriptions);
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(size as f32)
+        .height(size as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(vk::Extent2D {
+            width: size,
+            height: size,
+        });
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // Both sides of the triangles cast shadows (so the quads shadow each
+    // other from either side), and the depth bias is set when the pass is
+    // recorded (see `cmd_shadow_pass`). The depth bias doesn't apply to the
+    // distances written by the point light shadow maps, which are biased when
+    // they are sampled instead.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(true);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // Depth Stencil State
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder().logic_op_enable(false);
+
+    // Dynamic State
+
+    let dynamic_states = &[vk::DynamicState::DEPTH_BIAS];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    // Create
+
+    let stages = [Some(vert_stage), frag_stage]
+        .into_iter()
+        .flatten()
+        .map(|s| s.build())
+        .collect::<Vec<_>>();
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(*pipeline_layout)
+        .render_pass(*data.shadow_map.render_pass)
+        .subpass(0);
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    if let Some(module) = frag_shader_module {
+        device.destroy_shader_module(module, None);
+    }
+
+    Ok((pipeline_layout, Owned::new(device, pipeline)))
+}
+
+/// Fits the light space of the first directional light which casts shadows
+/// (if any) to the bounds of the supplied draws.
+pub fn update_light_space(data: &mut AppData, draws: &[Draw], lights: &[Light]) {
+    data.shadow_map.point_lights = get_shadowed_point_lights(lights)
+        .map(|l| l.position.push(l.range))
+        .collect();
+
+    let light = lights
+        .iter()
+        .find(|l| l.kind == LightKind::Directional && l.cast_shadows);
+
+    let light = match light {
+        Some(light) => light,
+        None => {
+            data.shadow_map.light_space = glm::identity();
+            return;
+        }
+    };
+
+    // The corners of the bounding boxes of the meshes in world space.
+    let corners = draws.iter().flat_map(|d| {
+        let bounds = data
+            .resources
+            .mesh(d.mesh)
+            .map_or(Aabb::from_points([]), |m| m.bounds);
+        let model = d.model;
+        (0..8).map(move |i| {
+            let corner = glm::vec3(
+                if i & 1 == 0 {
+                    bounds.min.x
+                } else {
+                    bounds.max.x
+                },
+                if i & 2 == 0 {
+                    bounds.min.y
+                } else {
+                    bounds.max.y
+                },
+                if i & 4 == 0 {
+                    bounds.min.z
+                } else {
+                    bounds.max.z
+                },
+            );
+            (model * corner.push(1.0)).xyz()
+        })
+    });
+
+    let bounds = Aabb::from_points(corners);
+    let radius = glm::distance(&bounds.min, &bounds.max) / 2.0;
+
+    data.shadow_map.light_space =
+        get_light_space(&light.direction, &bounds.center(), radius.max(0.1));
+}
+
+/// Returns the matrix which transforms world space to the clip space of an
+/// orthographic projection along the supplied direction, which contains the
+/// sphere with the supplied center and radius.
+pub fn get_light_space(direction: &Vec3, center: &Vec3, radius: f32) -> Mat4 {
+    // The up vector can't be parallel to the direction.
+    let up = if direction.z.abs() > 0.99 {
+        glm::vec3(0.0, 1.0, 0.0)
+    } else {
+        glm::vec3(0.0, 0.0, 1.0)
+    };
+
+    let eye = center - direction.normalize() * radius * 2.0;
+    let view = glm::look_at_rh(&eye, center, &up);
+    let proj = glm::ortho_rh_zo(-radius, radius, -radius, radius, radius, radius * 3.0);
+
+    proj * view
+}
+
+/// Returns the point lights which are rendered into the point light shadow
+/// maps (the first `MAX_POINT_SHADOWS` of the shaded point lights which cast
+/// shadows), in the order of the shadow maps.
+pub fn get_shadowed_point_lights(lights: &[Light]) -> impl Iterator<Item = &Light> {
+    lights
+        .iter()
+        .take(MAX_LIGHTS)
+        .filter(|l| l.kind == LightKind::Point && l.cast_shadows)
+        .take(MAX_POINT_SHADOWS)
+}
+
+/// Returns the matrix which transforms directions from the center of a cube
+/// map to the clip space of one of its faces (+X, -X, +Y, -Y, +Z, -Z), with
+/// the faces laid out as in "Cube Map Face Selection" in the Vulkan
+/// specification and depths from `near` to 1.
+pub fn get_cube_face_projection(face: usize, near: f32) -> Mat4 {
+    // The directions of the X and Y axes of the face and the face itself.
+    let (x, y, z) = match face {
+        0 => (
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+        ),
+        1 => (
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(-1.0, 0.0, 0.0),
+        ),
+        2 => (
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ),
+        3 => (
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(0.0, -1.0, 0.0),
+        ),
+        4 => (
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+        ),
+        _ => (
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(0.0, 0.0, -1.0),
+        ),
+    };
+
+    // A 90 degree perspective projection onto the face.
+    let depth = z / (1.0 - near);
+    Mat4::from_rows(&[
+        x.push(0.0).transpose(),
+        y.push(0.0).transpose(),
+        depth.push(-near / (1.0 - near)).transpose(),
+        z.push(0.0).transpose(),
+    ])
+}
+
+/// Records the shadow pass, which renders the depth of the supplied draws
+/// from the light into the shadow map (and from the point lights which cast
+/// shadows into their shadow maps).
+pub unsafe fn cmd_shadow_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    draws: &[Draw],
+) {
+    let clear_values = &[vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    }];
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(vk::Extent2D {
+            width: SHADOW_MAP_SIZE,
+            height: SHADOW_MAP_SIZE,
+        });
+
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.shadow_map.render_pass)
+        .framebuffer(*data.shadow_map.framebuffer)
+        .render_area(render_area)
+        .clear_values(clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.shadow_map.pipeline,
+    );
+
+    let (constant_factor, slope_factor) = data.options.shadow_bias;
+    device.cmd_set_depth_bias(command_buffer, constant_factor, 0.0, slope_factor);
+
+    for draw in draws {
+        let mesh = match data.resources.mesh(draw.mesh) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+
+        let light_space_model = data.shadow_map.light_space * draw.model;
+        let bytes = std::slice::from_raw_parts(
+            &light_space_model as *const Mat4 as *const u8,
+            size_of::<Mat4>(),
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            *data.shadow_map.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            bytes,
+        );
+
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer.buffer], &[0]);
+        device.cmd_bind_index_buffer(
+            command_buffer,
+            mesh.index_buffer.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
+
+        for submesh in &mesh.submeshes {
+            let indices = &submesh.indices;
+            device.cmd_draw_indexed(command_buffer, indices.len() as u32, 1, indices.start, 0, 0);
+        }
+    }
+
+    device.cmd_end_render_pass(command_buffer);
+
+    for (index, point_shadow) in data.shadow_map.point_shadows.iter().enumerate() {
+        let light = data.shadow_map.point_lights.get(index);
+        for (face, framebuffer) in point_shadow.framebuffers.iter().enumerate() {
+            cmd_point_shadow_pass(
+                device,
+                data,
+                command_buffer,
+                **framebuffer,
+                face,
+                light,
+                draws,
+            );
+        }
+    }
+}
+
+/// Records a pass which renders the distances of the supplied draws from a
+/// point light into a face of its shadow map (or only clears it if there is
+/// no point light for the shadow map).
+unsafe fn cmd_point_shadow_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    framebuffer: vk::Framebuffer,
+    face: usize,
+    light: Option<&Vec4>,
+    draws: &[Draw],
+) {
+    // Everything is out of range of the light until it is rendered.
+    let clear_values = &[vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    }];
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(vk::Extent2D {
+            width: POINT_SHADOW_SIZE,
+            height: POINT_SHADOW_SIZE,
+        });
+
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.shadow_map.render_pass)
+        .framebuffer(framebuffer)
+        .render_area(render_area)
+        .clear_values(clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+    if let Some(light) = light {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *data.shadow_map.point_pipeline,
+        );
+
+        let face_projection = get_cube_face_projection(face, POINT_SHADOW_NEAR);
+
+        // The light model matrix transforms the vertices to positions
+        // relative to the light (in units of its range).
+        let light_view = glm::scale(&glm::identity(), &(glm::vec3(1.0, 1.0, 1.0) / light.w))
+            * glm::translate(&glm::identity(), &-light.xyz());
+
+        for draw in draws {
+            let mesh = match data.resources.mesh(draw.mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            let matrices = [face_projection, light_view * draw.model];
+            let bytes =
+                std::slice::from_raw_parts(matrices.as_ptr() as *const u8, size_of::<[Mat4; 2]>());
+            device.cmd_push_constants(
+                command_buffer,
+                *data.shadow_map.point_pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytes,
+            );
+
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                mesh.index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            for submesh in &mesh.submeshes {
+                let indices = &submesh.indices;
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    indices.len() as u32,
+                    1,
+                    indices.start,
+                    0,
+                    0,
+                );
+            }
+        }
+    }
+
+    device.cmd_end_render_pass(command_buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_space() {
+        let center = glm::vec3(1.0, 2.0, 3.0);
+        let light_space = get_light_space(&glm::vec3(0.0, 0.0, -1.0), &center, 2.0);
+
+        // The center is in the middle of the shadow map and its depth range.
+        let clip = light_space * center.push(1.0);
+        assert!(glm::distance(&clip, &glm::vec4(0.0, 0.0, 0.5, 1.0)) < 1e-5);
+
+        // The sphere is within the shadow map (closer points have less depth).
+        let top = light_space * (center + glm::vec3(0.0, 0.0, 2.0)).push(1.0);
+        let side = light_space * (center + glm::vec3(2.0, 0.0, 0.0)).push(1.0);
+        assert!(top.z.abs() < 1e-5);
+        assert!((side.x.abs() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cube_face_projection() {
+        // The directions through the texels of the faces (see
+        // `getDirection` in `equirect_to_cube.comp`).
+        let (u, v) = (0.5, -0.25);
+        let directions = [
+            glm::vec3(1.0, -v, -u),
+            glm::vec3(-1.0, -v, u),
+            glm::vec3(u, 1.0, v),
+            glm::vec3(u, -1.0, -v),
+            glm::vec3(u, -v, 1.0),
+            glm::vec3(-u, -v, -1.0),
+        ];
+
+        for (face, direction) in directions.iter().enumerate() {
+            let projection = get_cube_face_projection(face, 0.1);
+
+            let clip = projection * (direction * 0.5).push(1.0);
+            assert!((clip.x / clip.w - u).abs() < 1e-5);
+            assert!((clip.y / clip.w - v).abs() < 1e-5);
+
+            // The depths range from the near plane to a distance of 1.
+            let near = projection * (direction * 0.1).push(1.0);
+            let far = projection * direction.push(1.0);
+            assert!((near.z / near.w).abs() < 1e-5);
+            assert!((far.z / far.w - 1.0).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Swapchain creation and surface format, present mode and extent selection.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSurfaceExtension;
+use vulkanalia::vk::KhrSwapchainExtension;
+use winit::window::Window;
+
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::device::QueueFamilyIndices;
+use crate::image::{create_image, create_image_view, Image};
+
+/// How presented swapchain images are synchronized with the display.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PresentMode {
+    /// Waits for vertical blanks (i.e., vsync).
+    Fifo,
+    /// Waits for vertical blanks but replaces queued images with newer ones
+    /// (i.e., triple buffering).
+    #[default]
+    Mailbox,
+    /// Presents images immediately, which may cause tearing (i.e., uncapped).
+    Immediate,
+}
+
+impl PresentMode {
+    /// The Vulkan present modes to try for this present mode, in order of
+    /// preference.
+    fn candidates(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            Self::Fifo => &[vk::PresentModeKHR::FIFO],
+            Self::Mailbox => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            Self::Immediate => &[
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO,
+            ],
+        }
+    }
+}
+
+/// A swapchain and the views of its images which are destroyed when dropped.
+///
+/// An offscreen swapchain has no swapchain handle and owns its images instead
+/// (see `create_offscreen_swapchain`). A default swapchain owns no Vulkan
+/// handles.
+#[derive(Debug, Default)]
+pub struct Swapchain {
+    device: Option<Device>,
+    pub swapchain: vk::SwapchainKHR,
+    pub format: vk::Format,
+    pub color_space: vk::ColorSpaceKHR,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+    pub images: Vec<vk::Image>,
+    pub image_views: Vec<vk::ImageView>,
+    /// The images which are rendered to instead of swapchain images when
+    /// rendering offscreen.
+    pub offscreen: Vec<Image>,
+}
+
+impl Swapchain {
+    /// Whether the images are rendered offscreen rather than presented.
+    pub fn is_offscreen(&self) -> bool {
+        self.swapchain.is_null()
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        if let Some(device) = &self.device {
+            unsafe {
+                self.image_views
+                    .iter()
+                    .for_each(|v| device.destroy_image_view(*v, None));
+                device.destroy_swapchain_khr(self.swapchain, None);
+            }
+        }
+    }
+}
+
+pub unsafe fn create_swapchain(
+    window: &Window,
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Image
+
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let support = SwapchainSupport::get(instance, data, data.physical_device)?;
+
+    let surface_format =
+        get_swapchain_surface_format(&support.formats, data.options.srgb, data.options.hdr);
+    let present_mode =
+        get_swapchain_present_mode(&support.present_modes, data.options.present_mode);
+    let extent = get_swapchain_extent(window, support.capabilities);
+
+    // Swapchain images can only be copied from (e.g., for screenshots) if the
+    // surface supports it.
+    let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if support
+        .capabilities
+        .supported_usage_flags
+        .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+    {
+        usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+
+    let mut image_count = support.capabilities.min_image_count + 1;
+    if support.capabilities.max_image_count != 0
+        && image_count > support.capabilities.max_image_count
+    {
+        image_count = support.capabilities.max_image_count;
+    }
+
+    let mut queue_family_indices = vec![];
+    let image_sharing_mode = if indices.graphics != indices.present {
+        queue_family_indices.push(indices.graphics);
+        queue_family_indices.push(indices.present);
+        vk::SharingMode::CONCURRENT
+    } else {
+        vk::SharingMode::EXCLUSIVE
+    };
+
+    // Create
+
+    let info = vk::SwapchainCreateInfoKHR::builder()
+        .surface(data.surface)
+        .min_image_count(image_count)
+        .image_format(surface_format.format)
+        .image_color_space(surface_format.color_space)
+        .image_extent(extent)
+        .image_array_layers(1)
+        .image_usage(usage)
+        .image_sharing_mode(image_sharing_mode)
+        .queue_family_indices(&queue_family_indices)
+        .pre_transform(support.capabilities.current_transform)
+        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        .present_mode(present_mode)
+        .clipped(true)
+        .old_swapchain(vk::SwapchainKHR::null());
+
+    data.swapchain = Swapchain {
+        device: Some(device.clone()),
+        swapchain: device.create_swapchain_khr(&info, None)?,
+        format: surface_format.format,
+        color_space: surface_format.color_space,
+        extent,
+        usage,
+        images: vec![],
+        image_views: vec![],
+        offscreen: vec![],
+    };
+
+    // Images
+
+    data.swapchain.images = device.get_swapchain_images_khr(data.swapchain.swapchain)?;
+
+    Ok(())
+}
+
+/// Creates images of the size of the window (in pixels) which are rendered to
+/// in turn instead of swapchain images, so frames can be rendered without a
+/// window system (e.g., for golden-image tests).
+///
+/// The images go through the same layouts as swapchain images (including the
+/// present layout the render passes leave them in), which is why the swapchain
+/// extension is still required.
+pub unsafe fn create_offscreen_swapchain(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let format = if data.options.srgb {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    };
+
+    let extent = vk::Extent2D::builder()
+        .width(data.options.width)
+        .height(data.options.height)
+        .build();
+
+    let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+
+    // Each frame in flight renders to its own image.
+    let offscreen = (0..MAX_FRAMES_IN_FLIGHT)
+        .map(|_| {
+            create_image(
+                instance,
+                device,
+                data,
+                extent.width,
+                extent.height,
+                1,
+                vk::SampleCountFlags::_1,
+                format,
+                vk::ImageTiling::OPTIMAL,
+                usage,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    data.swapchain = Swapchain {
+        device: Some(device.clone()),
+        swapchain: vk::SwapchainKHR::null(),
+        format,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        extent,
+        usage,
+        images: offscreen.iter().map(|i| i.image).collect(),
+        image_views: vec![],
+        offscreen,
+    };
+
+    Ok(())
+}
+
+pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData) -> Result<()> {
+    data.swapchain.image_views = data
+        .swapchain
+        .images
+        .iter()
+        .map(|i| {
+            create_image_view(
+                device,
+                *i,
+                data.swapchain.format,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(())
+}
+
+/// The HDR surface formats, in order of preference.
+const HDR_SURFACE_FORMATS: &[(vk::Format, vk::ColorSpaceKHR)] = &[
+    (
+        vk::Format::R16G16B16A16_SFLOAT,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    ),
+    (
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+];
+
+fn get_swapchain_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    srgb: bool,
+    hdr: bool,
+) -> vk::SurfaceFormatKHR {
+    if hdr {
+        let format = HDR_SURFACE_FORMATS
+            .iter()
+            .find_map(|(format, color_space)| {
+                formats
+                    .iter()
+                    .cloned()
+                    .find(|f| f.format == *format && f.color_space == *color_space)
+            });
+
+        if let Some(format) = format {
+            info!(
+                "Using HDR surface format ({:?} / {:?}).",
+                format.format, format.color_space
+            );
+            return format;
+        }
+
+        warn!("HDR requested but no HDR surface formats are supported.");
+    }
+
+    // A UNORM format is preferred when sRGB output is disabled so that the
+    // linear colors written by the fragment shader are displayed as is.
+    let preferred = if srgb {
+        vk::Format::B8G8R8A8_SRGB
+    } else {
+        vk::Format::B8G8R8A8_UNORM
+    };
+
+    formats
+        .iter()
+        .cloned()
+        .find(|f| f.format == preferred && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+        .unwrap_or_else(|| formats[0])
+}
+
+/// How the fragment shader has to encode its output for a surface color space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum OutputTransfer {
+    /// SDR output (`SRGB_NONLINEAR`).
+    Sdr = 0,
+    /// Linear scRGB output where 1.0 is 80 nits (`EXTENDED_SRGB_LINEAR`).
+    ScRgb = 1,
+    /// Rec. 2020 primaries with the PQ transfer function (`HDR10_ST2084`).
+    Hdr10 = 2,
+}
+
+impl OutputTransfer {
+    pub fn new(color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Hdr10,
+            _ => Self::Sdr,
+        }
+    }
+}
+
+/// Returns whether a shader which writes to the swapchain images has to encode
+/// its output as sRGB (if sRGB output is enabled but the swapchain format
+/// doesn't encode it on write) and how it encodes HDR output for the color
+/// space of the swapchain.
+pub fn get_output_encoding(data: &AppData) -> (bool, OutputTransfer) {
+    let output_transfer = OutputTransfer::new(data.swapchain.color_space);
+    let encode_srgb = output_transfer == OutputTransfer::Sdr
+        && data.options.srgb
+        && !is_srgb_format(data.swapchain.format);
+    (encode_srgb, output_transfer)
+}
+
+/// Returns how the shaders which draw the scene in the main pass encode their
+/// output (see `get_output_encoding`).
+///
+/// With tone mapping, they write linear colors to the HDR intermediate target
+/// instead, which are encoded by the tone mapping pass (see `ToneMap`).
+pub fn get_scene_encoding(data: &AppData) -> (bool, OutputTransfer) {
+    if data.options.tone_mapping.is_some() {
+        return (false, OutputTransfer::Sdr);
+    }
+
+    get_output_encoding(data)
+}
+
+/// Returns whether the supplied format encodes colors as sRGB when written.
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+fn get_swapchain_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+    requested: PresentMode,
+) -> vk::PresentModeKHR {
+    // FIFO is the last candidate for every present mode and is always supported.
+    let candidates = requested.candidates();
+    let present_mode = candidates
+        .iter()
+        .cloned()
+        .find(|m| present_modes.contains(m))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+
+    if present_mode != candidates[0] {
+        warn!(
+            "Requested present mode ({:?}) not supported, using {:?}.",
+            candidates[0], present_mode
+        );
+    }
+
+    present_mode
+}
+
+fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        capabilities.current_extent
+    } else {
+        let size = window.inner_size();
+        let clamp = |min: u32, max: u32, v: u32| min.max(max.min(v));
+        vk::Extent2D::builder()
+            .width(clamp(
+                capabilities.min_image_extent.width,
+                capabilities.max_image_extent.width,
+                size.width,
+            ))
+            .height(clamp(
+                capabilities.min_image_extent.height,
+                capabilities.max_image_extent.height,
+                size.height,
+            ))
+            .build()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SwapchainSupport {
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+impl SwapchainSupport {
+    pub unsafe fn get(
+        instance: &Instance,
+        data: &AppData,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Self> {
+        Ok(Self {
+            capabilities: instance
+                .get_physical_device_surface_capabilities_khr(physical_device, data.surface)?,
+            formats: instance
+                .get_physical_device_surface_formats_khr(physical_device, data.surface)?,
+            present_modes: instance
+                .get_physical_device_surface_present_modes_khr(physical_device, data.surface)?,
+        })
+    }
+}
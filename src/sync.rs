@@ -0,0 +1,163 @@
+//! The semaphores and fences used to synchronize frames in flight.
+//!
+//! If timeline semaphores are supported, the fences are replaced by a single
+//! timeline semaphore for the graphics queue whose value is incremented by
+//! every frame submitted, so a frame (or the swapchain image it rendered to)
+//! is waited for by waiting for the value its submission signaled.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_2::*;
+
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::handle::Owned;
+
+pub unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()> {
+    let semaphore_info = vk::SemaphoreCreateInfo::builder();
+    let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+
+    // Presentation only supports binary semaphores, so these are needed
+    // either way.
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let semaphore = device.create_semaphore(&semaphore_info, None)?;
+        data.image_available_semaphores
+            .push(Owned::new(device, semaphore));
+        let semaphore = device.create_semaphore(&semaphore_info, None)?;
+        data.render_finished_semaphores
+            .push(Owned::new(device, semaphore));
+    }
+
+    if data.features.timeline_semaphore {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+        data.timeline_semaphore = Owned::new(device, device.create_semaphore(&info, None)?);
+        data.timeline_value = 0;
+        data.frame_timeline_values = vec![0; MAX_FRAMES_IN_FLIGHT];
+        data.image_timeline_values = vec![0; data.swapchain.images.len()];
+    } else {
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let fence = device.create_fence(&fence_info, None)?;
+            data.in_flight_fences.push(Owned::new(device, fence));
+        }
+
+        data.images_in_flight = data
+            .swapchain
+            .images
+            .iter()
+            .map(|_| vk::Fence::null())
+            .collect();
+    }
+
+    Ok(())
+}
+
+/// Waits for the last submission of the frame in flight at the supplied index
+/// to complete.
+pub unsafe fn wait_for_frame(device: &Device, data: &AppData, frame: usize) -> Result<()> {
+    if data.features.timeline_semaphore {
+        wait_for_timeline_value(device, data, data.frame_timeline_values[frame])
+    } else {
+        device.wait_for_fences(&[*data.in_flight_fences[frame]], true, u64::MAX)?;
+        Ok(())
+    }
+}
+
+/// Waits for the last frame rendered to the swapchain image at the supplied
+/// index to complete.
+pub unsafe fn wait_for_image(device: &Device, data: &AppData, image_index: usize) -> Result<()> {
+    if data.features.timeline_semaphore {
+        wait_for_timeline_value(device, data, data.image_timeline_values[image_index])
+    } else if !data.images_in_flight[image_index].is_null() {
+        device.wait_for_fences(&[data.images_in_flight[image_index]], true, u64::MAX)?;
+        Ok(())
+    } else {
+        Ok(())
+    }
+}
+
+unsafe fn wait_for_timeline_value(device: &Device, data: &AppData, value: u64) -> Result<()> {
+    let semaphores = &[*data.timeline_semaphore];
+    let values = &[value];
+    let info = vk::SemaphoreWaitInfo::builder()
+        .semaphores(semaphores)
+        .values(values);
+
+    device.wait_semaphores(&info, u64::MAX)?;
+
+    Ok(())
+}
+
+/// Submits the command buffer for the swapchain image at the supplied index
+/// for the frame in flight at the supplied index.
+pub unsafe fn submit_frame(
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+    image_index: usize,
+) -> Result<()> {
+    let mut wait_semaphores = vec![];
+    let mut wait_stages = vec![];
+    let mut signal_semaphores = vec![];
+    let command_buffers = &[data.command_buffers[image_index]];
+
+    // Offscreen images are neither acquired nor presented, so there are no
+    // binary semaphores to wait for or to signal.
+    if !data.swapchain.is_offscreen() {
+        wait_semaphores.push(*data.image_available_semaphores[frame]);
+        wait_stages.push(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+        signal_semaphores.push(*data.render_finished_semaphores[frame]);
+    }
+
+    // The particles drawn by the frame are updated by the compute queue first.
+    if data.options.particles {
+        wait_semaphores.push(*data.particles.semaphores[frame]);
+        wait_stages.push(vk::PipelineStageFlags::VERTEX_INPUT);
+    }
+
+    // Fences
+
+    if !data.features.timeline_semaphore {
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        data.images_in_flight[image_index] = *data.in_flight_fences[frame];
+        device.reset_fences(&[*data.in_flight_fences[frame]])?;
+        device.queue_submit(
+            data.graphics_queue,
+            &[submit_info],
+            *data.in_flight_fences[frame],
+        )?;
+
+        return Ok(());
+    }
+
+    // Timeline Semaphore
+
+    let value = data.timeline_value + 1;
+
+    // The value of the binary semaphore (if there is one) is ignored.
+    let mut signal_values = vec![0; signal_semaphores.len()];
+    signal_semaphores.push(*data.timeline_semaphore);
+    signal_values.push(value);
+    let mut timeline_info =
+        vk::TimelineSemaphoreSubmitInfo::builder().signal_semaphore_values(&signal_values);
+    let submit_info = vk::SubmitInfo::builder()
+        .wait_semaphores(&wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(command_buffers)
+        .signal_semaphores(&signal_semaphores)
+        .push_next(&mut timeline_info);
+
+    device.queue_submit(data.graphics_queue, &[submit_info], vk::Fence::null())?;
+
+    data.timeline_value = value;
+    data.frame_timeline_values[frame] = value;
+    data.image_timeline_values[image_index] = value;
+
+    Ok(())
+}
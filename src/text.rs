@@ -0,0 +1,402 @@
+//! Bitmap font text, which is laid out as quads of glyphs from a font atlas and
+//! drawn in screen space by the UI renderer (without a UI library).
+//!
+//! Fonts are loaded from AngelCode BMFont files in the text format (`.fnt`),
+//! which describe the metrics of the glyphs and the kerning pairs of a font
+//! and name the PNG files of the pages of its font atlas. These can be created
+//! with BMFont, Hiero, or msdf-bmfont (among others).
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::ui::{UiFrame, UiImage, UiMesh, UiTextureId, UiVertex};
+use crate::vertex::Vec2;
+
+/// The bit which tells the textures of the bitmap fonts apart from the
+/// textures of the other UIs.
+const TEXT_TEXTURES: UiTextureId = 1 << 62;
+
+/// A glyph in the font atlas of a bitmap font.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Glyph {
+    /// The position and size of the glyph in the page (in pixels).
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The offset of the glyph from the pen position (in pixels).
+    pub offset: Vec2,
+    /// The distance the pen position is advanced after the glyph (in pixels).
+    pub advance: f32,
+    pub page: u32,
+}
+
+/// The metrics of a bitmap font.
+#[derive(Clone, Debug, Default)]
+pub struct BitmapFont {
+    /// The distance between lines of text (in pixels).
+    pub line_height: f32,
+    /// The size of the pages of the font atlas (in pixels).
+    pub width: u32,
+    pub height: u32,
+    /// The file names of the pages of the font atlas.
+    pub pages: Vec<String>,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+impl BitmapFont {
+    /// Returns a font without any glyphs whose font atlas is a single page of
+    /// the supplied size (e.g., for a generated font atlas).
+    pub fn new(line_height: f32, width: u32, height: u32) -> Self {
+        Self {
+            line_height,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_glyph(&mut self, char: char, glyph: Glyph) {
+        self.glyphs.insert(char, glyph);
+    }
+
+    /// Adds the adjustment of the distance between two characters (in pixels)
+    /// when the second follows the first.
+    pub fn add_kerning(&mut self, first: char, second: char, amount: f32) {
+        self.kerning.insert((first, second), amount);
+    }
+
+    /// Parses the metrics of a font in the BMFont text format.
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut font = Self::default();
+
+        for line in source.lines() {
+            let (tag, a) = parse_line(line);
+
+            match tag {
+                "common" => {
+                    font.line_height = a.get("lineHeight")?;
+                    font.width = a.get("scaleW")?;
+                    font.height = a.get("scaleH")?;
+                }
+                "page" => {
+                    let id = a.get::<usize>("id")?;
+                    font.pages
+                        .resize(font.pages.len().max(id + 1), String::new());
+                    font.pages[id] = a.get("file")?;
+                }
+                "char" => {
+                    let Some(char) = get_char(a.get("id")?) else {
+                        continue;
+                    };
+
+                    let glyph = Glyph {
+                        x: a.get("x")?,
+                        y: a.get("y")?,
+                        width: a.get("width")?,
+                        height: a.get("height")?,
+                        offset: Vec2::new(a.get("xoffset")?, a.get("yoffset")?),
+                        advance: a.get("xadvance")?,
+                        page: a.get("page")?,
+                    };
+
+                    font.glyphs.insert(char, glyph);
+                }
+                "kerning" => {
+                    if let (Some(first), Some(second)) =
+                        (get_char(a.get("first")?), get_char(a.get("second")?))
+                    {
+                        font.kerning.insert((first, second), a.get("amount")?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if font.width == 0 || font.height == 0 {
+            return Err(anyhow!("Font is missing its `common` line."));
+        }
+
+        Ok(font)
+    }
+
+    /// Returns the glyph for a character, or the glyph for `?` if the font
+    /// doesn't have one.
+    pub fn glyph(&self, char: char) -> Option<&Glyph> {
+        self.glyphs.get(&char).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    /// Returns the width of the widest line of a string (in pixels at a scale
+    /// of 1).
+    pub fn measure(&self, text: &str) -> f32 {
+        let mut width = 0.0f32;
+        self.layout(text, |_, _, next| width = width.max(next.x));
+        width
+    }
+
+    /// Calls a function with every glyph of a string, the pen position of the
+    /// glyph (in pixels at a scale of 1, from the top left corner of the
+    /// string), and the pen position after it.
+    pub fn layout(&self, text: &str, mut f: impl FnMut(&Glyph, Vec2, Vec2)) {
+        let mut pen = Vec2::new(0.0, 0.0);
+        let mut previous = None;
+
+        for char in text.chars() {
+            if char == '\n' {
+                pen = Vec2::new(0.0, pen.y + self.line_height);
+                previous = None;
+                continue;
+            }
+
+            let Some(glyph) = self.glyph(char) else {
+                continue;
+            };
+
+            if let Some(kerning) = previous.and_then(|p| self.kerning.get(&(p, char))) {
+                pen.x += kerning;
+            }
+
+            let next = Vec2::new(pen.x + glyph.advance, pen.y);
+            f(glyph, pen, next);
+            pen = next;
+            previous = Some(char);
+        }
+    }
+}
+
+/// A bitmap font and the text drawn with it over the next frame.
+#[derive(Debug, Default)]
+pub struct Text {
+    font: Option<BitmapFont>,
+    /// The pixels of the pages of the font atlas, until they have been drawn
+    /// with.
+    pages: Vec<UiImage>,
+    /// The glyph quads drawn over the next frame, with a mesh per page.
+    meshes: Vec<UiMesh>,
+}
+
+impl Text {
+    /// Loads a bitmap font from a BMFont file (and the pages of its font atlas,
+    /// which are relative to it).
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+        let font = BitmapFont::parse(&source)?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let pages = font
+            .pages
+            .iter()
+            .map(|p| load_page(&directory.join(p)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let meshes = (0..pages.len())
+            .map(|i| UiMesh {
+                texture: TEXT_TEXTURES | i as UiTextureId,
+                clip_rect: [0.0, 0.0, f32::MAX, f32::MAX],
+                ..Default::default()
+            })
+            .collect();
+
+        Ok(Self {
+            font: Some(font),
+            pages,
+            meshes,
+        })
+    }
+
+    pub fn font(&self) -> Option<&BitmapFont> {
+        self.font.as_ref()
+    }
+
+    /// Draws a string over the next frame with its top left corner at the
+    /// supplied position (in pixels), the supplied scale, and the supplied
+    /// color (in sRGB with premultiplied alpha).
+    ///
+    /// Nothing is drawn if there isn't a font.
+    pub fn draw(&mut self, text: &str, position: [f32; 2], scale: f32, color: [u8; 4]) {
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        let origin = Vec2::new(position[0], position[1]);
+        let atlas = Vec2::new(font.width as f32, font.height as f32);
+
+        font.layout(text, |glyph, pen, _| {
+            let Some(mesh) = self.meshes.get_mut(glyph.page as usize) else {
+                return;
+            };
+
+            let size = Vec2::new(glyph.width as f32, glyph.height as f32);
+            if size.x == 0.0 || size.y == 0.0 {
+                return;
+            }
+
+            let min = origin + (pen + glyph.offset) * scale;
+            let max = min + size * scale;
+            let uv_min = Vec2::new(glyph.x as f32, glyph.y as f32).component_div(&atlas);
+            let uv_max = uv_min + size.component_div(&atlas);
+
+            let first = mesh.vertices.len() as u32;
+            mesh.vertices.extend(
+                [
+                    (min, uv_min),
+                    (Vec2::new(max.x, min.y), Vec2::new(uv_max.x, uv_min.y)),
+                    (max, uv_max),
+                    (Vec2::new(min.x, max.y), Vec2::new(uv_min.x, uv_max.y)),
+                ]
+                .map(|(position, uv)| UiVertex {
+                    position,
+                    uv,
+                    color,
+                }),
+            );
+            mesh.indices.extend([0, 1, 2, 2, 3, 0].map(|i| first + i));
+        });
+    }
+
+    /// Returns the text drawn since the last frame, which is then cleared.
+    pub fn end_frame(&mut self) -> UiFrame {
+        let meshes = self
+            .meshes
+            .iter_mut()
+            .map(|m| UiMesh {
+                vertices: std::mem::take(&mut m.vertices),
+                indices: std::mem::take(&mut m.indices),
+                ..m.clone()
+            })
+            .filter(|m| !m.indices.is_empty())
+            .collect();
+
+        let textures = self
+            .pages
+            .drain(..)
+            .enumerate()
+            .map(|(i, p)| (TEXT_TEXTURES | i as UiTextureId, p))
+            .collect();
+
+        UiFrame {
+            meshes,
+            textures,
+            freed: vec![],
+        }
+    }
+}
+
+/// The attributes of a line of a BMFont file.
+struct Attributes<'a>(Vec<(&'a str, &'a str)>);
+
+impl Attributes<'_> {
+    /// Returns the parsed value of the attribute with the supplied key.
+    fn get<T: FromStr>(&self, key: &str) -> Result<T> {
+        let (_, value) = self
+            .0
+            .iter()
+            .find(|(k, _)| *k == key)
+            .ok_or_else(|| anyhow!("Font is missing `{}`.", key))?;
+        value
+            .parse()
+            .map_err(|_| anyhow!("Font has an invalid `{}` (`{}`).", key, value))
+    }
+}
+
+/// Splits a line of a BMFont file into its tag and its attributes (whose
+/// values may be quoted).
+fn parse_line(line: &str) -> (&str, Attributes<'_>) {
+    let line = line.trim();
+    let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+
+    let mut attributes = vec![];
+    while let Some((key, value)) = rest.trim_start().split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(' ').unwrap_or((value, "")),
+        };
+
+        attributes.push((key.trim(), value));
+        rest = next;
+    }
+
+    (tag, Attributes(attributes))
+}
+
+/// Returns the character for a character ID (which is `-1` for the glyph of
+/// invalid characters in some fonts).
+fn get_char(id: i64) -> Option<char> {
+    u32::try_from(id).ok().and_then(char::from_u32)
+}
+
+/// Loads a page of a font atlas from an 8-bit RGBA PNG file (with straight
+/// alpha) or an 8-bit grayscale PNG file (with the coverage of white glyphs).
+fn load_page(path: &Path) -> Result<UiImage> {
+    let page =
+        File::open(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+
+    let decoder = png::Decoder::new(page);
+    let mut reader = decoder.read_info()?;
+
+    if reader.info().bit_depth != png::BitDepth::Eight {
+        return Err(anyhow!("Font atlas page must be 8-bit."));
+    }
+
+    let mut bytes = vec![0; reader.info().raw_bytes()];
+    reader.next_frame(&mut bytes)?;
+
+    let pixels = match reader.info().color_type {
+        png::ColorType::Rgba => bytes
+            .chunks_exact(4)
+            .map(|p| {
+                let multiply = |c: u8| ((c as u32 * p[3] as u32 + 127) / 255) as u8;
+                [multiply(p[0]), multiply(p[1]), multiply(p[2]), p[3]]
+            })
+            .collect(),
+        png::ColorType::Grayscale => bytes.iter().map(|c| [*c; 4]).collect(),
+        _ => return Err(anyhow!("Font atlas page must be RGBA or grayscale.")),
+    };
+
+    let (width, height) = reader.info().size();
+    Ok(UiImage {
+        position: None,
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &str = r#"info face="Test Sans" size=16 bold=0 italic=0
+common lineHeight=20 base=16 scaleW=128 scaleH=64 pages=1 packed=0
+page id=0 file="test_0.png"
+chars count=3
+char id=65   x=0     y=0     width=10    height=12    xoffset=1     yoffset=2     xadvance=11    page=0  chnl=15
+char id=86   x=10    y=0     width=10    height=12    xoffset=0     yoffset=2     xadvance=10    page=0  chnl=15
+char id=-1   x=20    y=0     width=4     height=4     xoffset=0     yoffset=0     xadvance=4     page=0  chnl=15
+kernings count=1
+kerning first=65  second=86  amount=-2
+"#;
+
+    #[test]
+    fn bmfont() {
+        let font = BitmapFont::parse(FONT).unwrap();
+
+        assert_eq!(font.line_height, 20.0);
+        assert_eq!((font.width, font.height), (128, 64));
+        assert_eq!(font.pages, ["test_0.png"]);
+        assert_eq!(font.glyphs.len(), 2);
+        assert_eq!(font.glyph('A').unwrap().offset, Vec2::new(1.0, 2.0));
+        assert!(font.glyph('B').is_none());
+
+        assert_eq!(font.measure("AV"), 11.0 - 2.0 + 10.0);
+        assert_eq!(font.measure("AV\nA"), 19.0);
+
+        assert!(BitmapFont::parse("info face=\"Test Sans\"").is_err());
+    }
+}
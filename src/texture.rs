@@ -0,0 +1,532 @@
+//! Texture loading (from PNG files or texture containers), mipmap generation
+//! and sampling.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::container::{is_container_path, TextureContainer, TranscodeTarget};
+use crate::handle::Owned;
+use crate::image::{
+    cmd_transition_image_layers, cmd_transition_image_layout, create_image, create_image_view,
+    create_layered_image, create_layered_image_view, Image,
+};
+use crate::upload::{
+    get_graphics_upload_command_buffer, get_upload_command_buffer, upload_levels_to_image,
+    upload_to_image,
+};
+
+/// A sampled image with a chain of mipmaps.
+#[derive(Debug)]
+pub struct Texture {
+    pub name: String,
+    pub image: Image,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+}
+
+/// Creates a texture from a PNG file or a texture container (a KTX2, DDS, or
+/// Basis Universal file), uploading it with the pending uploads.
+pub unsafe fn create_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Texture> {
+    if is_container_path(path) {
+        return create_container_texture(instance, device, data, path);
+    }
+
+    let (pixels, width, height) = load_png(path)?;
+
+    create_texture_from_pixels(
+        instance,
+        device,
+        data,
+        get_texture_name(path),
+        &pixels,
+        width,
+        height,
+        vk::Format::R8G8B8A8_SRGB,
+    )
+}
+
+/// Creates a texture which doesn't store colors (e.g., a height map) from a
+/// PNG file or a texture container, uploading it with the pending uploads.
+///
+/// Unlike `create_texture`, PNG files are not sRGB encoded.
+pub unsafe fn create_data_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Texture> {
+    if is_container_path(path) {
+        return create_container_texture(instance, device, data, path);
+    }
+
+    let (pixels, width, height) = load_png(path)?;
+
+    create_texture_from_pixels(
+        instance,
+        device,
+        data,
+        get_texture_name(path),
+        &pixels,
+        width,
+        height,
+        vk::Format::R8G8B8A8_UNORM,
+    )
+}
+
+/// Loads the pixels, width, and height of an 8-bit RGBA PNG file.
+fn load_png(path: &Path) -> Result<(Vec<u8>, u32, u32)> {
+    let image =
+        File::open(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+
+    let decoder = png::Decoder::new(image);
+    let mut reader = decoder.read_info()?;
+
+    if reader.info().color_type != png::ColorType::Rgba
+        || reader.info().bit_depth != png::BitDepth::Eight
+    {
+        return Err(anyhow!("Texture image must be 8-bit RGBA."));
+    }
+
+    let mut pixels = vec![0; reader.info().raw_bytes()];
+    reader.next_frame(&mut pixels)?;
+
+    let (width, height) = reader.info().size();
+
+    Ok((pixels, width, height))
+}
+
+/// Creates a texture from 8-bit RGBA pixels (in a format with four 8-bit
+/// components), uploading it with the pending uploads and generating its mip
+/// levels.
+pub unsafe fn create_texture_from_pixels(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    name: String,
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    format: vk::Format,
+) -> Result<Texture> {
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+
+    // Create
+
+    let mut texture_image = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Transition + Upload
+
+    let image = texture_image.image;
+
+    cmd_transition_image_layout(
+        device,
+        get_upload_command_buffer(device, data)?,
+        image,
+        format,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+    )?;
+
+    upload_to_image(
+        instance, device, data, pixels, image, width, height, mip_levels,
+    )?;
+
+    // Mipmaps
+
+    // Blits are only supported by graphics queues.
+    let command_buffer = get_graphics_upload_command_buffer(device, data)?;
+    cmd_generate_mipmaps(
+        instance,
+        device,
+        data,
+        command_buffer,
+        image,
+        format,
+        width,
+        height,
+        mip_levels,
+        1,
+    )?;
+
+    // View
+
+    texture_image.view = create_image_view(
+        device,
+        image,
+        format,
+        vk::ImageAspectFlags::COLOR,
+        mip_levels,
+    )?;
+
+    Ok(Texture {
+        name,
+        image: texture_image,
+        mip_levels,
+        array_layers: 1,
+    })
+}
+
+/// Creates a texture from a texture container with the mip levels, array
+/// layers, and format stored in the file (or transcoded from it).
+unsafe fn create_container_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Texture> {
+    // Load
+
+    let target = get_transcode_target(instance, data);
+    let container = TextureContainer::load(path, target)?;
+    let TextureContainer {
+        format,
+        width,
+        height,
+        array_layers,
+        cube,
+        ..
+    } = container;
+    let mip_levels = container.levels.len() as u32;
+
+    // Support
+
+    if !instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    {
+        return Err(anyhow!(
+            "Texture format {:?} of `{}` is not supported.",
+            format,
+            path.display()
+        ));
+    }
+
+    // Create
+
+    let (flags, view_type) = match (cube, container.layers()) {
+        (true, 1) => (
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            vk::ImageViewType::CUBE,
+        ),
+        (true, _) => (
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            vk::ImageViewType::CUBE_ARRAY,
+        ),
+        (false, 1) => (vk::ImageCreateFlags::empty(), vk::ImageViewType::_2D),
+        (false, _) => (vk::ImageCreateFlags::empty(), vk::ImageViewType::_2D_ARRAY),
+    };
+
+    let mut texture_image = create_layered_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        array_layers,
+        flags,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Transition + Upload
+
+    let image = texture_image.image;
+
+    cmd_transition_image_layers(
+        device,
+        get_upload_command_buffer(device, data)?,
+        image,
+        format,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+        array_layers,
+    )?;
+
+    upload_levels_to_image(
+        instance,
+        device,
+        data,
+        &container.levels,
+        image,
+        width,
+        height,
+        array_layers,
+    )?;
+
+    cmd_transition_image_layers(
+        device,
+        get_graphics_upload_command_buffer(device, data)?,
+        image,
+        format,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        mip_levels,
+        array_layers,
+    )?;
+
+    // View
+
+    texture_image.view = create_layered_image_view(
+        device,
+        image,
+        view_type,
+        format,
+        vk::ImageAspectFlags::COLOR,
+        mip_levels,
+        array_layers,
+    )?;
+
+    Ok(Texture {
+        name: get_texture_name(path),
+        image: texture_image,
+        mip_levels,
+        array_layers,
+    })
+}
+
+/// Returns the best format supported by the physical device which Basis
+/// Universal textures can be transcoded to.
+unsafe fn get_transcode_target(instance: &Instance, data: &AppData) -> TranscodeTarget {
+    let features = data.features;
+    TranscodeTarget::ALL
+        .into_iter()
+        .find(|t| {
+            let enabled = match t {
+                TranscodeTarget::Bc7 => features.texture_compression_bc,
+                TranscodeTarget::Astc4x4 => features.texture_compression_astc_ldr,
+                TranscodeTarget::Etc2 => features.texture_compression_etc2,
+                TranscodeTarget::Rgba8 => true,
+            };
+
+            let required = vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+            enabled
+                && instance
+                    .get_physical_device_format_properties(data.physical_device, t.format(true))
+                    .optimal_tiling_features
+                    .contains(required)
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the name of a texture loaded from a file (for debugging).
+fn get_texture_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(|| "Texture".into(), |s| s.to_string_lossy().into_owned())
+}
+
+/// Records the generation of the mip levels of every array layer of an image
+/// from its first mip level, after which the image can be sampled by fragment
+/// and compute shaders.
+///
+/// Every mip level has to be in the `TRANSFER_DST_OPTIMAL` layout.
+pub unsafe fn cmd_generate_mipmaps(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    array_layers: u32,
+) -> Result<()> {
+    // Support
+
+    if !instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+    {
+        return Err(anyhow!(
+            "Texture image format does not support linear blitting!"
+        ));
+    }
+
+    // Mipmaps
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_array_layer(0)
+        .layer_count(array_layers)
+        .level_count(1);
+
+    let mut barrier = vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(subresource);
+
+    let mut mip_width = width;
+    let mut mip_height = height;
+
+    for i in 1..mip_levels {
+        // Transition the previous level so it can be blitted from.
+
+        barrier.subresource_range.base_mip_level = i - 1;
+        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        // Blit the previous level into the current level at half the size.
+
+        let src_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(i - 1)
+            .base_array_layer(0)
+            .layer_count(array_layers);
+
+        let dst_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(i)
+            .base_array_layer(0)
+            .layer_count(array_layers);
+
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: mip_width as i32,
+                    y: mip_height as i32,
+                    z: 1,
+                },
+            ])
+            .src_subresource(src_subresource)
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: (if mip_width > 1 { mip_width / 2 } else { 1 }) as i32,
+                    y: (if mip_height > 1 { mip_height / 2 } else { 1 }) as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(dst_subresource);
+
+        device.cmd_blit_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        // Transition the previous level so it can be sampled from.
+
+        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
+        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        if mip_width > 1 {
+            mip_width /= 2;
+        }
+
+        if mip_height > 1 {
+            mip_height /= 2;
+        }
+    }
+
+    // The last level was never blitted from, so it is still a transfer destination.
+
+    barrier.subresource_range.base_mip_level = mip_levels - 1;
+    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    Ok(())
+}
+
+pub unsafe fn create_texture_sampler(
+    device: &Device,
+    data: &mut AppData,
+    filter: vk::Filter,
+    address_mode: vk::SamplerAddressMode,
+) -> Result<()> {
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .anisotropy_enable(data.features.sampler_anisotropy)
+        .max_anisotropy(16.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .mip_lod_bias(0.0)
+        .min_lod(0.0)
+        // The sampler is shared by every texture, so the level of detail
+        // isn't clamped to the mipmaps of any one of them.
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    data.texture_sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! Tone mapping, which draws the scene into an HDR intermediate target in the
+//! main pass and then maps it to the swapchain image with a tone mapping
+//! operator and an exposure as the first effect of the post-processing stack
+//! (see `PostProcessStack`).
+//!
+//! Without tone mapping, the main pass renders to the swapchain image directly
+//! and the shaders which draw the scene encode their output themselves (see
+//! `get_scene_encoding`). With tone mapping, they write linear colors and the
+//! tone mapping pass encodes them instead.
+//!
+//! The UI is drawn after the post-processing stack, so it is unaffected by the
+//! exposure.
+
+use std::mem::size_of;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::image::{create_image, create_image_view, Image};
+use crate::post_process::{EffectPipeline, PostProcess, Stage};
+use crate::shaders;
+use crate::swapchain::get_output_encoding;
+
+/// The format of the HDR intermediate target.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The operators the HDR colors of the scene can be tone mapped with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMapping {
+    /// `x / (1 + x)` per channel, which desaturates bright colors.
+    Reinhard,
+    /// A fit of the ACES filmic curve (by Krzysztof Narkowicz).
+    Aces,
+    /// The filmic curve of Uncharted 2 (by John Hable).
+    Uncharted2,
+}
+
+impl ToneMapping {
+    /// Returns the operator after this one (wrapping around).
+    pub fn next(self) -> Self {
+        match self {
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::Uncharted2,
+            Self::Uncharted2 => Self::Reinhard,
+        }
+    }
+}
+
+/// The HDR intermediate target the scene is drawn into (see `PostProcessStack`
+/// for the passes which read it).
+///
+/// The intermediate target is the size of the swapchain images, so it is
+/// recreated with the swapchain.
+#[derive(Debug, Default)]
+pub struct ToneMap {
+    /// The HDR intermediate target the main pass renders to (or resolves to).
+    pub image: Image,
+}
+
+/// Creates the HDR intermediate target.
+///
+/// This does nothing unless tone mapping is enabled in the options.
+pub unsafe fn create_tone_map(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if data.options.tone_mapping.is_none() {
+        return Ok(());
+    }
+
+    let extent = data.swapchain.extent;
+
+    let mut image = create_image(
+        instance,
+        device,
+        data,
+        extent.width,
+        extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        HDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image.view = create_image_view(
+        device,
+        image.image,
+        HDR_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    data.tone_map.image = image;
+
+    Ok(())
+}
+
+/// The effect which tone maps the scene with the operator and exposure in the
+/// options (and encodes it for the swapchain).
+#[derive(Debug, Default)]
+pub struct ToneMapEffect {
+    pipeline: EffectPipeline,
+}
+
+impl PostProcess for ToneMapEffect {
+    fn name(&self) -> &'static str {
+        "Tone Mapping"
+    }
+
+    /// The tone mapped colors are encoded for the swapchain, so any further
+    /// effects write the swapchain format too.
+    fn output_format(&self, data: &AppData, _input: vk::Format) -> vk::Format {
+        data.swapchain.format
+    }
+
+    unsafe fn create(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        stage: &Stage,
+    ) -> Result<()> {
+        let (encode_srgb, output_transfer) = get_output_encoding(data);
+
+        let specialization_data = [encode_srgb as vk::Bool32, output_transfer as u32]
+            .map(u32::to_ne_bytes)
+            .concat();
+        let map_entries = &[
+            vk::SpecializationMapEntry::builder()
+                .constant_id(0)
+                .offset(0)
+                .size(size_of::<vk::Bool32>()),
+            vk::SpecializationMapEntry::builder()
+                .constant_id(1)
+                .offset(4)
+                .size(size_of::<u32>()),
+        ];
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(map_entries)
+            .data(&specialization_data);
+
+        // The operator (see `ToneMapping`) and the exposure multiplier.
+        self.pipeline = EffectPipeline::create(
+            device,
+            data,
+            stage,
+            &[stage.input],
+            shaders::TONEMAP_FRAG,
+            size_of::<u32>() + size_of::<f32>(),
+            Some(&specialization_info),
+        )?;
+
+        Ok(())
+    }
+
+    unsafe fn cmd_apply(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+        let Some(tone_mapping) = data.options.tone_mapping else {
+            return;
+        };
+
+        // The exposure is in stops (EV).
+        let push_constants = [
+            (tone_mapping as u32).to_ne_bytes(),
+            data.options.exposure.exp2().to_ne_bytes(),
+        ]
+        .concat();
+        self.pipeline
+            .cmd_draw(device, command_buffer, &push_constants);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next() {
+        let mut tone_mapping = ToneMapping::Reinhard;
+        for expected in [
+            ToneMapping::Aces,
+            ToneMapping::Uncharted2,
+            ToneMapping::Reinhard,
+        ] {
+            tone_mapping = tone_mapping.next();
+            assert_eq!(tone_mapping, expected);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Transcoding of Basis Universal textures (through the Basis Universal
+//! transcoder) to the GPU-compressed format supported by the physical device
+//! (see `TranscodeTarget`), and inflating of Zstandard supercompressed KTX2
+//! textures.
+
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use basis_universal::{
+    BasisTextureType, DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc,
+    TranscodeParameters, Transcoder, TranscoderBlockFormat, TranscoderTextureFormat,
+};
+
+use crate::container::{get_image_size, TextureContainer, TranscodeTarget};
+
+/// The number of bytes in each block of 4x4 pixels of a UASTC texture.
+const UASTC_BLOCK_SIZE: usize = 16;
+
+/// Inflates a Zstandard supercompressed mip level of a KTX2 texture.
+pub fn inflate_zstd(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = ruzstd::StreamingDecoder::new(bytes)
+        .map_err(|e| anyhow!("Failed to inflate texture: {}", e))?;
+
+    let mut inflated = vec![];
+    decoder.read_to_end(&mut inflated)?;
+
+    Ok(inflated)
+}
+
+/// Transcodes the mip levels of a UASTC texture (from a KTX2 file) in place.
+pub fn transcode_uastc(
+    container: &mut TextureContainer,
+    srgb: bool,
+    target: TranscodeTarget,
+) -> Result<()> {
+    let transcoder = LowLevelUastcTranscoder::new();
+    let block_format = match target {
+        TranscodeTarget::Bc7 => TranscoderBlockFormat::BC7,
+        TranscodeTarget::Astc4x4 => TranscoderBlockFormat::ASTC_4x4,
+        TranscodeTarget::Etc2 => TranscoderBlockFormat::ETC2_RGBA,
+        TranscodeTarget::Rgba8 => TranscoderBlockFormat::RGBA32,
+    };
+
+    let format = target.format(srgb);
+    let layers = container.array_layers as usize;
+
+    for (i, level) in container.levels.iter_mut().enumerate() {
+        let width = (container.width >> i).max(1);
+        let height = (container.height >> i).max(1);
+        let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+
+        // Each mip level has a slice of blocks for each array layer.
+        let slice_size = (blocks_x * blocks_y) as usize * UASTC_BLOCK_SIZE;
+        if level.len() != slice_size * layers {
+            return Err(anyhow!("Invalid size of mip level {}.", i));
+        }
+
+        let image_size = get_image_size(format, width, height)?;
+        let mut transcoded = Vec::with_capacity(image_size * layers);
+        for slice in level.chunks_exact(slice_size) {
+            let parameters = SliceParametersUastc {
+                num_blocks_x: blocks_x,
+                num_blocks_y: blocks_y,
+                has_alpha: true,
+                original_width: width,
+                original_height: height,
+            };
+
+            let pixels = transcoder
+                .transcode_slice(slice, parameters, DecodeFlags::HIGH_QUALITY, block_format)
+                .map_err(|e| anyhow!("Failed to transcode mip level {}: {:?}", i, e))?;
+            if pixels.len() != image_size {
+                return Err(anyhow!("Unexpected size of transcoded mip level {}.", i));
+            }
+
+            transcoded.extend_from_slice(&pixels);
+        }
+
+        *level = transcoded;
+    }
+
+    container.format = format;
+
+    Ok(())
+}
+
+/// Transcodes a Basis Universal file (`.basis`), whose images are the array
+/// layers (or cube map faces) of a texture.
+///
+/// The textures are assumed to be sRGB since the files can't say otherwise.
+pub fn transcode_basis(bytes: &[u8], target: TranscodeTarget) -> Result<TextureContainer> {
+    basis_universal::transcoder_init();
+
+    let mut transcoder = Transcoder::new();
+    if !transcoder.validate_header(bytes) {
+        return Err(anyhow!("Invalid Basis Universal file."));
+    }
+
+    let texture_type = transcoder.basis_texture_type(bytes);
+    let cube = match texture_type {
+        BasisTextureType::TextureType2D | BasisTextureType::TextureType2DArray => false,
+        BasisTextureType::TextureTypeCubemapArray => true,
+        _ => return Err(anyhow!("Unsupported texture type ({:?}).", texture_type)),
+    };
+
+    let images = transcoder.image_count(bytes);
+    let level_count = transcoder.image_level_count(bytes, 0);
+    let description = transcoder
+        .image_level_description(bytes, 0, 0)
+        .ok_or_else(|| anyhow!("Basis Universal file has no images."))?;
+
+    let texture_format = match target {
+        TranscodeTarget::Bc7 => TranscoderTextureFormat::BC7_RGBA,
+        TranscodeTarget::Astc4x4 => TranscoderTextureFormat::ASTC_4x4_RGBA,
+        TranscodeTarget::Etc2 => TranscoderTextureFormat::ETC2_RGBA,
+        TranscodeTarget::Rgba8 => TranscoderTextureFormat::RGBA32,
+    };
+
+    // Transcode
+
+    transcoder
+        .prepare_transcoding(bytes)
+        .map_err(|_| anyhow!("Failed to prepare Basis Universal file for transcoding."))?;
+
+    // The mip levels have the pixels of every image one after another.
+    let levels = (0..level_count)
+        .map(|level| {
+            let mut pixels = vec![];
+            for image in 0..images {
+                let parameters = TranscodeParameters {
+                    image_index: image,
+                    level_index: level,
+                    ..Default::default()
+                };
+
+                let image = transcoder
+                    .transcode_image_level(bytes, texture_format, parameters)
+                    .map_err(|e| anyhow!("Failed to transcode mip level {}: {:?}", level, e))?;
+                pixels.extend_from_slice(&image);
+            }
+
+            Ok(pixels)
+        })
+        .collect::<Result<Vec<_>>>();
+
+    transcoder.end_transcoding();
+
+    Ok(TextureContainer {
+        format: target.format(true),
+        width: description.original_width,
+        height: description.original_height,
+        array_layers: images,
+        cube,
+        levels: levels?,
+    })
+}
@@ -0,0 +1,988 @@
+//! A renderer for immediate mode UIs (e.g., egui or Dear ImGui), which draws
+//! the meshes a UI produces for a frame over the rendered frame in a final UI
+//! pass.
+//!
+//! The vertices and indices of the meshes are copied to host-visible buffers
+//! for the swapchain image (which grow as needed). The textures of a UI are
+//! kept until the UI frees them, along with copies of their pixels so they can
+//! be partially updated (by recreating them) and recreated with the logical
+//! device.
+
+use std::collections::HashMap;
+use std::mem::{self, size_of};
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_2::*;
+use vulkanalia::vk::DeviceV1_3;
+use vulkanalia::vk::KhrDynamicRenderingExtension;
+
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
+use crate::buffers::{create_buffer, Buffer};
+use crate::debug::{begin_command_label, end_command_label, UI_LABEL_COLOR};
+use crate::device::RenderingPath;
+use crate::handle::Owned;
+use crate::image::{cmd_transition_image_layout, create_image, create_image_view, Image};
+use crate::pipeline::create_shader_module;
+use crate::render_graph::{Access, CompiledGraph, PassId, RenderGraph, State};
+use crate::shaders;
+use crate::swapchain::{is_srgb_format, OutputTransfer};
+use crate::upload::{
+    get_graphics_upload_command_buffer, get_upload_command_buffer, upload_to_image,
+};
+use crate::vertex::Vec2;
+
+/// The largest number of textures the UI can have at once.
+const MAX_UI_TEXTURES: u32 = 256;
+
+/// The smallest number of vertices or indices the buffers are created for.
+const MIN_UI_BUFFER_LEN: usize = 4096;
+
+/// The format of the textures of the UI.
+const UI_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Identifies a texture of the UI.
+pub type UiTextureId = u64;
+
+/// A vertex of the UI, which is laid out like the vertices of egui and Dear
+/// ImGui.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UiVertex {
+    /// The position in pixels from the top left corner of the screen.
+    pub position: Vec2,
+    pub uv: Vec2,
+    /// The color in sRGB with premultiplied alpha.
+    pub color: [u8; 4],
+}
+
+impl UiVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<UiVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(0)
+            .build();
+        let uv = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(size_of::<Vec2>() as u32)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .offset((size_of::<Vec2>() + size_of::<Vec2>()) as u32)
+            .build();
+        [position, uv, color]
+    }
+}
+
+/// Triangles of the UI which are drawn with a texture and clipped to a
+/// rectangle.
+#[derive(Clone, Debug, Default)]
+pub struct UiMesh {
+    pub vertices: Vec<UiVertex>,
+    pub indices: Vec<u32>,
+    pub texture: UiTextureId,
+    /// The rectangle the triangles are clipped to (the minimum and maximum
+    /// corners in pixels).
+    pub clip_rect: [f32; 4],
+}
+
+/// The pixels of a texture of the UI, or of a region of one.
+#[derive(Clone, Debug, Default)]
+pub struct UiImage {
+    /// The position of the region of the texture, or `None` if these are the
+    /// pixels of the whole texture.
+    pub position: Option<[u32; 2]>,
+    pub width: u32,
+    pub height: u32,
+    /// The pixels in sRGB with premultiplied alpha, row by row.
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl UiImage {
+    /// Copies the pixels of a region (clipped to this image) into this image.
+    pub fn blit(&mut self, region: &UiImage) {
+        let [x, y] = region.position.unwrap_or_default();
+        let width = region.width.min(self.width.saturating_sub(x)) as usize;
+        for row in 0..region.height.min(self.height.saturating_sub(y)) {
+            let source = (row * region.width) as usize;
+            let destination = ((y + row) * self.width + x) as usize;
+            self.pixels[destination..destination + width]
+                .copy_from_slice(&region.pixels[source..source + width]);
+        }
+    }
+}
+
+/// What the UI draws over a frame.
+#[derive(Clone, Debug, Default)]
+pub struct UiFrame {
+    pub meshes: Vec<UiMesh>,
+    /// The textures which are created or updated before the meshes are drawn.
+    pub textures: Vec<(UiTextureId, UiImage)>,
+    /// The textures which are freed once the meshes have been drawn.
+    pub freed: Vec<UiTextureId>,
+}
+
+impl UiFrame {
+    /// Adds what another UI draws to this frame, over what is already drawn
+    /// (the UIs must use different texture IDs).
+    pub fn append(&mut self, mut other: UiFrame) {
+        self.meshes.append(&mut other.meshes);
+        self.textures.append(&mut other.textures);
+        self.freed.append(&mut other.freed);
+    }
+}
+
+/// A texture of the UI and the descriptor set it is sampled with.
+#[derive(Debug)]
+struct UiTexture {
+    image: Image,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// A draw of a mesh of the UI.
+#[derive(Copy, Clone, Debug)]
+struct UiDraw {
+    descriptor_set: vk::DescriptorSet,
+    scissor: vk::Rect2D,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+}
+
+/// The vertices and indices of the UI drawn over a swapchain image.
+#[derive(Debug, Default)]
+struct UiBuffers {
+    vertex_buffer: Buffer,
+    vertex_capacity: usize,
+    index_buffer: Buffer,
+    index_capacity: usize,
+    draws: Vec<UiDraw>,
+    /// The size of the screen in pixels.
+    screen_size: Vec2,
+}
+
+/// The Vulkan handles and textures used to draw the UI.
+#[derive(Debug, Default)]
+pub struct UiRenderer {
+    /// The frame of the UI which will be drawn over the next frame rendered.
+    frame: Option<UiFrame>,
+    /// The pixels of the textures, which outlive the logical device.
+    images: HashMap<UiTextureId, UiImage>,
+    /// The textures which have to be (re)created before the next draw.
+    dirty: Vec<UiTextureId>,
+    /// The textures which are freed after the next draw.
+    freed: Vec<UiTextureId>,
+    textures: HashMap<UiTextureId, UiTexture>,
+    /// The number of frames which have been prepared.
+    frames: u64,
+    /// The replaced or freed textures and the frame they were removed during.
+    garbage: Vec<(u64, UiTexture)>,
+    descriptor_set_layout: Owned<vk::DescriptorSetLayout>,
+    descriptor_pool: Owned<vk::DescriptorPool>,
+    sampler: Owned<vk::Sampler>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    // Swapchain
+    pipeline: Owned<vk::Pipeline>,
+    render_pass: Owned<vk::RenderPass>,
+    framebuffers: Vec<Owned<vk::Framebuffer>>,
+    graphs: Vec<(CompiledGraph, PassId)>,
+    buffers: Vec<UiBuffers>,
+}
+
+impl UiRenderer {
+    /// Queues a frame of the UI to be drawn over the next frame rendered.
+    ///
+    /// This replaces the meshes of a frame which hasn't been drawn yet (e.g.,
+    /// because the swapchain was out of date), but not its texture updates.
+    pub fn queue(&mut self, mut frame: UiFrame) {
+        for (id, image) in frame.textures.drain(..) {
+            if image.position.is_none() {
+                self.images.insert(id, image);
+            } else if let Some(target) = self.images.get_mut(&id) {
+                target.blit(&image);
+            } else {
+                continue;
+            }
+
+            if !self.dirty.contains(&id) {
+                self.dirty.push(id);
+            }
+        }
+
+        self.freed.append(&mut frame.freed);
+        self.frame = Some(frame);
+    }
+
+    /// Takes the parts of the renderer which outlive the logical device (the
+    /// queued frame and the pixels of the textures), leaving everything used
+    /// to draw the UI to be destroyed when the rest is dropped.
+    pub fn take_persistent(&mut self) -> Self {
+        Self {
+            frame: self.frame.take(),
+            images: mem::take(&mut self.images),
+            freed: mem::take(&mut self.freed),
+            ..Default::default()
+        }
+    }
+}
+
+/// Creates the descriptor set layout, descriptor pool, sampler, and pipeline
+/// layout used to draw the UI, and (re)creates its textures.
+pub unsafe fn create_ui_objects(device: &Device, data: &mut AppData) -> Result<()> {
+    // Descriptor Set Layout
+
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.ui.descriptor_set_layout =
+        Owned::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // Descriptor Pool
+
+    // The descriptor set of a texture is freed along with the texture.
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(MAX_UI_TEXTURES);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        .pool_sizes(pool_sizes)
+        .max_sets(MAX_UI_TEXTURES);
+
+    data.ui.descriptor_pool = Owned::new(device, device.create_descriptor_pool(&info, None)?);
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .max_lod(0.0);
+
+    data.ui.sampler = Owned::new(device, device.create_sampler(&info, None)?);
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(8 /* 2 × 4 byte floats */);
+
+    let set_layouts = &[*data.ui.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.ui.pipeline_layout =
+        Owned::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    // Textures
+
+    data.ui.dirty = data.ui.images.keys().copied().collect();
+
+    Ok(())
+}
+
+/// Creates the pipeline (and the render pass and framebuffers or render
+/// graphs) which draws the UI over the swapchain images.
+pub unsafe fn create_ui_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    // Render Pass
+
+    if data.rendering_path == RenderingPath::RenderPass {
+        // The UI is drawn over the swapchain image once the main pass has
+        // left it ready to be presented.
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(data.swapchain.format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments);
+
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            );
+
+        let attachments = &[color_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        data.ui.render_pass = Owned::new(device, device.create_render_pass(&info, None)?);
+
+        data.ui.framebuffers = data
+            .swapchain
+            .image_views
+            .iter()
+            .map(|i| {
+                let attachments = &[*i];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(*data.ui.render_pass)
+                    .attachments(attachments)
+                    .width(data.swapchain.extent.width)
+                    .height(data.swapchain.extent.height)
+                    .layers(1);
+
+                let framebuffer = device.create_framebuffer(&info, None)?;
+                Ok(Owned::new(device, framebuffer))
+            })
+            .collect::<Result<_>>()?;
+    }
+
+    // Render Graphs
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        data.ui.graphs = data
+            .swapchain
+            .images
+            .iter()
+            .map(|i| {
+                let mut graph = RenderGraph::new();
+
+                // The main pass last wrote to the swapchain image before
+                // leaving it ready to be presented.
+                let swapchain_image = graph.import_image(
+                    "Swapchain Image",
+                    *i,
+                    vk::ImageAspectFlags::COLOR,
+                    State {
+                        stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                        access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                    },
+                );
+
+                let pass = graph.add_pass("UI Pass");
+                graph.use_image(pass, swapchain_image, Access::ColorAttachment);
+                graph.export_image(swapchain_image, Access::Present);
+
+                Ok((graph.compile(instance, device, data)?, pass))
+            })
+            .collect::<Result<_>>()?;
+    }
+
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, shaders::UI_VERT)?;
+    let frag_shader_module = create_shader_module(device, shaders::UI_FRAG)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The UI is always encoded as sRGB, even while the scene isn't.
+    let encode_srgb = OutputTransfer::new(data.swapchain.color_space) == OutputTransfer::Sdr
+        && !is_srgb_format(data.swapchain.format);
+
+    let specialization_data = (encode_srgb as vk::Bool32).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry::builder()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<vk::Bool32>())];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&specialization_data);
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Vertex Input State
+
+    let binding_descriptions = &[UiVertex::binding_description()];
+    let attribute_descriptions = UiVertex::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    // Input Assembly State
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    // The scissor is set for each mesh (and the viewport with it).
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    // Rasterization State
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Multisample State
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // Depth Stencil State
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color Blend State
+
+    // The colors have premultiplied alpha.
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    // Rendering
+
+    let color_attachment_formats = &[data.swapchain.format];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(*data.ui.pipeline_layout)
+        .render_pass(*data.ui.render_pass)
+        .subpass(0);
+
+    if data.rendering_path != RenderingPath::RenderPass {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.ui.pipeline = Owned::new(
+        device,
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0,
+    );
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    // Buffers
+
+    data.ui.buffers = (0..data.swapchain.images.len())
+        .map(|_| UiBuffers::default())
+        .collect();
+
+    Ok(())
+}
+
+/// Prepares the queued frame of the UI (if any) to be drawn over the swapchain
+/// image at the supplied index, uploading the textures it created or updated.
+///
+/// The command buffer for the swapchain image has to have completed.
+pub unsafe fn prepare_ui(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+) -> Result<()> {
+    data.ui.frames += 1;
+
+    // Garbage
+
+    let frames = data.ui.frames;
+    let (expired, garbage) = mem::take(&mut data.ui.garbage)
+        .into_iter()
+        .partition::<Vec<_>, _>(|(f, _)| f + MAX_FRAMES_IN_FLIGHT as u64 <= frames);
+    data.ui.garbage = garbage;
+
+    for (_, texture) in expired {
+        device.free_descriptor_sets(*data.ui.descriptor_pool, &[texture.descriptor_set])?;
+    }
+
+    // Textures
+
+    for id in mem::take(&mut data.ui.dirty) {
+        create_ui_texture(instance, device, data, id)?;
+    }
+
+    // Meshes
+
+    data.ui.buffers[image_index].draws.clear();
+
+    if let Some(frame) = data.ui.frame.take() {
+        write_ui_buffers(instance, device, data, image_index, &frame)?;
+    }
+
+    // Freed
+
+    for id in mem::take(&mut data.ui.freed) {
+        data.ui.images.remove(&id);
+        if let Some(texture) = data.ui.textures.remove(&id) {
+            data.ui.garbage.push((frames, texture));
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates (or recreates) a texture of the UI from its pixels, uploading it
+/// with the pending uploads.
+unsafe fn create_ui_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    id: UiTextureId,
+) -> Result<()> {
+    let Some(image) = data.ui.images.get(&id) else {
+        return Ok(());
+    };
+
+    let (width, height) = (image.width, image.height);
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let pixels = image.pixels.clone();
+
+    // Create
+
+    let mut texture_image = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        UI_TEXTURE_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Transition + Upload
+
+    let image = texture_image.image;
+
+    cmd_transition_image_layout(
+        device,
+        get_upload_command_buffer(device, data)?,
+        image,
+        UI_TEXTURE_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+    )?;
+
+    upload_to_image(instance, device, data, &pixels, image, width, height, 1)?;
+
+    cmd_transition_image_layout(
+        device,
+        get_graphics_upload_command_buffer(device, data)?,
+        image,
+        UI_TEXTURE_FORMAT,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+    )?;
+
+    texture_image.view = create_image_view(
+        device,
+        image,
+        UI_TEXTURE_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    // Descriptor Set
+
+    let layouts = &[*data.ui.descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.ui.descriptor_pool)
+        .set_layouts(layouts);
+
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(texture_image.view)
+        .sampler(*data.ui.sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+    // The texture being replaced may still be used by a frame in flight.
+    let texture = UiTexture {
+        image: texture_image,
+        descriptor_set,
+    };
+
+    if let Some(previous) = data.ui.textures.insert(id, texture) {
+        data.ui.garbage.push((data.ui.frames, previous));
+    }
+
+    Ok(())
+}
+
+/// Copies the meshes of a frame of the UI to the buffers for the swapchain
+/// image at the supplied index (growing them if needed).
+unsafe fn write_ui_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    image_index: usize,
+    frame: &UiFrame,
+) -> Result<()> {
+    let extent = data.swapchain.extent;
+
+    // Only the meshes with a texture which aren't clipped away are drawn.
+    let meshes = frame
+        .meshes
+        .iter()
+        .filter_map(|m| {
+            let descriptor_set = data.ui.textures.get(&m.texture)?.descriptor_set;
+
+            let [min_x, min_y, max_x, max_y] = m.clip_rect;
+            let min_x = (min_x.max(0.0).round() as u32).min(extent.width);
+            let min_y = (min_y.max(0.0).round() as u32).min(extent.height);
+            let max_x = (max_x.max(0.0).round() as u32).min(extent.width);
+            let max_y = (max_y.max(0.0).round() as u32).min(extent.height);
+            if min_x >= max_x || min_y >= max_y || m.indices.is_empty() {
+                return None;
+            }
+
+            let scissor = vk::Rect2D::builder()
+                .offset(vk::Offset2D {
+                    x: min_x as i32,
+                    y: min_y as i32,
+                })
+                .extent(vk::Extent2D {
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                })
+                .build();
+
+            Some((m, descriptor_set, scissor))
+        })
+        .collect::<Vec<_>>();
+
+    // Buffers
+
+    let vertex_count = meshes.iter().map(|(m, ..)| m.vertices.len()).sum::<usize>();
+    let index_count = meshes.iter().map(|(m, ..)| m.indices.len()).sum::<usize>();
+
+    if data.ui.buffers[image_index].vertex_capacity < vertex_count {
+        let capacity = vertex_count.next_power_of_two().max(MIN_UI_BUFFER_LEN);
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            (capacity * size_of::<UiVertex>()) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let buffers = &mut data.ui.buffers[image_index];
+        buffers.vertex_buffer = buffer;
+        buffers.vertex_capacity = capacity;
+    }
+
+    if data.ui.buffers[image_index].index_capacity < index_count {
+        let capacity = index_count.next_power_of_two().max(MIN_UI_BUFFER_LEN);
+        let buffer = create_buffer(
+            instance,
+            device,
+            data,
+            (capacity * size_of::<u32>()) as u64,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let buffers = &mut data.ui.buffers[image_index];
+        buffers.index_buffer = buffer;
+        buffers.index_capacity = capacity;
+    }
+
+    // Copy
+
+    let buffers = &mut data.ui.buffers[image_index];
+    if meshes.is_empty() {
+        return Ok(());
+    }
+
+    let vertices = buffers.vertex_buffer.allocation.map()?.cast::<UiVertex>();
+    let indices = buffers.index_buffer.allocation.map()?.cast::<u32>();
+
+    let (mut vertex_offset, mut first_index) = (0, 0);
+    for (mesh, descriptor_set, scissor) in meshes {
+        memcpy(
+            mesh.vertices.as_ptr(),
+            vertices.add(vertex_offset),
+            mesh.vertices.len(),
+        );
+        memcpy(
+            mesh.indices.as_ptr(),
+            indices.add(first_index),
+            mesh.indices.len(),
+        );
+
+        buffers.draws.push(UiDraw {
+            descriptor_set,
+            scissor,
+            index_count: mesh.indices.len() as u32,
+            first_index: first_index as u32,
+            vertex_offset: vertex_offset as i32,
+        });
+
+        vertex_offset += mesh.vertices.len();
+        first_index += mesh.indices.len();
+    }
+
+    buffers.screen_size = Vec2::new(extent.width as f32, extent.height as f32);
+
+    Ok(())
+}
+
+/// Records the UI pass, which draws the UI prepared for the swapchain image at
+/// the supplied index over it (if there is anything to draw).
+pub unsafe fn cmd_draw_ui(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) -> Result<()> {
+    let buffers = &data.ui.buffers[image_index];
+    if buffers.draws.is_empty() {
+        return Ok(());
+    }
+
+    begin_command_label(instance, data, command_buffer, "UI Pass", UI_LABEL_COLOR)?;
+
+    let extent = data.swapchain.extent;
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(extent);
+
+    // Begin
+
+    if data.rendering_path == RenderingPath::RenderPass {
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*data.ui.render_pass)
+            .framebuffer(*data.ui.framebuffers[image_index])
+            .render_area(render_area);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    } else {
+        let (graph, pass) = &data.ui.graphs[image_index];
+        graph.cmd_barriers(device, command_buffer, *pass);
+
+        let color_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(data.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let color_attachments = &[color_attachment];
+        let info = vk::RenderingInfo::builder()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(color_attachments);
+
+        if data.rendering_path == RenderingPath::DynamicRendering {
+            device.cmd_begin_rendering(command_buffer, &info);
+        } else {
+            device.cmd_begin_rendering_khr(command_buffer, &info);
+        }
+    }
+
+    // Draw
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let screen_size = [buffers.screen_size.x, buffers.screen_size.y];
+    let push_constants = screen_size.map(f32::to_ne_bytes).concat();
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.ui.pipeline,
+    );
+    device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffers.vertex_buffer.buffer], &[0]);
+    device.cmd_bind_index_buffer(
+        command_buffer,
+        buffers.index_buffer.buffer,
+        0,
+        vk::IndexType::UINT32,
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *data.ui.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX,
+        0,
+        &push_constants,
+    );
+
+    for draw in &buffers.draws {
+        device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *data.ui.pipeline_layout,
+            0,
+            &[draw.descriptor_set],
+            &[],
+        );
+        device.cmd_draw_indexed(
+            command_buffer,
+            draw.index_count,
+            1,
+            draw.first_index,
+            draw.vertex_offset,
+            0,
+        );
+    }
+
+    // End
+
+    if data.rendering_path == RenderingPath::RenderPass {
+        device.cmd_end_render_pass(command_buffer);
+    } else {
+        if data.rendering_path == RenderingPath::DynamicRendering {
+            device.cmd_end_rendering(command_buffer);
+        } else {
+            device.cmd_end_rendering_khr(command_buffer);
+        }
+
+        data.ui.graphs[image_index]
+            .0
+            .cmd_final_barriers(device, command_buffer);
+    }
+
+    end_command_label(instance, data, command_buffer);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blit() {
+        let mut image = UiImage {
+            position: None,
+            width: 3,
+            height: 2,
+            pixels: vec![[0; 4]; 6],
+        };
+
+        // The region is clipped to the image.
+        let region = UiImage {
+            position: Some([2, 1]),
+            width: 2,
+            height: 2,
+            pixels: vec![[1; 4], [2; 4], [3; 4], [4; 4]],
+        };
+
+        image.blit(&region);
+        assert_eq!(
+            image.pixels,
+            vec![[0; 4], [0; 4], [0; 4], [0; 4], [0; 4], [1; 4]],
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! An egui UI which is built every frame by `App::ui`, receives its input from
+//! the window events (through `egui_winit`), and is drawn by the UI renderer.
+
+use std::fmt;
+
+use egui::epaint::{ImageDelta, Primitive};
+use egui::{Context, FullOutput, ImageData, RawInput, TextureId, ViewportId};
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use crate::ui::{UiFrame, UiImage, UiMesh, UiTextureId, UiVertex};
+use crate::vertex::Vec2;
+
+/// The egui context and the state which turns window events into egui input.
+pub struct Egui {
+    pub context: Context,
+    state: egui_winit::State,
+}
+
+impl fmt::Debug for Egui {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Egui")
+            .field("context", &self.context)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Egui {
+    pub fn new(window: &Window) -> Self {
+        let context = Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+
+        Self { context, state }
+    }
+
+    /// Passes a window event to egui, returning whether egui used it (e.g., a
+    /// click on one of its windows), in which case our Vulkan app shouldn't.
+    pub fn handle_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.state.on_window_event(window, event).consumed
+    }
+
+    /// Returns the input for the next frame of the UI.
+    pub fn begin_frame(&mut self, window: &Window) -> RawInput {
+        self.state.take_egui_input(window)
+    }
+
+    /// Handles the output of a frame of the UI (e.g., changing the cursor),
+    /// returning what it draws.
+    pub fn end_frame(&mut self, window: &Window, output: FullOutput) -> UiFrame {
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        // egui works in points rather than pixels.
+        let scale = output.pixels_per_point;
+
+        let meshes = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point)
+            .into_iter()
+            .filter_map(|p| {
+                // Paint callbacks (i.e., custom rendering) aren't supported.
+                let Primitive::Mesh(mesh) = p.primitive else {
+                    return None;
+                };
+
+                let vertices = mesh
+                    .vertices
+                    .iter()
+                    .map(|v| UiVertex {
+                        position: Vec2::new(v.pos.x, v.pos.y) * scale,
+                        uv: Vec2::new(v.uv.x, v.uv.y),
+                        color: v.color.to_array(),
+                    })
+                    .collect();
+
+                Some(UiMesh {
+                    vertices,
+                    indices: mesh.indices,
+                    texture: get_texture_id(mesh.texture_id),
+                    clip_rect: [
+                        p.clip_rect.min.x * scale,
+                        p.clip_rect.min.y * scale,
+                        p.clip_rect.max.x * scale,
+                        p.clip_rect.max.y * scale,
+                    ],
+                })
+            })
+            .collect();
+
+        let textures = output
+            .textures_delta
+            .set
+            .iter()
+            .map(|(id, delta)| (get_texture_id(*id), get_image(delta)))
+            .collect();
+
+        let freed = output
+            .textures_delta
+            .free
+            .into_iter()
+            .map(get_texture_id)
+            .collect();
+
+        UiFrame {
+            meshes,
+            textures,
+            freed,
+        }
+    }
+}
+
+/// Returns the ID of the texture of the UI for an egui texture (the textures
+/// managed by egui and the user textures are told apart by the lowest bit).
+fn get_texture_id(id: TextureId) -> UiTextureId {
+    match id {
+        TextureId::Managed(id) => id << 1,
+        TextureId::User(id) => id << 1 | 1,
+    }
+}
+
+/// Returns the pixels of an update to an egui texture.
+fn get_image(delta: &ImageDelta) -> UiImage {
+    let pixels = match &delta.image {
+        ImageData::Color(image) => image.pixels.iter().map(|c| c.to_array()).collect(),
+        ImageData::Font(image) => image.srgba_pixels(None).map(|c| c.to_array()).collect(),
+    };
+
+    let [width, height] = delta.image.size();
+    UiImage {
+        position: delta.pos.map(|[x, y]| [x as u32, y as u32]),
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    }
+}
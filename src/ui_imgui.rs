@@ -0,0 +1,231 @@
+//! A Dear ImGui UI (through imgui-rs) which is built every frame by
+//! `App::imgui_ui`, receives its input from the window events (through
+//! `imgui-winit-support`), and is drawn by the UI renderer.
+
+use std::fmt;
+use std::time::Instant;
+
+use imgui::{Context, DrawCmd, DrawData, FontConfig, FontSource, TextureId, Ui};
+use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use log::*;
+use winit::event::{Event, WindowEvent};
+use winit::window::Window;
+
+use crate::ui::{UiFrame, UiImage, UiMesh, UiTextureId, UiVertex};
+use crate::vertex::Vec2;
+
+/// The bit which tells the textures of Dear ImGui apart from the textures of
+/// the other UIs.
+const IMGUI_TEXTURES: UiTextureId = 1 << 63;
+
+/// The Dear ImGui texture of the font atlas.
+const FONT_TEXTURE: usize = 0;
+
+/// The size of the default font (in points).
+const FONT_SIZE: f32 = 13.0;
+
+/// The Dear ImGui context and the platform which turns window events into
+/// Dear ImGui input.
+pub struct Imgui {
+    pub context: Context,
+    platform: WinitPlatform,
+    /// The pixels of the font atlas, until they have been drawn with.
+    font: Option<UiImage>,
+    last_frame: Instant,
+}
+
+impl fmt::Debug for Imgui {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Imgui")
+            .field("last_frame", &self.last_frame)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Imgui {
+    pub fn new(window: &Window) -> Self {
+        let mut context = Context::create();
+        context.set_ini_filename(None);
+
+        let mut platform = WinitPlatform::new(&mut context);
+        platform.attach_window(context.io_mut(), window, HiDpiMode::Default);
+
+        // The font is rasterized at the scale factor of the window (and scaled
+        // back down) so it isn't blurry on high DPI monitors.
+        let scale = platform.hidpi_factor() as f32;
+        context.fonts().add_font(&[FontSource::DefaultFontData {
+            config: Some(FontConfig {
+                size_pixels: FONT_SIZE * scale,
+                ..FontConfig::default()
+            }),
+        }]);
+        context.io_mut().font_global_scale = 1.0 / scale;
+
+        let font = get_font_image(&mut context);
+
+        Self {
+            context,
+            platform,
+            font: Some(font),
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Passes a window event to Dear ImGui, returning whether Dear ImGui
+    /// wants it (e.g., a click on one of its windows), in which case our
+    /// Vulkan app shouldn't use it.
+    pub fn handle_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        let event = Event::<()>::WindowEvent {
+            window_id: window.id(),
+            event: event.clone(),
+        };
+        self.platform
+            .handle_event(self.context.io_mut(), window, &event);
+
+        let io = self.context.io();
+        match event {
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput { .. }
+                    | WindowEvent::ModifiersChanged(_)
+                    | WindowEvent::Ime(_),
+                ..
+            } => io.want_capture_keyboard,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::CursorMoved { .. }
+                    | WindowEvent::MouseInput { .. }
+                    | WindowEvent::MouseWheel { .. },
+                ..
+            } => io.want_capture_mouse,
+            _ => false,
+        }
+    }
+
+    /// Builds a frame of the UI with the supplied function, returning what it
+    /// draws.
+    pub fn run(&mut self, window: &Window, build: impl FnOnce(&mut Ui)) -> UiFrame {
+        let now = Instant::now();
+        self.context
+            .io_mut()
+            .update_delta_time(now - self.last_frame);
+        self.last_frame = now;
+
+        if let Err(error) = self.platform.prepare_frame(self.context.io_mut(), window) {
+            warn!("Failed to prepare the Dear ImGui frame: {}", error);
+        }
+
+        let ui = self.context.new_frame();
+        build(ui);
+        self.platform.prepare_render(ui, window);
+
+        let meshes = get_meshes(self.context.render());
+        let textures = self
+            .font
+            .take()
+            .map(|f| (get_texture_id(TextureId::new(FONT_TEXTURE)), f))
+            .into_iter()
+            .collect();
+
+        UiFrame {
+            meshes,
+            textures,
+            freed: vec![],
+        }
+    }
+}
+
+/// Returns the ID of the texture of the UI for a Dear ImGui texture.
+fn get_texture_id(id: TextureId) -> UiTextureId {
+    IMGUI_TEXTURES | id.id() as UiTextureId
+}
+
+/// Builds the font atlas, returning its pixels.
+fn get_font_image(context: &mut Context) -> UiImage {
+    let fonts = context.fonts();
+
+    let texture = fonts.build_rgba32_texture();
+    let image = UiImage {
+        position: None,
+        width: texture.width,
+        height: texture.height,
+        pixels: texture
+            .data
+            .chunks_exact(4)
+            .map(|p| premultiply([p[0], p[1], p[2], p[3]]))
+            .collect(),
+    };
+
+    fonts.tex_id = TextureId::new(FONT_TEXTURE);
+
+    image
+}
+
+/// Returns the meshes of a frame of the UI, with one mesh per draw command.
+fn get_meshes(draw_data: &DrawData) -> Vec<UiMesh> {
+    // Dear ImGui positions are in points from the top left corner of the
+    // display (which isn't always the origin).
+    let [x, y] = draw_data.display_pos;
+    let [scale_x, scale_y] = draw_data.framebuffer_scale;
+    let position = |[px, py]: [f32; 2]| Vec2::new((px - x) * scale_x, (py - y) * scale_y);
+
+    let mut meshes = vec![];
+    for list in draw_data.draw_lists() {
+        let vertices = list.vtx_buffer();
+        let indices = list.idx_buffer();
+
+        for command in list.commands() {
+            // Callbacks (i.e., custom rendering) aren't supported.
+            let DrawCmd::Elements { count, cmd_params } = command else {
+                continue;
+            };
+
+            let vertices = &vertices[cmd_params.vtx_offset..];
+            let indices = &indices[cmd_params.idx_offset..cmd_params.idx_offset + count];
+
+            // Only the vertices the indices refer to are copied, since the
+            // draw commands of a list share its vertices.
+            let (Some(&min), Some(&max)) = (indices.iter().min(), indices.iter().max()) else {
+                continue;
+            };
+
+            let [min_x, min_y, max_x, max_y] = cmd_params.clip_rect;
+            let (min_corner, max_corner) = (position([min_x, min_y]), position([max_x, max_y]));
+
+            meshes.push(UiMesh {
+                vertices: vertices[min as usize..=max as usize]
+                    .iter()
+                    .map(|v| UiVertex {
+                        position: position(v.pos),
+                        uv: Vec2::new(v.uv[0], v.uv[1]),
+                        color: premultiply(v.col),
+                    })
+                    .collect(),
+                indices: indices.iter().map(|i| (i - min) as u32).collect(),
+                texture: get_texture_id(cmd_params.texture_id),
+                clip_rect: [min_corner.x, min_corner.y, max_corner.x, max_corner.y],
+            });
+        }
+    }
+
+    meshes
+}
+
+/// Returns a color with straight alpha (which Dear ImGui uses) with
+/// premultiplied alpha (which the UI renderer uses).
+fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let multiply = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+    [multiply(r), multiply(g), multiply(b), a]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiplied_colors() {
+        assert_eq!(premultiply([255, 255, 255, 255]), [255, 255, 255, 255]);
+        assert_eq!(premultiply([255, 128, 0, 128]), [128, 64, 0, 128]);
+        assert_eq!(premultiply([255, 255, 255, 0]), [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,631 @@
+//! Uploads to device-local buffers and images, which are batched into a
+//! single submission instead of each waiting for the graphics queue to idle.
+//!
+//! The data is copied to one of a ring of reusable staging buffers and the
+//! copies are recorded into a command buffer which is submitted by
+//! `flush_uploads` (while our Vulkan app is being created and before every
+//! frame). A staging buffer is only reused once the uploads copied from it
+//! have completed.
+//!
+//! If the physical device has a queue family which only supports transfers
+//! (which is usually backed by dedicated DMA hardware), the copies are
+//! submitted to a queue of that family so they overlap with rendering. The
+//! uploaded buffers and images are then transferred to the graphics queue
+//! family by a second command buffer, which the graphics queue only executes
+//! once a semaphore signals that the copies have completed.
+
+use std::mem::size_of_val;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, Buffer};
+use crate::debug::{begin_queue_label, end_queue_label, UPLOAD_LABEL_COLOR};
+use crate::device::QueueFamilyIndices;
+use crate::handle::Owned;
+use crate::memory::align_up;
+
+/// The number of batches of uploads which can be in flight at once.
+const MAX_BATCHES_IN_FLIGHT: usize = 2;
+
+/// The size of each staging buffer (larger uploads get a staging buffer of
+/// their own).
+const STAGING_BUFFER_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+/// The alignment of the data in a staging buffer, which satisfies the
+/// alignment required to copy to an image of any uncompressed format.
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+/// The command pools and staging buffers used to upload to buffers and
+/// images.
+#[derive(Debug, Default)]
+pub struct UploadContext {
+    /// The queue family the copies are submitted to, which is the graphics
+    /// queue family if there is no dedicated transfer queue family.
+    transfer_family: u32,
+    graphics_family: u32,
+    transfer_command_pool: Owned<vk::CommandPool>,
+    graphics_command_pool: Owned<vk::CommandPool>,
+    batches: Vec<Batch>,
+    /// The index of the batch which uploads are recorded into.
+    batch: usize,
+}
+
+impl UploadContext {
+    /// Whether the copies are submitted to a dedicated transfer queue.
+    fn is_async(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+}
+
+/// A batch of uploads and the staging buffer they are copied from.
+#[derive(Debug, Default)]
+struct Batch {
+    /// The command buffer the copies are recorded into (which is freed along
+    /// with its command pool, like the graphics command buffer).
+    command_buffer: vk::CommandBuffer,
+    /// The command buffer for the graphics queue which acquires the uploaded
+    /// buffers and images and finishes preparing them (e.g., generating
+    /// mipmaps) once the copies have completed.
+    graphics_command_buffer: vk::CommandBuffer,
+    /// Signaled once the copies have completed (if they are submitted to a
+    /// dedicated transfer queue).
+    semaphore: Owned<vk::Semaphore>,
+    fence: Owned<vk::Fence>,
+    /// Whether the command buffers are being recorded.
+    recording: bool,
+    staging_buffer: Buffer,
+    /// The number of bytes of the staging buffer which have been used.
+    used: vk::DeviceSize,
+    /// The staging buffers created for uploads which were too large for the
+    /// staging buffer, which are destroyed once the uploads complete.
+    large_staging_buffers: Vec<Buffer>,
+}
+
+pub unsafe fn create_upload_context(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    data.upload.transfer_family = indices.transfer;
+    data.upload.graphics_family = indices.graphics;
+
+    if data.upload.is_async() {
+        info!(
+            "Uploading with a dedicated transfer queue (family {}).",
+            indices.transfer
+        );
+    }
+
+    // Command Pools
+
+    let flags =
+        vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(flags)
+        .queue_family_index(indices.transfer);
+
+    data.upload.transfer_command_pool =
+        Owned::new(device, device.create_command_pool(&info, None)?);
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(flags)
+        .queue_family_index(indices.graphics);
+
+    data.upload.graphics_command_pool =
+        Owned::new(device, device.create_command_pool(&info, None)?);
+
+    // Batches
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*data.upload.transfer_command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(MAX_BATCHES_IN_FLIGHT as u32);
+
+    let command_buffers = device.allocate_command_buffers(&info)?;
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*data.upload.graphics_command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(MAX_BATCHES_IN_FLIGHT as u32);
+
+    let graphics_command_buffers = device.allocate_command_buffers(&info)?;
+
+    let semaphore_info = vk::SemaphoreCreateInfo::builder();
+    let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+
+    for (command_buffer, graphics_command_buffer) in
+        command_buffers.into_iter().zip(graphics_command_buffers)
+    {
+        let staging_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            STAGING_BUFFER_SIZE,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.upload.batches.push(Batch {
+            command_buffer,
+            graphics_command_buffer,
+            semaphore: Owned::new(device, device.create_semaphore(&semaphore_info, None)?),
+            fence: Owned::new(device, device.create_fence(&fence_info, None)?),
+            staging_buffer,
+            ..Default::default()
+        });
+    }
+
+    data.upload.batch = 0;
+
+    Ok(())
+}
+
+/// Returns the command buffer the copies are being recorded into, beginning a
+/// new batch of uploads if needed.
+///
+/// Commands which have to happen before the copies (e.g., image layout
+/// transitions) can be recorded into this command buffer as well, as long as
+/// transfer queues support them.
+pub unsafe fn get_upload_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+) -> Result<vk::CommandBuffer> {
+    let batch = &mut data.upload.batches[data.upload.batch];
+    if batch.recording {
+        return Ok(batch.command_buffer);
+    }
+
+    // The uploads last recorded into this batch have to complete before its
+    // command buffers and staging buffers can be reused.
+    device.wait_for_fences(&[*batch.fence], true, u64::MAX)?;
+    batch.used = 0;
+    batch.large_staging_buffers.clear();
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    for command_buffer in [batch.command_buffer, batch.graphics_command_buffer] {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(command_buffer, &info)?;
+    }
+
+    batch.recording = true;
+
+    Ok(batch.command_buffer)
+}
+
+/// Returns the command buffer the graphics queue executes once the copies
+/// recorded so far have completed, beginning a new batch of uploads if needed.
+pub unsafe fn get_graphics_upload_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+) -> Result<vk::CommandBuffer> {
+    get_upload_command_buffer(device, data)?;
+    Ok(data.upload.batches[data.upload.batch].graphics_command_buffer)
+}
+
+/// Records an upload of the supplied values to the start of a buffer.
+pub unsafe fn upload_to_buffer<T: Copy>(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    values: &[T],
+    buffer: vk::Buffer,
+) -> Result<()> {
+    let (source, offset) = stage(instance, device, data, values)?;
+    let command_buffer = get_upload_command_buffer(device, data)?;
+
+    let region = vk::BufferCopy::builder()
+        .src_offset(offset)
+        .dst_offset(0)
+        .size(size_of_val(values) as u64);
+
+    device.cmd_copy_buffer(command_buffer, source, buffer, &[region]);
+
+    // Ownership Transfer
+
+    if !data.upload.is_async() {
+        return Ok(());
+    }
+
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_queue_family_index(data.upload.transfer_family)
+        .dst_queue_family_index(data.upload.graphics_family)
+        .buffer(buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64)
+        .build();
+
+    let release = vk::BufferMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        ..barrier
+    };
+
+    let acquire = vk::BufferMemoryBarrier {
+        dst_access_mask: vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+            | vk::AccessFlags::INDEX_READ
+            | vk::AccessFlags::UNIFORM_READ
+            | vk::AccessFlags::SHADER_READ,
+        ..barrier
+    };
+
+    cmd_transfer_ownership(device, data, &[release], &[acquire], &[], &[])?;
+
+    Ok(())
+}
+
+/// Records an upload of the supplied pixels to the first mip level of an
+/// image, which has to be in the `TRANSFER_DST_OPTIMAL` layout (and stays in
+/// that layout).
+pub unsafe fn upload_to_image<T: Copy>(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    pixels: &[T],
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) -> Result<()> {
+    let (source, offset) = stage(instance, device, data, pixels)?;
+    let command_buffer = get_upload_command_buffer(device, data)?;
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(offset)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        });
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        source,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+
+    // Ownership Transfer
+
+    if !data.upload.is_async() {
+        return Ok(());
+    }
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(data.upload.transfer_family)
+        .dst_queue_family_index(data.upload.graphics_family)
+        .image(image)
+        .subresource_range(subresource)
+        .build();
+
+    let release = vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        ..barrier
+    };
+
+    // The mip levels are generated from the first mip level after the image
+    // has been acquired, which reads and writes the image with transfers.
+    let acquire = vk::ImageMemoryBarrier {
+        dst_access_mask: vk::AccessFlags::TRANSFER_READ
+            | vk::AccessFlags::TRANSFER_WRITE
+            | vk::AccessFlags::SHADER_READ,
+        ..barrier
+    };
+
+    cmd_transfer_ownership(device, data, &[], &[], &[release], &[acquire])?;
+
+    Ok(())
+}
+
+/// Records an upload of the supplied mip levels (each of which has the pixels
+/// of every array layer one after another) to an image, which has to be in the
+/// `TRANSFER_DST_OPTIMAL` layout (and stays in that layout).
+pub unsafe fn upload_levels_to_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    levels: &[Vec<u8>],
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    array_layers: u32,
+) -> Result<()> {
+    // The levels are staged together, each aligned for the copy.
+    let mut pixels = vec![];
+    let mut offsets = vec![];
+    for level in levels {
+        pixels.resize(align_up(pixels.len() as u64, STAGING_ALIGNMENT) as usize, 0);
+        offsets.push(pixels.len() as u64);
+        pixels.extend_from_slice(level);
+    }
+
+    let (source, offset) = stage(instance, device, data, &pixels)?;
+    let command_buffer = get_upload_command_buffer(device, data)?;
+
+    let regions = offsets
+        .iter()
+        .enumerate()
+        .map(|(i, level_offset)| {
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(i as u32)
+                .base_array_layer(0)
+                .layer_count(array_layers);
+
+            vk::BufferImageCopy::builder()
+                .buffer_offset(offset + level_offset)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: (width >> i).max(1),
+                    height: (height >> i).max(1),
+                    depth: 1,
+                })
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        source,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &regions,
+    );
+
+    // Ownership Transfer
+
+    if !data.upload.is_async() {
+        return Ok(());
+    }
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(levels.len() as u32)
+        .base_array_layer(0)
+        .layer_count(array_layers);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(data.upload.transfer_family)
+        .dst_queue_family_index(data.upload.graphics_family)
+        .image(image)
+        .subresource_range(subresource)
+        .build();
+
+    let release = vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        ..barrier
+    };
+
+    let acquire = vk::ImageMemoryBarrier {
+        dst_access_mask: vk::AccessFlags::SHADER_READ,
+        ..barrier
+    };
+
+    cmd_transfer_ownership(device, data, &[], &[], &[release], &[acquire])?;
+
+    Ok(())
+}
+
+/// Records the release of buffers and images by the transfer queue family
+/// and their acquisition by the graphics queue family.
+unsafe fn cmd_transfer_ownership(
+    device: &Device,
+    data: &mut AppData,
+    buffer_releases: &[vk::BufferMemoryBarrier],
+    buffer_acquires: &[vk::BufferMemoryBarrier],
+    image_releases: &[vk::ImageMemoryBarrier],
+    image_acquires: &[vk::ImageMemoryBarrier],
+) -> Result<()> {
+    let command_buffer = get_upload_command_buffer(device, data)?;
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        buffer_releases,
+        image_releases,
+    );
+
+    let command_buffer = get_graphics_upload_command_buffer(device, data)?;
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        buffer_acquires,
+        image_acquires,
+    );
+
+    Ok(())
+}
+
+/// Copies the supplied values to a staging buffer, returning the staging
+/// buffer and the offset they were copied to.
+unsafe fn stage<T: Copy>(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    values: &[T],
+) -> Result<(vk::Buffer, vk::DeviceSize)> {
+    let size = size_of_val(values) as u64;
+
+    // Large
+
+    if size > STAGING_BUFFER_SIZE {
+        let staging_buffer = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        memcpy(
+            values.as_ptr(),
+            staging_buffer.allocation.map()?.cast(),
+            values.len(),
+        );
+
+        get_upload_command_buffer(device, data)?;
+        let buffer = staging_buffer.buffer;
+        let batch = &mut data.upload.batches[data.upload.batch];
+        batch.large_staging_buffers.push(staging_buffer);
+
+        return Ok((buffer, 0));
+    }
+
+    // Ring
+
+    get_upload_command_buffer(device, data)?;
+
+    let batch = &data.upload.batches[data.upload.batch];
+    if align_up(batch.used, STAGING_ALIGNMENT) + size > STAGING_BUFFER_SIZE {
+        flush_uploads(instance, device, data)?;
+        get_upload_command_buffer(device, data)?;
+    }
+
+    let batch = &mut data.upload.batches[data.upload.batch];
+    let offset = align_up(batch.used, STAGING_ALIGNMENT);
+    let memory = batch.staging_buffer.allocation.map()?.add(offset as usize);
+
+    memcpy(values.as_ptr(), memory.cast(), values.len());
+    batch.used = offset + size;
+
+    Ok((batch.staging_buffer.buffer, offset))
+}
+
+/// Submits the uploads which have been recorded (if any).
+///
+/// The uploads complete before any work submitted to the graphics queue
+/// afterwards reads the uploaded buffers and images.
+pub unsafe fn flush_uploads(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let batch = &data.upload.batches[data.upload.batch];
+    if !batch.recording {
+        return Ok(());
+    }
+
+    let command_buffer = batch.command_buffer;
+    let graphics_command_buffer = batch.graphics_command_buffer;
+    let semaphore = *batch.semaphore;
+    let fence = *batch.fence;
+
+    // Barrier
+
+    // This covers the copies if they are submitted to the graphics queue and
+    // any transfers recorded by the graphics queue (e.g., generating mipmaps).
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                | vk::AccessFlags::INDEX_READ
+                | vk::AccessFlags::UNIFORM_READ
+                | vk::AccessFlags::SHADER_READ,
+        );
+
+    device.cmd_pipeline_barrier(
+        graphics_command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    device.end_command_buffer(command_buffer)?;
+    device.end_command_buffer(graphics_command_buffer)?;
+
+    device.reset_fences(&[fence])?;
+
+    // Submit (Graphics Queue)
+
+    if !data.upload.is_async() {
+        let queue = data.graphics_queue;
+        begin_queue_label(instance, data, queue, "Uploads", UPLOAD_LABEL_COLOR)?;
+
+        let command_buffers = &[command_buffer, graphics_command_buffer];
+        let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+        device.queue_submit(queue, &[info], fence)?;
+
+        end_queue_label(instance, data, queue);
+    }
+
+    // Submit (Transfer Queue)
+
+    if data.upload.is_async() {
+        let queue = data.transfer_queue;
+        begin_queue_label(instance, data, queue, "Uploads", UPLOAD_LABEL_COLOR)?;
+
+        let command_buffers = &[command_buffer];
+        let signal_semaphores = &[semaphore];
+        let info = vk::SubmitInfo::builder()
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores);
+
+        device.queue_submit(queue, &[info], vk::Fence::null())?;
+
+        end_queue_label(instance, data, queue);
+
+        let queue = data.graphics_queue;
+        begin_queue_label(instance, data, queue, "Acquire Uploads", UPLOAD_LABEL_COLOR)?;
+
+        let wait_semaphores = &[semaphore];
+        let wait_stages = &[vk::PipelineStageFlags::ALL_COMMANDS];
+        let command_buffers = &[graphics_command_buffer];
+        let info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(command_buffers);
+
+        device.queue_submit(queue, &[info], fence)?;
+
+        end_queue_label(instance, data, queue);
+    }
+
+    data.upload.batches[data.upload.batch].recording = false;
+    data.upload.batch = (data.upload.batch + 1) % data.upload.batches.len();
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+//! The geometry rendered by our Vulkan app.
+
+use std::mem::size_of;
+
+use lazy_static::lazy_static;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+pub type Vec2 = glm::Vec2;
+pub type Vec3 = glm::Vec3;
+pub type Vec4 = glm::Vec4;
+pub type Mat4 = glm::Mat4;
+
+lazy_static! {
+    // The quads face up (+Z) and their U texture coordinates increase along
+    // -X, so their tangents are -X (with a handedness of -1 since their V
+    // texture coordinates increase along +Y).
+    pub static ref VERTICES: Vec<Vertex> = vec![
+        Vertex::new(
+            glm::vec3(-0.5, -0.5, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec2(1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(0.5, -0.5, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec2(0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(0.5, 0.5, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec2(0.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(-0.5, 0.5, 0.0),
+            glm::vec3(1.0, 1.0, 1.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(-0.5, -0.5, -0.5),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec2(1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(0.5, -0.5, -0.5),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec2(0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(0.5, 0.5, -0.5),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec2(0.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+        Vertex::new(
+            glm::vec3(-0.5, 0.5, -0.5),
+            glm::vec3(1.0, 1.0, 1.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec4(-1.0, 0.0, 0.0, -1.0)
+        ),
+    ];
+}
+
+pub const INDICES: &[u16] = &[0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Vertex {
+    pub pos: Vec3,
+    pub color: Vec3,
+    pub tex_coord: Vec2,
+    pub normal: Vec3,
+    /// The direction the U texture coordinate increases along and, in W, the
+    /// handedness of the tangent space (the sign the cross product of the
+    /// normal and the tangent is multiplied by to get the bitangent).
+    pub tangent: Vec4,
+}
+
+impl Vertex {
+    pub const fn new(pos: Vec3, color: Vec3, tex_coord: Vec2, normal: Vec3, tangent: Vec4) -> Self {
+        Self {
+            pos,
+            color,
+            tex_coord,
+            normal,
+            tangent,
+        }
+    }
+
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 5] {
+        let pos = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+        let tex_coord = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset((size_of::<Vec3>() + size_of::<Vec3>()) as u32)
+            .build();
+        let normal = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset((size_of::<Vec3>() + size_of::<Vec3>() + size_of::<Vec2>()) as u32)
+            .build();
+        let tangent = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(4)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<Vec3>() * 3 + size_of::<Vec2>()) as u32)
+            .build();
+        [pos, color, tex_coord, normal, tangent]
+    }
+}
@@ -0,0 +1,198 @@
+//! Additional windows our Vulkan app renders the scene to.
+
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSurfaceExtension;
+use winit::window::{Window, WindowId};
+
+use crate::app::{App, AppData, AppOptions};
+use crate::camera::Camera;
+use crate::device::QueueFamilyIndices;
+use crate::instance::{create_surface, OwnedSurface};
+use crate::profiling::GpuProfiler;
+use crate::queries::FrameStats;
+
+/// A window our Vulkan app renders the scene to in addition to the window it
+/// was created for.
+///
+/// A view has its own surface and its own `AppData` (with its own swapchain,
+/// framebuffers, pipelines, and copies of the meshes and textures), which
+/// share the instance and the logical device of our Vulkan app. While a view
+/// is rendered to, its data is swapped with the data of our Vulkan app so the
+/// same functions can render to it.
+#[derive(Debug)]
+pub struct View {
+    /// The camera the scene is rendered from, or `None` to follow the camera
+    /// of our Vulkan app.
+    pub camera: Option<Camera>,
+    data: AppData,
+    frame: usize,
+    resized: bool,
+    minimized: bool,
+    stats: FrameStats,
+    profiler: GpuProfiler,
+    surface: OwnedSurface,
+    window: Window,
+}
+
+impl View {
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+}
+
+impl App {
+    /// Opens a view which renders the scene to the supplied window.
+    pub unsafe fn open_view(&mut self, window: Window, camera: Option<Camera>) -> Result<()> {
+        // The physical device and the queues are shared with our Vulkan app,
+        // but the particles and recording are only for the main window.
+        let mut data = AppData {
+            options: AppOptions {
+                particles: false,
+                record: None,
+                ..self.data.options.clone()
+            },
+            api_version: self.data.api_version,
+            physical_device: self.data.physical_device,
+            msaa_samples: self.data.msaa_samples,
+            features: self.data.features,
+            rendering_path: self.data.rendering_path,
+            graphics_queue: self.data.graphics_queue,
+            present_queue: self.data.present_queue,
+            transfer_queue: self.data.transfer_queue,
+            compute_queue: self.data.compute_queue,
+            ..Default::default()
+        };
+
+        let surface = create_surface(&window, &self.instance, &mut data)?;
+
+        // Images are presented to every window with the same queue.
+        let indices = QueueFamilyIndices::get(&self.instance, &self.data, data.physical_device)?;
+        if !self.instance.get_physical_device_surface_support_khr(
+            data.physical_device,
+            indices.present,
+            data.surface,
+        )? {
+            return Err(anyhow!(
+                "The window can't be presented to by the present queue."
+            ));
+        }
+
+        let mut view = View {
+            camera,
+            data,
+            frame: 0,
+            resized: false,
+            minimized: false,
+            stats: FrameStats::default(),
+            profiler: GpuProfiler::default(),
+            surface,
+            window,
+        };
+
+        if let Err(error) = self.with_view(&mut view, |app, window| {
+            app.create_device_objects(Some(window))
+        }) {
+            self.device.device_wait_idle()?;
+            self.with_view(&mut view, |app, _| app.destroy_device_objects());
+            return Err(error);
+        }
+
+        self.views.push(view);
+
+        Ok(())
+    }
+
+    /// Closes the view for the window with the supplied ID, if there is one.
+    pub unsafe fn close_view(&mut self, id: WindowId) -> Result<()> {
+        if let Some(index) = self.views.iter().position(|v| v.window.id() == id) {
+            self.device.device_wait_idle()?;
+            let mut view = self.views.remove(index);
+            self.with_view(&mut view, |app, _| app.destroy_device_objects());
+        }
+
+        Ok(())
+    }
+
+    /// Marks the view for the window with the supplied ID (if there is one) as
+    /// having been resized to the supplied size.
+    pub fn resize_view(&mut self, id: WindowId, width: u32, height: u32) {
+        if let Some(view) = self.views.iter_mut().find(|v| v.window.id() == id) {
+            view.minimized = width == 0 || height == 0;
+            view.resized |= !view.minimized;
+        }
+    }
+
+    /// Renders a frame to each view which isn't minimized.
+    pub(crate) unsafe fn render_views(&mut self) -> Result<()> {
+        let mut views = mem::take(&mut self.views);
+
+        let result = views
+            .iter_mut()
+            .filter(|v| !v.minimized)
+            .try_for_each(|view| {
+                view.data.camera = view.camera.unwrap_or(self.data.camera);
+                self.with_view(view, |app, window| app.render_frame(window))
+            });
+
+        self.views = views;
+        result
+    }
+
+    /// Destroys everything created with the logical device for the views
+    /// (e.g., before it is recreated).
+    pub(crate) unsafe fn destroy_view_objects(&mut self) {
+        let mut views = mem::take(&mut self.views);
+        for view in &mut views {
+            self.with_view(view, |app, _| app.destroy_device_objects());
+        }
+        self.views = views;
+    }
+
+    /// Recreates everything created with the logical device for the views
+    /// (e.g., after it has been recreated).
+    pub(crate) unsafe fn create_view_objects(&mut self) -> Result<()> {
+        let mut views = mem::take(&mut self.views);
+
+        let result = views.iter_mut().try_for_each(|view| {
+            // The queues were recreated with the logical device.
+            view.data.graphics_queue = self.data.graphics_queue;
+            view.data.present_queue = self.data.present_queue;
+            view.data.transfer_queue = self.data.transfer_queue;
+            view.data.compute_queue = self.data.compute_queue;
+            view.frame = 0;
+            view.resized = false;
+            self.with_view(view, |app, window| app.create_device_objects(Some(window)))
+        });
+
+        self.views = views;
+        result
+    }
+
+    /// Calls a function with the data of a view swapped in for the data of
+    /// our Vulkan app (and without the screenshot or recording, which are only
+    /// for the main window).
+    fn with_view<T>(&mut self, view: &mut View, f: impl FnOnce(&mut App, &Window) -> T) -> T {
+        let screenshot = self.screenshot.take();
+        let recorder = self.recorder.take();
+        self.swap(view);
+
+        let result = f(self, &view.window);
+
+        self.swap(view);
+        self.screenshot = screenshot;
+        self.recorder = recorder;
+
+        result
+    }
+
+    fn swap(&mut self, view: &mut View) {
+        mem::swap(&mut self.data, &mut view.data);
+        mem::swap(&mut self.frame, &mut view.frame);
+        mem::swap(&mut self.resized, &mut view.resized);
+        mem::swap(&mut self.stats, &mut view.stats);
+        mem::swap(&mut self.profiler, &mut view.profiler);
+    }
+}
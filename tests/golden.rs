@@ -0,0 +1,23 @@
+//! Renders a deterministic frame offscreen (so no display is needed) and
+//! compares it to the reference image.
+//!
+//! The reference image is rendered with Mesa's lavapipe (the software
+//! rasterizer picked by `--software`), since other drivers rasterize and
+//! resolve edges differently enough to exceed the tolerance. It's regenerated
+//! (and should be checked by eye before it's committed) with
+//! `cargo run -- --software --golden tests/golden/quad.png --bless`.
+
+use std::process::Command;
+
+#[test]
+#[ignore = "requires a Vulkan device"]
+fn golden_image() {
+    let status = Command::new(env!("CARGO_BIN_EXE_vulkan-tutorial"))
+        .args(["--software", "--golden", "tests/golden/quad.png"])
+        .status()
+        .expect("failed to run vulkan-tutorial");
+    assert!(
+        status.success(),
+        "rendered frame doesn't match reference image"
+    );
+}